@@ -0,0 +1,480 @@
+//! A metadata catalogue that loads the metadata of each country on demand.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use futures::{stream, StreamExt};
+use itertools::Itertools;
+use log::{debug, info};
+use polars::lazy::{dsl::col, frame::IntoLazy};
+use polars::prelude::DataFrame;
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::{
+    config::Config,
+    error::MetadataError,
+    metadata::{
+        check_schema_version, get_country_names, get_schema_version, load_each_country,
+        merge_metadata, CountryMetadataLoader, Metadata,
+    },
+    search::{Country, SearchParams, SearchResults},
+    COL,
+};
+
+/// A metadata catalogue that, unlike `metadata::load_all`, only loads the metadata of a country
+/// once a search or selection references it. Loaded countries are kept, so each country is
+/// loaded at most once.
+#[derive(Debug)]
+pub struct MetadataCatalogue {
+    config: Config,
+    countries: OnceCell<Vec<String>>,
+    loaded: Mutex<BTreeMap<String, Metadata>>,
+    /// The country tables of each country, used to resolve country names given in searches
+    country_tables: Mutex<BTreeMap<String, DataFrame>>,
+}
+
+impl MetadataCatalogue {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            countries: OnceCell::new(),
+            loaded: Mutex::new(BTreeMap::new()),
+            country_tables: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The countries available in the catalogue, or only those in `Config::countries` if given.
+    /// The schema version of the catalogue is checked the first time this is called.
+    pub async fn available_countries(&self) -> Result<&[String], MetadataError> {
+        let countries = self
+            .countries
+            .get_or_try_init(|| async {
+                if let Some(version) = get_schema_version(&self.config).await? {
+                    check_schema_version(version);
+                }
+                let available = get_country_names(&self.config).await?;
+                let Some(countries) = self.config.countries.as_ref() else {
+                    return Ok(available);
+                };
+                countries
+                    .iter()
+                    .map(|country| {
+                        available
+                            .iter()
+                            .find(|name| name.eq_ignore_ascii_case(country))
+                            .cloned()
+                            .ok_or_else(|| MetadataError::UnknownCountry {
+                                country: country.clone(),
+                                available: available.clone(),
+                            })
+                    })
+                    .collect()
+            })
+            .await?;
+        Ok(countries)
+    }
+
+    /// The countries whose metadata has been loaded so far
+    pub async fn loaded_countries(&self) -> Vec<String> {
+        self.loaded.lock().await.keys().cloned().collect()
+    }
+
+    /// The merged metadata of `countries`, loading any that have not been loaded yet
+    pub async fn metadata_for<S: AsRef<str>>(
+        &self,
+        countries: &[S],
+    ) -> Result<Metadata, MetadataError> {
+        let available = self.available_countries().await?;
+        let countries = countries
+            .iter()
+            .map(|country| {
+                available
+                    .iter()
+                    .find(|name| name.eq_ignore_ascii_case(country.as_ref()))
+                    .ok_or_else(|| MetadataError::UnknownCountry {
+                        country: country.as_ref().to_string(),
+                        available: available.to_vec(),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut loaded = self.loaded.lock().await;
+        let to_load = countries
+            .iter()
+            .filter(|country| !loaded.contains_key(country.as_str()))
+            .unique()
+            .collect_vec();
+        if !to_load.is_empty() {
+            info!("Loading country metadata: {to_load:?}");
+            let metadata = load_each_country(&self.config, &to_load).await;
+            for (country, metadata) in to_load.into_iter().zip(metadata) {
+                loaded.insert(country.to_string(), metadata?);
+            }
+        }
+        let metadata = merge_metadata(
+            &countries
+                .iter()
+                .unique()
+                .map(|country| (country.as_str(), &loaded[country.as_str()]))
+                .collect_vec(),
+            self.config.relaxed_merge,
+        )?;
+        metadata.validate_schema()?;
+        Ok(metadata)
+    }
+
+    /// The countries that a search with `params` needs to load. A country given as one of the
+    /// catalogue's country IDs is resolved directly. Otherwise the country tables, which are much
+    /// smaller than the rest of the metadata, are fetched to match it against country names and
+    /// codes. Searches that do not name a country need every country. Returns
+    /// `MetadataError::UnknownCountry` if no country matches.
+    pub async fn countries_for(&self, params: &SearchParams) -> Result<Vec<String>> {
+        let available = self.available_countries().await?;
+        let Some(country) = params.country.as_ref() else {
+            return Ok(available.to_vec());
+        };
+        if let Some(id) = available
+            .iter()
+            .find(|id| id.eq_ignore_ascii_case(country.value.trim()))
+        {
+            return Ok(vec![id.clone()]);
+        }
+        let mut country_tables = self.country_tables.lock().await;
+        let to_load = available
+            .iter()
+            .filter(|id| !country_tables.contains_key(id.as_str()))
+            .collect_vec();
+        let tables: Vec<_> = stream::iter(&to_load)
+            .map(|id| async {
+                CountryMetadataLoader::new(id)
+                    .load_country_table(&self.config)
+                    .await
+            })
+            .buffered(self.config.max_concurrent_countries.max(1))
+            .collect()
+            .await;
+        for (id, table) in to_load.into_iter().zip(tables) {
+            country_tables.insert(id.clone(), table?);
+        }
+        let mut countries = vec![];
+        for (id, table) in country_tables.iter() {
+            if matches_country(table, country)? {
+                countries.push(id.clone());
+            }
+        }
+        debug!("Resolved country {:?} to {countries:?}", country.value);
+        if countries.is_empty() {
+            Err(MetadataError::UnknownCountry {
+                country: country.value.clone(),
+                available: available.to_vec(),
+            })?;
+        }
+        Ok(countries)
+    }
+
+    /// Search the catalogue, loading only the countries the search needs (see `countries_for`)
+    pub async fn search(&self, params: &SearchParams) -> Result<SearchResults> {
+        let countries = self.countries_for(params).await?;
+        let metadata = self.metadata_for(&countries).await?;
+        Ok(params
+            .clone()
+            .resolve_geometry_levels(&metadata, &self.config.geometry_level_aliases)?
+            .search(&metadata.combined_metric_source_geometry()?))
+    }
+}
+
+/// Whether any row of a country table matches `country`
+fn matches_country(table: &DataFrame, country: &Country) -> Result<bool> {
+    let matching = table
+        .clone()
+        .lazy()
+        // The country filter also matches the countries of interest of data publishers
+        .with_column(col(COL::COUNTRY_ID).alias(COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST))
+        .filter(country.clone().into())
+        .collect()?;
+    Ok(matching.height() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex as StdMutex},
+        time::Duration,
+    };
+
+    use httpmock::{prelude::*, Mock};
+    use polars::{
+        df,
+        prelude::{NamedFrom, ParquetWriter},
+        series::Series,
+    };
+
+    use super::*;
+    use crate::{
+        config::AuthConfig,
+        metadata::{load_all, paths as PATHS},
+        progress::{ProgressCallback, ProgressEvent},
+        search::{CaseSensitivity, MatchType, SearchConfig},
+        test_util::file_server,
+    };
+
+    fn country_metadata(id: &str, name: &str) -> Vec<(&'static str, DataFrame)> {
+        let release = format!("{id}_release");
+        let geometry = format!("{id}_geometry");
+        let publisher = format!("{id}_publisher");
+        vec![
+            (
+                PATHS::METRIC_METADATA,
+                df!(
+                    COL::METRIC_ID => &[format!("{id}_metric")],
+                    COL::METRIC_HUMAN_READABLE_NAME => &["Population"],
+                    COL::METRIC_DESCRIPTION => &["Total population"],
+                    COL::METRIC_HXL_TAG => &["#population"],
+                    COL::METRIC_SOURCE_METRIC_ID => &["total"],
+                    COL::METRIC_PARQUET_PATH => &[format!("{id}/metrics.parquet")],
+                    COL::METRIC_PARQUET_COLUMN_NAME => &["total"],
+                    COL::METRIC_SOURCE_DATA_RELEASE_ID => &[release.as_str()],
+                    COL::METRIC_SOURCE_DOWNLOAD_URL => &["https://example.com"],
+                )
+                .unwrap(),
+            ),
+            (
+                PATHS::GEOMETRY_METADATA,
+                df!(
+                    COL::GEOMETRY_ID => &[geometry.as_str()],
+                    COL::GEOMETRY_FILEPATH_STEM => &[format!("{id}/geometries")],
+                    COL::GEOMETRY_LEVEL => &["region"],
+                )
+                .unwrap(),
+            ),
+            (
+                PATHS::SOURCE,
+                df!(
+                    COL::SOURCE_DATA_RELEASE_ID => &[release.as_str()],
+                    COL::SOURCE_DATA_RELEASE_NAME => &["Census"],
+                    COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START => &[None::<&str>],
+                    COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END => &[None::<&str>],
+                    COL::SOURCE_DATA_RELEASE_COLLECTION_PERIOD_START => &[None::<&str>],
+                    COL::SOURCE_DATA_RELEASE_GEOMETRY_METADATA_ID => &[geometry.as_str()],
+                    COL::SOURCE_DATA_RELEASE_DATA_PUBLISHER_ID => &[publisher.as_str()],
+                )
+                .unwrap(),
+            ),
+            (
+                PATHS::PUBLISHER,
+                df!(
+                    COL::DATA_PUBLISHER_ID => &[publisher.as_str()],
+                    COL::DATA_PUBLISHER_NAME => &["Statistics office"],
+                    COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST => &[Series::new("", &[id])],
+                )
+                .unwrap(),
+            ),
+            (
+                PATHS::COUNTRY,
+                df!(
+                    COL::COUNTRY_ID => &[id],
+                    COL::COUNTRY_NAME_SHORT_EN => &[name],
+                    COL::COUNTRY_NAME_OFFICIAL => &[name],
+                    COL::COUNTRY_ISO2 => &[&id[..2]],
+                    COL::COUNTRY_ISO3 => &[id],
+                    COL::COUNTRY_ISO3166_2 => &[None::<&str>],
+                )
+                .unwrap(),
+            ),
+        ]
+    }
+
+    /// Serves the metadata of each country, returning the mock of each country's metric metadata
+    fn serve_countries<'a>(server: &'a MockServer, countries: &[(&str, &str)]) -> Vec<Mock<'a>> {
+        let names = countries.iter().map(|(id, _)| *id).join("\n");
+        server.mock(|when, then| {
+            when.method(GET).path("/countries.txt");
+            then.status(200).body(names);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/version.txt");
+            then.status(404);
+        });
+        let mut metric_mocks = vec![];
+        for (id, name) in countries {
+            for (path, mut df) in country_metadata(id, name) {
+                let mut bytes = vec![];
+                ParquetWriter::new(&mut bytes).finish(&mut df).unwrap();
+                let mock = server.mock(|when, then| {
+                    when.method(GET).path(format!("/{id}/{path}"));
+                    // Delayed so that the loads of several countries overlap
+                    then.status(200)
+                        .delay(Duration::from_millis(20))
+                        .body(bytes);
+                });
+                if path == PATHS::METRIC_METADATA {
+                    metric_mocks.push(mock);
+                }
+            }
+        }
+        metric_mocks
+    }
+
+    /// Serves the metadata of each country along with `countries.txt` with `file_server`, so
+    /// that it can be scanned by polars. Returns the base URL.
+    async fn range_server(countries: &[(&str, &str)]) -> String {
+        let mut files = HashMap::from([(
+            "/countries.txt".to_string(),
+            countries.iter().map(|(id, _)| *id).join("\n").into_bytes(),
+        )]);
+        for (id, name) in countries {
+            for (path, mut df) in country_metadata(id, name) {
+                let mut bytes = vec![];
+                ParquetWriter::new(&mut bytes).finish(&mut df).unwrap();
+                files.insert(format!("/{id}/{path}"), bytes);
+            }
+        }
+        file_server(files).await
+    }
+
+    fn test_config(server: &MockServer) -> Config {
+        Config {
+            base_path: server.base_url(),
+            // Fetch the metadata with reqwest rather than polars' HTTP scans
+            auth: Some(AuthConfig::Bearer {
+                token: "token".into(),
+            }),
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_country_should_not_be_merged() {
+        let server = MockServer::start();
+        serve_countries(&server, &[("bel", "Belgium"), ("usa", "USA")]);
+        let catalogue = MetadataCatalogue::new(test_config(&server));
+
+        let err = catalogue
+            .search(&country_search("Atlantis"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<MetadataError>(),
+                Some(MetadataError::UnknownCountry { country, .. }) if country == "Atlantis"
+            ),
+            "{err}"
+        );
+        assert!(catalogue.loaded_countries().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn single_country_search_should_scan_without_auth() {
+        // Without credentials the metadata is scanned by polars rather than fetched with reqwest
+        let config = Config {
+            base_path: range_server(&[("bel", "Belgium"), ("usa", "USA")]).await,
+            ..Config::default()
+        };
+        let catalogue = MetadataCatalogue::new(config);
+
+        let results = catalogue.search(&country_search("Belgium")).await.unwrap();
+        assert_eq!(results.0.height(), 1);
+        assert_eq!(catalogue.loaded_countries().await, ["bel"]);
+    }
+
+    fn country_search(value: &str) -> SearchParams {
+        SearchParams {
+            country: Some(Country {
+                value: value.into(),
+                config: SearchConfig {
+                    match_type: MatchType::Exact,
+                    case_sensitivity: CaseSensitivity::Insensitive,
+                },
+            }),
+            ..SearchParams::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn single_country_search_should_load_one_country() {
+        let server = MockServer::start();
+        let metric_mocks = serve_countries(&server, &[("bel", "Belgium"), ("usa", "USA")]);
+        let catalogue = MetadataCatalogue::new(test_config(&server));
+
+        let results = catalogue.search(&country_search("BEL")).await.unwrap();
+        assert_eq!(results.0.height(), 1);
+        assert_eq!(catalogue.loaded_countries().await, ["bel"]);
+        metric_mocks[0].assert_hits(1);
+        metric_mocks[1].assert_hits(0);
+
+        // Loaded countries are not loaded again
+        catalogue.search(&country_search("bel")).await.unwrap();
+        metric_mocks[0].assert_hits(1);
+
+        // Other countries are loaded once a search references them
+        let results = catalogue.search(&country_search("usa")).await.unwrap();
+        assert_eq!(results.0.height(), 1);
+        metric_mocks[1].assert_hits(1);
+    }
+
+    #[tokio::test]
+    async fn search_without_country_should_load_all_countries() {
+        let server = MockServer::start();
+        let metric_mocks = serve_countries(&server, &[("bel", "Belgium"), ("usa", "USA")]);
+        let catalogue = MetadataCatalogue::new(test_config(&server));
+
+        let results = catalogue.search(&SearchParams::default()).await.unwrap();
+        assert_eq!(results.0.height(), 2);
+        assert_eq!(catalogue.loaded_countries().await, ["bel", "usa"]);
+
+        let results = catalogue.search(&country_search("Belgium")).await.unwrap();
+        assert_eq!(results.0.height(), 1);
+        for mock in metric_mocks {
+            mock.assert_hits(1);
+        }
+    }
+
+    #[tokio::test]
+    async fn countries_loaded_at_once_should_be_limited() {
+        let server = MockServer::start();
+        let countries = [
+            ("bel", "Belgium"),
+            ("fra", "France"),
+            ("gbr", "United Kingdom"),
+            ("nld", "Netherlands"),
+            ("usa", "USA"),
+        ];
+        serve_countries(&server, &countries);
+        // The number of files being fetched for each country, and the most countries seen
+        // fetching files at once
+        let in_flight = Arc::new(StdMutex::new((HashMap::<String, usize>::new(), 0)));
+        let country_prefix = format!("{}/", server.base_url());
+        let progress = {
+            let in_flight = in_flight.clone();
+            ProgressCallback::new(move |event| {
+                let (path, delta) = match &event {
+                    ProgressEvent::Started { path } => (path, 1),
+                    ProgressEvent::Finished { path, .. } => (path, -1),
+                    ProgressEvent::Transferred { .. } => return,
+                };
+                let Some((country, _)) = path
+                    .strip_prefix(&country_prefix)
+                    .and_then(|path| path.split_once('/'))
+                else {
+                    return;
+                };
+                let (files, max_countries) = &mut *in_flight.lock().unwrap();
+                let count = files.entry(country.to_string()).or_default();
+                *count = count.checked_add_signed(delta).unwrap();
+                files.retain(|_, count| *count > 0);
+                *max_countries = (*max_countries).max(files.len());
+            })
+        };
+        let config = Config {
+            max_concurrent_countries: 2,
+            progress,
+            ..test_config(&server)
+        };
+        let metadata = load_all(&config).await.unwrap();
+        assert_eq!(metadata.metrics.height(), countries.len());
+        let (files, max_countries) = &*in_flight.lock().unwrap();
+        assert!(files.is_empty());
+        assert_eq!(*max_countries, 2);
+    }
+}
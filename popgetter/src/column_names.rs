@@ -3,6 +3,9 @@
 //! defined in the upstream metadata classes!
 
 pub const GEO_ID: &str = "GEO_ID";
+/// Columns of a geography hierarchy, mapping each `GEO_ID` to its parent at a coarser level
+pub const PARENT_GEO_ID: &str = "parent_GEO_ID";
+pub const PARENT_GEOMETRY_LEVEL: &str = "parent_geometry_level";
 
 pub const COUNTRY_ID: &str = "country_id";
 pub const COUNTRY_NAME_SHORT_EN: &str = "country_name_short_en";
@@ -23,6 +26,14 @@ pub const GEOMETRY_VALIDITY_PERIOD_START: &str = "geometry_validity_period_start
 pub const GEOMETRY_VALIDITY_PERIOD_END: &str = "geometry_validity_period_end";
 pub const GEOMETRY_LEVEL: &str = "geometry_level";
 pub const GEOMETRY_HXL_TAG: &str = "geometry_hxl_tag";
+/// Description of a geometry level. Not present in all metadata releases, and older releases
+/// name it `description`, which `Metadata::combined_metric_source_geometry` renames.
+pub const GEOMETRY_DESCRIPTION: &str = "geometry_description";
+/// Ordinal resolution of a geometry level, where higher values are finer-grained
+pub const GEOMETRY_RESOLUTION: &str = "geometry_resolution";
+/// Column of the geometry files holding the ID of each geometry, if it is not `GEO_ID`. Not
+/// present in all metadata releases.
+pub const GEOMETRY_GEO_ID_COLUMN: &str = "geometry_geo_id_column";
 
 pub const SOURCE_DATA_RELEASE_ID: &str = "source_data_release_id";
 pub const SOURCE_DATA_RELEASE_NAME: &str = "source_data_release_name";
@@ -52,6 +63,11 @@ pub const METRIC_PARQUET_COLUMN_NAME: &str = "metric_parquet_column_name";
 pub const METRIC_PARQUET_MARGIN_OF_ERROR_COLUMN: &str = "metric_parquet_margin_of_error_column";
 pub const METRIC_PARQUET_MARGIN_OF_ERROR_FILE: &str = "metric_parquet_margin_of_error_file";
 pub const METRIC_POTENTIAL_DENOMINATOR_IDS: &str = "metric_potential_denominator_ids";
+/// Whether a metric is a `count` or a `continuous` value. Not present in all metadata releases.
+pub const METRIC_DATA_TYPE: &str = "metric_data_type";
+/// The unit of measurement of a metric, e.g. `people` or `%`. Not present in all metadata
+/// releases.
+pub const METRIC_UNIT: &str = "metric_unit";
 pub const METRIC_PARENT_METRIC_ID: &str = "metric_parent_metric_id";
 pub const METRIC_SOURCE_DATA_RELEASE_ID: &str = "metric_source_data_release_id";
 pub const METRIC_SOURCE_DOWNLOAD_URL: &str = "metric_source_download_url";
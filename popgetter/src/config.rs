@@ -1,9 +1,78 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use log::warn;
 use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    error::ConnectivityError,
+    metadata::{join_path, CountryMetadataPaths},
+    progress::ProgressCallback,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(default)]
 pub struct Config {
     pub base_path: String,
+    pub retry: RetryConfig,
+    /// ISO3 codes of the countries to load metadata for. All countries are loaded if `None`.
+    pub countries: Option<Vec<String>>,
+    /// Directory in which metadata is cached. Defaults to `popgetter` in the user's cache
+    /// directory if `None`.
+    pub cache_dir: Option<PathBuf>,
+    /// Age in seconds after which cached metadata is fetched again. Never expires if `None`.
+    pub cache_ttl_secs: Option<u64>,
+    /// Ignore any cached metadata and fetch it again
+    pub force_refresh: bool,
+    /// Fill columns missing from the metadata of some countries with nulls when merging the
+    /// metadata of several countries, rather than failing
+    pub relaxed_merge: bool,
+    /// Aliases of geometry levels that geometry level searches also match, mapping each geometry
+    /// level to its aliases, e.g. `lsoa21 = ["LSOA", "Lower Super Output Area"]`. These are in
+    /// addition to `search::GEOMETRY_LEVEL_ALIASES`.
+    pub geometry_level_aliases: BTreeMap<String, Vec<String>>,
+    /// Names of the metadata files of countries that do not use the default names, keyed on the
+    /// country's directory under the base path, e.g. `bel`
+    pub metadata_paths: BTreeMap<String, CountryMetadataPaths>,
+    /// Maximum number of metric files downloaded at the same time
+    pub max_concurrent_downloads: usize,
+    /// Maximum number of countries whose metadata is loaded at the same time. The metadata files
+    /// of each country are loaded concurrently.
+    pub max_concurrent_countries: usize,
+    /// Credentials attached to requests for metadata and metrics, if they are hosted behind an
+    /// authenticated endpoint
+    pub auth: Option<AuthConfig>,
+    /// Timeout in milliseconds for a whole request, including reading the response. Large files
+    /// can take minutes to download, so this is `None` by default and stalled requests are instead
+    /// caught by `connect_timeout_ms` and `read_timeout_ms`.
+    pub http_timeout_ms: Option<u64>,
+    /// Timeout in milliseconds for establishing a connection
+    pub connect_timeout_ms: Option<u64>,
+    /// Timeout in milliseconds for each read of a response, so a download fails once no bytes
+    /// have arrived for this long however long the whole download takes
+    pub read_timeout_ms: Option<u64>,
+    /// Timeout in milliseconds for each attempt at loading a metadata file. Polars' scans do not
+    /// use the HTTP client, so are not covered by the timeouts above, and a stalled scan would
+    /// otherwise hang the load. Never times out if `None`.
+    pub load_timeout_ms: Option<u64>,
+    /// Maximum number of idle connections kept open to each host. Unlimited if `None`.
+    pub pool_max_idle_per_host: Option<usize>,
+    /// The HTTP client shared by all requests made with this config
+    #[serde(skip)]
+    pub http_client: SharedClient,
+    /// Called as each metadata or metric file is fetched
+    #[serde(skip)]
+    pub progress: ProgressCallback,
+    /// Cancels metadata loads and metric downloads made with this config
+    #[serde(skip)]
+    pub cancellation: Cancellation,
 }
 
 impl Default for Config {
@@ -12,6 +81,729 @@ impl Default for Config {
             // TODO: add fn to generate the release directory name from the CLI version directly
             // E.g. this could be achieved with: https://docs.rs/built/latest/built/
             base_path: "https://popgetter.blob.core.windows.net/releases/v0.2".into(),
+            retry: RetryConfig::default(),
+            countries: None,
+            cache_dir: None,
+            cache_ttl_secs: None,
+            force_refresh: false,
+            relaxed_merge: false,
+            geometry_level_aliases: BTreeMap::new(),
+            metadata_paths: BTreeMap::new(),
+            max_concurrent_downloads: 4,
+            max_concurrent_countries: 4,
+            auth: None,
+            http_timeout_ms: None,
+            connect_timeout_ms: Some(10_000),
+            read_timeout_ms: Some(30_000),
+            load_timeout_ms: Some(300_000),
+            pool_max_idle_per_host: None,
+            http_client: SharedClient::default(),
+            progress: ProgressCallback::default(),
+            cancellation: Cancellation::default(),
+        }
+    }
+}
+
+impl Config {
+    /// A `ConfigBuilder` starting from the default config
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Key identifying the metadata fetched with this config, used to name its cache directory so
+    /// that caches for different base paths, sets of countries or `metadata_paths` overrides are
+    /// kept separate.
+    pub fn cache_key(&self) -> String {
+        let mut key = self.base_path.trim_end_matches('/').to_string();
+        if let Some(countries) = self.countries.as_ref() {
+            let mut countries = countries.clone();
+            countries.sort();
+            key = format!("{key}_{}", countries.join("_"));
+        }
+        // Overrides can be long, so are included as a hash. Overrides of the default names
+        // change nothing, so are left out to share the cache of a config without them.
+        let overrides = self
+            .metadata_paths
+            .iter()
+            .filter(|(_, paths)| **paths != CountryMetadataPaths::default())
+            .collect::<BTreeMap<_, _>>();
+        if !overrides.is_empty() {
+            let overrides =
+                serde_json::to_vec(&overrides).expect("metadata paths should serialize");
+            key = format!("{key}_paths-{:016x}", fnv1a(&overrides));
+        }
+        key.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
+    /// The names of the metadata files of `country`, from `metadata_paths` or else the defaults
+    pub fn metadata_paths_for(&self, country: &str) -> CountryMetadataPaths {
+        self.metadata_paths
+            .get(country)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The HTTP client for this config, built from its timeout and connection pool settings on
+    /// first use. Clones of the config share the same client and so the same connection pool.
+    pub fn client(&self) -> &reqwest::Client {
+        self.http_client.0.get_or_init(|| {
+            let mut builder = reqwest::Client::builder();
+            if let Some(timeout) = self.http_timeout_ms {
+                builder = builder.timeout(Duration::from_millis(timeout));
+            }
+            if let Some(timeout) = self.connect_timeout_ms {
+                builder = builder.connect_timeout(Duration::from_millis(timeout));
+            }
+            if let Some(timeout) = self.read_timeout_ms {
+                builder = builder.read_timeout(Duration::from_millis(timeout));
+            }
+            if let Some(max_idle) = self.pool_max_idle_per_host {
+                builder = builder.pool_max_idle_per_host(max_idle);
+            }
+            builder.build().unwrap_or_else(|err| {
+                warn!("Failed to build HTTP client, falling back to the defaults: {err}");
+                reqwest::Client::new()
+            })
+        })
+    }
+
+    /// The timeout for a whole request, if any
+    pub fn http_timeout(&self) -> Option<Duration> {
+        self.http_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// The timeout for each attempt at loading a metadata file: the shorter of `load_timeout_ms`
+    /// and `http_timeout_ms`, if either is set
+    pub fn load_timeout(&self) -> Option<Duration> {
+        [self.load_timeout_ms, self.http_timeout_ms]
+            .into_iter()
+            .flatten()
+            .min()
+            .map(Duration::from_millis)
+    }
+
+    /// Whether the file at `path` should be downloaded with the shared client rather than
+    /// scanned by polars. Polars' HTTP scans cannot attach credentials, so remote files are
+    /// downloaded with the client when `auth` is set. Other files are scanned, with the
+    /// `cancellation` token checked before each scan starts.
+    pub(crate) fn fetch_with_client(&self, path: &str) -> bool {
+        let is_remote = path.starts_with("http://") || path.starts_with("https://");
+        is_remote && self.auth.is_some()
+    }
+
+    /// Build a GET request for `url` with the shared client, with the configured credentials
+    /// attached
+    pub fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        match self.auth.as_ref() {
+            Some(auth) => auth.get(self.client(), url),
+            None => self.client().get(url),
+        }
+    }
+
+    /// Check that the catalogue at the base path can be reached with the configured credentials,
+    /// by fetching `countries.txt` and requesting the country metadata of the first configured
+    /// (or listed) country. Requests are not retried, so a misconfiguration fails fast.
+    pub async fn check_connectivity(&self) -> Result<(), ConnectivityError> {
+        let countries_url = join_path(&self.base_path, "countries.txt");
+        let response = self.check_url(&countries_url).await?;
+        let country = match self.countries.as_ref() {
+            Some(countries) => countries.first().cloned(),
+            None => {
+                let text =
+                    response
+                        .text()
+                        .await
+                        .map_err(|source| ConnectivityError::Unreachable {
+                            url: countries_url,
+                            source: redact(source),
+                        })?;
+                text.lines()
+                    .map(str::trim)
+                    .find(|line| !line.is_empty())
+                    .map(Into::into)
+            }
+        };
+        if let Some(country) = country {
+            // Only the status is needed, so the body of the response is never read
+            let path = self.metadata_paths_for(&country).countries;
+            self.check_url(&join_path(&self.base_path, &format!("{country}/{path}")))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Request `url`, mapping failures to the setting most likely to be at fault
+    async fn check_url(&self, url: &str) -> Result<reqwest::Response, ConnectivityError> {
+        let response =
+            self.get(url)
+                .send()
+                .await
+                .map_err(|source| ConnectivityError::Unreachable {
+                    url: url.to_string(),
+                    source: redact(source),
+                })?;
+        let status = response.status();
+        match status {
+            _ if status.is_success() => Ok(response),
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                Err(ConnectivityError::Unauthorized {
+                    url: url.to_string(),
+                    status,
+                })
+            }
+            reqwest::StatusCode::NOT_FOUND => Err(ConnectivityError::NotFound {
+                url: url.to_string(),
+            }),
+            _ => Err(ConnectivityError::Status {
+                url: url.to_string(),
+                status,
+            }),
+        }
+    }
+}
+
+/// Builds a `Config`, validating and normalizing it in `build`. A builder can also be created from
+/// an existing config, e.g. one read from a file, to validate it.
+///
+/// ```
+/// use popgetter::config::Config;
+///
+/// let config = Config::builder()
+///     .base_path("https://example.com/releases/v0.2/")
+///     .countries(["bel"])
+///     .build()
+///     .unwrap();
+/// assert_eq!(config.base_path, "https://example.com/releases/v0.2");
+/// ```
+#[derive(Debug, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl From<Config> for ConfigBuilder {
+    fn from(config: Config) -> Self {
+        Self { config }
+    }
+}
+
+impl ConfigBuilder {
+    pub fn base_path(mut self, base_path: impl Into<String>) -> Self {
+        self.config.base_path = base_path.into();
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryConfig) -> Self {
+        self.config.retry = retry;
+        self
+    }
+
+    pub fn countries<S: Into<String>>(mut self, countries: impl IntoIterator<Item = S>) -> Self {
+        self.config.countries = Some(countries.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.config.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    pub fn cache_ttl_secs(mut self, cache_ttl_secs: u64) -> Self {
+        self.config.cache_ttl_secs = Some(cache_ttl_secs);
+        self
+    }
+
+    pub fn force_refresh(mut self, force_refresh: bool) -> Self {
+        self.config.force_refresh = force_refresh;
+        self
+    }
+
+    pub fn relaxed_merge(mut self, relaxed_merge: bool) -> Self {
+        self.config.relaxed_merge = relaxed_merge;
+        self
+    }
+
+    /// Add `aliases` of `geometry_level` to `Config::geometry_level_aliases`
+    pub fn geometry_level_aliases<S: Into<String>>(
+        mut self,
+        geometry_level: impl Into<String>,
+        aliases: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.config
+            .geometry_level_aliases
+            .entry(geometry_level.into())
+            .or_default()
+            .extend(aliases.into_iter().map(Into::into));
+        self
+    }
+
+    /// Use `paths` for the names of the metadata files of `country`
+    pub fn metadata_paths(
+        mut self,
+        country: impl Into<String>,
+        paths: CountryMetadataPaths,
+    ) -> Self {
+        self.config.metadata_paths.insert(country.into(), paths);
+        self
+    }
+
+    pub fn max_concurrent_downloads(mut self, max_concurrent_downloads: usize) -> Self {
+        self.config.max_concurrent_downloads = max_concurrent_downloads;
+        self
+    }
+
+    pub fn max_concurrent_countries(mut self, max_concurrent_countries: usize) -> Self {
+        self.config.max_concurrent_countries = max_concurrent_countries;
+        self
+    }
+
+    pub fn auth(mut self, auth: AuthConfig) -> Self {
+        self.config.auth = Some(auth);
+        self
+    }
+
+    pub fn http_timeout_ms(mut self, http_timeout_ms: Option<u64>) -> Self {
+        self.config.http_timeout_ms = http_timeout_ms;
+        self
+    }
+
+    pub fn connect_timeout_ms(mut self, connect_timeout_ms: Option<u64>) -> Self {
+        self.config.connect_timeout_ms = connect_timeout_ms;
+        self
+    }
+
+    pub fn read_timeout_ms(mut self, read_timeout_ms: Option<u64>) -> Self {
+        self.config.read_timeout_ms = read_timeout_ms;
+        self
+    }
+
+    pub fn load_timeout_ms(mut self, load_timeout_ms: Option<u64>) -> Self {
+        self.config.load_timeout_ms = load_timeout_ms;
+        self
+    }
+
+    pub fn pool_max_idle_per_host(mut self, pool_max_idle_per_host: usize) -> Self {
+        self.config.pool_max_idle_per_host = Some(pool_max_idle_per_host);
+        self
+    }
+
+    pub fn progress(mut self, progress: ProgressCallback) -> Self {
+        self.config.progress = progress;
+        self
+    }
+
+    pub fn cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.config.cancellation = cancellation;
+        self
+    }
+
+    /// Validate and return the config. Trailing slashes are trimmed from the base path, since
+    /// file paths are joined to it with `/`. Returns an error if the base path is empty, no
+    /// downloads or country loads are allowed at once, or the cache directory cannot be created
+    /// or written to.
+    pub fn build(self) -> anyhow::Result<Config> {
+        let mut config = self.config;
+        config.base_path = config.base_path.trim().trim_end_matches('/').to_string();
+        if config.base_path.is_empty() {
+            bail!("The base path must not be empty");
+        }
+        if config.max_concurrent_downloads == 0 {
+            bail!("At least one concurrent download must be allowed");
+        }
+        if config.max_concurrent_countries == 0 {
+            bail!("At least one country must be allowed to load at once");
         }
+        if let Some(cache_dir) = config.cache_dir.as_ref() {
+            check_writable(cache_dir)?;
+        }
+        Ok(config)
+    }
+}
+
+/// Check that files can be written to `dir`, creating it if it does not exist
+fn check_writable(dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+    let probe = dir.join(".popgetter_write_test");
+    std::fs::write(&probe, [])
+        .with_context(|| format!("Cache directory {} is not writable", dir.display()))?;
+    std::fs::remove_file(&probe)?;
+    Ok(())
+}
+
+/// A `reqwest::Client` that is built lazily and shared between clones of a `Config`. It is
+/// derived from the other settings of the config, so it is ignored when comparing configs.
+#[derive(Clone, Default)]
+pub struct SharedClient(Arc<OnceLock<reqwest::Client>>);
+
+impl fmt::Debug for SharedClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SharedClient")
+            .field(&self.0.get().is_some())
+            .finish()
+    }
+}
+
+impl PartialEq for SharedClient {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// An optional `CancellationToken` for the loads and downloads made with a `Config`. Once the
+/// token is cancelled, outstanding requests are dropped and the load or download returns a
+/// `Cancelled` error. Never cancels if no token is given.
+///
+/// Requests made with the HTTP client are dropped on cancellation. Files scanned by polars (see
+/// `Config::fetch_with_client`) are scanned on blocking threads, so no further scans are started
+/// once the token is cancelled, but a scan already running completes in the background.
+#[derive(Clone, Default)]
+pub struct Cancellation(Option<CancellationToken>);
+
+impl Cancellation {
+    pub fn new(token: CancellationToken) -> Self {
+        Self(Some(token))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.as_ref().is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Completes once the token is cancelled, or never if there is no token
+    pub(crate) async fn cancelled(&self) {
+        match self.0.as_ref() {
+            Some(token) => token.cancelled().await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+impl fmt::Debug for Cancellation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Cancellation")
+            .field(&self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Tokens cannot be compared, so they are ignored when comparing configs
+impl PartialEq for Cancellation {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// Credentials for an authenticated endpoint. The token is never included in `Debug` output, so
+/// a `Config` can be logged safely.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthConfig {
+    /// Sent as an `Authorization: Bearer <token>` header
+    Bearer { token: String },
+    /// A shared access signature (e.g. for a private Azure container), appended to the query
+    /// string of each request
+    SasToken { token: String },
+}
+
+/// Remove the URL from a request error before it is logged or returned, as the URL of a request
+/// made with `AuthConfig::SasToken` includes the token
+pub(crate) fn redact(err: reqwest::Error) -> reqwest::Error {
+    err.without_url()
+}
+
+impl AuthConfig {
+    /// Build a GET request for `url` with these credentials attached
+    pub fn get(&self, client: &reqwest::Client, url: &str) -> reqwest::RequestBuilder {
+        match self {
+            AuthConfig::Bearer { token } => client.get(url).bearer_auth(token),
+            AuthConfig::SasToken { token } => {
+                // The token is already URL encoded so is appended as is
+                let token = token.trim_start_matches('?');
+                let separator = if url.contains('?') { '&' } else { '?' };
+                client.get(format!("{url}{separator}{token}"))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            AuthConfig::Bearer { .. } => "Bearer",
+            AuthConfig::SasToken { .. } => "SasToken",
+        };
+        f.debug_struct(kind).field("token", &"<redacted>").finish()
+    }
+}
+
+/// Policy for retrying requests that fail with a transient error (e.g. throttling or a dropped
+/// connection). The delay before attempt `n + 1` is `base_delay_ms * 2^(n - 1)` plus a random
+/// jitter of up to `jitter_ms`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Maximum number of attempts, including the first
+    pub max_attempts: u32,
+    /// Delay before the first retry in milliseconds
+    pub base_delay_ms: u64,
+    /// Maximum random jitter added to each delay in milliseconds
+    pub jitter_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: 3,
+            base_delay_ms: 500,
+            jitter_ms: 250,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Delay to wait after the given failed `attempt` (starting from 1)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay_ms
+            .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1)));
+        // Jitter only needs to spread out retries so the clock is a sufficient source
+        let jitter = match self.jitter_ms {
+            0 => 0,
+            jitter_ms => {
+                let nanos = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.subsec_nanos())
+                    .unwrap_or_default();
+                u64::from(nanos) % (jitter_ms + 1)
+            }
+        };
+        Duration::from_millis(backoff.saturating_add(jitter))
+    }
+}
+
+/// The 64-bit FNV-1a hash of `bytes`. Unlike the hashers in `std`, this is stable across
+/// releases of Rust, so can be used to name files that outlive the process.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::prelude::*;
+
+    use super::*;
+    use crate::metadata::paths;
+
+    #[test]
+    fn cache_key_should_depend_on_base_path_and_countries() {
+        let config = Config {
+            base_path: "https://example.com/releases/v0.2/".into(),
+            ..Config::default()
+        };
+        assert_eq!(config.cache_key(), "https___example.com_releases_v0.2");
+        let config_with_countries = Config {
+            countries: Some(vec!["usa".into(), "bel".into()]),
+            ..config.clone()
+        };
+        assert_eq!(
+            config_with_countries.cache_key(),
+            "https___example.com_releases_v0.2_bel_usa"
+        );
+
+        let with_paths = |metrics: &str| Config {
+            metadata_paths: BTreeMap::from([(
+                "bel".to_string(),
+                CountryMetadataPaths {
+                    metrics: metrics.into(),
+                    ..CountryMetadataPaths::default()
+                },
+            )]),
+            ..config.clone()
+        };
+        let key = with_paths("metrics_2023.parquet").cache_key();
+        assert!(key.starts_with("https___example.com_releases_v0.2_paths-"));
+        assert_eq!(key, with_paths("metrics_2023.parquet").cache_key());
+        assert_ne!(key, with_paths("metrics_2024.parquet").cache_key());
+        // Overriding a name with its default shares the cache of the config without overrides
+        assert_eq!(
+            with_paths(paths::METRIC_METADATA).cache_key(),
+            config.cache_key()
+        );
+    }
+
+    #[test]
+    fn builder_should_normalize_base_path() {
+        for base_path in [
+            "https://example.com/releases/v0.2",
+            "https://example.com/releases/v0.2/",
+            " https://example.com/releases/v0.2// ",
+        ] {
+            let config = Config::builder().base_path(base_path).build().unwrap();
+            assert_eq!(config.base_path, "https://example.com/releases/v0.2");
+        }
+        let tempdir = tempfile::TempDir::new().unwrap();
+        let cache_dir = tempdir.path().join("cache");
+        let config = Config::builder()
+            .cache_dir(&cache_dir)
+            .countries(["bel"])
+            .build()
+            .unwrap();
+        assert!(cache_dir.is_dir());
+        assert_eq!(config.countries, Some(vec!["bel".to_string()]));
+    }
+
+    #[test]
+    fn builder_should_reject_invalid_config() {
+        assert!(Config::builder().base_path("").build().is_err());
+        assert!(Config::builder().base_path("/").build().is_err());
+        assert!(Config::builder()
+            .max_concurrent_downloads(0)
+            .build()
+            .is_err());
+        assert!(Config::builder()
+            .max_concurrent_countries(0)
+            .build()
+            .is_err());
+        // The cache directory cannot be created inside a file
+        let file = tempfile::NamedTempFile::new().unwrap();
+        assert!(Config::builder()
+            .cache_dir(file.path().join("cache"))
+            .build()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn connectivity_check_should_report_rejected_credentials() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/countries.txt");
+            then.status(200).body("bel\n");
+        });
+        let metadata = server.mock(|when, then| {
+            when.method(GET).path(format!("/bel/{}", paths::COUNTRY));
+            then.status(200);
+        });
+        let config = Config::builder()
+            .base_path(server.base_url())
+            .build()
+            .unwrap();
+        config.check_connectivity().await.unwrap();
+        metadata.assert_hits(1);
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/countries.txt");
+            then.status(401);
+        });
+        let config = Config::builder()
+            .base_path(server.base_url())
+            .auth(AuthConfig::Bearer {
+                token: "expired".into(),
+            })
+            .build()
+            .unwrap();
+        let err = config.check_connectivity().await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                ConnectivityError::Unauthorized {
+                    status: reqwest::StatusCode::UNAUTHORIZED,
+                    ..
+                }
+            ),
+            "{err}"
+        );
+        assert!(err.to_string().contains("credentials"));
+    }
+
+    #[test]
+    fn retry_delay_should_back_off_exponentially() {
+        let retry = RetryConfig {
+            max_attempts: 4,
+            base_delay_ms: 100,
+            jitter_ms: 0,
+        };
+        assert_eq!(retry.delay(1), Duration::from_millis(100));
+        assert_eq!(retry.delay(2), Duration::from_millis(200));
+        assert_eq!(retry.delay(3), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn auth_token_should_not_appear_in_debug_output() {
+        let config = Config {
+            auth: Some(AuthConfig::Bearer {
+                token: "secret-token".into(),
+            }),
+            ..Config::default()
+        };
+        let debug = format!("{config:?} {config:#?}");
+        assert!(!debug.contains("secret-token"));
+        assert!(debug.contains("Bearer"));
+    }
+
+    #[test]
+    fn sas_token_should_be_appended_to_query_string() {
+        let client = reqwest::Client::new();
+        let auth = AuthConfig::SasToken {
+            token: "?sv=2022-11-02&sig=abc%2Bdef".into(),
+        };
+        let request = auth
+            .get(&client, "https://example.com/countries.txt")
+            .build()
+            .unwrap();
+        assert_eq!(
+            request.url().as_str(),
+            "https://example.com/countries.txt?sv=2022-11-02&sig=abc%2Bdef"
+        );
+        let request = auth
+            .get(&client, "https://example.com/countries.txt?a=1")
+            .build()
+            .unwrap();
+        assert_eq!(
+            request.url().query(),
+            Some("a=1&sv=2022-11-02&sig=abc%2Bdef")
+        );
+    }
+
+    #[test]
+    fn authenticated_remote_files_should_be_fetched_with_client() {
+        let remote = "https://example.com/bel/metric_metadata.parquet";
+        let local = "/tmp/bel/metric_metadata.parquet";
+        let config = Config::default();
+        assert!(!config.fetch_with_client(remote));
+
+        // Remote files are still scanned by polars when only a cancellation token is set
+        let config = Config {
+            cancellation: Cancellation::new(CancellationToken::new()),
+            ..Config::default()
+        };
+        assert!(!config.fetch_with_client(remote));
+        assert!(!config.fetch_with_client(local));
+
+        let config = Config {
+            auth: Some(AuthConfig::Bearer {
+                token: "secret".into(),
+            }),
+            ..Config::default()
+        };
+        assert!(config.fetch_with_client(remote));
+        assert!(!config.fetch_with_client(local));
+    }
+
+    #[test]
+    fn http_client_should_be_shared_between_clones() {
+        let config = Config::default();
+        let clone = config.clone();
+        assert!(std::ptr::eq(config.client(), clone.client()));
+        assert_eq!(config, Config::default());
     }
 }
@@ -1,21 +1,105 @@
+use std::path::Path;
+
+use anyhow::Context;
 use itertools::Itertools;
 use nonempty::nonempty;
+use polars::lazy::{dsl::Expr, frame::IntoLazy};
 use serde::{Deserialize, Serialize};
 
+use crate::error::PopgetterError;
 use crate::geo::BBox;
+use crate::metadata::Metadata;
 use crate::search::{
-    CaseSensitivity, DownloadParams, GeometryLevel, MatchType, MetricId, Params, SearchConfig,
-    SearchContext, SearchParams, SearchText, YearRange,
+    CaseSensitivity, CompositeMetric, DownloadParams, GeometryLevel, MatchType, MetricId, Params,
+    SearchConfig, SearchContext, SearchParams, SearchText, TextCombination, YearRange,
 };
+use crate::transform::TransformPipeline;
+use crate::COL;
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct DataRequestSpec {
     pub geometry: Option<GeometrySpec>,
+    /// The areas to restrict the request to. Bounding boxes are given in WGS84 longitude and
+    /// latitude as `[min_lon, min_lat, max_lon, max_lat]`, whatever the coordinate reference
+    /// system of the geometries, and are reprojected to it when downloading.
     pub region: Vec<RegionSpec>,
     pub metrics: Vec<MetricSpec>,
     pub years: Option<Vec<String>>,
 }
 
+impl DataRequestSpec {
+    /// Load a `DataRequestSpec` from a JSON recipe file
+    pub fn from_recipe_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let recipe = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read recipe file {}", path.display()))?;
+        serde_json::from_str(&recipe)
+            .with_context(|| format!("Failed to parse recipe file {}", path.display()))
+    }
+
+    /// Check that the metric IDs, geometry level and years of the request all exist in the
+    /// `metadata` and that its bounding boxes are valid WGS84 bounding boxes, so that mistakes are
+    /// caught before anything is downloaded. All problems are reported together in a
+    /// `PopgetterError::InvalidRecipe`.
+    pub fn validate(&self, metadata: &Metadata) -> anyhow::Result<()> {
+        let matches = |df: &polars::frame::DataFrame, expr: Expr| -> anyhow::Result<bool> {
+            Ok(df.clone().lazy().filter(expr).collect()?.height() > 0)
+        };
+        let mut problems = vec![];
+        for metric in &self.metrics {
+            if let MetricSpec::MetricId(metric_id) = metric {
+                if !matches(&metadata.metrics, metric_id.clone().into())? {
+                    problems.push(format!(
+                        "Unknown metric ID '{}'{}",
+                        metric_id.id,
+                        did_you_mean(&metric.suggestions(metadata)?)
+                    ));
+                }
+            }
+        }
+        if let Some(geometry_level) = self
+            .geometry
+            .as_ref()
+            .and_then(|geometry| geometry.geometry_level.as_ref())
+        {
+            let level = GeometryLevel {
+                value: geometry_level.clone(),
+                config: SearchConfig {
+                    match_type: MatchType::Exact,
+                    case_sensitivity: CaseSensitivity::Insensitive,
+                },
+            };
+            if !matches(&metadata.geometries, level.into())? {
+                problems.push(format!("Unknown geometry level '{geometry_level}'"));
+            }
+        }
+        for year in self.years.iter().flatten() {
+            match year.parse::<YearRange>() {
+                Ok(year_range) => {
+                    if !matches(&metadata.source_data_releases, year_range.into())? {
+                        problems.push(format!("No data available for the years '{year}'"));
+                    }
+                }
+                Err(err) => problems.push(format!("Invalid years '{year}': {err}")),
+            }
+        }
+        for bbox in self.region.iter().filter_map(RegionSpec::bbox) {
+            if let Err(err) = BBox::new(bbox[0], bbox[1], bbox[2], bbox[3]) {
+                problems.push(format!(
+                    "Invalid bounding box {:?}, which must be in WGS84 longitude and latitude: \
+                     {err}",
+                    bbox.0
+                ));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(PopgetterError::InvalidRecipe { problems }.into())
+        }
+    }
+}
+
 // Since `DataRequestSpec` contains parameters relevant to both `SearchParams` and `DownloadParams`,
 // the conversion is implemented for `Params`.
 impl TryFrom<DataRequestSpec> for Params {
@@ -40,10 +124,13 @@ impl TryFrom<DataRequestSpec> for Params {
                                 match_type: MatchType::Regex,
                                 case_sensitivity: CaseSensitivity::Insensitive,
                             },
+                            exact: true,
+                            match_types: Default::default(),
                         }),
                         _ => None,
                     })
                     .collect_vec(),
+                text_combination: TextCombination::All,
                 year_range: if let Some(v) = value.years {
                     Some(
                         v.iter()
@@ -61,6 +148,14 @@ impl TryFrom<DataRequestSpec> for Params {
                         _ => None,
                     })
                     .collect_vec(),
+                composite_metric: value
+                    .metrics
+                    .iter()
+                    .filter_map(|metric| match metric {
+                        MetricSpec::Composite(texts) => Some(CompositeMetric(texts.clone())),
+                        _ => None,
+                    })
+                    .collect_vec(),
                 geometry_level: value.geometry.as_ref().and_then(|geometry| {
                     geometry
                         .geometry_level
@@ -78,11 +173,17 @@ impl TryFrom<DataRequestSpec> for Params {
                 data_publisher: None,
                 country: None,
                 source_metric_id: None,
+                hxl_attribute: vec![],
+                exclude_geometry_level: vec![],
+                exclude_data_publisher: vec![],
+                exclude_country: vec![],
                 region_spec: value.region.clone(),
             },
             download: DownloadParams {
                 include_geoms: value.geometry.unwrap_or_default().include_geoms,
                 region_spec: value.region,
+                include_moe: false,
+                transformations: TransformPipeline::default(),
             },
         })
     }
@@ -93,6 +194,58 @@ pub enum MetricSpec {
     MetricId(MetricId),
     MetricText(String),
     DataProduct(String),
+    Composite(Vec<SearchText>),
+}
+
+impl MetricSpec {
+    /// Suggested corrections for a metric spec that matches no metrics, taken from the metric
+    /// metadata the spec is matched against: metric IDs for `MetricId`, human readable names
+    /// and HXL tags for `MetricText`, and the columns of the search contexts of each text in a
+    /// `Composite`. Data products are not suggested.
+    pub fn suggestions(&self, metadata: &Metadata) -> anyhow::Result<Vec<String>> {
+        let text_columns = |text: &SearchText| {
+            text.context
+                .iter()
+                .map(|context| match context {
+                    SearchContext::Hxl => COL::METRIC_HXL_TAG,
+                    SearchContext::HumanReadableName => COL::METRIC_HUMAN_READABLE_NAME,
+                    SearchContext::Description => COL::METRIC_DESCRIPTION,
+                })
+                .collect_vec()
+        };
+        match self {
+            MetricSpec::MetricId(metric_id) => {
+                metadata.suggest_metric_values(&[COL::METRIC_ID], &metric_id.id)
+            }
+            MetricSpec::MetricText(text) => metadata.suggest_metric_values(
+                &[COL::METRIC_HUMAN_READABLE_NAME, COL::METRIC_HXL_TAG],
+                text,
+            ),
+            MetricSpec::Composite(texts) => Ok(texts
+                .iter()
+                .map(|text| metadata.suggest_metric_values(&text_columns(text), &text.text))
+                .flatten_ok()
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .into_iter()
+                .unique()
+                .collect()),
+            MetricSpec::DataProduct(_) => Ok(vec![]),
+        }
+    }
+}
+
+/// Format `suggestions` to be appended to a message, e.g. "; did you mean 'population'?"
+fn did_you_mean(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        return String::new();
+    }
+    format!(
+        "; did you mean {}?",
+        suggestions
+            .iter()
+            .map(|suggestion| format!("'{suggestion}'"))
+            .join(" or ")
+    )
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -110,8 +263,9 @@ impl Default for GeometrySpec {
     }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub enum RegionSpec {
+    /// A bounding box in WGS84 longitude and latitude (see `BBox::new`)
     BoundingBox(BBox),
     Polygon(Polygon),
     NamedArea(String),
@@ -126,8 +280,108 @@ impl RegionSpec {
     }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Polygon;
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use chrono::NaiveDate;
+    use polars::df;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn metadata() -> Metadata {
+        let date = |year| NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+        Metadata {
+            metrics: df!(COL::METRIC_ID => &["f29c1976", "079f3ba3"]).unwrap(),
+            geometries: df!(COL::GEOMETRY_LEVEL => &["tract", "county"]).unwrap(),
+            source_data_releases: df!(
+                COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START => &[date(2021)],
+                COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END => &[date(2021)],
+            )
+            .unwrap(),
+            data_publishers: df!(COL::DATA_PUBLISHER_ID => &["p"]).unwrap(),
+            countries: df!(COL::COUNTRY_ID => &["c"]).unwrap(),
+        }
+    }
+
+    fn load_recipe(recipe: &str) -> DataRequestSpec {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("recipe.json");
+        std::fs::write(&path, recipe).unwrap();
+        DataRequestSpec::from_recipe_file(path).unwrap()
+    }
+
+    #[test]
+    fn valid_recipe_should_pass_validation() {
+        let recipe = DataRequestSpec::from_recipe_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../test_recipe.json"
+        ))
+        .unwrap();
+        let mut metadata = metadata();
+        metadata.metrics = df!(COL::METRIC_ID => &["f29c1976", "079f3ba3", "81cae95d"]).unwrap();
+        recipe.validate(&metadata).unwrap();
+    }
+
+    #[test]
+    fn invalid_recipe_should_report_all_problems() {
+        let recipe = load_recipe(
+            r#"{
+                "region": [{"BoundingBox": [530000, 180000, 531000, 181000]}],
+                "metrics": [
+                    {"MetricId": {"id": "f29c1976"}},
+                    {"MetricId": {"id": "deadbeef"}}
+                ],
+                "years": ["2021", "1850"],
+                "geometry": {"geometry_level": "trcat", "include_geoms": true}
+            }"#,
+        );
+        let err = recipe.validate(&metadata()).unwrap_err();
+        // Nothing close to 'deadbeef' is suggested
+        assert_eq!(
+            err.to_string(),
+            "Invalid recipe:\n\
+             Unknown metric ID 'deadbeef'\n\
+             Unknown geometry level 'trcat'\n\
+             No data available for the years '1850'\n\
+             Invalid bounding box [530000.0, 180000.0, 531000.0, 181000.0], which must be in \
+             WGS84 longitude and latitude: Longitude 530000 is outside the range -180 to 180"
+        );
+    }
+
+    #[test]
+    fn unknown_metrics_should_suggest_corrections() {
+        let mut metadata = metadata();
+        metadata.metrics = df!(
+            COL::METRIC_ID => &["population", "households", "f29c1976"],
+            COL::METRIC_HUMAN_READABLE_NAME => &["Total population", "Households", "Age"],
+            COL::METRIC_HXL_TAG => &["#population", "#household", "#age"],
+            COL::METRIC_DESCRIPTION => &["", "", ""],
+        )
+        .unwrap();
+        let recipe =
+            load_recipe(r#"{"region": [], "metrics": [{"MetricId": {"id": "popualtion"}}]}"#);
+        let err = recipe.validate(&metadata).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid recipe:\nUnknown metric ID 'popualtion'; did you mean 'population'?"
+        );
+
+        // Names and HXL tags are suggested for text, not IDs
+        let text = MetricSpec::MetricText("housholds".into());
+        let suggestions = text.suggestions(&metadata).unwrap();
+        assert!(suggestions.contains(&"Households".to_string()));
+        assert!(!suggestions.contains(&"households".to_string()));
+        let composite = MetricSpec::Composite(vec![SearchText {
+            text: "Total populaton".into(),
+            context: nonempty![SearchContext::HumanReadableName],
+            ..SearchText::default()
+        }]);
+        assert_eq!(
+            composite.suggestions(&metadata).unwrap(),
+            ["Total population"]
+        );
+    }
+}
@@ -1,5 +1,9 @@
 //! Error types.
 
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
 #[derive(thiserror::Error, Debug)]
 pub enum PopgetterError {
     #[error("Connection failure.")]
@@ -10,12 +14,174 @@ pub enum PopgetterError {
     InvalidSearchQuery(String),
     #[error("Non-existent geometry for metric requested: {0}")]
     NonExistentGeometry(String),
+    #[error(
+        "'{denominator_id}' is not a potential denominator of metric '{metric_id}', \
+         potential denominators are: {potential:?}"
+    )]
+    InvalidDenominator {
+        metric_id: String,
+        denominator_id: String,
+        potential: Vec<String>,
+    },
+    #[error(
+        "Cannot give '{metric_id}' ({unit}) as a percentage of '{denominator_id}' \
+         ({denominator_unit})"
+    )]
+    IncompatibleUnits {
+        metric_id: String,
+        unit: String,
+        denominator_id: String,
+        denominator_unit: String,
+    },
+    #[error("'{column}' is not a facet of the metadata, facet columns are: {available:?}")]
+    UnknownFacet {
+        column: String,
+        available: Vec<String>,
+    },
+    #[error(
+        "Metrics are not available at geometry level '{geometry_level}': {}",
+        metrics.join(", ")
+    )]
+    MetricsUnavailableAtGeometry {
+        geometry_level: String,
+        metrics: Vec<String>,
+    },
+    #[error("Download cancelled")]
+    Cancelled,
+    #[error("Invalid recipe:\n{}", problems.join("\n"))]
+    InvalidRecipe { problems: Vec<String> },
     #[error("Wrapped polars error: {0}")]
     PolarsError(#[from] polars::error::PolarsError),
     #[error("Unknown error.")]
     Unknown,
 }
 
+/// Errors that can occur while loading and combining the metadata catalogue.
+#[derive(thiserror::Error, Debug)]
+pub enum MetadataError {
+    #[error("Failed to fetch country list from '{url}': {source}")]
+    CountryListFetch { url: String, source: reqwest::Error },
+    #[error("Failed to fetch catalogue version from '{url}': {source}")]
+    VersionFetch { url: String, source: reqwest::Error },
+    #[error("Failed to read '{path}' under the local base path: {source}")]
+    LocalRead {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Invalid catalogue version '{version}', expected a version such as '0.2'")]
+    InvalidVersion { version: String },
+    #[error("Failed to fetch parquet file '{path}': {source}")]
+    ParquetFetch {
+        path: String,
+        source: reqwest::Error,
+    },
+    #[error("Failed to scan parquet file '{path}': {source}")]
+    ParquetScan {
+        path: String,
+        source: polars::error::PolarsError,
+    },
+    #[error(
+        "Cannot download '{path}' as it is a glob, which can only be expanded when scanning a \
+         local `base_path`. Give remote metadata as a single parquet file."
+    )]
+    RemoteGlob { path: String },
+    #[error("Timed out after {timeout:?} loading '{path}'")]
+    Timeout { path: String, timeout: Duration },
+    #[error("Country '{country}' is not available, available countries are: {available:?}")]
+    UnknownCountry {
+        country: String,
+        available: Vec<String>,
+    },
+    #[error(
+        "Failed to load '{file}', the metadata file configured for country '{country}' in \
+         `metadata_paths`: {source}"
+    )]
+    OverriddenFile {
+        country: String,
+        file: String,
+        source: Box<MetadataError>,
+    },
+    #[error("Missing column in metadata: {column}")]
+    MissingColumn { column: String },
+    #[error("Metadata is missing expected columns: {}", missing.join(", "))]
+    InvalidSchema { missing: Vec<String> },
+    #[error(
+        "The {table} metadata of '{country}' has different columns to that of \
+         '{expected_country}', missing: {missing:?}, extra: {extra:?}. Set `relaxed_merge` in the \
+         config to fill missing columns with nulls."
+    )]
+    SchemaMismatch {
+        table: String,
+        country: String,
+        expected_country: String,
+        missing: Vec<String>,
+        extra: Vec<String>,
+    },
+    #[error("Failed to join metadata: {0}")]
+    Join(#[source] polars::error::PolarsError),
+    #[error("Failed to merge metadata across countries: {0}")]
+    Merge(#[source] polars::error::PolarsError),
+    #[error("Metadata loading cancelled")]
+    Cancelled,
+    #[error("Metadata loading task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+/// Errors from `Config::check_connectivity`, each suggesting which setting is likely at fault.
+#[derive(thiserror::Error, Debug)]
+pub enum ConnectivityError {
+    #[error("Could not reach '{url}', check `base_path` and the network connection: {source}")]
+    Unreachable { url: String, source: reqwest::Error },
+    #[error("Access to '{url}' was denied ({status}), check the credentials in `auth`")]
+    Unauthorized { url: String, status: StatusCode },
+    #[error("'{url}' was not found, check that `base_path` points at a popgetter catalogue")]
+    NotFound { url: String },
+    #[error("Request for '{url}' failed ({status})")]
+    Status { url: String, status: StatusCode },
+}
+
+impl MetadataError {
+    /// Whether the error is likely to be transient (e.g. throttling or a dropped connection) and
+    /// so worth retrying. Errors such as a missing file or column are never transient.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            MetadataError::CountryListFetch { source, .. }
+            | MetadataError::VersionFetch { source, .. }
+            | MetadataError::ParquetFetch { source, .. } => match source.status() {
+                Some(status) => matches!(
+                    status,
+                    StatusCode::TOO_MANY_REQUESTS
+                        | StatusCode::BAD_GATEWAY
+                        | StatusCode::SERVICE_UNAVAILABLE
+                        | StatusCode::GATEWAY_TIMEOUT
+                ),
+                None => {
+                    source.is_connect()
+                        || source.is_timeout()
+                        || source.is_request()
+                        || source.is_body()
+                }
+            },
+            // Polars only exposes HTTP failures from a scan as an error message
+            MetadataError::ParquetScan { source, .. } => {
+                let message = source.to_string().to_lowercase();
+                [
+                    "429",
+                    "503",
+                    "too many requests",
+                    "service unavailable",
+                    "connection reset",
+                    "timed out",
+                ]
+                .iter()
+                .any(|pattern| message.contains(pattern))
+            }
+            MetadataError::Timeout { .. } => true,
+            _ => false,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use polars::error::{ErrString, PolarsError};
@@ -28,4 +194,15 @@ mod tests {
         let popgetter_error: PopgetterError = polars_error.into();
         println!("{}", popgetter_error);
     }
+
+    #[test]
+    fn test_metadata_error_display() {
+        let metadata_error = MetadataError::MissingColumn {
+            column: "metric_id".into(),
+        };
+        assert_eq!(
+            metadata_error.to_string(),
+            "Missing column in metadata: metric_id"
+        );
+    }
 }
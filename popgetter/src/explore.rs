@@ -0,0 +1,179 @@
+//! Read-only exploration of the metadata catalogue.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use polars::prelude::DataFrame;
+
+use crate::{metadata::Metadata, search::SearchParams};
+
+/// A read-only handle on the metadata catalogue, returned by `Popgetter::explore`. It only holds
+/// the metadata already in memory and the geometry level aliases, but no `Config`, and its searches return plain `DataFrame`s
+/// rather than `SearchResults`, so nothing reachable from it can fetch metric data.
+///
+/// ```compile_fail
+/// # async fn run(popgetter: popgetter::Popgetter) {
+/// let explorer = popgetter.explore();
+/// let results = explorer.search(&Default::default()).unwrap();
+/// // Neither the explorer nor its results can download metrics
+/// results.download(&popgetter.config, &Default::default()).await;
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Explorer<'a> {
+    metadata: &'a Metadata,
+    geometry_level_aliases: &'a BTreeMap<String, Vec<String>>,
+}
+
+impl<'a> Explorer<'a> {
+    /// Explore `metadata`, resolving searched geometry levels with `geometry_level_aliases` (see
+    /// `Config::geometry_level_aliases`)
+    pub fn new(
+        metadata: &'a Metadata,
+        geometry_level_aliases: &'a BTreeMap<String, Vec<String>>,
+    ) -> Self {
+        Self {
+            metadata,
+            geometry_level_aliases,
+        }
+    }
+
+    /// See `Metadata::distinct_values`
+    pub fn distinct_values(&self, column: &str) -> Result<Vec<String>> {
+        self.metadata.distinct_values(column)
+    }
+
+    /// See `Metadata::facet_counts`
+    pub fn facet_counts(&self, column: &str) -> Result<Vec<(String, u32)>> {
+        self.metadata.facet_counts(column)
+    }
+
+    /// The combined metadata of the metrics matching `search_params`, after resolving its
+    /// geometry levels (see `SearchParams::resolve_geometry_levels`)
+    pub fn search(&self, search_params: &SearchParams) -> Result<DataFrame> {
+        Ok(search_params
+            .clone()
+            .resolve_geometry_levels(self.metadata, self.geometry_level_aliases)?
+            .search(&self.metadata.combined_metric_source_geometry()?)
+            .0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::{df, prelude::NamedFrom, series::Series};
+
+    use super::*;
+    use crate::{
+        search::{CaseSensitivity, Country, GeometryLevel, MatchType, SearchConfig},
+        COL,
+    };
+
+    fn test_metadata() -> Metadata {
+        Metadata {
+            metrics: df!(
+                COL::METRIC_ID => &["bel_metric", "nir_metric"],
+                COL::METRIC_SOURCE_DATA_RELEASE_ID => &["bel_release", "nir_release"],
+            )
+            .unwrap(),
+            geometries: df!(
+                COL::GEOMETRY_ID => &["bel_geometry", "nir_geometry"],
+                COL::GEOMETRY_LEVEL => &["municipality", "sdz21"],
+            )
+            .unwrap(),
+            source_data_releases: df!(
+                COL::SOURCE_DATA_RELEASE_ID => &["bel_release", "nir_release"],
+                COL::SOURCE_DATA_RELEASE_GEOMETRY_METADATA_ID => &["bel_geometry", "nir_geometry"],
+                COL::SOURCE_DATA_RELEASE_DATA_PUBLISHER_ID => &["statbel", "nisra"],
+            )
+            .unwrap(),
+            data_publishers: df!(
+                COL::DATA_PUBLISHER_ID => &["statbel", "nisra"],
+                COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST => &[
+                    Series::new("", &["BEL"]),
+                    Series::new("", &["NIR"]),
+                ],
+            )
+            .unwrap(),
+            countries: df!(
+                COL::COUNTRY_ID => &["BEL", "NIR"],
+                COL::COUNTRY_NAME_SHORT_EN => &["Belgium", "Northern Ireland"],
+                COL::COUNTRY_NAME_OFFICIAL => &["Kingdom of Belgium", "Northern Ireland"],
+                COL::COUNTRY_ISO2 => &["BE", "GB"],
+                COL::COUNTRY_ISO3 => &["BEL", "GBR"],
+                COL::COUNTRY_ISO3166_2 => &[None::<&str>, Some("GB-NIR")],
+            )
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn explorer_should_search_and_summarise_metadata() {
+        let metadata = test_metadata();
+        let aliases = BTreeMap::new();
+        let explorer = Explorer::new(&metadata, &aliases);
+        assert_eq!(
+            explorer
+                .distinct_values(COL::COUNTRY_NAME_SHORT_EN)
+                .unwrap(),
+            ["Belgium", "Northern Ireland"]
+        );
+        assert_eq!(
+            explorer
+                .facet_counts(COL::SOURCE_DATA_RELEASE_DATA_PUBLISHER_ID)
+                .unwrap(),
+            [("nisra".to_string(), 1), ("statbel".to_string(), 1)]
+        );
+        let results = explorer
+            .search(&SearchParams {
+                country: Some(Country {
+                    value: "Belgium".into(),
+                    config: SearchConfig {
+                        match_type: MatchType::Exact,
+                        case_sensitivity: CaseSensitivity::Insensitive,
+                    },
+                }),
+                ..SearchParams::default()
+            })
+            .unwrap();
+        assert_eq!(
+            results
+                .column(COL::METRIC_ID)
+                .unwrap()
+                .str()
+                .unwrap()
+                .get(0),
+            Some("bel_metric")
+        );
+        assert_eq!(results.height(), 1);
+    }
+
+    #[test]
+    fn explorer_search_should_resolve_geometry_level_aliases() {
+        let metadata = test_metadata();
+        let aliases = BTreeMap::from([("sdz21".to_string(), vec!["Super Data Zone".to_string()])]);
+        let explorer = Explorer::new(&metadata, &aliases);
+        let results = explorer
+            .search(&SearchParams {
+                geometry_level: Some(GeometryLevel {
+                    value: "super data zone".into(),
+                    config: SearchConfig {
+                        match_type: MatchType::Exact,
+                        case_sensitivity: CaseSensitivity::Insensitive,
+                    },
+                }),
+                ..SearchParams::default()
+            })
+            .unwrap();
+        assert_eq!(
+            results
+                .column(COL::METRIC_ID)
+                .unwrap()
+                .str()
+                .unwrap()
+                .into_no_null_iter()
+                .collect::<Vec<_>>(),
+            ["nir_metric"]
+        );
+    }
+}
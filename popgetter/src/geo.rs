@@ -1,22 +1,50 @@
-use crate::COL;
-use anyhow::{Context, Result};
-use flatgeobuf::{geozero, FeatureProperties, HttpFgbReader};
+use crate::{
+    config::Config,
+    metadata::{fetch_parquet, join_path},
+    COL,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use flatgeobuf::{
+    geozero, FallibleStreamingIterator, FeatureProperties, FgbFeature, FgbReader, Header,
+    HttpFgbReader,
+};
+use geo::{coord, Geometry, Intersects, Rect};
 use geozero::ToWkt;
-use polars::{frame::DataFrame, prelude::NamedFrom, series::Series};
+use log::warn;
+use polars::{
+    frame::DataFrame,
+    lazy::dsl::{col, lit},
+    prelude::{
+        BooleanChunked, DataFrameJoinOps, DataType, IntoLazy, LazyFrame, NamedFrom, ScanArgsParquet,
+    },
+    series::Series,
+};
+use proj4rs::{proj::Proj, transform::transform};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
     ops::{Index, IndexMut},
     str::FromStr,
 };
+use wkt::TryFromWkt;
 
 /// Function to request geometries from a remotly hosted FGB
 ///
 /// `file_url`: The url of the file to read from
 /// `bbox`: an optional bounding box to filter the features by
+/// `geo_id_column`: the column of the file holding the ID of each geometry, which is renamed to
+/// `GEO_ID` in the output (see `Metadata::geo_id_column`)
 ///
 /// Returns: a Result object containing a vector of (geometry, properties).
-pub async fn get_geometries(file_url: &str, bbox: Option<BBox>) -> Result<DataFrame> {
+pub async fn get_geometries(
+    file_url: &str,
+    bbox: Option<BBox>,
+    geo_id_column: &str,
+) -> Result<DataFrame> {
     let fgb = HttpFgbReader::open(file_url).await?;
+    check_geo_id_column(file_url, &fgb.header(), geo_id_column)?;
 
     let mut fgb = if let Some(bbox) = bbox {
         fgb.select_bbox(bbox[0], bbox[1], bbox[2], bbox[3]).await?
@@ -24,22 +52,427 @@ pub async fn get_geometries(file_url: &str, bbox: Option<BBox>) -> Result<DataFr
         fgb.select_all().await?
     };
 
-    let mut geoms: Vec<String> = vec![];
-    let mut ids: Vec<String> = vec![];
-
+    let mut builder = GeometryBuilder::new(geo_id_column);
     while let Some(feature) = fgb.next().await? {
-        let props = feature.properties()?;
-        geoms.push(feature.to_wkt()?);
+        builder.push(feature)?;
+    }
+    Ok(builder.finish()?.df)
+}
+
+/// Geometries read from a FlatGeobuf file, with a `GEO_ID` column and a `geometry` column
+/// containing each geometry as WKT.
+#[derive(Clone, Debug)]
+pub struct GeometryFrame {
+    pub df: DataFrame,
+    /// EPSG code of the coordinate reference system of the geometries, if known
+    pub epsg: Option<i32>,
+}
+
+/// Collects the IDs and WKT geometries of features as they are read from a FlatGeobuf file. The
+/// IDs are read from `geo_id_column` and always output as `GEO_ID`.
+struct GeometryBuilder {
+    geo_id_column: String,
+    ids: Vec<String>,
+    geoms: Vec<String>,
+    epsg: Option<i32>,
+}
+
+impl GeometryBuilder {
+    fn new(geo_id_column: &str) -> Self {
+        Self {
+            geo_id_column: geo_id_column.to_string(),
+            ids: vec![],
+            geoms: vec![],
+            epsg: None,
+        }
+    }
+
+    fn push(&mut self, feature: &FgbFeature) -> Result<()> {
+        let mut props = feature.properties()?;
+        self.geoms.push(feature.to_wkt()?);
         let id = props
-            .get(COL::GEO_ID)
-            .with_context(|| "failed to get geoid")?;
-        ids.push(id.clone());
+            .remove(&self.geo_id_column)
+            .with_context(|| format!("Feature has no '{}' property", self.geo_id_column))?;
+        self.ids.push(id);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<GeometryFrame> {
+        let ids = Series::new(COL::GEO_ID, self.ids);
+        let geoms = Series::new("geometry", self.geoms);
+        Ok(GeometryFrame {
+            df: DataFrame::new(vec![ids, geoms])?,
+            epsg: self.epsg,
+        })
+    }
+}
+
+/// Returns an error if the FlatGeobuf file described by `header` has no `geo_id_column` column.
+fn check_geo_id_column(path: &str, header: &Header, geo_id_column: &str) -> Result<()> {
+    let has_geo_id = header
+        .columns()
+        .map(|columns| columns.iter().any(|column| column.name() == geo_id_column))
+        .unwrap_or(false);
+    if !has_geo_id {
+        bail!("Geometry file '{path}' does not have a '{geo_id_column}' ID column");
     }
+    Ok(())
+}
 
-    let ids = Series::new(COL::GEO_ID, ids);
-    let geoms = Series::new("geometry", geoms);
-    let result = DataFrame::new(vec![ids, geoms])?;
-    Ok(result)
+/// EPSG code of the coordinate reference system in a FlatGeobuf header, if it has one
+fn header_epsg(header: &Header) -> Option<i32> {
+    header
+        .crs()
+        .filter(|crs| {
+            crs.code() != 0 && crs.org().is_none_or(|org| org.eq_ignore_ascii_case("EPSG"))
+        })
+        .map(|crs| crs.code())
+}
+
+/// Load all geometries from a FlatGeobuf file, which can either be a local path or a URL starting
+/// with `http://` or `https://`. Features are read one at a time rather than buffering the whole
+/// file in memory.
+///
+/// Returns an error if the file does not have a `GEO_ID` column.
+pub async fn load_geometries(path: &str) -> Result<GeometryFrame> {
+    read_geometries(path, None, COL::GEO_ID).await
+}
+
+/// Like `load_geometries`, but reads the ID of each geometry from `geo_id_column` rather than
+/// `GEO_ID`, for geometry levels whose files name it differently (see `Metadata::geo_id_column`).
+/// The IDs are output as `GEO_ID`, so the geometries can be joined to metrics as usual.
+///
+/// Returns an error if the file does not have a `geo_id_column` column.
+pub async fn load_geometries_with_id_column(
+    path: &str,
+    geo_id_column: &str,
+) -> Result<GeometryFrame> {
+    read_geometries(path, None, geo_id_column).await
+}
+
+/// Like `load_geometries_with_id_column`, but only reads the features whose bounding boxes
+/// intersect `bbox`, given in WGS84 longitude and latitude, using the spatial index of the file.
+async fn read_geometries(
+    path: &str,
+    bbox: Option<&BBox>,
+    geo_id_column: &str,
+) -> Result<GeometryFrame> {
+    let mut builder = GeometryBuilder::new(geo_id_column);
+    if path.starts_with("http://") || path.starts_with("https://") {
+        let fgb = HttpFgbReader::open(path).await?;
+        check_geo_id_column(path, &fgb.header(), geo_id_column)?;
+        builder.epsg = header_epsg(&fgb.header());
+        let mut fgb = match bbox {
+            Some(bbox) => {
+                let bbox = bbox_in_crs(path, bbox, builder.epsg)?;
+                fgb.select_bbox(bbox[0], bbox[1], bbox[2], bbox[3]).await?
+            }
+            None => fgb.select_all().await?,
+        };
+        while let Some(feature) = fgb.next().await? {
+            builder.push(feature)?;
+        }
+    } else {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open geometry file '{path}'"))?;
+        let fgb = FgbReader::open(BufReader::new(file))?;
+        check_geo_id_column(path, &fgb.header(), geo_id_column)?;
+        builder.epsg = header_epsg(&fgb.header());
+        let mut fgb = match bbox {
+            Some(bbox) => {
+                let bbox = bbox_in_crs(path, bbox, builder.epsg)?;
+                fgb.select_bbox(bbox[0], bbox[1], bbox[2], bbox[3])?
+            }
+            None => fgb.select_all()?,
+        };
+        while let Some(feature) = fgb.next()? {
+            builder.push(feature)?;
+        }
+    }
+    builder.finish()
+}
+
+/// Check that `bbox` is a valid WGS84 bounding box (see `BBox::new`) and reproject it to `epsg`,
+/// the coordinate reference system of the geometry file at `path`, which is assumed to be WGS84
+/// if unknown
+fn bbox_in_crs(path: &str, bbox: &BBox, epsg: Option<i32>) -> Result<BBox> {
+    let bbox = BBox::new(bbox[0], bbox[1], bbox[2], bbox[3]).context(
+        "Bounding boxes must be given in WGS84 longitude and latitude as \
+         [min_lon, min_lat, max_lon, max_lat]",
+    )?;
+    bbox.reproject(WGS84, epsg.unwrap_or(WGS84))
+        .with_context(|| format!("Failed to reproject the bounding box for '{path}'"))
+}
+
+/// The geometries in the FlatGeobuf file at `path` that intersect `bbox`, given in WGS84
+/// longitude and latitude. The bounding box is reprojected to the coordinate reference system of
+/// the file, which is assumed to be WGS84 if the file does not specify one. The IDs are read from
+/// `geo_id_column` (see `Metadata::geo_id_column`).
+///
+/// Returns an error if `bbox` is not a valid WGS84 bounding box, or the file is in a coordinate
+/// reference system that bounding boxes cannot be reprojected to (see `BBox::reproject`).
+pub async fn geometries_in_bbox(
+    path: &str,
+    bbox: &BBox,
+    geo_id_column: &str,
+) -> Result<GeometryFrame> {
+    let geometries = read_geometries(path, Some(bbox), geo_id_column).await?;
+    let bbox = bbox_in_crs(path, bbox, geometries.epsg)?;
+    let rect = Rect::new(
+        coord! { x: bbox[0], y: bbox[1] },
+        coord! { x: bbox[2], y: bbox[3] },
+    );
+    let ids = geometries.df.column(COL::GEO_ID)?.str()?;
+    let wkts = geometries.df.column("geometry")?.str()?;
+    let mut mask = vec![];
+    // The spatial index only compares bounding boxes, so check the geometries themselves
+    for (geo_id, wkt) in ids.into_iter().zip(wkts) {
+        let (Some(geo_id), Some(wkt)) = (geo_id, wkt) else {
+            mask.push(false);
+            continue;
+        };
+        let geometry = Geometry::<f64>::try_from_wkt_str(wkt)
+            .map_err(|err| anyhow!("Invalid geometry for GEO_ID '{geo_id}': {err}"))?;
+        mask.push(rect.intersects(&geometry));
+    }
+    Ok(GeometryFrame {
+        df: geometries.df.filter(&BooleanChunked::new("", mask))?,
+        epsg: geometries.epsg,
+    })
+}
+
+/// The `GEO_ID`s of the geometries in the FlatGeobuf file at `path` that intersect `bbox` (see
+/// `geometries_in_bbox`). These can be set as the `geoids` of `MetricRequest`s so that only the
+/// metrics for the area are downloaded.
+pub async fn geo_ids_in_bbox(path: &str, bbox: &BBox, geo_id_column: &str) -> Result<Vec<String>> {
+    let geometries = geometries_in_bbox(path, bbox, geo_id_column).await?;
+    Ok(geometries
+        .df
+        .column(COL::GEO_ID)?
+        .str()?
+        .into_no_null_iter()
+        .map(String::from)
+        .collect())
+}
+
+/// Column of the geometry names files holding English names, which are preferred over other
+/// languages
+pub const ENGLISH_NAMES: &str = "eng";
+
+/// Look up the human readable names of `geo_ids` in the names file of a geometry,
+/// `{base_path}/{geometry_filepath_stem}.parquet`. This has a `GEO_ID` column and a column of
+/// names for each language, named by ISO 639-3 code. English names are used if there are any,
+/// otherwise the names in the first language.
+///
+/// Every one of the `geo_ids` is a key of the returned map, with `None` for IDs that are not in
+/// the names file.
+pub async fn resolve_geoid_names(
+    geo_ids: &[String],
+    geometry_filepath_stem: &str,
+    config: &Config,
+) -> Result<HashMap<String, Option<String>>> {
+    let path = join_path(
+        &config.base_path,
+        &format!("{geometry_filepath_stem}.parquet"),
+    );
+    let names = if config.fetch_with_client(&path) {
+        fetch_parquet(path.clone(), config).await?
+    } else {
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || {
+            LazyFrame::scan_parquet(&path, ScanArgsParquet::default())?.collect()
+        })
+        .await??
+    };
+    let name_column = if names.get_column_index(ENGLISH_NAMES).is_some() {
+        ENGLISH_NAMES
+    } else {
+        names
+            .get_columns()
+            .iter()
+            .find(|series| series.name() != COL::GEO_ID && series.dtype() == &DataType::String)
+            .map(|series| series.name())
+            .with_context(|| format!("Geometry names file '{path}' has no name columns"))?
+    };
+    let lookup = names
+        .column(COL::GEO_ID)?
+        .str()?
+        .into_iter()
+        .zip(names.column(name_column)?.str()?)
+        .filter_map(|(geo_id, name)| Some((geo_id?, name?)))
+        .collect::<HashMap<_, _>>();
+    let resolved = geo_ids
+        .iter()
+        .map(|geo_id| {
+            let name = lookup.get(geo_id.as_str()).map(|name| name.to_string());
+            (geo_id.clone(), name)
+        })
+        .collect::<HashMap<_, _>>();
+    let unmatched = resolved.values().filter(|name| name.is_none()).count();
+    if unmatched > 0 {
+        warn!("{unmatched} GEO_IDs have no name in '{path}'");
+    }
+    Ok(resolved)
+}
+
+/// How metrics are joined to geometries on `GEO_ID`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GeometryJoin {
+    /// Only keep geometries that have metric values
+    #[default]
+    Inner,
+    /// Keep all geometries, with null metric values where there is no match
+    Left,
+}
+
+/// Metrics joined to their geometries, along with counts of the `GEO_ID`s that could not be
+/// matched.
+#[derive(Clone, Debug)]
+pub struct JoinedMetrics {
+    pub df: DataFrame,
+    /// EPSG code of the coordinate reference system of the geometries, if known
+    pub epsg: Option<i32>,
+    /// Number of rows in the metrics with no matching geometry. These are always dropped.
+    pub unmatched_metrics: usize,
+    /// Number of geometries with no matching metrics. These are only kept for
+    /// `GeometryJoin::Left`.
+    pub unmatched_geometries: usize,
+}
+
+/// Number of rows in `df` whose `GEO_ID` is not one of `geo_ids`
+fn count_unmatched(df: &DataFrame, geo_ids: &Series) -> Result<usize> {
+    Ok(df
+        .clone()
+        .lazy()
+        .filter(col(COL::GEO_ID).is_in(lit(geo_ids.clone())).not())
+        .collect()?
+        .height())
+}
+
+/// Join a metrics dataframe to geometries on their `GEO_ID` columns. Returns an error if either
+/// has no `GEO_ID` column.
+pub fn join_metrics_to_geometries(
+    metrics: &DataFrame,
+    geometries: &GeometryFrame,
+    join: GeometryJoin,
+) -> Result<JoinedMetrics> {
+    let metric_ids = metrics
+        .column(COL::GEO_ID)
+        .with_context(|| format!("The metrics have no '{}' column to join on", COL::GEO_ID))?;
+    let geometry_ids = geometries
+        .df
+        .column(COL::GEO_ID)
+        .with_context(|| format!("The geometries have no '{}' column to join on", COL::GEO_ID))?;
+    let unmatched_metrics = count_unmatched(metrics, geometry_ids)?;
+    let unmatched_geometries = count_unmatched(&geometries.df, metric_ids)?;
+    if unmatched_metrics > 0 || unmatched_geometries > 0 {
+        warn!(
+            "{unmatched_metrics} metric rows have no matching geometry and \
+             {unmatched_geometries} geometries have no matching metrics"
+        );
+    }
+    let df = match join {
+        GeometryJoin::Inner => geometries
+            .df
+            .inner_join(metrics, [COL::GEO_ID], [COL::GEO_ID])?,
+        GeometryJoin::Left => geometries
+            .df
+            .left_join(metrics, [COL::GEO_ID], [COL::GEO_ID])?,
+    };
+    Ok(JoinedMetrics {
+        df,
+        epsg: geometries.epsg,
+        unmatched_metrics,
+        unmatched_geometries,
+    })
+}
+
+/// Join a metrics dataframe to geometries on their `GEO_ID` columns and serialize the result as a
+/// GeoJSON FeatureCollection, with one feature per geometry and the metric values as properties.
+#[cfg(feature = "formatters")]
+pub fn metrics_to_geojson(
+    metrics: &DataFrame,
+    geometries: &GeometryFrame,
+    join: GeometryJoin,
+) -> Result<String> {
+    use crate::formatters::{GeoJSONFormatter, OutputGenerator};
+
+    let mut joined = join_metrics_to_geometries(metrics, geometries, join)?;
+    GeoJSONFormatter.format(&mut joined.df)
+}
+
+/// Write metrics joined to their geometries to a GeoPackage at `path`, as a single layer named
+/// `metrics`. Metric columns are written as fields of the matching type (e.g. integer columns are
+/// written as integer fields) and the CRS is taken from the EPSG code of the source geometries.
+#[cfg(feature = "geopackage")]
+pub fn write_geopackage<P: AsRef<std::path::Path>>(path: P, joined: &JoinedMetrics) -> Result<()> {
+    use gdal::{
+        spatial_ref::SpatialRef,
+        vector::{FieldDefn, FieldValue, Geometry, LayerAccess, LayerOptions, OGRFieldType},
+        DriverManager,
+    };
+    use polars::prelude::{AnyValue, DataType};
+
+    let driver = DriverManager::get_driver_by_name("GPKG")?;
+    let mut dataset = driver.create_vector_only(path.as_ref())?;
+    let srs = joined
+        .epsg
+        .map(|epsg| SpatialRef::from_epsg(epsg as u32))
+        .transpose()?;
+    let mut txn = dataset.start_transaction()?;
+    let mut layer = txn.create_layer(LayerOptions {
+        name: "metrics",
+        srs: srs.as_ref(),
+        ..Default::default()
+    })?;
+
+    let geometry_col = joined.df.column("geometry")?.str()?;
+    let fields = joined.df.drop("geometry")?;
+    for series in fields.get_columns() {
+        let field_type = match series.dtype() {
+            DataType::Boolean
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::UInt8
+            | DataType::UInt16 => OGRFieldType::OFTInteger,
+            DataType::Int64 | DataType::UInt32 | DataType::UInt64 => OGRFieldType::OFTInteger64,
+            DataType::Float32 | DataType::Float64 => OGRFieldType::OFTReal,
+            _ => OGRFieldType::OFTString,
+        };
+        FieldDefn::new(series.name(), field_type)?.add_to_layer(&layer)?;
+    }
+
+    for (idx, wkt) in geometry_col.into_iter().enumerate() {
+        let Some(wkt) = wkt else {
+            continue;
+        };
+        let mut names = vec![];
+        let mut values = vec![];
+        for series in fields.get_columns() {
+            let value = match series.get(idx)? {
+                AnyValue::Null => continue,
+                AnyValue::Boolean(v) => FieldValue::IntegerValue(v.into()),
+                AnyValue::Int8(v) => FieldValue::IntegerValue(v.into()),
+                AnyValue::Int16(v) => FieldValue::IntegerValue(v.into()),
+                AnyValue::Int32(v) => FieldValue::IntegerValue(v),
+                AnyValue::UInt8(v) => FieldValue::IntegerValue(v.into()),
+                AnyValue::UInt16(v) => FieldValue::IntegerValue(v.into()),
+                AnyValue::Int64(v) => FieldValue::Integer64Value(v),
+                AnyValue::UInt32(v) => FieldValue::Integer64Value(v.into()),
+                AnyValue::UInt64(v) => FieldValue::Integer64Value(v.try_into()?),
+                AnyValue::Float32(v) => FieldValue::RealValue(v.into()),
+                AnyValue::Float64(v) => FieldValue::RealValue(v),
+                AnyValue::String(v) => FieldValue::StringValue(v.to_string()),
+                v => FieldValue::StringValue(v.to_string()),
+            };
+            names.push(series.name());
+            values.push(value);
+        }
+        layer.create_feature_fields(Geometry::from_wkt(wkt)?, &names, &values)?;
+    }
+    txn.commit()?;
+    Ok(())
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -52,9 +485,212 @@ pub enum RegionSpec {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Polygon;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// A bounding box given as `[left, bottom, right, top]`. Bounding boxes in WGS84 (e.g. from
+/// geocoding) are `[min_lon, min_lat, max_lon, max_lat]` and can be checked with `BBox::new`;
+/// bounding boxes in a projected coordinate reference system can be constructed directly.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BBox(pub [f64; 4]);
 
+/// EPSG code of WGS84 longitude and latitude
+pub const WGS84: i32 = 4326;
+
+/// PROJ definitions of the coordinate reference systems used by popgetter geometries, keyed on
+/// EPSG code. Bounding boxes can only be reprojected between these.
+const PROJ_STRINGS: &[(i32, &str)] = &[
+    (4326, "+proj=longlat +datum=WGS84 +no_defs"),
+    (
+        3857,
+        "+proj=merc +a=6378137 +b=6378137 +lat_ts=0 +lon_0=0 +x_0=0 +y_0=0 +k=1 +units=m \
+         +no_defs",
+    ),
+    // British National Grid
+    (
+        27700,
+        "+proj=tmerc +lat_0=49 +lon_0=-2 +k=0.9996012717 +x_0=400000 +y_0=-100000 \
+         +ellps=airy +towgs84=446.448,-125.157,542.06,0.15,0.247,0.842,-20.489 +units=m \
+         +no_defs",
+    ),
+    // Irish Grid
+    (
+        29902,
+        "+proj=tmerc +lat_0=53.5 +lon_0=-8 +k=1.000035 +x_0=200000 +y_0=250000 \
+         +ellps=mod_airy +towgs84=482.5,-130.6,564.6,-1.042,-0.214,-0.631,8.15 +units=m \
+         +no_defs",
+    ),
+    // Belgian Lambert 2008
+    (
+        3812,
+        "+proj=lcc +lat_0=50.797815 +lon_0=4.35921583333333 +lat_1=49.8333333333333 \
+         +lat_2=51.1666666666667 +x_0=649328 +y_0=665262 +ellps=GRS80 \
+         +towgs84=0,0,0,0,0,0,0 +units=m +no_defs",
+    ),
+];
+
+/// The PROJ definition of the coordinate reference system with the EPSG code `epsg`. Returns an
+/// error naming the supported coordinate reference systems if it is not in `PROJ_STRINGS`.
+fn proj_string(epsg: i32) -> Result<&'static str> {
+    PROJ_STRINGS
+        .iter()
+        .find(|(code, _)| *code == epsg)
+        .map(|(_, definition)| *definition)
+        .with_context(|| {
+            let supported = PROJ_STRINGS
+                .iter()
+                .map(|(code, _)| format!("EPSG:{code}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                "Unsupported coordinate reference system EPSG:{epsg}, bounding boxes can only be \
+                 reprojected to {supported}"
+            )
+        })
+}
+
+/// Approximate WGS84 bounds `[min_lon, min_lat, max_lon, max_lat]` of some of the countries in
+/// the catalogue by ISO3 code, with a box for each separate part of a country
+pub const COUNTRY_BOUNDS: &[(&str, [f64; 4])] = &[
+    ("BEL", [2.54, 49.49, 6.41, 51.51]),
+    // Great Britain and Northern Ireland
+    ("GBR", [-7.57, 49.86, 1.77, 60.86]),
+    ("GBR", [-8.18, 54.02, -5.43, 55.31]),
+    // Contiguous United States, Alaska and Hawaii
+    ("USA", [-124.85, 24.4, -66.88, 49.39]),
+    ("USA", [-179.15, 51.21, -129.98, 71.39]),
+    ("USA", [-160.25, 18.91, -154.81, 22.24]),
+];
+
+/// The sorted ISO3 codes of the `countries`, given by ISO3 code, whose bounds in
+/// `COUNTRY_BOUNDS` overlap any of the WGS84 `bboxes`. Countries without bounds in
+/// `COUNTRY_BOUNDS` cannot be ruled out, so are always included. As the bounds are rectangles,
+/// countries near a bounding box may be included even if it does not cover any of their land.
+pub fn countries_in_bboxes<'a>(bboxes: &[BBox], countries: &[&'a str]) -> Vec<&'a str> {
+    let mut countries: Vec<&str> = countries
+        .iter()
+        .copied()
+        .filter(|country| {
+            let mut bounds = COUNTRY_BOUNDS
+                .iter()
+                .filter(|(code, _)| code.eq_ignore_ascii_case(country))
+                .peekable();
+            bounds.peek().is_none()
+                || bounds
+                    .any(|(_, bounds)| bboxes.iter().any(|bbox| bbox.intersects(&BBox(*bounds))))
+        })
+        .collect();
+    countries.sort();
+    countries.dedup();
+    countries
+}
+
+/// Number of points along each edge of a bounding box that are reprojected, so that the
+/// reprojected box contains the whole of the original box even where its edges become curved
+const BBOX_EDGE_POINTS: usize = 21;
+
+impl BBox {
+    /// A WGS84 bounding box. Returns an error if the minimum longitude or latitude is not less
+    /// than the maximum, or any coordinate is out of range.
+    pub fn new(min_lon: f64, min_lat: f64, max_lon: f64, max_lat: f64) -> Result<BBox> {
+        for lon in [min_lon, max_lon] {
+            if !(-180.0..=180.0).contains(&lon) {
+                bail!("Longitude {lon} is outside the range -180 to 180");
+            }
+        }
+        for lat in [min_lat, max_lat] {
+            if !(-90.0..=90.0).contains(&lat) {
+                bail!("Latitude {lat} is outside the range -90 to 90");
+            }
+        }
+        if min_lon >= max_lon {
+            bail!("Minimum longitude {min_lon} is not less than maximum longitude {max_lon}");
+        }
+        if min_lat >= max_lat {
+            bail!("Minimum latitude {min_lat} is not less than maximum latitude {max_lat}");
+        }
+        Ok(BBox([min_lon, min_lat, max_lon, max_lat]))
+    }
+
+    /// The WGS84 bounding box with opposite corners at the `(lon, lat)` points `a` and `b`, which
+    /// can be any pair of opposite corners
+    pub fn from_corners(a: (f64, f64), b: (f64, f64)) -> Result<BBox> {
+        BBox::new(a.0.min(b.0), a.1.min(b.1), a.0.max(b.0), a.1.max(b.1))
+    }
+
+    pub fn min_lon(&self) -> f64 {
+        self.0[0]
+    }
+
+    pub fn min_lat(&self) -> f64 {
+        self.0[1]
+    }
+
+    pub fn max_lon(&self) -> f64 {
+        self.0[2]
+    }
+
+    pub fn max_lat(&self) -> f64 {
+        self.0[3]
+    }
+
+    /// Whether this bounding box overlaps `other`, including touching at an edge. Both must be in
+    /// the same coordinate reference system.
+    pub fn intersects(&self, other: &BBox) -> bool {
+        self.0[0] <= other.0[2]
+            && other.0[0] <= self.0[2]
+            && self.0[1] <= other.0[3]
+            && other.0[1] <= self.0[3]
+    }
+
+    /// The smallest bounding box in the coordinate reference system `to_epsg` containing this
+    /// bounding box in `from_epsg`. Only the coordinate reference systems used by popgetter
+    /// geometries are supported, and others return an error listing them.
+    pub fn reproject(&self, from_epsg: i32, to_epsg: i32) -> Result<BBox> {
+        if from_epsg == to_epsg {
+            return Ok(self.clone());
+        }
+        let proj = |epsg: i32| -> Result<Proj> {
+            let definition = proj_string(epsg)?;
+            Proj::from_proj_string(definition)
+                .map_err(|err| anyhow!("Invalid definition of EPSG:{epsg}: {err}"))
+        };
+        let (from, to) = (proj(from_epsg)?, proj(to_epsg)?);
+        let [left, bottom, right, top] = self.0;
+        let steps = (0..BBOX_EDGE_POINTS).map(|step| step as f64 / (BBOX_EDGE_POINTS - 1) as f64);
+        let points = steps.flat_map(|t| {
+            let x = left + t * (right - left);
+            let y = bottom + t * (top - bottom);
+            [(x, bottom), (x, top), (left, y), (right, y)]
+        });
+        let mut reprojected = [
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ];
+        for (x, y) in points {
+            // proj4rs works in radians for geographic coordinates
+            let mut point = if from.is_latlong() {
+                (x.to_radians(), y.to_radians(), 0.0)
+            } else {
+                (x, y, 0.0)
+            };
+            transform(&from, &to, &mut point)
+                .map_err(|err| anyhow!("Failed to reproject bounding box: {err}"))?;
+            let (x, y) = if to.is_latlong() {
+                (point.0.to_degrees(), point.1.to_degrees())
+            } else {
+                (point.0, point.1)
+            };
+            reprojected = [
+                reprojected[0].min(x),
+                reprojected[1].min(y),
+                reprojected[2].max(x),
+                reprojected[3].max(y),
+            ];
+        }
+        Ok(BBox(reprojected))
+    }
+}
+
 impl Index<usize> for BBox {
     type Output = f64;
 
@@ -87,16 +723,22 @@ impl FromStr for BBox {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use ::geozero::{geojson::GeoJson, ColumnValue};
     use flatgeobuf::{geozero::PropertyProcessor, ColumnType, FgbWriter, GeometryType};
     use httpmock::prelude::*;
     use polars::datatypes::AnyValue;
 
-    fn test_fgb() -> FgbWriter<'static> {
+    /// Two polygons in WGS84 with the `GEO_ID`s `one` and `two`, the first around
+    /// (-2.1, 52.3) and the second around (-0.3, 51.5)
+    pub(crate) fn test_fgb() -> FgbWriter<'static> {
+        test_fgb_with_id_column(COL::GEO_ID)
+    }
+
+    fn test_fgb_with_id_column(geo_id_column: &str) -> FgbWriter<'static> {
         let mut fgb = FgbWriter::create("countries", GeometryType::Polygon).unwrap();
-        fgb.add_column(COL::GEO_ID, ColumnType::String, |_fbb, col| {
+        fgb.add_column(geo_id_column, ColumnType::String, |_fbb, col| {
             col.nullable = false
         });
         let geom1 = GeoJson(
@@ -204,7 +846,7 @@ mod tests {
         let server = mock_fgb_server();
 
         // Get the geometries
-        let geoms = get_geometries(&server.url("/fgb_example.fgb"), None).await;
+        let geoms = get_geometries(&server.url("/fgb_example.fgb"), None, COL::GEO_ID).await;
         println!("{geoms:#?}");
         assert!(geoms.is_ok(), "The geometry call should not error");
         let geoms = geoms.unwrap();
@@ -235,7 +877,7 @@ mod tests {
             -1.373_095_490_899_146_4,
             53.026_908_220_355_35,
         ]);
-        let geoms = get_geometries(&server.url("/fgb_example.fgb"), Some(bbox)).await;
+        let geoms = get_geometries(&server.url("/fgb_example.fgb"), Some(bbox), COL::GEO_ID).await;
 
         assert!(geoms.is_ok(), "The geometry call should not error");
         let geoms = geoms.unwrap();
@@ -246,6 +888,259 @@ mod tests {
         println!("{geoms:#?}");
     }
 
+    #[tokio::test]
+    async fn geometries_should_load_from_local_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        test_fgb().write(&mut file.as_file()).unwrap();
+
+        let geoms = load_geometries(file.path().to_str().unwrap()).await;
+        assert!(geoms.is_ok(), "The geometry call should not error");
+        let geoms = geoms.unwrap().df;
+        assert_eq!(geoms.shape(), (2, 2), "Should recover two features");
+    }
+
+    #[tokio::test]
+    async fn geo_ids_in_bbox_should_select_intersecting_geometries() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        test_fgb().write(&mut file.as_file()).unwrap();
+        let path = file.path().to_str().unwrap();
+
+        // Covers the first polygon only
+        let bbox = BBox([-3.0, 51.8, -1.4, 53.0]);
+        assert_eq!(
+            geo_ids_in_bbox(path, &bbox, COL::GEO_ID).await.unwrap(),
+            vec!["one"]
+        );
+        // Inside the bounding box of the first polygon but outside the polygon itself
+        let bbox = BBox([-2.5, 52.7, -2.4, 52.8]);
+        assert!(geo_ids_in_bbox(path, &bbox, COL::GEO_ID)
+            .await
+            .unwrap()
+            .is_empty());
+        // Bounding boxes must be in WGS84, not the coordinate reference system of the file
+        let bbox = BBox([530_000.0, 180_000.0, 531_000.0, 181_000.0]);
+        let err = geo_ids_in_bbox(path, &bbox, COL::GEO_ID).await.unwrap_err();
+        assert!(format!("{err:#}").contains("WGS84"), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn geometries_without_geo_id_should_not_load() {
+        let mut fgb = FgbWriter::create("countries", GeometryType::Polygon).unwrap();
+        fgb.add_column("id", ColumnType::String, |_fbb, col| col.nullable = false);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fgb.write(&mut file.as_file()).unwrap();
+
+        let err = load_geometries(file.path().to_str().unwrap())
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains(COL::GEO_ID),
+            "The error should name the missing column"
+        );
+    }
+
+    #[tokio::test]
+    async fn geometries_with_other_id_column_should_join_to_metrics() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        test_fgb_with_id_column("lad_code")
+            .write(&mut file.as_file())
+            .unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let err = load_geometries(path).await.unwrap_err();
+        assert!(
+            err.to_string().contains(COL::GEO_ID),
+            "The error should name the missing column"
+        );
+        let geometries = load_geometries_with_id_column(path, "lad_code")
+            .await
+            .unwrap();
+        let joined =
+            join_metrics_to_geometries(&test_metrics(), &geometries, GeometryJoin::Inner).unwrap();
+        assert_eq!(
+            joined.df.column(COL::GEO_ID).unwrap().str().unwrap().get(0),
+            Some("one")
+        );
+        assert_eq!(joined.unmatched_metrics, 1);
+        assert_eq!(joined.unmatched_geometries, 1);
+    }
+
+    #[test]
+    fn join_should_name_missing_geo_id_column() {
+        let metrics = test_metrics().drop(COL::GEO_ID).unwrap();
+        let err = join_metrics_to_geometries(&metrics, &test_geometry_frame(), GeometryJoin::Inner)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("The metrics have no '{}' column to join on", COL::GEO_ID)
+        );
+    }
+
+    fn test_geometry_frame() -> GeometryFrame {
+        GeometryFrame {
+            df: polars::df!(
+                COL::GEO_ID => &["one", "two"],
+                "geometry" => &[
+                    "POLYGON ((0 0, 1 0, 1 1, 0 1, 0 0))",
+                    "POLYGON ((1 0, 2 0, 2 1, 1 1, 1 0))",
+                ],
+            )
+            .unwrap(),
+            epsg: Some(4326),
+        }
+    }
+
+    fn test_metrics() -> DataFrame {
+        polars::df!(
+            COL::GEO_ID => &["one", "three"],
+            "population" => &[10u32, 30],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn inner_join_should_drop_unmatched_ids() {
+        let joined = join_metrics_to_geometries(
+            &test_metrics(),
+            &test_geometry_frame(),
+            GeometryJoin::Inner,
+        )
+        .unwrap();
+        assert_eq!(joined.df.height(), 1);
+        assert_eq!(joined.unmatched_metrics, 1);
+        assert_eq!(joined.unmatched_geometries, 1);
+    }
+
+    #[test]
+    fn left_join_should_keep_all_geometries() {
+        let joined =
+            join_metrics_to_geometries(&test_metrics(), &test_geometry_frame(), GeometryJoin::Left)
+                .unwrap();
+        assert_eq!(joined.df.height(), 2);
+        assert_eq!(joined.df.column("population").unwrap().null_count(), 1);
+    }
+
+    #[cfg(feature = "formatters")]
+    #[test]
+    fn metrics_should_serialize_to_geojson() {
+        let geojson =
+            metrics_to_geojson(&test_metrics(), &test_geometry_frame(), GeometryJoin::Left)
+                .unwrap();
+        let collection = geojson.parse::<geojson::GeoJson>().unwrap();
+        let geojson::GeoJson::FeatureCollection(collection) = collection else {
+            panic!("Should be a feature collection");
+        };
+        assert_eq!(collection.features.len(), 2);
+        let properties = collection.features[0].properties.as_ref().unwrap();
+        assert_eq!(properties[COL::GEO_ID], "one");
+        assert_eq!(properties["population"], 10);
+    }
+
+    #[cfg(feature = "geopackage")]
+    #[test]
+    fn metrics_should_round_trip_through_geopackage() {
+        use gdal::{
+            vector::{FieldValue, LayerAccess},
+            Dataset,
+        };
+
+        let joined = join_metrics_to_geometries(
+            &test_metrics(),
+            &test_geometry_frame(),
+            GeometryJoin::Inner,
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics.gpkg");
+        write_geopackage(&path, &joined).unwrap();
+
+        let dataset = Dataset::open(&path).unwrap();
+        let mut layer = dataset.layer(0).unwrap();
+        assert_eq!(layer.feature_count(), 1);
+        assert_eq!(
+            layer.spatial_ref().unwrap().auth_code().unwrap(),
+            4326,
+            "The CRS should be taken from the geometries"
+        );
+        let feature = layer.features().next().unwrap();
+        assert_eq!(
+            feature.field("population").unwrap(),
+            Some(FieldValue::Integer64Value(10))
+        );
+    }
+
+    #[tokio::test]
+    async fn geoid_names_should_be_resolved_from_names_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut names = polars::df!(
+            COL::GEO_ID => &["E01", "E02", "E03"],
+            "cym" => &["Caerdydd", "Abertawe", "Casnewydd"],
+            ENGLISH_NAMES => &[Some("Cardiff"), Some("Swansea"), None],
+        )
+        .unwrap();
+        polars::prelude::ParquetWriter::new(File::create(dir.path().join("oa.parquet")).unwrap())
+            .finish(&mut names)
+            .unwrap();
+        let config = Config {
+            base_path: dir.path().to_str().unwrap().to_string(),
+            ..Config::default()
+        };
+
+        let geo_ids = ["E01", "E03", "missing"].map(String::from);
+        let resolved = resolve_geoid_names(&geo_ids, "oa", &config).await.unwrap();
+        assert_eq!(
+            resolved,
+            HashMap::from([
+                ("E01".to_string(), Some("Cardiff".to_string())),
+                ("E03".to_string(), None),
+                ("missing".to_string(), None),
+            ])
+        );
+    }
+
+    #[test]
+    fn bbox_should_be_validated() {
+        let bbox = BBox::new(-3.0, 51.8, -1.4, 53.0).unwrap();
+        assert_eq!((bbox.min_lon(), bbox.max_lat()), (-3.0, 53.0));
+        assert_eq!(
+            BBox::from_corners((-1.4, 51.8), (-3.0, 53.0)).unwrap(),
+            bbox
+        );
+        // Inverted
+        assert!(BBox::new(-1.4, 51.8, -3.0, 53.0).is_err());
+        assert!(BBox::new(-3.0, 53.0, -1.4, 51.8).is_err());
+        // Empty
+        assert!(BBox::from_corners((-3.0, 51.8), (-3.0, 53.0)).is_err());
+        // Out of range
+        assert!(BBox::new(-181.0, 51.8, -1.4, 53.0).is_err());
+        assert!(BBox::new(-3.0, -91.0, -1.4, 53.0).is_err());
+        assert!(BBox::from_corners((530_000.0, 180_000.0), (531_000.0, 181_000.0)).is_err());
+        assert!(BBox::new(f64::NAN, 51.8, -1.4, 53.0).is_err());
+    }
+
+    #[test]
+    fn bbox_should_reproject_to_british_national_grid() {
+        // Around Big Ben, at about (530_268, 179_640) in the British National Grid
+        let bbox = BBox([-0.1246, 51.5007, -0.1246, 51.5007]);
+        let projected = bbox.reproject(WGS84, 27700).unwrap();
+        assert!((projected[0] - 530_268.0).abs() < 10.0, "{projected:?}");
+        assert!((projected[1] - 179_640.0).abs() < 10.0, "{projected:?}");
+
+        // Reprojected boxes contain the original box
+        let bbox = BBox([-1.0, 51.0, 1.0, 52.0]);
+        let round_trip = bbox
+            .reproject(WGS84, 27700)
+            .and_then(|projected| projected.reproject(27700, WGS84))
+            .unwrap();
+        assert!(round_trip[0] <= bbox[0] && round_trip[1] <= bbox[1]);
+        assert!(round_trip[2] >= bbox[2] && round_trip[3] >= bbox[3]);
+        let err = bbox.reproject(WGS84, 1234).unwrap_err().to_string();
+        assert!(
+            err.contains("EPSG:1234") && err.contains("EPSG:27700"),
+            "The error should name the unsupported and supported systems: {err}"
+        );
+    }
+
     #[test]
     fn bbox_should_parse_if_correct() {
         let bbox = BBox::from_str("0.0,1.0,2.0,3.0");
@@ -267,4 +1162,25 @@ mod tests {
         let bbox = BBox::from_str("0.0sdfsd,1.0,2.0");
         assert!(bbox.is_err(), "A string with letters shouldn't parse");
     }
+
+    #[test]
+    fn countries_in_bboxes_should_include_countries_without_bounds() {
+        let countries = ["USA", "GBR", "BEL", "FRA"];
+        // Belfast
+        let belfast = BBox::new(-6.05, 54.55, -5.8, 54.65).unwrap();
+        assert_eq!(countries_in_bboxes(&[belfast], &countries), ["FRA", "GBR"]);
+        // Spanning the Channel from Kent to Flanders
+        let channel = BBox::new(1.0, 50.9, 3.0, 51.3).unwrap();
+        assert_eq!(
+            countries_in_bboxes(&[channel], &countries),
+            ["BEL", "FRA", "GBR"]
+        );
+        // Mid-Atlantic
+        let atlantic = BBox::new(-40.0, 40.0, -39.0, 41.0).unwrap();
+        assert_eq!(
+            countries_in_bboxes(std::slice::from_ref(&atlantic), &countries),
+            ["FRA"]
+        );
+        assert!(countries_in_bboxes(&[atlantic], &["USA", "GBR"]).is_empty());
+    }
 }
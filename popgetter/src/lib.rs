@@ -1,30 +1,42 @@
-use std::path::Path;
+use std::{collections::BTreeMap, path::Path};
 
 use anyhow::Result;
 #[cfg(feature = "cache")]
 use anyhow::{anyhow, Context};
 use data_request_spec::DataRequestSpec;
+use explore::Explorer;
 use log::{debug, error};
 use metadata::Metadata;
+use parquet::{get_metrics_with_config, with_percentage};
 use polars::frame::DataFrame;
-use search::{Params, SearchParams, SearchResults};
+use search::{
+    CaseSensitivity, MatchType, MetricId, Params, SearchConfig, SearchParams, SearchResults,
+};
 
 use crate::config::Config;
 
 // Re-exports
 pub use column_names as COL;
+pub use geo::BBox;
 
 // Modules
+pub mod catalogue;
 pub mod column_names;
 pub mod config;
 pub mod data_request_spec;
 pub mod error;
+pub mod explore;
 #[cfg(feature = "formatters")]
 pub mod formatters;
 pub mod geo;
 pub mod metadata;
 pub mod parquet;
+pub mod progress;
 pub mod search;
+#[cfg(test)]
+pub(crate) mod test_util;
+mod trace;
+pub mod transform;
 
 /// Type for popgetter metadata, config and API
 #[derive(Debug, PartialEq)]
@@ -48,12 +60,17 @@ impl Popgetter {
 
     // Only include method with "cache" feature since it requires a filesystem
     #[cfg(feature = "cache")]
-    /// Setup the Popgetter object with custom configuration from cache
+    /// Setup the Popgetter object with custom configuration from cache. The cache is stored in
+    /// `Config::cache_dir` under a directory keyed on the base path and countries of the config.
     pub async fn new_with_config_and_cache(config: Config) -> Result<Self> {
-        // On macOS: ~/Library/Caches
-        let path = dirs::cache_dir()
-            .ok_or(anyhow!("Failed to get cache directory"))?
-            .join("popgetter");
+        let cache_dir = match config.cache_dir.as_ref() {
+            Some(cache_dir) => cache_dir.clone(),
+            // On macOS: ~/Library/Caches
+            None => dirs::cache_dir()
+                .ok_or(anyhow!("Failed to get cache directory"))?
+                .join("popgetter"),
+        };
+        let path = cache_dir.join(config.cache_key());
         Popgetter::new_with_config_and_cache_path(config, path).await
     }
 
@@ -63,8 +80,9 @@ impl Popgetter {
         config: Config,
         path: P,
     ) -> Result<Self> {
-        // Try to read metadata from cache
-        if path.as_ref().exists() {
+        // Try to read metadata from cache unless forced to refresh or the cache has expired
+        let ttl = config.cache_ttl_secs.map(std::time::Duration::from_secs);
+        if !config.force_refresh && Metadata::cache_is_fresh(&path, ttl) {
             match Popgetter::new_from_cache_path(config.clone(), &path) {
                 Ok(popgetter) => return Ok(popgetter),
                 Err(err) => {
@@ -94,11 +112,40 @@ impl Popgetter {
     }
 
     /// Generates `SearchResults` using popgetter given `SearchParams`
+    ///
+    /// ```no_run
+    /// use popgetter::{
+    ///     search::{SearchContext, SearchParams, SearchText},
+    ///     Popgetter,
+    /// };
+    ///
+    /// # async fn run() -> anyhow::Result<()> {
+    /// let popgetter = Popgetter::new().await?;
+    /// let search_params = SearchParams {
+    ///     text: vec![SearchText {
+    ///         text: "population".to_string(),
+    ///         context: SearchContext::all(),
+    ///         ..SearchText::default()
+    ///     }],
+    ///     ..SearchParams::default()
+    /// };
+    /// let search_results = popgetter.search(&search_params)?;
+    /// println!("{}", search_results.0);
+    /// # Ok(())
+    /// # }
+    /// ```
     // TODO: consider reverting to an API where `SearchParams` are moved, add benches
-    pub fn search(&self, search_params: &SearchParams) -> SearchResults {
-        search_params
+    pub fn search(&self, search_params: &SearchParams) -> Result<SearchResults> {
+        Ok(search_params
             .clone()
-            .search(&self.metadata.combined_metric_source_geometry())
+            .resolve_geometry_levels(&self.metadata, &self.config.geometry_level_aliases)?
+            .search(&self.metadata.combined_metric_source_geometry()?))
+    }
+
+    /// A read-only handle for exploring the metadata that cannot fetch metric data (see
+    /// `Explorer`)
+    pub fn explore(&self) -> Explorer<'_> {
+        Explorer::new(&self.metadata, &self.config.geometry_level_aliases)
     }
 
     /// Downloads data using popgetter given a `DataRequestSpec`
@@ -107,17 +154,86 @@ impl Popgetter {
         data_request_spec: &DataRequestSpec,
     ) -> Result<DataFrame> {
         let params: Params = data_request_spec.clone().try_into()?;
-        let search_results = self.search(&params.search);
-        search_results
-            .download(&self.config, &params.download)
-            .await
+        self.download_params(&params).await
     }
 
-    /// Downloads data using popgetter given `Params`
+    /// Downloads data using popgetter given `Params`. Any transformations in the
+    /// `DownloadParams` are validated against the metadata before downloading and applied to the
+    /// downloaded data in order.
     pub async fn download_params(&self, params: &Params) -> Result<DataFrame> {
-        self.search(&params.search)
+        let search_results = self.search(&params.search)?;
+        let transformations = &params.download.transformations;
+        transformations.validate(
+            search_results.download_columns(&params.download),
+            &self.metadata,
+        )?;
+        let df = search_results
             .download(&self.config, &params.download)
-            .await
+            .await?;
+        transformations.apply(df, &self.metadata)
+    }
+
+    /// Like `download_params`, but downloads the metrics at each of `geometry_levels`, returning
+    /// a map from geometry level to the downloaded data. Any geometry level in the search
+    /// parameters is ignored, and an error is returned if a metric is not available at one of
+    /// the requested levels.
+    pub async fn download_params_by_geometry_level(
+        &self,
+        params: &Params,
+        geometry_levels: &[&str],
+    ) -> Result<BTreeMap<String, DataFrame>> {
+        let search_params = SearchParams {
+            geometry_level: None,
+            ..params.search.clone()
+        };
+        let search_results = self.search(&search_params)?;
+        let transformations = &params.download.transformations;
+        transformations.validate(
+            search_results.download_columns(&params.download),
+            &self.metadata,
+        )?;
+        search_results
+            .download_by_geometry_level(&self.config, &params.download, geometry_levels)
+            .await?
+            .into_iter()
+            .map(|(geometry_level, df)| {
+                Ok((geometry_level, transformations.apply(df, &self.metadata)?))
+            })
+            .collect()
+    }
+
+    /// Downloads the metric `metric_id` along with `denominator_id` and adds a column giving the
+    /// metric as a percentage of the denominator (see `parquet::with_percentage`). Returns an
+    /// error before downloading if `denominator_id` is not one of the metric's
+    /// `potential_denominator_ids`, or if their units make a percentage meaningless (see
+    /// `Metadata::validate_percentage_units`).
+    pub async fn download_percentage(
+        &self,
+        metric_id: &str,
+        denominator_id: &str,
+    ) -> Result<DataFrame> {
+        self.metadata
+            .validate_denominator(metric_id, denominator_id)?;
+        self.metadata
+            .validate_percentage_units(metric_id, denominator_id)?;
+        let search_params = SearchParams {
+            metric_id: [metric_id, denominator_id]
+                .map(|id| MetricId {
+                    id: id.to_string(),
+                    config: SearchConfig {
+                        match_type: MatchType::Exact,
+                        case_sensitivity: CaseSensitivity::Sensitive,
+                    },
+                })
+                .to_vec(),
+            ..Default::default()
+        };
+        let search_results = self.search(&search_params)?;
+        let metric_column = self.metadata.parquet_column_name(metric_id)?;
+        let denominator_column = self.metadata.parquet_column_name(denominator_id)?;
+        let metric_requests = search_results.to_metric_requests(&self.config);
+        let df = get_metrics_with_config(&metric_requests, None, &self.config).await?;
+        with_percentage(df, &metric_column, &denominator_column)
     }
 }
 
@@ -125,10 +241,22 @@ impl Popgetter {
 #[cfg(feature = "cache")]
 mod tests {
 
+    use httpmock::prelude::*;
+    use polars::df;
     use tempfile::TempDir;
 
     use super::*;
 
+    fn test_metadata() -> Metadata {
+        Metadata {
+            metrics: df!(COL::METRIC_ID => &["a", "b"]).unwrap(),
+            geometries: df!(COL::GEOMETRY_ID => &["g"]).unwrap(),
+            source_data_releases: df!(COL::SOURCE_DATA_RELEASE_ID => &["s"]).unwrap(),
+            data_publishers: df!(COL::DATA_PUBLISHER_ID => &["p"]).unwrap(),
+            countries: df!(COL::COUNTRY_ID => &["c"]).unwrap(),
+        }
+    }
+
     #[tokio::test]
     async fn test_popgetter_cache() -> anyhow::Result<()> {
         let tempdir = TempDir::new()?;
@@ -140,4 +268,49 @@ mod tests {
         assert_eq!(popgetter, popgetter_from_cache);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn warm_cache_should_not_perform_requests() -> anyhow::Result<()> {
+        // Fail every request so that any network access can be detected
+        let server = MockServer::start();
+        let any_request = server.mock(|when, then| {
+            when.any_request();
+            then.status(500);
+        });
+        let tempdir = TempDir::new()?;
+        let metadata = test_metadata();
+        metadata.write_cache(&tempdir)?;
+        let config = Config {
+            base_path: server.base_url(),
+            cache_ttl_secs: Some(3600),
+            ..Config::default()
+        };
+        let popgetter = Popgetter::new_with_config_and_cache_path(config, &tempdir).await?;
+        assert_eq!(popgetter.metadata, metadata);
+        any_request.assert_hits(0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn force_refresh_should_bypass_cache() -> anyhow::Result<()> {
+        let server = MockServer::start();
+        let any_request = server.mock(|when, then| {
+            when.any_request();
+            then.status(500);
+        });
+        let tempdir = TempDir::new()?;
+        test_metadata().write_cache(&tempdir)?;
+        let config = Config {
+            base_path: server.base_url(),
+            force_refresh: true,
+            ..Config::default()
+        };
+        let result = Popgetter::new_with_config_and_cache_path(config, &tempdir).await;
+        assert!(
+            result.is_err(),
+            "Metadata should be fetched from the server"
+        );
+        assert!(any_request.hits() > 0);
+        Ok(())
+    }
 }
@@ -1,21 +1,43 @@
+use std::collections::{BTreeMap, HashMap};
 use std::default::Default;
 use std::fmt::Display;
+use std::future::Future;
+use std::io::Cursor;
 use std::path::Path;
+use std::str::FromStr;
 
-use anyhow::{anyhow, Result};
-use futures::future::join_all;
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{stream, StreamExt};
+use itertools::Itertools;
 use log::debug;
 use log::info;
+use log::warn;
 use polars::{
     lazy::{
-        dsl::col,
+        dsl::{col, len, lit, when, Expr},
         frame::{IntoLazy, LazyFrame, ScanArgsParquet},
     },
-    prelude::{DataFrame, JoinArgs, JoinType, ParquetCompression, ParquetWriter, UnionArgs},
+    prelude::{
+        CsvWriterOptions, DataFrame, DataType, JoinArgs, JoinType, NamedFrom, Null,
+        ParquetCompression, ParquetReader, ParquetWriteOptions, ParquetWriter, PolarsResult,
+        SerReader, SortMultipleOptions, UnionArgs,
+    },
+    series::Series,
 };
+use serde::{Deserialize, Serialize};
 use tokio::try_join;
 
-use crate::{config::Config, search::MetricId, COL};
+use crate::{
+    config::{redact, Config, RetryConfig},
+    error::{MetadataError, PopgetterError},
+    parquet::{estimate_download, DownloadEstimate},
+    search::{
+        combine_exprs_with_or, CaseSensitivity, MatchType, MetricId, SearchConfig, SearchResults,
+        YearRange,
+    },
+    trace::{record_rows, SpanTimer},
+    COL,
+};
 
 /// This module contains the names of the files that contain the metadata.
 pub mod paths {
@@ -27,9 +49,52 @@ pub mod paths {
 }
 use paths as PATHS;
 
+/// The names of the metadata files of a country, relative to its directory under the base path.
+/// Each defaults to the name in `paths`, so countries that ship differently named files can
+/// override only those in `Config::metadata_paths`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CountryMetadataPaths {
+    pub metrics: String,
+    pub geometries: String,
+    pub source_data_releases: String,
+    pub data_publishers: String,
+    pub countries: String,
+}
+
+impl Default for CountryMetadataPaths {
+    fn default() -> Self {
+        Self {
+            metrics: PATHS::METRIC_METADATA.into(),
+            geometries: PATHS::GEOMETRY_METADATA.into(),
+            source_data_releases: PATHS::SOURCE.into(),
+            data_publishers: PATHS::PUBLISHER.into(),
+            countries: PATHS::COUNTRY.into(),
+        }
+    }
+}
+
+/// This module contains the names of the columns in the summary data frames returned by
+/// `ExpandedMetadata` (e.g. `available_years`).
+pub mod summary_columns {
+    pub const YEAR: &str = "year";
+    pub const COUNT: &str = "count";
+}
+use summary_columns as SUMMARY_COL;
+
+/// This module contains the units of measurement inferred for metrics without a `metric_unit`
+/// (see `infer_unit`).
+pub mod units {
+    pub const PEOPLE: &str = "people";
+    pub const HOUSEHOLDS: &str = "households";
+    pub const PERCENT: &str = "%";
+    /// A count of something other than people or households
+    pub const COUNT: &str = "count";
+}
+
 /// `CountryMetadataLoader` takes a country iso string
-/// along with a CountryMetadataPaths and provides methods
-/// for fetching and constructing a `Metadata` catalogue.
+/// and provides methods for fetching and constructing a `Metadata` catalogue
+/// from the country's `CountryMetadataPaths` (see `Config::metadata_paths`).
 pub struct CountryMetadataLoader {
     country: String,
 }
@@ -42,6 +107,479 @@ impl ExpandedMetadata {
     pub fn as_df(&self) -> LazyFrame {
         self.0.clone()
     }
+
+    /// Filter the metadata to metrics whose reference period covers any of the given `years`.
+    /// A metric with a reference period spanning several years is kept if any of the requested
+    /// years falls within `[reference_period_start, reference_period_end]`.
+    pub fn select_years(&self, years: &[&str]) -> Result<ExpandedMetadata> {
+        let year_ranges = years
+            .iter()
+            .map(|year| year.parse::<YearRange>())
+            .collect::<Result<Vec<_>>>()?;
+        let expr = combine_exprs_with_or(year_ranges.into_iter().map(Into::into).collect());
+        Ok(match expr {
+            Some(expr) => ExpandedMetadata(self.as_df().filter(expr)),
+            None => ExpandedMetadata(self.as_df()),
+        })
+    }
+
+    /// Returns the geometry levels in the metadata along with the number of metrics available at
+    /// each level, sorted by descending count.
+    pub fn available_geometries(&self) -> Result<DataFrame> {
+        self.value_counts(COL::GEOMETRY_LEVEL)
+    }
+
+    /// Returns the distinct values of `column` along with the number of metrics having each
+    /// value, sorted by descending count and then by value. Nulls are counted as a group.
+    pub fn value_counts(&self, column: &str) -> Result<DataFrame> {
+        Ok(self
+            .as_df()
+            .group_by([col(column)])
+            .agg([len().alias(SUMMARY_COL::COUNT)])
+            .sort(
+                [SUMMARY_COL::COUNT, column],
+                SortMultipleOptions::default().with_order_descending_multi([true, false]),
+            )
+            .collect()?)
+    }
+
+    /// Returns the geometry levels in the metadata along with their resolution and the number of
+    /// metrics available at each level, sorted by how close their resolution is to
+    /// `target_resolution` and then by descending count. Levels with an unknown resolution are
+    /// ranked last. Requires the `geometry_resolution` column in the geometry metadata.
+    pub fn available_geometries_ranked_by_resolution(
+        &self,
+        target_resolution: u32,
+    ) -> Result<DataFrame> {
+        let resolution = col(COL::GEOMETRY_RESOLUTION).cast(DataType::Int64);
+        let target = lit(i64::from(target_resolution));
+        let distance = when(resolution.clone().gt_eq(target.clone()))
+            .then(resolution.clone() - target.clone())
+            .otherwise(target - resolution);
+        Ok(self
+            .as_df()
+            .group_by([col(COL::GEOMETRY_LEVEL)])
+            .agg([
+                col(COL::GEOMETRY_RESOLUTION).first(),
+                distance.first().alias("distance"),
+                len().alias(SUMMARY_COL::COUNT),
+            ])
+            .sort(
+                ["distance", SUMMARY_COL::COUNT, COL::GEOMETRY_LEVEL],
+                SortMultipleOptions::default()
+                    .with_order_descending_multi([false, true, false])
+                    .with_nulls_last(true),
+            )
+            .drop(["distance"])
+            .collect()?)
+    }
+
+    /// Returns the years covered by the reference periods in the metadata along with the number
+    /// of metrics available for each year, sorted by descending count. A metric with a reference
+    /// period spanning several years is counted once for each of those years.
+    pub fn available_years(&self) -> Result<DataFrame> {
+        let df = self
+            .as_df()
+            .select([
+                col(COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START)
+                    .dt()
+                    .year()
+                    .alias("start"),
+                col(COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END)
+                    .dt()
+                    .year()
+                    .alias("end"),
+            ])
+            .collect()?;
+        let mut counts: HashMap<i32, u32> = HashMap::new();
+        for (start, end) in df
+            .column("start")?
+            .i32()?
+            .into_iter()
+            .zip(df.column("end")?.i32()?)
+        {
+            // Fall back to a single year if only one end of the reference period is known
+            let (start, end) = match (start, end) {
+                (Some(start), Some(end)) => (start, end),
+                (Some(year), None) | (None, Some(year)) => (year, year),
+                (None, None) => continue,
+            };
+            for year in start..=end {
+                *counts.entry(year).or_default() += 1;
+            }
+        }
+        // Ties are broken by the most recent year
+        let (years, counts): (Vec<String>, Vec<u32>) = counts
+            .into_iter()
+            .sorted_by(|(year_a, count_a), (year_b, count_b)| {
+                count_b.cmp(count_a).then(year_b.cmp(year_a))
+            })
+            .map(|(year, count)| (year.to_string(), count))
+            .unzip();
+        Ok(DataFrame::new(vec![
+            Series::new(SUMMARY_COL::YEAR, years),
+            Series::new(SUMMARY_COL::COUNT, counts),
+        ])?)
+    }
+
+    /// Generate a `FullSelectionPlan` for the given `metrics`. Where `geometry` or `years` are
+    /// not given, the options with the most matching metrics are chosen and any alternatives are
+    /// recorded in the advice of the plan. If `target_resolution` is given, the default geometry
+    /// is instead the one with the closest resolution (see
+    /// `available_geometries_ranked_by_resolution`). Where `years` are not given and a metric
+    /// matches metrics from several source data releases, only its latest release (see
+    /// `releases_by_recency`) is selected.
+    pub fn generate_selection_plan(
+        &self,
+        metrics: &[MetricId],
+        geometry: Option<&str>,
+        years: Option<&[&str]>,
+        target_resolution: Option<u32>,
+    ) -> Result<FullSelectionPlan> {
+        let metrics_expr = combine_exprs_with_or(metrics.iter().cloned().map(Into::into).collect())
+            .ok_or(anyhow!("No metrics given to generate a selection plan"))?;
+        let selection = ExpandedMetadata(self.as_df().filter(metrics_expr));
+        let mut advice: Vec<SelectionAdvice> = vec![];
+
+        // Select the geometry
+        let geometry = match geometry {
+            Some(geometry) => geometry.to_string(),
+            None => {
+                let geometries = match target_resolution {
+                    Some(target_resolution) => {
+                        selection.available_geometries_ranked_by_resolution(target_resolution)?
+                    }
+                    None => selection.available_geometries()?,
+                };
+                let geometries = str_values(&geometries, COL::GEOMETRY_LEVEL)?;
+                let (geometry, alternatives) = geometries
+                    .split_first()
+                    .ok_or(anyhow!("No geometries available for the requested metrics"))?;
+                if !alternatives.is_empty() {
+                    advice.push(SelectionAdvice::AlternativeGeometries(
+                        alternatives.to_vec(),
+                    ));
+                }
+                geometry.clone()
+            }
+        };
+        let selection = ExpandedMetadata(
+            selection
+                .as_df()
+                .filter(col(COL::GEOMETRY_LEVEL).eq(lit(geometry.clone()))),
+        );
+
+        // Without requested years, prefer the latest release of each metric
+        let schema = selection.as_df().limit(0).collect()?;
+        let has_release_dates = [
+            COL::METRIC_SOURCE_DATA_RELEASE_ID,
+            COL::SOURCE_DATA_RELEASE_NAME,
+            COL::SOURCE_DATA_RELEASE_DATE_PUBLISHED,
+        ]
+        .iter()
+        .all(|column| schema.get_column_index(column).is_some());
+        let selection = if years.is_none() && has_release_dates {
+            let mut latest_exprs = vec![];
+            for metric in metrics {
+                let releases = ExpandedMetadata(selection.as_df().filter(metric.clone().into()))
+                    .releases_by_recency()?;
+                let Some((latest, alternatives)) = releases.split_first() else {
+                    continue;
+                };
+                if !alternatives.is_empty() {
+                    advice.push(SelectionAdvice::LatestRelease {
+                        metric_id: metric.id.clone(),
+                        release: latest.name.clone(),
+                        alternatives: alternatives
+                            .iter()
+                            .map(|release| release.name.clone())
+                            .collect(),
+                    });
+                }
+                latest_exprs.push(
+                    Expr::from(metric.clone())
+                        .and(col(COL::METRIC_SOURCE_DATA_RELEASE_ID).eq(lit(latest.id.clone()))),
+                );
+            }
+            match combine_exprs_with_or(latest_exprs) {
+                Some(expr) => ExpandedMetadata(selection.as_df().filter(expr)),
+                None => selection,
+            }
+        } else {
+            selection
+        };
+
+        // Select the years
+        let year = match years {
+            Some(years) => years.iter().map(|year| year.to_string()).collect_vec(),
+            None => {
+                let years = selection.available_years()?;
+                let years = str_values(&years, SUMMARY_COL::YEAR)?;
+                let (year, alternatives) = years
+                    .split_first()
+                    .ok_or(anyhow!("No years available for the requested metrics"))?;
+                if !alternatives.is_empty() {
+                    advice.push(SelectionAdvice::AlternativeYears(alternatives.to_vec()));
+                }
+                vec![year.clone()]
+            }
+        };
+        let selection = selection.select_years(&year.iter().map(String::as_str).collect_vec())?;
+
+        let mismatched = selection.geometry_validity_mismatches()?;
+        if !mismatched.is_empty() {
+            warn!(
+                "The reference periods of metrics {mismatched:?} fall outside the validity period \
+                 of the '{geometry}' geometry"
+            );
+            advice.push(SelectionAdvice::GeometryValidityMismatch {
+                geometry: geometry.clone(),
+                metric_ids: mismatched,
+            });
+        }
+
+        let selected = selection.as_df().select([col(COL::METRIC_ID)]).collect()?;
+        let explicit_metric_ids = str_values(&selected, COL::METRIC_ID)?
+            .into_iter()
+            .unique()
+            .map(|id| MetricId {
+                id,
+                config: SearchConfig {
+                    match_type: MatchType::Exact,
+                    case_sensitivity: CaseSensitivity::Insensitive,
+                },
+            })
+            .collect();
+
+        Ok(FullSelectionPlan {
+            explicit_metric_ids,
+            geometry,
+            year,
+            advice,
+        })
+    }
+
+    /// The sorted distinct IDs of metrics whose reference period is not within the validity period
+    /// of their geometry, e.g. 2021 census metrics joined to boundaries that were replaced in
+    /// 2020. An unknown start or end of either period is treated as unbounded, and metadata
+    /// without geometry validity periods gives no mismatches.
+    pub fn geometry_validity_mismatches(&self) -> Result<Vec<String>> {
+        let df = self.as_df();
+        let schema = df.clone().limit(0).collect()?;
+        if schema
+            .get_column_index(COL::GEOMETRY_VALIDITY_PERIOD_START)
+            .is_none()
+            && schema
+                .get_column_index(COL::GEOMETRY_VALIDITY_PERIOD_END)
+                .is_none()
+        {
+            return Ok(vec![]);
+        }
+        let outside = |column: &str, bound: Expr| {
+            if schema.get_column_index(column).is_some() {
+                bound.fill_null(lit(false))
+            } else {
+                lit(false)
+            }
+        };
+        let reference_start = col(COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START);
+        let reference_end =
+            col(COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END).fill_null(reference_start.clone());
+        let starts_before = outside(
+            COL::GEOMETRY_VALIDITY_PERIOD_START,
+            reference_start.lt(col(COL::GEOMETRY_VALIDITY_PERIOD_START)),
+        );
+        let ends_after = outside(
+            COL::GEOMETRY_VALIDITY_PERIOD_END,
+            reference_end.gt(col(COL::GEOMETRY_VALIDITY_PERIOD_END)),
+        );
+        let mismatched = df
+            .filter(starts_before.or(ends_after))
+            .select([col(COL::METRIC_ID)])
+            .collect()?;
+        Ok(str_values(&mismatched, COL::METRIC_ID)?
+            .into_iter()
+            .unique()
+            .sorted()
+            .collect())
+    }
+
+    /// The distinct source data releases of the metrics in the metadata, newest first by
+    /// `date_published`. Releases without a publication date come last, and releases published on
+    /// the same date are sorted by ID so that the order is deterministic.
+    pub fn releases_by_recency(&self) -> Result<Vec<SourceDataRelease>> {
+        let df = self
+            .as_df()
+            .select([
+                col(COL::METRIC_SOURCE_DATA_RELEASE_ID),
+                col(COL::SOURCE_DATA_RELEASE_NAME),
+                col(COL::SOURCE_DATA_RELEASE_DATE_PUBLISHED),
+            ])
+            .sort(
+                [
+                    COL::SOURCE_DATA_RELEASE_DATE_PUBLISHED,
+                    COL::METRIC_SOURCE_DATA_RELEASE_ID,
+                ],
+                SortMultipleOptions::default()
+                    .with_order_descending_multi([true, false])
+                    .with_nulls_last(true),
+            )
+            .collect()?;
+        let ids = df.column(COL::METRIC_SOURCE_DATA_RELEASE_ID)?.str()?;
+        let names = df.column(COL::SOURCE_DATA_RELEASE_NAME)?.str()?;
+        let dates = df
+            .column(COL::SOURCE_DATA_RELEASE_DATE_PUBLISHED)?
+            .cast(&DataType::String)?;
+        Ok(ids
+            .into_iter()
+            .zip(names)
+            .zip(dates.str()?)
+            .filter_map(|((id, name), date_published)| {
+                Some(SourceDataRelease {
+                    id: id?.to_string(),
+                    name: name.unwrap_or_default().to_string(),
+                    date_published: date_published.map(String::from),
+                })
+            })
+            .unique_by(|release| release.id.clone())
+            .collect())
+    }
+
+    /// Estimate the number of metric columns, geographic units and bytes that downloading `plan`
+    /// would fetch. Only the parquet footers of the metric files are read, so this is cheap to
+    /// run before committing to a download.
+    pub async fn estimate_download(
+        &self,
+        plan: &FullSelectionPlan,
+        config: &Config,
+    ) -> Result<DownloadEstimate> {
+        let metrics_expr = combine_exprs_with_or(
+            plan.explicit_metric_ids
+                .iter()
+                .cloned()
+                .map(Into::into)
+                .collect(),
+        )
+        .ok_or(anyhow!("The selection plan contains no metrics"))?;
+        let selection = SearchResults(self.as_df().filter(metrics_expr).collect()?);
+        estimate_download(&selection.to_metric_requests(config), config).await
+    }
+}
+
+/// Lowercases `text` and sorts its words so that strings can be compared independently of case
+/// and word order.
+fn normalize_words(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .sorted()
+        .join(" ")
+}
+
+/// Maximum number of suggestions returned by `Metadata::suggest_metric_values`
+pub const MAX_SUGGESTIONS: usize = 3;
+
+/// Minimum similarity for a value to be suggested by `Metadata::suggest_metric_values`, so that
+/// unrelated values are not suggested when nothing is close
+const MIN_SUGGESTION_SCORE: f64 = 0.6;
+
+/// The value reported by `Metadata::facet_counts` for metrics with a null value
+pub const NO_FACET_VALUE: &str = "(none)";
+
+/// Check that `column` is a string column of `df` and so can be used as a facet
+fn check_facet(df: &DataFrame, column: &str) -> Result<()> {
+    let available = df
+        .get_columns()
+        .iter()
+        .filter(|series| series.dtype() == &DataType::String)
+        .map(|series| series.name().to_string())
+        .collect::<Vec<_>>();
+    if available.iter().any(|name| name == column) {
+        Ok(())
+    } else {
+        Err(PopgetterError::UnknownFacet {
+            column: column.to_string(),
+            available,
+        }
+        .into())
+    }
+}
+
+/// Returns the non-null values of a string column
+fn str_values(df: &DataFrame, column: &str) -> Result<Vec<String>> {
+    Ok(df
+        .column(column)?
+        .str()?
+        .into_iter()
+        .flatten()
+        .map(String::from)
+        .collect())
+}
+
+/// The values of the `columns` of each row of `df`, keyed on the value of `id_column`. Rows
+/// without an ID are skipped.
+fn row_values(
+    df: &DataFrame,
+    id_column: &str,
+    columns: &[&str],
+) -> Result<BTreeMap<String, Vec<String>>> {
+    let series = columns
+        .iter()
+        .map(|column| df.column(column))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut rows = BTreeMap::new();
+    for (idx, id) in df.column(id_column)?.str()?.into_iter().enumerate() {
+        let Some(id) = id else { continue };
+        let values = series
+            .iter()
+            .map(|series| Ok(series.get(idx)?.to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        rows.insert(id.to_string(), values);
+    }
+    Ok(rows)
+}
+
+/// Compare the rows of the `old` and `new` versions of a metadata table by `id_column`. Only the
+/// columns present in both versions are compared for changes.
+fn diff_table(old: &DataFrame, new: &DataFrame, id_column: &str) -> Result<TableDiff> {
+    let columns = old
+        .get_column_names()
+        .into_iter()
+        .filter(|&name| name != id_column && new.get_column_index(name).is_some())
+        .collect_vec();
+    let old_rows = row_values(old, id_column, &columns)?;
+    let new_rows = row_values(new, id_column, &columns)?;
+    let added = new_rows
+        .keys()
+        .filter(|id| !old_rows.contains_key(*id))
+        .cloned()
+        .collect();
+    let removed = old_rows
+        .keys()
+        .filter(|id| !new_rows.contains_key(*id))
+        .cloned()
+        .collect();
+    let changed = old_rows
+        .iter()
+        .filter_map(|(id, old_values)| {
+            let new_values = new_rows.get(id)?;
+            let changed_columns = columns
+                .iter()
+                .zip(old_values.iter().zip(new_values))
+                .filter(|(_, (old_value, new_value))| old_value != new_value)
+                .map(|(column, _)| column.to_string())
+                .collect_vec();
+            (!changed_columns.is_empty()).then(|| ChangedRecord {
+                id: id.clone(),
+                columns: changed_columns,
+            })
+        })
+        .collect();
+    Ok(TableDiff {
+        added,
+        removed,
+        changed,
+    })
 }
 
 /// The metadata struct contains the polars `DataFrames` for
@@ -95,6 +633,29 @@ impl Metadata {
         })
     }
 
+    /// Whether a complete metadata cache exists in `cache_dir` and is younger than `ttl` (if
+    /// given).
+    pub fn cache_is_fresh<P: AsRef<Path>>(cache_dir: P, ttl: Option<std::time::Duration>) -> bool {
+        let paths = [
+            PATHS::METRIC_METADATA,
+            PATHS::GEOMETRY_METADATA,
+            PATHS::SOURCE,
+            PATHS::PUBLISHER,
+            PATHS::COUNTRY,
+        ];
+        paths.iter().all(|file_name| {
+            let Ok(modified) =
+                std::fs::metadata(prepend(&cache_dir, file_name)).and_then(|m| m.modified())
+            else {
+                return false;
+            };
+            match ttl {
+                Some(ttl) => modified.elapsed().map(|age| age < ttl).unwrap_or(false),
+                None => true,
+            }
+        })
+    }
+
     pub fn write_cache<P: AsRef<Path>>(&self, cache_dir: P) -> anyhow::Result<()> {
         df_to_file(prepend(&cache_dir, PATHS::METRIC_METADATA), &self.metrics)?;
         df_to_file(
@@ -111,6 +672,13 @@ impl Metadata {
     }
 }
 
+/// File formats that the catalogue can be exported to with `Metadata::export_catalogue`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CatalogueFormat {
+    Parquet,
+    Csv,
+}
+
 /// Describes a fully specified selection plan. The MetricIds should all
 /// be the ID variant. Geometry and years are backed in now.
 /// Advice specifies and alternative options that the user should
@@ -119,7 +687,7 @@ pub struct FullSelectionPlan {
     pub explicit_metric_ids: Vec<MetricId>,
     pub geometry: String,
     pub year: Vec<String>,
-    pub advice: String,
+    pub advice: Vec<SelectionAdvice>,
 }
 
 impl Display for FullSelectionPlan {
@@ -130,202 +698,3344 @@ impl Display for FullSelectionPlan {
             self.explicit_metric_ids.len(),
             self.geometry,
             self.year.join(",")
-        )
+        )?;
+        for advice in &self.advice {
+            write!(f, "\n{advice}")?;
+        }
+        Ok(())
     }
 }
 
-impl Metadata {
-    /// Generate a Lazy DataFrame which joins the metrics, source and geometry metadata
-    pub fn combined_metric_source_geometry(&self) -> ExpandedMetadata {
-        let mut df: LazyFrame = self
-            .metrics
-            .clone()
-            .lazy()
-            // Join source data releases
-            .join(
-                self.source_data_releases.clone().lazy(),
-                [col(COL::METRIC_SOURCE_DATA_RELEASE_ID)],
-                [col(COL::SOURCE_DATA_RELEASE_ID)],
-                JoinArgs::new(JoinType::Inner),
-            )
-            // Join geometry metadata
-            .join(
-                self.geometries.clone().lazy(),
-                [col(COL::SOURCE_DATA_RELEASE_GEOMETRY_METADATA_ID)],
-                [col(COL::GEOMETRY_ID)],
-                JoinArgs::new(JoinType::Inner),
-            )
-            // Join data publishers
-            .join(
-                self.data_publishers.clone().lazy(),
-                [col(COL::SOURCE_DATA_RELEASE_DATA_PUBLISHER_ID)],
-                [col(COL::DATA_PUBLISHER_ID)],
-                JoinArgs::new(JoinType::Inner),
-            )
-            // TODO: consider case when many countries
-            .explode([col(COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST)])
-            .join(
-                self.countries.clone().lazy(),
-                [col(COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST)],
-                [col(COL::COUNTRY_ID)],
-                JoinArgs::new(JoinType::Inner),
-            );
-
-        // Debug print the column names so that we know what we can access
-        let schema = df.schema().unwrap();
-        let column_names = schema
-            .iter_names()
-            .map(|s| s.as_str())
-            .collect::<Vec<&str>>();
-        debug!("Column names in merged metadata: {:?}", column_names);
+/// Alternative options to those chosen by default in a `FullSelectionPlan`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SelectionAdvice {
+    /// Other geometry levels the metrics are available for, in order of preference
+    AlternativeGeometries(Vec<String>),
+    /// Other years the metrics are available for, in order of preference
+    AlternativeYears(Vec<String>),
+    /// The latest source data release was chosen for a metric that matches metrics from the
+    /// `alternatives` releases too, newest first
+    LatestRelease {
+        metric_id: String,
+        release: String,
+        alternatives: Vec<String>,
+    },
+    /// Metrics whose reference period falls outside the validity period of the chosen geometry,
+    /// so the boundaries may not match those the data were published for
+    GeometryValidityMismatch {
+        geometry: String,
+        metric_ids: Vec<String>,
+    },
+}
 
-        ExpandedMetadata(df)
+impl Display for SelectionAdvice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SelectionAdvice::AlternativeGeometries(geometries) => write!(
+                f,
+                "The metrics are also available for the geometries: {}",
+                geometries.join(", ")
+            ),
+            SelectionAdvice::AlternativeYears(years) => write!(
+                f,
+                "The metrics are also available for the years: {}",
+                years.join(", ")
+            ),
+            SelectionAdvice::LatestRelease {
+                metric_id,
+                release,
+                alternatives,
+            } => write!(
+                f,
+                "Using the latest release '{release}' for the metric '{metric_id}', which is also \
+                 available in the releases: {}",
+                alternatives.join(", ")
+            ),
+            SelectionAdvice::GeometryValidityMismatch {
+                geometry,
+                metric_ids,
+            } => write!(
+                f,
+                "The reference periods of the metrics {} fall outside the validity period of the \
+                 '{geometry}' geometry",
+                metric_ids.join(", ")
+            ),
+        }
     }
 }
 
-impl CountryMetadataLoader {
-    /// Create a metadata loader for a specific Country
-    pub fn new(country: &str) -> Self {
-        Self {
-            country: country.into(),
+/// The descriptive metadata of a single metric, produced by `Metadata::metrics_detail`. Fields are
+/// `None` where the value is null or the column is not present in the metadata.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricDetail {
+    pub metric_id: String,
+    pub human_readable_name: Option<String>,
+    pub description: Option<String>,
+    pub hxl_tag: Option<String>,
+    pub source_metric_id: Option<String>,
+    pub parquet_path: Option<String>,
+    pub parquet_column_name: Option<String>,
+    pub geometry_level: Option<String>,
+    pub source_data_release_name: Option<String>,
+    pub reference_period_start: Option<String>,
+    pub reference_period_end: Option<String>,
+    pub data_publisher_name: Option<String>,
+    pub country_name_short_en: Option<String>,
+    pub source_download_url: Option<String>,
+    /// Path of the source data within the archive at `source_download_url`, if it is an archive
+    pub source_archive_file_path: Option<String>,
+    pub source_documentation_url: Option<String>,
+    /// The unit of measurement, from `metric_unit` or else inferred from the HXL tag
+    pub unit: Option<MetricUnit>,
+}
+
+/// Where the source data of a metric was published, produced by `Metadata::source_reference`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceReference {
+    pub download_url: String,
+    /// Path of the source data within the archive at `download_url`, or `None` if the download is
+    /// the source data file itself
+    pub archive_file_path: Option<String>,
+}
+
+impl Display for SourceReference {
+    /// The download URL, followed by `!/` and the path within the archive if the source data is
+    /// in an archive, e.g. `https://example.com/census.zip!/tables/population.csv`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.download_url)?;
+        if let Some(archive_file_path) = &self.archive_file_path {
+            write!(f, "!/{}", archive_file_path.trim_start_matches('/'))?;
         }
+        Ok(())
     }
+}
 
-    /// Load the Metadata catalouge for this country with
-    /// the specified metadata paths
-    pub async fn load(self, config: &Config) -> Result<Metadata> {
-        let t = try_join!(
-            self.load_metadata(PATHS::METRIC_METADATA, config),
-            self.load_metadata(PATHS::GEOMETRY_METADATA, config),
-            self.load_metadata(PATHS::SOURCE, config),
-            self.load_metadata(PATHS::PUBLISHER, config),
-            self.load_metadata(PATHS::COUNTRY, config),
-        )?;
-        Ok(Metadata {
-            metrics: t.0,
-            geometries: t.1,
-            source_data_releases: t.2,
-            data_publishers: t.3,
-            countries: t.4,
+/// The result of `Metadata::metrics_detail`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsDetail {
+    /// The metrics matching each of the requested IDs, in the order the IDs were given
+    pub found: Vec<MetricDetail>,
+    /// The requested IDs that matched no metric, in the order they were given
+    pub not_found: Vec<String>,
+}
+
+/// The `MetricDetail` of each row of `df`, a subset of the combined metadata
+fn metric_details(df: &DataFrame) -> Result<Vec<MetricDetail>> {
+    let values = |column: &str| -> Result<Vec<Option<String>>> {
+        Ok(match df.column(column) {
+            Ok(series) => series
+                .cast(&DataType::String)?
+                .str()?
+                .into_iter()
+                .map(|value| value.map(String::from))
+                .collect(),
+            Err(_) => vec![None; df.height()],
+        })
+    };
+    let metric_id = values(COL::METRIC_ID)?;
+    let human_readable_name = values(COL::METRIC_HUMAN_READABLE_NAME)?;
+    let description = values(COL::METRIC_DESCRIPTION)?;
+    let hxl_tag = values(COL::METRIC_HXL_TAG)?;
+    let source_metric_id = values(COL::METRIC_SOURCE_METRIC_ID)?;
+    let parquet_path = values(COL::METRIC_PARQUET_PATH)?;
+    let parquet_column_name = values(COL::METRIC_PARQUET_COLUMN_NAME)?;
+    let geometry_level = values(COL::GEOMETRY_LEVEL)?;
+    let source_data_release_name = values(COL::SOURCE_DATA_RELEASE_NAME)?;
+    let reference_period_start = values(COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START)?;
+    let reference_period_end = values(COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END)?;
+    let data_publisher_name = values(COL::DATA_PUBLISHER_NAME)?;
+    let country_name_short_en = values(COL::COUNTRY_NAME_SHORT_EN)?;
+    let source_download_url = values(COL::METRIC_SOURCE_DOWNLOAD_URL)?;
+    let source_archive_file_path = values(COL::METRIC_SOURCE_ARCHIVE_FILE_PATH)?;
+    let source_documentation_url = values(COL::METRIC_SOURCE_DOCUMENTATION_URL)?;
+    let unit = values(COL::METRIC_UNIT)?;
+    Ok((0..df.height())
+        .map(|idx| MetricDetail {
+            metric_id: metric_id[idx].clone().unwrap_or_default(),
+            human_readable_name: human_readable_name[idx].clone(),
+            description: description[idx].clone(),
+            hxl_tag: hxl_tag[idx].clone(),
+            source_metric_id: source_metric_id[idx].clone(),
+            parquet_path: parquet_path[idx].clone(),
+            parquet_column_name: parquet_column_name[idx].clone(),
+            geometry_level: geometry_level[idx].clone(),
+            source_data_release_name: source_data_release_name[idx].clone(),
+            reference_period_start: reference_period_start[idx].clone(),
+            reference_period_end: reference_period_end[idx].clone(),
+            data_publisher_name: data_publisher_name[idx].clone(),
+            country_name_short_en: country_name_short_en[idx].clone(),
+            source_download_url: source_download_url[idx].clone(),
+            source_archive_file_path: source_archive_file_path[idx].clone(),
+            source_documentation_url: source_documentation_url[idx].clone(),
+            unit: MetricUnit::from_metadata(unit[idx].as_deref(), hxl_tag[idx].as_deref()),
         })
+        .collect())
+}
+
+/// A citation for the data from one source data release, produced by `Metadata::citation_for`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Citation {
+    pub publisher: String,
+    pub release: String,
+    pub date_published: Option<String>,
+    /// The distinct documentation URLs of the selected metrics from the release
+    pub documentation_urls: Vec<String>,
+}
+
+impl Display for Citation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}. {}", self.publisher, self.release)?;
+        if let Some(date_published) = &self.date_published {
+            write!(f, " ({date_published})")?;
+        }
+        write!(f, ".")?;
+        for url in &self.documentation_urls {
+            write!(f, " {url}")?;
+        }
+        Ok(())
     }
+}
 
-    /// Performs a load of a given metadata parquet file
-    async fn load_metadata(&self, path: &str, config: &Config) -> Result<DataFrame> {
-        let full_path = format!("{}/{}/{path}", config.base_path, self.country);
-        let args = ScanArgsParquet::default();
-        info!("Attempting to load dataframe from {full_path}");
-        tokio::task::spawn_blocking(move || {
-            LazyFrame::scan_parquet(&full_path, args)?
-                .collect()
-                .map_err(|e| anyhow!("Failed to load '{full_path}': {e}"))
-        })
-        .await?
+/// A source data release of metrics, produced by `Metadata::latest_release`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceDataRelease {
+    pub id: String,
+    pub name: String,
+    pub date_published: Option<String>,
+}
+
+/// A record present in both versions of a metadata table whose values differ
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangedRecord {
+    pub id: String,
+    /// The columns whose values differ
+    pub columns: Vec<String>,
+}
+
+/// The differences between two versions of a metadata table, each sorted by ID
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<ChangedRecord>,
+}
+
+impl TableDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
     }
 }
 
-async fn get_country_names(config: &Config) -> anyhow::Result<Vec<String>> {
-    Ok(reqwest::Client::new()
-        .get(&format!("{}/countries.txt", config.base_path))
-        .send()
-        .await?
-        .text()
-        .await?
-        .lines()
-        .map(|s| s.to_string())
-        .collect())
+/// The differences between two versions of the metadata catalogue, produced by `Metadata::diff`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetadataDiff {
+    pub metrics: TableDiff,
+    pub geometries: TableDiff,
+    pub source_data_releases: TableDiff,
 }
 
-/// Load the metadata for a list of countries and merge them into
-/// a single `Metadata` catalogue.
-pub async fn load_all(config: &Config) -> Result<Metadata> {
-    let country_names = get_country_names(config).await?;
+impl MetadataDiff {
+    pub fn is_empty(&self) -> bool {
+        self.metrics.is_empty()
+            && self.geometries.is_empty()
+            && self.source_data_releases.is_empty()
+    }
+}
 
-    info!("Detected country names: {:?}", country_names);
-    let metadata: Result<Vec<Metadata>> = join_all(
-        country_names
-            .iter()
-            .map(|c| CountryMetadataLoader::new(c).load(config)),
-    )
-    .await
-    .into_iter()
-    .collect();
-    let metadata = metadata?;
+impl Display for MetadataDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "No differences");
+        }
+        for (name, diff) in [
+            ("metrics", &self.metrics),
+            ("geometries", &self.geometries),
+            ("source data releases", &self.source_data_releases),
+        ] {
+            if diff.is_empty() {
+                continue;
+            }
+            writeln!(f, "{name}:")?;
+            for id in &diff.added {
+                writeln!(f, "  + {id}")?;
+            }
+            for id in &diff.removed {
+                writeln!(f, "  - {id}")?;
+            }
+            for record in &diff.changed {
+                writeln!(f, "  ~ {} ({})", record.id, record.columns.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
 
-    // Merge metrics
-    let metric_dfs: Vec<LazyFrame> = metadata.iter().map(|m| m.metrics.clone().lazy()).collect();
-    let metrics = polars::prelude::concat(metric_dfs, UnionArgs::default())?.collect()?;
-    info!("Merged metrics with shape: {:?}", metrics.shape());
+/// The kind of value a metric holds, which determines how it can be transformed (e.g. whether
+/// it can be summed when aggregating or interpolating)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricDataType {
+    /// A count of something, e.g. a number of people
+    Count,
+    /// A ratio or percentage of two quantities
+    Ratio,
+    /// A category, e.g. a classification code
+    Categorical,
+    /// Any other continuous value, e.g. a median
+    Continuous,
+}
 
-    // Merge geometries
-    let geometries_dfs: Vec<LazyFrame> = metadata
-        .iter()
-        .map(|m| m.geometries.clone().lazy())
-        .collect();
-    let geometries = polars::prelude::concat(geometries_dfs, UnionArgs::default())?.collect()?;
-    info!("Merged geometries with shape: {:?}", geometries.shape());
+impl FromStr for MetricDataType {
+    type Err = anyhow::Error;
 
-    // Merge source data relaeses
-    let source_data_dfs: Vec<LazyFrame> = metadata
-        .iter()
-        .map(|m| m.source_data_releases.clone().lazy())
-        .collect();
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "count" => Ok(MetricDataType::Count),
+            "ratio" => Ok(MetricDataType::Ratio),
+            "categorical" => Ok(MetricDataType::Categorical),
+            "continuous" => Ok(MetricDataType::Continuous),
+            _ => Err(anyhow!("Unknown metric data type '{value}'")),
+        }
+    }
+}
 
-    let source_data_releases =
-        polars::prelude::concat(source_data_dfs, UnionArgs::default())?.collect()?;
-    info!(
-        "Merged source data releases with shape: {:?}",
-        source_data_releases.shape()
-    );
+/// The unit of measurement of a metric, e.g. `people`, `%` or a currency code such as `USD`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetricUnit {
+    pub unit: String,
+    /// Whether the unit was inferred from the HXL tag of the metric rather than given in the
+    /// metadata, and so may be wrong
+    pub inferred: bool,
+}
 
-    // Merge source data publishers
-    let data_publisher_dfs: Vec<LazyFrame> = metadata
-        .iter()
-        .map(|m| m.data_publishers.clone().lazy())
-        .collect();
+impl MetricUnit {
+    /// The unit given in the metadata, `declared`, or else the unit inferred from `hxl_tag`
+    pub fn from_metadata(declared: Option<&str>, hxl_tag: Option<&str>) -> Option<MetricUnit> {
+        match declared.filter(|unit| !unit.is_empty()) {
+            Some(unit) => Some(MetricUnit {
+                unit: unit.to_string(),
+                inferred: false,
+            }),
+            None => hxl_tag.and_then(infer_unit).map(|unit| MetricUnit {
+                unit,
+                inferred: true,
+            }),
+        }
+    }
+
+    /// Whether a metric in this unit can sensibly be given as a percentage of a metric in
+    /// `denominator`. Percentages cannot be taken of or by a percentage, and counts of different
+    /// things (e.g. people or households) are not comparable. A generic count is comparable to
+    /// any other count.
+    pub fn can_be_percentage_of(&self, denominator: &MetricUnit) -> bool {
+        let is_count = |unit: &str| unit == units::COUNT;
+        self.unit != units::PERCENT
+            && denominator.unit != units::PERCENT
+            && (self.unit == denominator.unit
+                || is_count(&self.unit)
+                || is_count(&denominator.unit))
+    }
+}
+
+impl Display for MetricUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.unit)?;
+        if self.inferred {
+            write!(f, " (inferred)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Infer the unit of a metric from its HXL tag, e.g. `people` for `#population+total` or `%` for
+/// `#population+f+pct`. Returns `None` if the tag does not indicate a unit.
+pub fn infer_unit(hxl_tag: &str) -> Option<String> {
+    let mut parts = hxl_tag.trim().split('+').map(str::to_lowercase);
+    let hashtag = parts.next()?;
+    let attributes = parts.collect_vec();
+    let has = |attribute: &str| attributes.iter().any(|a| a == attribute);
+    let unit = if has("pct") {
+        units::PERCENT
+    } else if has("hh") || matches!(hashtag.as_str(), "#household" | "#households") {
+        units::HOUSEHOLDS
+    } else if has("ind") || hashtag == "#population" {
+        units::PEOPLE
+    } else if hashtag == "#value" {
+        // Monetary values are tagged with their currency code, e.g. `#value+usd`
+        return attributes
+            .iter()
+            .find(|a| a.len() == 3 && a.chars().all(|c| c.is_ascii_alphabetic()))
+            .map(|currency| currency.to_uppercase());
+    } else if has("total") || has("num") {
+        units::COUNT
+    } else {
+        return None;
+    };
+    Some(unit.to_string())
+}
+
+/// How the values of a metric for the child geographies of a parent are combined
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregation {
+    Sum,
+    Mean,
+    Min,
+    Max,
+}
+
+impl Aggregation {
+    fn expr(self, column: &str) -> Expr {
+        match self {
+            Aggregation::Sum => col(column).sum(),
+            Aggregation::Mean => col(column).mean(),
+            Aggregation::Min => col(column).min(),
+            Aggregation::Max => col(column).max(),
+        }
+    }
+}
+
+/// Name of the column counting the children of each parent geography that have no value, added
+/// by `Metadata::aggregate_to_level`
+pub const MISSING_CHILDREN: &str = "missing_children";
 
-    let data_publishers =
-        polars::prelude::concat(data_publisher_dfs, UnionArgs::default())?.collect()?;
-    info!(
-        "Merged data publishers with shape: {:?}",
-        data_publishers.shape()
-    );
+/// The prefix of the columns of each of the tables joined by
+/// `Metadata::combined_metric_source_geometry`, in the order they are joined
+const TABLE_PREFIXES: [&str; 5] = [
+    "metric_",
+    "source_data_release_",
+    "geometry_",
+    "data_publisher_",
+    "country_",
+];
 
-    // Merge countries
-    let countries_dfs: Vec<LazyFrame> = metadata
+/// Columns named without the prefix of their table in older metadata releases
+const UNPREFIXED_COLUMNS: [&str; 2] = ["description", "hxl_tag"];
+
+/// Resolve the columns with the same name in more than one of `tables`, each given with the
+/// prefix of its columns (see `TABLE_PREFIXES`), so that they can be joined without polars adding
+/// `_right` suffixes. A colliding column is kept only in the table whose prefix it has, if any, and
+/// is otherwise given the prefix of each table, e.g. a `description` column in the metrics and
+/// geometries becomes `metric_description` and `geometry_description`. If a table already has the
+/// prefixed column, the unprefixed one is dropped. The `UNPREFIXED_COLUMNS` of older metadata
+/// releases are given the prefix of their table even without a collision, so that e.g. the
+/// metric descriptions are always in `metric_description`.
+fn resolve_column_collisions(tables: &mut [(&str, DataFrame)]) -> PolarsResult<()> {
+    for (prefix, table) in tables.iter_mut() {
+        for name in UNPREFIXED_COLUMNS {
+            if table.column(name).is_ok() {
+                prefix_column(table, prefix, name)?;
+            }
+        }
+    }
+    let collisions = tables
         .iter()
-        .map(|m| m.countries.clone().lazy())
-        .collect();
-    let countries = polars::prelude::concat(countries_dfs, UnionArgs::default())?.collect()?;
-    info!("Merged countries with shape: {:?}", countries.shape());
+        .flat_map(|(_, table)| table.get_column_names().into_iter().map(String::from))
+        .counts()
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(name, _)| name)
+        .sorted()
+        .collect_vec();
+    for name in collisions {
+        let has_owner = tables
+            .iter()
+            .any(|(prefix, table)| name.starts_with(prefix) && table.column(&name).is_ok());
+        for (prefix, table) in tables.iter_mut() {
+            if table.column(&name).is_err() || name.starts_with(*prefix) {
+                continue;
+            }
+            if has_owner {
+                *table = table.drop(&name)?;
+            } else {
+                prefix_column(table, prefix, &name)?;
+            }
+        }
+    }
+    Ok(())
+}
 
-    Ok(Metadata {
-        metrics,
-        geometries,
-        source_data_releases,
-        data_publishers,
-        countries,
-    })
+/// Rename the column `name` of `table` to `prefix` followed by `name`, or drop it if `table`
+/// already has the prefixed column
+fn prefix_column(table: &mut DataFrame, prefix: &str, name: &str) -> PolarsResult<()> {
+    let prefixed = format!("{prefix}{name}");
+    if table.column(&prefixed).is_ok() {
+        *table = table.drop(name)?;
+    } else {
+        table.rename(name, &prefixed)?;
+    }
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    /// TODO stub out a mock here that we can use to test with.
+/// Columns of each metadata table that are relied on when joining, searching and downloading.
+const REQUIRED_COLUMNS: [(&str, &[&str]); 5] = [
+    (
+        "metrics",
+        &[
+            COL::METRIC_ID,
+            COL::METRIC_HUMAN_READABLE_NAME,
+            COL::METRIC_DESCRIPTION,
+            COL::METRIC_HXL_TAG,
+            COL::METRIC_SOURCE_METRIC_ID,
+            COL::METRIC_PARQUET_PATH,
+            COL::METRIC_PARQUET_COLUMN_NAME,
+            COL::METRIC_SOURCE_DATA_RELEASE_ID,
+            COL::METRIC_SOURCE_DOWNLOAD_URL,
+        ],
+    ),
+    (
+        "geometries",
+        &[
+            COL::GEOMETRY_ID,
+            COL::GEOMETRY_FILEPATH_STEM,
+            COL::GEOMETRY_LEVEL,
+        ],
+    ),
+    (
+        "source_data_releases",
+        &[
+            COL::SOURCE_DATA_RELEASE_ID,
+            COL::SOURCE_DATA_RELEASE_NAME,
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START,
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END,
+            COL::SOURCE_DATA_RELEASE_COLLECTION_PERIOD_START,
+            COL::SOURCE_DATA_RELEASE_GEOMETRY_METADATA_ID,
+            COL::SOURCE_DATA_RELEASE_DATA_PUBLISHER_ID,
+        ],
+    ),
+    (
+        "data_publishers",
+        &[
+            COL::DATA_PUBLISHER_ID,
+            COL::DATA_PUBLISHER_NAME,
+            COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST,
+        ],
+    ),
+    (
+        "countries",
+        &[
+            COL::COUNTRY_ID,
+            COL::COUNTRY_NAME_SHORT_EN,
+            COL::COUNTRY_NAME_OFFICIAL,
+            COL::COUNTRY_ISO2,
+            COL::COUNTRY_ISO3,
+            COL::COUNTRY_ISO3166_2,
+        ],
+    ),
+];
 
-    #[tokio::test]
-    async fn country_metadata_should_load() {
-        let config = Config::default();
-        let metadata = CountryMetadataLoader::new("bel").load(&config).await;
-        println!("{metadata:#?}");
-        assert!(metadata.is_ok(), "Data should have loaded ok");
+impl Metadata {
+    /// Compare this catalogue with a newer version `other`, reporting the metrics, geometries and
+    /// source data releases that were added, removed or changed, keyed by their IDs. A metric is
+    /// changed if e.g. its parquet path moved, and a source data release if e.g. its reference
+    /// period moved.
+    pub fn diff(&self, other: &Metadata) -> Result<MetadataDiff> {
+        Ok(MetadataDiff {
+            metrics: diff_table(&self.metrics, &other.metrics, COL::METRIC_ID)?,
+            geometries: diff_table(&self.geometries, &other.geometries, COL::GEOMETRY_ID)?,
+            source_data_releases: diff_table(
+                &self.source_data_releases,
+                &other.source_data_releases,
+                COL::SOURCE_DATA_RELEASE_ID,
+            )?,
+        })
     }
 
-    #[tokio::test]
-    async fn all_metadata_should_load() {
-        let config = Config::default();
-        let metadata = load_all(&config).await;
-        println!("{metadata:#?}");
-        assert!(metadata.is_ok(), "Data should have loaded ok");
+    /// Find the `limit` metrics whose human readable names are closest to `name`, along with their
+    /// similarity scores between 0 and 1 in descending order. Names are compared by normalized
+    /// edit distance after lowercasing and sorting their words, so differences in case and word
+    /// order are ignored and small misspellings still score highly.
+    pub fn fuzzy_find_metrics(&self, name: &str, limit: usize) -> Result<Vec<(MetricId, f64)>> {
+        let query = normalize_words(name);
+        let ids = self.metrics.column(COL::METRIC_ID)?.str()?;
+        let names = self
+            .metrics
+            .column(COL::METRIC_HUMAN_READABLE_NAME)?
+            .str()?;
+        Ok(ids
+            .into_iter()
+            .zip(names)
+            .filter_map(|(id, name)| Some((id?, name?)))
+            .map(|(id, name)| {
+                let score = strsim::normalized_levenshtein(&query, &normalize_words(name));
+                (id, score)
+            })
+            .sorted_by(|(id_a, score_a), (id_b, score_b)| {
+                score_b.total_cmp(score_a).then(id_a.cmp(id_b))
+            })
+            .take(limit)
+            .map(|(id, score)| {
+                let metric_id = MetricId {
+                    id: id.to_string(),
+                    config: SearchConfig {
+                        match_type: MatchType::Exact,
+                        case_sensitivity: CaseSensitivity::Insensitive,
+                    },
+                };
+                (metric_id, score)
+            })
+            .collect())
+    }
+
+    /// The distinct values of the metric metadata `columns` closest to `value` by case insensitive
+    /// edit distance, most similar first, for suggesting corrections to a value that matched no
+    /// metrics. At most `MAX_SUGGESTIONS` values are returned, and none if nothing is close.
+    pub fn suggest_metric_values(&self, columns: &[&str], value: &str) -> Result<Vec<String>> {
+        let query = value.to_lowercase();
+        let mut scores: HashMap<String, f64> = HashMap::new();
+        for column in columns {
+            for candidate in self.metrics.column(column)?.str()?.into_iter().flatten() {
+                let score = strsim::normalized_levenshtein(&query, &candidate.to_lowercase());
+                if score >= MIN_SUGGESTION_SCORE {
+                    scores.insert(candidate.to_string(), score);
+                }
+            }
+        }
+        Ok(scores
+            .into_iter()
+            .sorted_by(|(value_a, score_a), (value_b, score_b)| {
+                score_b.total_cmp(score_a).then(value_a.cmp(value_b))
+            })
+            .take(MAX_SUGGESTIONS)
+            .map(|(value, _)| value)
+            .collect())
+    }
+
+    /// The name of the column holding `metric_id` in its parquet file
+    pub fn parquet_column_name(&self, metric_id: &str) -> Result<String> {
+        let metric = self
+            .metrics
+            .clone()
+            .lazy()
+            .filter(col(COL::METRIC_ID).eq(lit(metric_id)))
+            .select([col(COL::METRIC_PARQUET_COLUMN_NAME)])
+            .collect()?;
+        metric
+            .column(COL::METRIC_PARQUET_COLUMN_NAME)?
+            .str()?
+            .get(0)
+            .map(String::from)
+            .ok_or_else(|| PopgetterError::MetricNotFound(metric_id.to_string()).into())
+    }
+
+    /// Where the source data of `metric_id` was published. Returns an error if there is no such
+    /// metric or it has no source download URL.
+    pub fn source_reference(&self, metric_id: &str) -> Result<SourceReference> {
+        let metric = self
+            .metrics
+            .clone()
+            .lazy()
+            .filter(col(COL::METRIC_ID).eq(lit(metric_id)))
+            .collect()?;
+        if metric.height() == 0 {
+            return Err(PopgetterError::MetricNotFound(metric_id.to_string()).into());
+        }
+        let Some(download_url) = metric
+            .column(COL::METRIC_SOURCE_DOWNLOAD_URL)?
+            .str()?
+            .get(0)
+            .map(String::from)
+        else {
+            bail!("Metric '{metric_id}' has no source download URL");
+        };
+        // Older metadata may not have the column at all, in which case no source is archived
+        let archive_file_path = match metric.column(COL::METRIC_SOURCE_ARCHIVE_FILE_PATH) {
+            Ok(series) => series
+                .str()?
+                .get(0)
+                .filter(|path| !path.is_empty())
+                .map(String::from),
+            Err(_) => None,
+        };
+        Ok(SourceReference {
+            download_url,
+            archive_file_path,
+        })
+    }
+
+    /// The data type of the metric matching `metric_id`, read from `metric_data_type`. Metrics
+    /// are assumed to be counts, with a warning, if the metadata has no data type for them.
+    pub fn metric_data_type(&self, metric_id: &MetricId) -> Result<MetricDataType> {
+        self.data_type_where(metric_id.clone().into(), &metric_id.id)
+    }
+
+    /// The data type of the metric stored in the parquet column `column`
+    pub(crate) fn metric_data_type_of_column(&self, column: &str) -> Result<MetricDataType> {
+        self.data_type_where(col(COL::METRIC_PARQUET_COLUMN_NAME).eq(lit(column)), column)
+    }
+
+    /// The data type of the single metric matching `filter`, described by `name` in messages
+    fn data_type_where(&self, filter: Expr, name: &str) -> Result<MetricDataType> {
+        let metric = self.metrics.clone().lazy().filter(filter).collect()?;
+        if metric.height() == 0 {
+            return Err(PopgetterError::MetricNotFound(name.to_string()).into());
+        }
+        let data_type = match metric.column(COL::METRIC_DATA_TYPE) {
+            Ok(data_types) => data_types.str()?.get(0).map(String::from),
+            Err(_) => None,
+        };
+        match data_type {
+            Some(data_type) => data_type.parse(),
+            None => {
+                warn!("No data type for metric '{name}', assuming it is a count");
+                Ok(MetricDataType::Count)
+            }
+        }
+    }
+
+    /// The unit of the metric `metric_id`, read from `metric_unit` or else inferred from its HXL
+    /// tag (see `infer_unit`). Returns `None` if neither gives a unit.
+    pub fn metric_unit(&self, metric_id: &str) -> Result<Option<MetricUnit>> {
+        let metric = self
+            .metrics
+            .clone()
+            .lazy()
+            .filter(col(COL::METRIC_ID).eq(lit(metric_id)))
+            .collect()?;
+        if metric.height() == 0 {
+            return Err(PopgetterError::MetricNotFound(metric_id.to_string()).into());
+        }
+        let first = |column: &str| -> Result<Option<String>> {
+            Ok(match metric.column(column) {
+                // A column with no values at all may have the null type rather than strings
+                Ok(series) => series
+                    .cast(&DataType::String)?
+                    .str()?
+                    .get(0)
+                    .map(String::from),
+                Err(_) => None,
+            })
+        };
+        Ok(MetricUnit::from_metadata(
+            first(COL::METRIC_UNIT)?.as_deref(),
+            first(COL::METRIC_HXL_TAG)?.as_deref(),
+        ))
+    }
+
+    /// Check that `metric_id` can sensibly be given as a percentage of `denominator_id` based on
+    /// their units (see `MetricUnit::can_be_percentage_of`). Metrics with an unknown unit are
+    /// allowed.
+    pub fn validate_percentage_units(&self, metric_id: &str, denominator_id: &str) -> Result<()> {
+        if let (Some(unit), Some(denominator_unit)) = (
+            self.metric_unit(metric_id)?,
+            self.metric_unit(denominator_id)?,
+        ) {
+            if !unit.can_be_percentage_of(&denominator_unit) {
+                return Err(PopgetterError::IncompatibleUnits {
+                    metric_id: metric_id.to_string(),
+                    unit: unit.to_string(),
+                    denominator_id: denominator_id.to_string(),
+                    denominator_unit: denominator_unit.to_string(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// The newest source data release, by `date_published`, of the metrics matching `metric`.
+    /// Releases published on the same date are ordered by ID, so the same release is always
+    /// chosen (see `ExpandedMetadata::releases_by_recency`).
+    pub fn latest_release(&self, metric: &MetricId) -> Result<SourceDataRelease> {
+        let releases = ExpandedMetadata(
+            self.metrics
+                .clone()
+                .lazy()
+                .filter(metric.clone().into())
+                .join(
+                    self.source_data_releases.clone().lazy(),
+                    [col(COL::METRIC_SOURCE_DATA_RELEASE_ID)],
+                    [col(COL::SOURCE_DATA_RELEASE_ID)],
+                    JoinArgs::new(JoinType::Inner),
+                ),
+        )
+        .releases_by_recency()?;
+        releases
+            .into_iter()
+            .next()
+            .ok_or_else(|| PopgetterError::MetricNotFound(metric.id.clone()).into())
+    }
+
+    /// The column holding the IDs of the geometries in the files of `geometry_level`, read from
+    /// `geometry_geo_id_column`. This is `GEO_ID` unless the geometry metadata names another
+    /// column for the level.
+    pub fn geo_id_column(&self, geometry_level: &str) -> Result<String> {
+        let geometries = self
+            .geometries
+            .clone()
+            .lazy()
+            .filter(col(COL::GEOMETRY_LEVEL).eq(lit(geometry_level)))
+            .collect()?;
+        if geometries.height() == 0 {
+            bail!("Unknown geometry level '{geometry_level}'");
+        }
+        Ok(geo_id_columns(&geometries)?
+            .into_iter()
+            .next()
+            .map(|(_, column)| column)
+            .unwrap_or_else(|| COL::GEO_ID.to_string()))
+    }
+
+    /// Aggregate the values of `metric` in `metrics` up to the coarser geometry `target_level`.
+    /// The `hierarchy` maps each child `GEO_ID` to its `parent_GEO_ID` at each
+    /// `parent_geometry_level`. Counts are summed unless another `aggregation` is given, which is
+    /// required for metrics of any other data type. The result has one row per parent with its
+    /// `GEO_ID`, the aggregated metric and a `missing_children` column counting the children in
+    /// the hierarchy that have no value, so partially covered parents can be identified.
+    pub fn aggregate_to_level(
+        &self,
+        metric: &MetricId,
+        metrics: &DataFrame,
+        hierarchy: &DataFrame,
+        target_level: &str,
+        aggregation: Option<Aggregation>,
+    ) -> Result<DataFrame> {
+        let column = self.parquet_column_name(&metric.id)?;
+        let aggregation = match (aggregation, self.metric_data_type(metric)?) {
+            (Some(aggregation), _) => aggregation,
+            (None, MetricDataType::Count) => Aggregation::Sum,
+            (None, data_type) => bail!(
+                "Metric '{}' has data type {data_type:?} so an aggregation must be given",
+                metric.id
+            ),
+        };
+        let children = hierarchy
+            .clone()
+            .lazy()
+            .filter(col(COL::PARENT_GEOMETRY_LEVEL).eq(lit(target_level)))
+            .select([col(COL::GEO_ID), col(COL::PARENT_GEO_ID)]);
+        let aggregated = children
+            .join(
+                metrics
+                    .clone()
+                    .lazy()
+                    .select([col(COL::GEO_ID), col(&column)]),
+                [col(COL::GEO_ID)],
+                [col(COL::GEO_ID)],
+                JoinArgs::new(JoinType::Left),
+            )
+            .group_by([col(COL::PARENT_GEO_ID)])
+            .agg([
+                aggregation.expr(&column),
+                col(&column)
+                    .is_null()
+                    .sum()
+                    .cast(DataType::UInt32)
+                    .alias(MISSING_CHILDREN),
+            ])
+            .rename([COL::PARENT_GEO_ID], [COL::GEO_ID])
+            .sort([COL::GEO_ID], SortMultipleOptions::default())
+            .collect()?;
+        if aggregated.height() == 0 {
+            bail!("No geographies in the hierarchy have a parent at level '{target_level}'");
+        }
+        Ok(aggregated)
+    }
+
+    /// The ids of the metrics listed as potential denominators of `metric_id`
+    pub fn potential_denominator_ids(&self, metric_id: &str) -> Result<Vec<String>> {
+        let metric = self
+            .metrics
+            .clone()
+            .lazy()
+            .filter(col(COL::METRIC_ID).eq(lit(metric_id)))
+            .select([col(COL::METRIC_POTENTIAL_DENOMINATOR_IDS)])
+            .collect()?;
+        if metric.height() == 0 {
+            return Err(PopgetterError::MetricNotFound(metric_id.to_string()).into());
+        }
+        let ids = metric.column(COL::METRIC_POTENTIAL_DENOMINATOR_IDS)?;
+        let ids = match ids.dtype() {
+            DataType::List(_) => ids.explode()?,
+            _ => ids.clone(),
+        };
+        Ok(ids.str()?.into_iter().flatten().map(String::from).collect())
+    }
+
+    /// Check that `denominator_id` is listed as a potential denominator of `metric_id`
+    pub fn validate_denominator(&self, metric_id: &str, denominator_id: &str) -> Result<()> {
+        let potential = self.potential_denominator_ids(metric_id)?;
+        if potential.iter().any(|id| id == denominator_id) {
+            Ok(())
+        } else {
+            Err(PopgetterError::InvalidDenominator {
+                metric_id: metric_id.to_string(),
+                denominator_id: denominator_id.to_string(),
+                potential,
+            }
+            .into())
+        }
+    }
+
+    /// The sorted distinct geometry levels of the geometry metadata
+    pub fn geometry_levels(&self) -> Result<Vec<String>> {
+        let mut levels = str_values(&self.geometries, COL::GEOMETRY_LEVEL)?;
+        levels.sort();
+        levels.dedup();
+        Ok(levels)
+    }
+
+    /// The sorted distinct non-null values of `column` across the combined metrics, source data
+    /// release, publisher, country and geometry metadata. Any string column of the combined
+    /// metadata can be used as a facet, e.g. `data_publisher_name` or `geometry_level`.
+    pub fn distinct_values(&self, column: &str) -> Result<Vec<String>> {
+        let combined = self.combined_metric_source_geometry()?.as_df().collect()?;
+        check_facet(&combined, column)?;
+        let mut values = str_values(&combined, column)?;
+        values.sort();
+        values.dedup();
+        Ok(values)
+    }
+
+    /// The distinct values of `column` in the combined metadata along with the number of metrics
+    /// having each value, sorted by descending count and then by value. Metrics with a null value
+    /// are counted under `NO_FACET_VALUE`, so the counts always sum to the number of metrics.
+    pub fn facet_counts(&self, column: &str) -> Result<Vec<(String, u32)>> {
+        let combined = self.combined_metric_source_geometry()?;
+        check_facet(&combined.as_df().limit(0).collect()?, column)?;
+        let counts = combined.value_counts(column)?;
+        Ok(counts
+            .column(column)?
+            .str()?
+            .into_iter()
+            .zip(counts.column(SUMMARY_COL::COUNT)?.u32()?)
+            .map(|(value, count)| {
+                (
+                    value.unwrap_or(NO_FACET_VALUE).to_string(),
+                    count.unwrap_or_default(),
+                )
+            })
+            .collect())
+    }
+
+    /// Citations crediting the data publisher and source data release of each of the `metrics`,
+    /// with one citation for each distinct (publisher, release) pair, sorted by publisher and then
+    /// release. Each citation lists the documentation URLs of the metrics from that release, if
+    /// the metadata has them. Returns an error if any of the `metrics` matches no metric.
+    pub fn citation_for(&self, metrics: &[MetricId]) -> Result<Vec<Citation>> {
+        let Some(expr) = combine_exprs_with_or(metrics.iter().cloned().map(Into::into).collect())
+        else {
+            return Ok(vec![]);
+        };
+        let selected = self
+            .combined_metric_source_geometry()?
+            .as_df()
+            .filter(expr)
+            .collect()?;
+        for metric in metrics {
+            if selected
+                .clone()
+                .lazy()
+                .filter(metric.clone().into())
+                .collect()?
+                .height()
+                == 0
+            {
+                return Err(PopgetterError::MetricNotFound(metric.id.clone()).into());
+            }
+        }
+        let optional_str_values = |column: &str| -> Result<Vec<Option<String>>> {
+            Ok(match selected.column(column) {
+                Ok(series) => series
+                    .cast(&DataType::String)?
+                    .str()?
+                    .into_iter()
+                    .map(|value| value.map(String::from))
+                    .collect(),
+                Err(_) => vec![None; selected.height()],
+            })
+        };
+        let publishers = selected.column(COL::DATA_PUBLISHER_NAME)?.str()?;
+        let releases = selected.column(COL::SOURCE_DATA_RELEASE_NAME)?.str()?;
+        let dates = optional_str_values(COL::SOURCE_DATA_RELEASE_DATE_PUBLISHED)?;
+        let urls = optional_str_values(COL::METRIC_SOURCE_DOCUMENTATION_URL)?;
+        let mut citations: BTreeMap<(String, String), Citation> = BTreeMap::new();
+        for (((publisher, release), date_published), url) in
+            publishers.into_iter().zip(releases).zip(dates).zip(urls)
+        {
+            let publisher = publisher.unwrap_or_default().to_string();
+            let release = release.unwrap_or_default().to_string();
+            let citation = citations
+                .entry((publisher.clone(), release.clone()))
+                .or_insert_with(|| Citation {
+                    publisher,
+                    release,
+                    date_published,
+                    documentation_urls: vec![],
+                });
+            if let Some(url) = url {
+                if !citation.documentation_urls.contains(&url) {
+                    citation.documentation_urls.push(url);
+                }
+            }
+        }
+        Ok(citations
+            .into_values()
+            .map(|mut citation| {
+                citation.documentation_urls.sort();
+                citation
+            })
+            .collect())
+    }
+
+    /// The descriptive metadata of the metrics matching each of `metric_ids`, e.g. the IDs saved in
+    /// a recipe. The metadata is filtered once for all of the IDs, and the details are returned in
+    /// the order of `metric_ids` along with any IDs that matched no metric.
+    pub fn metrics_detail(&self, metric_ids: &[MetricId]) -> Result<MetricsDetail> {
+        let Some(expr) =
+            combine_exprs_with_or(metric_ids.iter().cloned().map(Into::into).collect())
+        else {
+            return Ok(MetricsDetail::default());
+        };
+        let selected = self
+            .combined_metric_source_geometry()?
+            .as_df()
+            .filter(expr)
+            .collect()?;
+        let mut detail = MetricsDetail::default();
+        for metric_id in metric_ids {
+            let matching = selected
+                .clone()
+                .lazy()
+                .filter(metric_id.clone().into())
+                .collect()?;
+            if matching.height() == 0 {
+                detail.not_found.push(metric_id.id.clone());
+            } else {
+                detail.found.extend(metric_details(&matching)?);
+            }
+        }
+        Ok(detail)
+    }
+
+    /// Check that every column used downstream is present in the metadata tables, returning an
+    /// error listing all missing columns as `table.column`.
+    pub fn validate_schema(&self) -> Result<(), MetadataError> {
+        let tables = [
+            &self.metrics,
+            &self.geometries,
+            &self.source_data_releases,
+            &self.data_publishers,
+            &self.countries,
+        ];
+        let missing = REQUIRED_COLUMNS
+            .iter()
+            .zip(tables)
+            .flat_map(|((table, columns), df)| {
+                columns
+                    .iter()
+                    .filter(|column| df.get_column_index(column).is_none())
+                    .map(move |column| format!("{table}.{column}"))
+            })
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(MetadataError::InvalidSchema { missing })
+        }
+    }
+
+    /// Generate a Lazy DataFrame which joins the metrics, source and geometry metadata. The columns
+    /// are ordered by table, in the order metrics, source data releases, geometries, data
+    /// publishers and countries, and by name within each table, so the order does not depend on
+    /// the order of the columns in the metadata files or on how polars orders joined columns.
+    /// Columns with the same name in more than one table are resolved (see
+    /// `resolve_column_collisions`), so the result has no `_right` suffixed columns.
+    pub fn combined_metric_source_geometry(&self) -> Result<ExpandedMetadata, MetadataError> {
+        // Check the columns used as join keys are present before joining
+        for (df, column) in [
+            (&self.metrics, COL::METRIC_SOURCE_DATA_RELEASE_ID),
+            (&self.source_data_releases, COL::SOURCE_DATA_RELEASE_ID),
+            (
+                &self.source_data_releases,
+                COL::SOURCE_DATA_RELEASE_GEOMETRY_METADATA_ID,
+            ),
+            (
+                &self.source_data_releases,
+                COL::SOURCE_DATA_RELEASE_DATA_PUBLISHER_ID,
+            ),
+            (&self.geometries, COL::GEOMETRY_ID),
+            (&self.data_publishers, COL::DATA_PUBLISHER_ID),
+            (
+                &self.data_publishers,
+                COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST,
+            ),
+            (&self.countries, COL::COUNTRY_ID),
+        ] {
+            if df.column(column).is_err() {
+                return Err(MetadataError::MissingColumn {
+                    column: column.to_string(),
+                });
+            }
+        }
+
+        let mut tables = [
+            (TABLE_PREFIXES[0], self.metrics.clone()),
+            (TABLE_PREFIXES[1], self.source_data_releases.clone()),
+            (TABLE_PREFIXES[2], self.geometries.clone()),
+            (TABLE_PREFIXES[3], self.data_publishers.clone()),
+            (TABLE_PREFIXES[4], self.countries.clone()),
+        ];
+        resolve_column_collisions(&mut tables).map_err(MetadataError::Join)?;
+        let column_order = tables
+            .iter()
+            .flat_map(|(_, table)| {
+                table
+                    .get_column_names()
+                    .into_iter()
+                    .map(String::from)
+                    .sorted()
+            })
+            .collect_vec();
+        let [metrics, source_data_releases, geometries, data_publishers, countries] =
+            tables.map(|(_, table)| table);
+
+        let mut df: LazyFrame = metrics
+            .lazy()
+            // Join source data releases
+            .join(
+                source_data_releases.lazy(),
+                [col(COL::METRIC_SOURCE_DATA_RELEASE_ID)],
+                [col(COL::SOURCE_DATA_RELEASE_ID)],
+                JoinArgs::new(JoinType::Inner),
+            )
+            // Join geometry metadata
+            .join(
+                geometries.lazy(),
+                [col(COL::SOURCE_DATA_RELEASE_GEOMETRY_METADATA_ID)],
+                [col(COL::GEOMETRY_ID)],
+                JoinArgs::new(JoinType::Inner),
+            )
+            // Join data publishers
+            .join(
+                data_publishers.lazy(),
+                [col(COL::SOURCE_DATA_RELEASE_DATA_PUBLISHER_ID)],
+                [col(COL::DATA_PUBLISHER_ID)],
+                JoinArgs::new(JoinType::Inner),
+            )
+            // TODO: consider case when many countries
+            .explode([col(COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST)])
+            .join(
+                countries.lazy(),
+                [col(COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST)],
+                [col(COL::COUNTRY_ID)],
+                JoinArgs::new(JoinType::Inner),
+            )
+            // The join key from `countries` is coalesced into the left column, so restore it
+            .with_column(col(COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST).alias(COL::COUNTRY_ID));
+        // The join keys of the right tables are dropped by the joins
+        let schema = df.schema().map_err(MetadataError::Join)?;
+        df = df.select(
+            column_order
+                .iter()
+                .filter(|name| schema.contains(name))
+                .map(|name| col(name))
+                .collect_vec(),
+        );
+
+        // Debug print the column names so that we know what we can access
+        let schema = df.schema().map_err(MetadataError::Join)?;
+        let column_names = schema
+            .iter_names()
+            .map(|s| s.as_str())
+            .collect::<Vec<&str>>();
+        debug!("Column names in merged metadata: {:?}", column_names);
+
+        Ok(ExpandedMetadata(df))
+    }
+
+    /// Write the whole of `combined_metric_source_geometry` to a single file at `path`, e.g. for
+    /// building documentation of the catalogue. The joined frame is sunk to disk with the
+    /// streaming engine rather than collected, so large catalogues are not held in memory at
+    /// once. CSV cannot hold list columns, so these are written as `;` separated strings.
+    pub fn export_catalogue<P: AsRef<Path>>(&self, path: P, format: CatalogueFormat) -> Result<()> {
+        let path = path.as_ref();
+        let mut df = self.combined_metric_source_geometry()?.as_df();
+        let result = match format {
+            CatalogueFormat::Parquet => df.with_streaming(true).sink_parquet(
+                path,
+                ParquetWriteOptions {
+                    compression: ParquetCompression::Zstd(None),
+                    ..Default::default()
+                },
+            ),
+            CatalogueFormat::Csv => {
+                let schema = df.schema()?;
+                let columns = schema
+                    .iter()
+                    .map(|(name, dtype)| match dtype {
+                        DataType::List(_) => col(name)
+                            .cast(DataType::List(Box::new(DataType::String)))
+                            .list()
+                            .join(lit(";"), true),
+                        _ => col(name),
+                    })
+                    .collect_vec();
+                df.select(columns)
+                    .with_streaming(true)
+                    .sink_csv(path, CsvWriterOptions::default())
+            }
+        };
+        result.with_context(|| format!("Failed to export catalogue to {}", path.display()))
+    }
+}
+
+impl CountryMetadataLoader {
+    /// Create a metadata loader for a specific Country
+    pub fn new(country: &str) -> Self {
+        Self {
+            country: country.into(),
+        }
+    }
+
+    /// Load the Metadata catalouge for this country with
+    /// the specified metadata paths
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                country = %self.country,
+                rows = tracing::field::Empty,
+                elapsed_ms = tracing::field::Empty,
+            ),
+        )
+    )]
+    pub async fn load(self, config: &Config) -> Result<Metadata, MetadataError> {
+        let _timer = SpanTimer::start();
+        let paths = config.metadata_paths_for(&self.country);
+        let t = try_join!(
+            self.load_file(&paths.metrics, PATHS::METRIC_METADATA, config),
+            self.load_file(&paths.geometries, PATHS::GEOMETRY_METADATA, config),
+            self.load_file(&paths.source_data_releases, PATHS::SOURCE, config),
+            self.load_file(&paths.data_publishers, PATHS::PUBLISHER, config),
+            self.load_file(&paths.countries, PATHS::COUNTRY, config),
+        )?;
+        record_rows(t.0.height());
+        Ok(Metadata {
+            metrics: t.0,
+            geometries: t.1,
+            source_data_releases: t.2,
+            data_publishers: t.3,
+            countries: t.4,
+        })
+    }
+
+    /// Load only the country metadata of the country, e.g. to match it against a search before
+    /// loading the rest of its metadata
+    pub(crate) async fn load_country_table(
+        &self,
+        config: &Config,
+    ) -> Result<DataFrame, MetadataError> {
+        let path = config.metadata_paths_for(&self.country).countries;
+        self.load_file(&path, PATHS::COUNTRY, config).await
+    }
+
+    /// Load the metadata file `path`, whose default name is `default`. If the name was overridden
+    /// in `Config::metadata_paths`, a failure names the override so that a misconfigured or
+    /// missing file is easy to identify.
+    async fn load_file(
+        &self,
+        path: &str,
+        default: &str,
+        config: &Config,
+    ) -> Result<DataFrame, MetadataError> {
+        match self.load_metadata(path, config).await {
+            Err(err) if path != default && !matches!(err, MetadataError::Cancelled) => {
+                Err(MetadataError::OverriddenFile {
+                    country: self.country.clone(),
+                    file: path.to_string(),
+                    source: Box::new(err),
+                })
+            }
+            result => result,
+        }
+    }
+
+    /// Performs a load of a given metadata parquet file, retrying on transient errors. Each attempt
+    /// is abandoned after `Config::load_timeout`, if set, so a stalled download or scan cannot
+    /// hang the load.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                country = %self.country,
+                path = %path,
+                rows = tracing::field::Empty,
+                elapsed_ms = tracing::field::Empty,
+            ),
+        )
+    )]
+    pub(crate) async fn load_metadata(
+        &self,
+        path: &str,
+        config: &Config,
+    ) -> Result<DataFrame, MetadataError> {
+        let _timer = SpanTimer::start();
+        let full_path = join_path(&config.base_path, &format!("{}/{path}", self.country));
+        config.progress.started(&full_path);
+        let result = cancellable(
+            config,
+            with_retry(&config.retry, || async {
+                let load = self.load_metadata_once(path, config);
+                match config.load_timeout() {
+                    Some(timeout) => tokio::time::timeout(timeout, load).await.map_err(|_| {
+                        MetadataError::Timeout {
+                            path: full_path.clone(),
+                            timeout,
+                        }
+                    })?,
+                    None => load.await,
+                }
+            }),
+        )
+        .await;
+        config.progress.finished(&full_path, &result);
+        if let Ok(df) = &result {
+            record_rows(df.height());
+        }
+        result
+    }
+
+    /// Performs a single attempt at loading a given metadata parquet file
+    async fn load_metadata_once(
+        &self,
+        path: &str,
+        config: &Config,
+    ) -> Result<DataFrame, MetadataError> {
+        let full_path = join_path(&config.base_path, &format!("{}/{path}", self.country));
+        info!("Attempting to load dataframe from {full_path}");
+        if config.fetch_with_client(&full_path) {
+            return fetch_parquet(full_path, config).await;
+        }
+        let args = ScanArgsParquet::default();
+        tokio::task::spawn_blocking(move || {
+            LazyFrame::scan_parquet(scan_path(&full_path), args)
+                .and_then(|df| df.collect())
+                .map_err(|source| MetadataError::ParquetScan {
+                    path: full_path,
+                    source,
+                })
+        })
+        .await?
+    }
+}
+
+/// The path to scan for the metadata file at `path`. A local directory is a partitioned dataset
+/// whose parquet parts are scanned and unioned, so a metadata file can be split into parts without
+/// callers knowing. Other paths are scanned as given and may also be globs, e.g.
+/// `metric_metadata/*.parquet`. Partitioned datasets are only supported locally: remote
+/// directories cannot be listed, and remote globs are rejected when downloading with the client
+/// (see `fetch_parquet`).
+fn scan_path(path: &str) -> String {
+    if Path::new(path).is_dir() {
+        join_path(path, "*.parquet")
+    } else {
+        path.to_string()
+    }
+}
+
+/// Runs `future` until it completes or `Config::cancellation` is cancelled, in which case the
+/// future is dropped along with any requests it has in flight. Blocking scans of local files
+/// cannot be interrupted, so one already running completes in the background (see
+/// `Cancellation`).
+async fn cancellable<T>(
+    config: &Config,
+    future: impl Future<Output = Result<T, MetadataError>>,
+) -> Result<T, MetadataError> {
+    tokio::select! {
+        biased;
+        () = config.cancellation.cancelled() => Err(MetadataError::Cancelled),
+        result = future => result,
+    }
+}
+
+/// Runs `f`, retrying with exponential backoff according to the `RetryConfig` while it fails
+/// with a transient error.
+async fn with_retry<T, F, Fut>(retry: &RetryConfig, mut f: F) -> Result<T, MetadataError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, MetadataError>>,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Err(err) if err.is_transient() && attempt < retry.max_attempts => {
+                let delay = retry.delay(attempt);
+                warn!(
+                    "Attempt {attempt} of {} failed, retrying in {delay:?}: {err}",
+                    retry.max_attempts
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Download a remote parquet file with the configured credentials and read it from memory.
+/// Polars' HTTP scans cannot attach credentials, so this is used instead when
+/// `Config::fetch_with_client`. Only single files can be downloaded, so returns
+/// `MetadataError::RemoteGlob` if `path` is a glob.
+pub(crate) async fn fetch_parquet(
+    path: String,
+    config: &Config,
+) -> Result<DataFrame, MetadataError> {
+    if path.contains(['*', '?', '[']) {
+        return Err(MetadataError::RemoteGlob { path });
+    }
+    let bytes = async {
+        let response = config.get(&path).send().await?.error_for_status()?;
+        config.progress.read_body(&path, response).await
+    }
+    .await
+    .map_err(|source| MetadataError::ParquetFetch {
+        path: path.clone(),
+        source: redact(source),
+    })?;
+    tokio::task::spawn_blocking(move || {
+        ParquetReader::new(Cursor::new(bytes))
+            .finish()
+            .map_err(|source| MetadataError::ParquetScan { path, source })
+    })
+    .await?
+}
+
+/// Join `path` to the URL or local directory `base`, with exactly one `/` between them
+/// regardless of whether `base` ends or `path` starts with one.
+pub fn join_path(base: &str, path: &str) -> String {
+    let path = path.trim_start_matches('/');
+    match base.trim_end_matches('/') {
+        "" if base.starts_with('/') => format!("/{path}"),
+        "" => path.to_string(),
+        base => format!("{base}/{path}"),
+    }
+}
+
+/// The column holding the IDs of the geometries in the files of each geometry in `df`, keyed by
+/// `geometry_filepath_stem`. This is `geometry_geo_id_column` where it is given, which not all
+/// metadata releases have, and `GEO_ID` otherwise.
+pub(crate) fn geo_id_columns(df: &DataFrame) -> Result<BTreeMap<String, String>> {
+    let stems = df.column(COL::GEOMETRY_FILEPATH_STEM)?.str()?;
+    let columns = match df.column(COL::GEOMETRY_GEO_ID_COLUMN) {
+        Ok(columns) => columns.cast(&DataType::String)?,
+        Err(_) => Series::full_null(COL::GEOMETRY_GEO_ID_COLUMN, df.height(), &DataType::String),
+    };
+    Ok(stems
+        .into_iter()
+        .zip(columns.str()?)
+        .filter_map(|(stem, column)| {
+            let column = column
+                .filter(|column| !column.is_empty())
+                .unwrap_or(COL::GEO_ID);
+            Some((stem?.to_string(), column.to_string()))
+        })
+        .collect())
+}
+
+/// The schema version of a catalogue, `<major>.<minor>` as in the versions of popgetter and the
+/// release directories of the catalogue (e.g. `releases/v0.2`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SchemaVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl Display for SchemaVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The schema version of the catalogue that the column names in `COL` correspond to, that of the
+/// catalogue at the default base path
+pub const SUPPORTED_SCHEMA_VERSION: SchemaVersion = SchemaVersion { major: 0, minor: 2 };
+
+/// Fetch the schema version of the catalogue from `version.txt` under the base path, which is
+/// read directly if the base path is local. Catalogues published before the version file was
+/// introduced have no version, so a missing file gives `None`.
+pub(crate) async fn get_schema_version(
+    config: &Config,
+) -> Result<Option<SchemaVersion>, MetadataError> {
+    let url = join_path(&config.base_path, "version.txt");
+    if !is_remote(&url) {
+        return match tokio::fs::read_to_string(&url).await {
+            Ok(text) => parse_schema_version(&text).map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(source) => Err(MetadataError::LocalRead { path: url, source }),
+        };
+    }
+    let text = with_retry(&config.retry, || async {
+        async {
+            let response = config.get(&url).send().await?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            Ok(Some(response.error_for_status()?.text().await?))
+        }
+        .await
+        .map_err(|source| MetadataError::VersionFetch {
+            url: url.clone(),
+            source: redact(source),
+        })
+    })
+    .await?;
+    text.map(|text| parse_schema_version(&text)).transpose()
+}
+
+/// Parse a schema version such as `0.2`, `v0.2` or `0.2.1`, of which only the major and minor
+/// versions are significant. A missing minor version is taken to be `0`.
+fn parse_schema_version(text: &str) -> Result<SchemaVersion, MetadataError> {
+    let version = text.trim();
+    let mut parts = version.strip_prefix('v').unwrap_or(version).split('.');
+    let mut part = || parts.next().map(str::parse::<u32>);
+    match (part(), part()) {
+        (Some(Ok(major)), None) => Ok(SchemaVersion { major, minor: 0 }),
+        (Some(Ok(major)), Some(Ok(minor))) => Ok(SchemaVersion { major, minor }),
+        _ => Err(MetadataError::InvalidVersion {
+            version: version.to_string(),
+        }),
+    }
+}
+
+/// Check whether a catalogue with schema version `version` matches the version supported by this
+/// release of popgetter. Catalogues with a different version may still be readable, so the
+/// mismatch is only warned about, and any columns that are actually missing are reported when
+/// the metadata is validated (see `Metadata::validate_schema`).
+pub(crate) fn check_schema_version(version: SchemaVersion) {
+    match version.cmp(&SUPPORTED_SCHEMA_VERSION) {
+        std::cmp::Ordering::Equal => {}
+        std::cmp::Ordering::Less => warn!(
+            "The catalogue has schema version {version}, which is older than version \
+             {SUPPORTED_SCHEMA_VERSION} supported by this release of popgetter. Attempting to \
+             load it anyway, point `base_path` at a newer catalogue or use an older release of \
+             popgetter if this fails."
+        ),
+        std::cmp::Ordering::Greater => warn!(
+            "The catalogue has schema version {version}, which is newer than version \
+             {SUPPORTED_SCHEMA_VERSION} supported by this release of popgetter. Attempting to \
+             load it anyway, consider upgrading popgetter if this fails."
+        ),
+    }
+}
+
+/// Whether `path` is fetched over HTTP rather than read from the local filesystem
+fn is_remote(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// The names of the countries in the catalogue, from `countries.txt` under the base path, which
+/// is read directly if the base path is local
+pub(crate) async fn get_country_names(config: &Config) -> Result<Vec<String>, MetadataError> {
+    let url = join_path(&config.base_path, "countries.txt");
+    if !is_remote(&url) {
+        let text = tokio::fs::read_to_string(&url)
+            .await
+            .map_err(|source| MetadataError::LocalRead { path: url, source })?;
+        return Ok(text.lines().map(|s| s.to_string()).collect());
+    }
+    let text = with_retry(&config.retry, || async {
+        async {
+            config
+                .get(&url)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }
+        .await
+        .map_err(|source| MetadataError::CountryListFetch {
+            url: url.clone(),
+            source: redact(source),
+        })
+    })
+    .await?;
+    Ok(text.lines().map(|s| s.to_string()).collect())
+}
+
+/// Load the metadata for all countries, or only those in `Config::countries` if given, and merge
+/// them into a single `Metadata` catalogue. Returns `MetadataError::Cancelled` promptly if
+/// `Config::cancellation` is cancelled while loading.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        skip_all,
+        fields(
+            base_path = %config.base_path,
+            rows = tracing::field::Empty,
+            elapsed_ms = tracing::field::Empty,
+        ),
+    )
+)]
+pub async fn load_all(config: &Config) -> Result<Metadata, MetadataError> {
+    let _timer = SpanTimer::start();
+    let result = cancellable(config, async {
+        match get_schema_version(config).await? {
+            Some(version) => check_schema_version(version),
+            None => {
+                debug!("No catalogue version found, assuming version {SUPPORTED_SCHEMA_VERSION}")
+            }
+        }
+        if let Some(countries) = config.countries.as_ref() {
+            let metadata = load_countries(config, countries).await?;
+            metadata.validate_schema()?;
+            return Ok(metadata);
+        }
+        let country_names = get_country_names(config).await?;
+
+        info!("Detected country names: {:?}", country_names);
+        let metadata = merge_countries(config, &country_names).await?;
+        metadata.validate_schema()?;
+        Ok(metadata)
+    })
+    .await;
+    if let Ok(metadata) = &result {
+        record_rows(metadata.metrics.height());
+    }
+    result
+}
+
+/// Load the metadata for the given list of country ISO3 codes and merge them into a single
+/// `Metadata` catalogue. Returns an error naming the first code not present in the list of
+/// available countries.
+pub async fn load_countries<S: AsRef<str>>(
+    config: &Config,
+    countries: &[S],
+) -> Result<Metadata, MetadataError> {
+    let available = get_country_names(config).await?;
+    let country_names = countries
+        .iter()
+        .map(|country| {
+            available
+                .iter()
+                .find(|name| name.eq_ignore_ascii_case(country.as_ref()))
+                .cloned()
+                .ok_or_else(|| MetadataError::UnknownCountry {
+                    country: country.as_ref().to_string(),
+                    available: available.clone(),
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    info!("Loading country names: {:?}", country_names);
+    merge_countries(config, &country_names).await
+}
+
+/// Load the metadata of each of `countries`, returning the result for each in order. At most
+/// `Config::max_concurrent_countries` countries are loaded at once, bounding the number of files
+/// fetched concurrently, while the files of each country are loaded concurrently.
+pub(crate) async fn load_each_country<S: AsRef<str>>(
+    config: &Config,
+    countries: &[S],
+) -> Vec<Result<Metadata, MetadataError>> {
+    stream::iter(countries)
+        .map(|country| CountryMetadataLoader::new(country.as_ref()).load(config))
+        .buffered(config.max_concurrent_countries.max(1))
+        .collect()
+        .await
+}
+
+/// Load the metadata for each of the given countries and merge them
+async fn merge_countries(
+    config: &Config,
+    country_names: &[String],
+) -> Result<Metadata, MetadataError> {
+    let metadata: Result<Vec<Metadata>, MetadataError> = load_each_country(config, country_names)
+        .await
+        .into_iter()
+        .collect();
+    let metadata = metadata?;
+    merge_metadata(
+        &country_names
+            .iter()
+            .map(String::as_str)
+            .zip(&metadata)
+            .collect_vec(),
+        config.relaxed_merge,
+    )
+}
+
+/// Merge the metadata of several countries, given as `(country, metadata)` pairs, into a single
+/// `Metadata` catalogue. See `concat_tables` for how differences in the columns of each country
+/// are handled.
+pub(crate) fn merge_metadata(
+    metadata: &[(&str, &Metadata)],
+    relaxed: bool,
+) -> Result<Metadata, MetadataError> {
+    let merge = |table: &str, get: fn(&Metadata) -> &DataFrame| {
+        let tables = metadata
+            .iter()
+            .map(|(country, metadata)| (*country, get(metadata)))
+            .collect_vec();
+        let merged = concat_tables(table, &tables, relaxed)?;
+        info!("Merged {table} with shape: {:?}", merged.shape());
+        Ok::<_, MetadataError>(merged)
+    };
+    Ok(Metadata {
+        metrics: merge("metrics", |m| &m.metrics)?,
+        geometries: merge("geometries", |m| &m.geometries)?,
+        source_data_releases: merge("source_data_releases", |m| &m.source_data_releases)?,
+        data_publishers: merge("data_publishers", |m| &m.data_publishers)?,
+        countries: merge("countries", |m| &m.countries)?,
+    })
+}
+
+/// Concatenate the `table` metadata of several countries, given as `(country, table)` pairs.
+/// Unless `relaxed`, every country must have the same columns as the first, and an error naming
+/// the first country that differs and its missing and extra columns is returned otherwise. If
+/// `relaxed`, columns missing from some countries are filled with nulls.
+fn concat_tables(
+    table: &str,
+    tables: &[(&str, &DataFrame)],
+    relaxed: bool,
+) -> Result<DataFrame, MetadataError> {
+    let mut columns: Vec<(String, DataType)> = vec![];
+    if let Some((expected_country, expected)) = tables.first() {
+        columns = expected
+            .get_columns()
+            .iter()
+            .map(|series| (series.name().to_string(), series.dtype().clone()))
+            .collect();
+        for (country, df) in &tables[1..] {
+            let extra = df
+                .get_columns()
+                .iter()
+                .filter(|series| expected.get_column_index(series.name()).is_none())
+                .collect_vec();
+            let missing = columns
+                .iter()
+                .filter(|(name, _)| df.get_column_index(name).is_none())
+                .map(|(name, _)| name.clone())
+                .collect_vec();
+            if relaxed {
+                for series in extra {
+                    if !columns.iter().any(|(name, _)| name == series.name()) {
+                        columns.push((series.name().to_string(), series.dtype().clone()));
+                    }
+                }
+            } else if !extra.is_empty() || !missing.is_empty() {
+                return Err(MetadataError::SchemaMismatch {
+                    table: table.to_string(),
+                    country: country.to_string(),
+                    expected_country: expected_country.to_string(),
+                    missing,
+                    extra: extra
+                        .iter()
+                        .map(|series| series.name().to_string())
+                        .collect(),
+                });
+            }
+        }
+    }
+    // Select the columns in the same order for every country, filling any that are missing
+    let frames = tables
+        .iter()
+        .map(|&(_, df)| {
+            df.clone().lazy().select(
+                columns
+                    .iter()
+                    .map(|(name, dtype)| {
+                        if df.get_column_index(name).is_some() {
+                            col(name)
+                        } else {
+                            lit(Null {}).cast(dtype.clone()).alias(name)
+                        }
+                    })
+                    .collect_vec(),
+            )
+        })
+        .collect_vec();
+    polars::prelude::concat(frames, UnionArgs::default())
+        .and_then(|df| df.collect())
+        .map_err(MetadataError::Merge)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use crate::config::{AuthConfig, Cancellation};
+    use crate::progress::{ProgressCallback, ProgressEvent};
+    use crate::search::{Country, SearchParams};
+    use chrono::NaiveDate;
+    use httpmock::prelude::*;
+    use polars::{df, prelude::CsvReadOptions};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::test_util::{flaky_server, silent_server};
+    /// TODO stub out a mock here that we can use to test with.
+
+    #[tokio::test]
+    async fn country_metadata_should_load() {
+        let config = Config::default();
+        let metadata = CountryMetadataLoader::new("bel").load(&config).await;
+        println!("{metadata:#?}");
+        assert!(metadata.is_ok(), "Data should have loaded ok");
+    }
+
+    #[tokio::test]
+    async fn all_metadata_should_load() {
+        let config = Config::default();
+        let metadata = load_all(&config).await;
+        println!("{metadata:#?}");
+        assert!(metadata.is_ok(), "Data should have loaded ok");
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn metric_id(id: &str, match_type: MatchType) -> MetricId {
+        MetricId {
+            id: id.to_string(),
+            config: SearchConfig {
+                match_type,
+                case_sensitivity: CaseSensitivity::Insensitive,
+            },
+        }
+    }
+
+    fn exact_metric_id(id: &str) -> MetricId {
+        metric_id(id, MatchType::Exact)
+    }
+
+    #[tokio::test]
+    async fn select_years_should_drop_non_matching_rows() {
+        let config = Config::default();
+        let metadata = CountryMetadataLoader::new("bel")
+            .load(&config)
+            .await
+            .unwrap();
+        let expanded_metadata = metadata.combined_metric_source_geometry().unwrap();
+        let all_rows = expanded_metadata.as_df().collect().unwrap().height();
+        let filtered_rows = expanded_metadata
+            .select_years(&["1900"])
+            .unwrap()
+            .as_df()
+            .collect()
+            .unwrap()
+            .height();
+        assert!(all_rows > 0, "Belgium metadata should not be empty");
+        assert!(
+            filtered_rows < all_rows,
+            "Filtering by year should drop rows outside of the reference period"
+        );
+    }
+
+    #[test]
+    fn select_years_should_match_multi_year_reference_periods() {
+        let df = df!(
+            COL::METRIC_ID => &["a", "b", "c"],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START => &[date(2019, 1, 1), date(2021, 3, 21), date(2011, 3, 27)],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END => &[date(2023, 12, 31), date(2021, 3, 21), date(2011, 3, 27)],
+        )
+        .unwrap();
+        let expanded_metadata = ExpandedMetadata(df.lazy());
+        let filtered = expanded_metadata
+            .select_years(&["2021"])
+            .unwrap()
+            .as_df()
+            .collect()
+            .unwrap();
+        assert_eq!(
+            filtered.column(COL::METRIC_ID).unwrap(),
+            &Series::new(COL::METRIC_ID, &["a", "b"])
+        );
+        let filtered = expanded_metadata
+            .select_years(&["2011", "2022"])
+            .unwrap()
+            .as_df()
+            .collect()
+            .unwrap();
+        assert_eq!(
+            filtered.column(COL::METRIC_ID).unwrap(),
+            &Series::new(COL::METRIC_ID, &["a", "c"])
+        );
+    }
+
+    #[test]
+    fn available_years_should_be_sorted_by_count_without_duplicates() {
+        let df = df!(
+            COL::METRIC_ID => &["a", "b", "c", "d"],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START => &[date(2019, 1, 1), date(2021, 3, 21), date(2021, 3, 21), date(2011, 3, 27)],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END => &[date(2022, 12, 31), date(2021, 3, 21), date(2021, 3, 21), date(2011, 3, 27)],
+        )
+        .unwrap();
+        let years = ExpandedMetadata(df.lazy()).available_years().unwrap();
+        let counts: Vec<u32> = years
+            .column(SUMMARY_COL::COUNT)
+            .unwrap()
+            .u32()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        let years = str_values(&years, SUMMARY_COL::YEAR).unwrap();
+        assert_eq!(years.first().unwrap(), "2021");
+        assert_eq!(counts.first().unwrap(), &3);
+        assert!(
+            counts.windows(2).all(|pair| pair[0] >= pair[1]),
+            "Years should be sorted by descending count"
+        );
+        assert_eq!(
+            years.iter().unique().count(),
+            years.len(),
+            "Years should not contain duplicates"
+        );
+        assert_eq!(years.len(), 5);
+    }
+
+    #[test]
+    fn selection_plan_should_recommend_default_year() {
+        let df = df!(
+            COL::METRIC_ID => &["a", "b", "c"],
+            COL::METRIC_HXL_TAG => &["#population", "#population", "#population"],
+            COL::GEOMETRY_LEVEL => &["tract", "tract", "county"],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START => &[date(2021, 1, 1), date(2011, 1, 1), date(2021, 1, 1)],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END => &[date(2021, 12, 31), date(2011, 12, 31), date(2021, 12, 31)],
+        )
+        .unwrap();
+        let metric_ids = ["a", "b", "c"]
+            .into_iter()
+            .map(|id| MetricId {
+                id: id.to_string(),
+                config: SearchConfig {
+                    match_type: MatchType::Exact,
+                    case_sensitivity: CaseSensitivity::Insensitive,
+                },
+            })
+            .collect_vec();
+        let plan = ExpandedMetadata(df.lazy())
+            .generate_selection_plan(&metric_ids, None, None, None)
+            .unwrap();
+        assert_eq!(plan.geometry, "tract");
+        // Tied years are broken by the most recent year
+        assert_eq!(plan.year, vec!["2021"]);
+        assert_eq!(
+            plan.advice,
+            vec![
+                SelectionAdvice::AlternativeGeometries(vec!["county".to_string()]),
+                SelectionAdvice::AlternativeYears(vec!["2011".to_string()]),
+            ]
+        );
+        assert!(plan
+            .to_string()
+            .ends_with("The metrics are also available for the years: 2011"));
+        assert_eq!(plan.explicit_metric_ids.len(), 1);
+        assert_eq!(plan.explicit_metric_ids[0].id, "a");
+    }
+
+    #[test]
+    fn selection_plan_should_prefer_target_resolution() {
+        let df = df!(
+            COL::METRIC_ID => &["a", "a", "a", "a", "b"],
+            COL::GEOMETRY_LEVEL => &["country", "country", "county", "region", "tract"],
+            COL::GEOMETRY_RESOLUTION => &[Some(0u32), Some(0), Some(1), None, Some(3)],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START => &[date(2021, 1, 1); 5],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END => &[date(2021, 12, 31); 5],
+        )
+        .unwrap();
+        let expanded_metadata = ExpandedMetadata(df.lazy());
+
+        // The most common level is chosen without a target resolution
+        let plan = expanded_metadata
+            .generate_selection_plan(&[exact_metric_id("a")], None, None, None)
+            .unwrap();
+        assert_eq!(plan.geometry, "country");
+
+        // The closest level to the target resolution is chosen if given, and a level with an
+        // unknown resolution is never the closest
+        let plan = expanded_metadata
+            .generate_selection_plan(&[exact_metric_id("a")], None, None, Some(2))
+            .unwrap();
+        assert_eq!(plan.geometry, "county");
+
+        // A metric available at a single level uses that level
+        let plan = expanded_metadata
+            .generate_selection_plan(&[exact_metric_id("b")], None, None, Some(0))
+            .unwrap();
+        assert_eq!(plan.geometry, "tract");
+    }
+
+    #[test]
+    fn selection_plan_should_warn_about_geometry_validity_mismatch() {
+        let df = df!(
+            COL::METRIC_ID => &["a", "b"],
+            COL::GEOMETRY_LEVEL => &["ward", "ward"],
+            COL::GEOMETRY_VALIDITY_PERIOD_START => &[Some(date(2011, 1, 1)), Some(date(2011, 1, 1))],
+            COL::GEOMETRY_VALIDITY_PERIOD_END => &[Some(date(2020, 12, 31)), None],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START => &[date(2021, 3, 21); 2],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END => &[date(2021, 3, 21); 2],
+        )
+        .unwrap();
+        let metric_ids = ["a", "b"].map(exact_metric_id);
+        let plan = ExpandedMetadata(df.lazy())
+            .generate_selection_plan(&metric_ids, None, None, None)
+            .unwrap();
+        assert_eq!(
+            plan.advice,
+            vec![SelectionAdvice::GeometryValidityMismatch {
+                geometry: "ward".to_string(),
+                metric_ids: vec!["a".to_string()],
+            }]
+        );
+        assert_eq!(plan.explicit_metric_ids.len(), 2);
+    }
+
+    #[test]
+    fn latest_release_should_be_selected() {
+        let metadata = Metadata {
+            metrics: df!(
+                COL::METRIC_ID => &["pop_2011", "pop_2021", "pop_2021_rev", "households"],
+                COL::METRIC_SOURCE_DATA_RELEASE_ID => &["census_2011", "census_2021", "census_2021_rev", "census_2021"],
+            )
+            .unwrap(),
+            source_data_releases: df!(
+                COL::SOURCE_DATA_RELEASE_ID => &["census_2011", "census_2021", "census_2021_rev"],
+                COL::SOURCE_DATA_RELEASE_NAME => &["Census 2011", "Census 2021", "Census 2021 (revised)"],
+                COL::SOURCE_DATA_RELEASE_DATE_PUBLISHED => &[date(2012, 12, 11), date(2022, 6, 28), date(2022, 6, 28)],
+            )
+            .unwrap(),
+            geometries: DataFrame::default(),
+            data_publishers: DataFrame::default(),
+            countries: DataFrame::default(),
+        };
+        let latest = metadata
+            .latest_release(&metric_id("^pop_20(11|21)$", MatchType::Regex))
+            .unwrap();
+        assert_eq!(
+            latest,
+            SourceDataRelease {
+                id: "census_2021".to_string(),
+                name: "Census 2021".to_string(),
+                date_published: Some("2022-06-28".to_string()),
+            }
+        );
+        // Releases published on the same date are broken by ID
+        let latest = metadata
+            .latest_release(&metric_id("pop_", MatchType::Startswith))
+            .unwrap();
+        assert_eq!(latest.id, "census_2021");
+        assert!(metadata
+            .latest_release(&metric_id("income", MatchType::Exact))
+            .is_err());
+    }
+
+    #[test]
+    fn selection_plan_should_prefer_latest_release() {
+        let df = df!(
+            COL::METRIC_ID => &["pop_2011", "pop_2021"],
+            COL::GEOMETRY_LEVEL => &["ward", "ward"],
+            COL::METRIC_SOURCE_DATA_RELEASE_ID => &["census_2011", "census_2021"],
+            COL::SOURCE_DATA_RELEASE_NAME => &["Census 2011", "Census 2021"],
+            COL::SOURCE_DATA_RELEASE_DATE_PUBLISHED => &[date(2012, 12, 11), date(2022, 6, 28)],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START => &[date(2011, 3, 27), date(2021, 3, 21)],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END => &[date(2011, 3, 27), date(2021, 3, 21)],
+        )
+        .unwrap();
+        let metric_id = MetricId {
+            id: "pop_".to_string(),
+            config: SearchConfig {
+                match_type: MatchType::Startswith,
+                case_sensitivity: CaseSensitivity::Insensitive,
+            },
+        };
+        let plan = ExpandedMetadata(df.lazy())
+            .generate_selection_plan(&[metric_id], None, None, None)
+            .unwrap();
+        assert_eq!(plan.year, vec!["2021"]);
+        assert_eq!(plan.explicit_metric_ids.len(), 1);
+        assert_eq!(plan.explicit_metric_ids[0].id, "pop_2021");
+        assert_eq!(
+            plan.advice,
+            vec![SelectionAdvice::LatestRelease {
+                metric_id: "pop_".to_string(),
+                release: "Census 2021".to_string(),
+                alternatives: vec!["Census 2011".to_string()],
+            }]
+        );
+    }
+
+    /// Serves `countries.txt`, responding with `503 Service Unavailable` to the first `failures`
+    /// requests. Returns the base URL and a counter of the requests received.
+    async fn flaky_country_server(failures: usize) -> (String, Arc<AtomicUsize>) {
+        flaky_server(failures, "bel\nusa\n").await
+    }
+
+    fn test_retry_config() -> RetryConfig {
+        RetryConfig {
+            max_attempts: 3,
+            base_delay_ms: 10,
+            jitter_ms: 0,
+        }
+    }
+
+    #[test]
+    fn join_path_should_not_double_slashes() {
+        for base in ["https://example.com/v0.2", "https://example.com/v0.2/"] {
+            assert_eq!(
+                join_path(base, "bel/metrics.parquet"),
+                "https://example.com/v0.2/bel/metrics.parquet"
+            );
+            assert_eq!(
+                join_path(base, "/countries.txt"),
+                "https://example.com/v0.2/countries.txt"
+            );
+        }
+        for base in ["/data/popgetter", "/data/popgetter//"] {
+            assert_eq!(
+                join_path(base, "bel/metrics.parquet"),
+                "/data/popgetter/bel/metrics.parquet"
+            );
+        }
+        assert_eq!(join_path("data", "countries.txt"), "data/countries.txt");
+        assert_eq!(join_path("/", "countries.txt"), "/countries.txt");
+        assert_eq!(join_path("", "countries.txt"), "countries.txt");
+    }
+
+    #[test]
+    fn schema_versions_should_be_parsed() {
+        let version = |major, minor| SchemaVersion { major, minor };
+        assert_eq!(parse_schema_version("0.2\n").unwrap(), version(0, 2));
+        assert_eq!(parse_schema_version("v0.2").unwrap(), version(0, 2));
+        assert_eq!(parse_schema_version("0.2.1").unwrap(), version(0, 2));
+        assert_eq!(parse_schema_version("1").unwrap(), version(1, 0));
+        assert_eq!(SUPPORTED_SCHEMA_VERSION.to_string(), "0.2");
+        assert!(version(0, 10) > version(0, 2));
+        for invalid in ["two", "0.x", ""] {
+            assert!(matches!(
+                parse_schema_version(invalid),
+                Err(MetadataError::InvalidVersion { .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn schema_version_should_be_read_from_version_file() {
+        let server = MockServer::start();
+        let config = Config {
+            base_path: server.base_url(),
+            retry: test_retry_config(),
+            ..Config::default()
+        };
+        // Catalogues without a version file are assumed to be compatible
+        let mut missing = server.mock(|when, then| {
+            when.method(GET).path("/version.txt");
+            then.status(404);
+        });
+        assert_eq!(get_schema_version(&config).await.unwrap(), None);
+        missing.delete();
+        server.mock(|when, then| {
+            when.method(GET).path("/countries.txt");
+            then.status(404);
+        });
+
+        // Matching, older and newer versions are all loaded, and only the country list is missing
+        for version in ["0.2", "0.1", "0.3"] {
+            let mut file = server.mock(|when, then| {
+                when.method(GET).path("/version.txt");
+                then.status(200).body(format!("{version}\n"));
+            });
+            assert_eq!(
+                get_schema_version(&config).await.unwrap(),
+                Some(parse_schema_version(version).unwrap())
+            );
+            assert!(matches!(
+                load_all(&config).await,
+                Err(MetadataError::CountryListFetch { .. })
+            ));
+            file.delete();
+        }
+    }
+
+    #[tokio::test]
+    async fn catalogue_files_should_be_read_from_local_base_path() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let config = Config {
+            base_path: tmp.path().to_string_lossy().to_string(),
+            countries: Some(vec!["bel".into()]),
+            ..Config::default()
+        };
+        assert_eq!(get_schema_version(&config).await.unwrap(), None);
+
+        std::fs::write(tmp.path().join("version.txt"), "0.2\n").unwrap();
+        std::fs::write(tmp.path().join("countries.txt"), "bel\n").unwrap();
+        assert_eq!(
+            get_schema_version(&config).await.unwrap(),
+            Some(SUPPORTED_SCHEMA_VERSION)
+        );
+        // The version and country list are read without any HTTP requests, so the load only
+        // fails on the missing metadata of the country
+        let result = load_all(&config).await;
+        assert!(
+            matches!(result, Err(MetadataError::ParquetScan { .. })),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelling_load_should_return_promptly() {
+        let token = CancellationToken::new();
+        let config = Config {
+            base_path: silent_server().await,
+            cancellation: Cancellation::new(token.clone()),
+            ..Config::default()
+        };
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            token.cancel();
+        });
+        let start = std::time::Instant::now();
+        let result = load_all(&config).await;
+        assert!(matches!(result, Err(MetadataError::Cancelled)));
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn country_names_should_be_retried_on_transient_errors() {
+        let (base_path, requests) = flaky_country_server(2).await;
+        let config = Config {
+            base_path,
+            retry: test_retry_config(),
+            ..Config::default()
+        };
+        let country_names = get_country_names(&config).await.unwrap();
+        assert_eq!(country_names, vec!["bel", "usa"]);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn country_names_should_fail_after_max_attempts() {
+        let (base_path, requests) = flaky_country_server(5).await;
+        let config = Config {
+            base_path,
+            retry: test_retry_config(),
+            ..Config::default()
+        };
+        let result = get_country_names(&config).await;
+        assert!(result.unwrap_err().is_transient());
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn country_names_should_not_be_retried_on_not_found() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/countries.txt");
+            then.status(404);
+        });
+        let config = Config {
+            base_path: server.base_url(),
+            retry: test_retry_config(),
+            ..Config::default()
+        };
+        let result = get_country_names(&config).await;
+        assert!(matches!(
+            result,
+            Err(MetadataError::CountryListFetch { .. })
+        ));
+        mock.assert_hits(1);
+    }
+
+    #[tokio::test]
+    async fn unresponsive_server_should_time_out() {
+        let config = Config {
+            base_path: silent_server().await,
+            retry: RetryConfig {
+                max_attempts: 1,
+                ..test_retry_config()
+            },
+            http_timeout_ms: Some(200),
+            ..Config::default()
+        };
+        // The catalogue version is the first request made when loading
+        let start = std::time::Instant::now();
+        match load_all(&config).await {
+            Err(MetadataError::VersionFetch { source, .. }) => assert!(source.is_timeout()),
+            other => panic!("Loading from an unresponsive server should time out: {other:?}"),
+        }
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+        let start = std::time::Instant::now();
+        match get_country_names(&config).await {
+            Err(MetadataError::CountryListFetch { source, .. }) => assert!(source.is_timeout()),
+            other => panic!("Fetching the country list should time out: {other:?}"),
+        }
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stalled_scan_should_time_out() {
+        // Without credentials the metadata is scanned by polars, which the client's timeouts do
+        // not cover
+        let config = Config {
+            base_path: silent_server().await,
+            retry: RetryConfig {
+                max_attempts: 1,
+                ..test_retry_config()
+            },
+            load_timeout_ms: Some(200),
+            ..Config::default()
+        };
+        assert_eq!(config.http_timeout(), None);
+        assert!(!config.fetch_with_client(&join_path(&config.base_path, "bel")));
+        let start = std::time::Instant::now();
+        let result = CountryMetadataLoader::new("bel")
+            .load_metadata(PATHS::METRIC_METADATA, &config)
+            .await;
+        assert!(
+            matches!(result, Err(MetadataError::Timeout { .. })),
+            "A stalled scan should time out: {result:?}"
+        );
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn slow_download_should_not_time_out_while_bytes_arrive() {
+        // Sends the country list a byte at a time, taking longer in total than the read timeout
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_path = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 1024];
+            let _ = stream.read(&mut buffer).await.unwrap();
+            let body = "bel\nusa\n";
+            let head = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            for byte in body.bytes() {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                stream.write_all(&[byte]).await.unwrap();
+            }
+        });
+        let config = Config {
+            base_path,
+            retry: RetryConfig {
+                max_attempts: 1,
+                ..test_retry_config()
+            },
+            read_timeout_ms: Some(400),
+            ..Config::default()
+        };
+        assert_eq!(config.http_timeout(), None);
+        let start = std::time::Instant::now();
+        assert_eq!(get_country_names(&config).await.unwrap(), ["bel", "usa"]);
+        assert!(start.elapsed() > std::time::Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn progress_should_be_reported_for_each_metadata_file() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let country_dir = tmp.path().join("bel");
+        std::fs::create_dir(&country_dir).unwrap();
+        let paths = [
+            PATHS::METRIC_METADATA,
+            PATHS::GEOMETRY_METADATA,
+            PATHS::SOURCE,
+            PATHS::PUBLISHER,
+            PATHS::COUNTRY,
+        ];
+        for path in paths {
+            let mut df = df!(COL::COUNTRY_ID => &["bel"]).unwrap();
+            let file = std::fs::File::create(country_dir.join(path)).unwrap();
+            ParquetWriter::new(file).finish(&mut df).unwrap();
+        }
+        let started = Arc::new(AtomicUsize::new(0));
+        let finished = Arc::new(AtomicUsize::new(0));
+        let (started_count, finished_count) = (started.clone(), finished.clone());
+        let config = Config {
+            base_path: tmp.path().to_string_lossy().to_string(),
+            progress: ProgressCallback::new(move |event| match event {
+                ProgressEvent::Started { .. } => {
+                    started_count.fetch_add(1, Ordering::SeqCst);
+                }
+                ProgressEvent::Finished { success, .. } => {
+                    assert!(success);
+                    finished_count.fetch_add(1, Ordering::SeqCst);
+                }
+                ProgressEvent::Transferred { .. } => {}
+            }),
+            ..Config::default()
+        };
+
+        CountryMetadataLoader::new("bel")
+            .load(&config)
+            .await
+            .unwrap();
+
+        assert_eq!(started.load(Ordering::SeqCst), paths.len());
+        assert_eq!(finished.load(Ordering::SeqCst), paths.len());
+    }
+
+    #[tokio::test]
+    async fn overridden_metadata_paths_should_be_loaded() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let country_dir = tmp.path().join("bel");
+        std::fs::create_dir(&country_dir).unwrap();
+        let paths = CountryMetadataPaths {
+            metrics: "metrics_v2.parquet".into(),
+            countries: "country.parquet".into(),
+            ..CountryMetadataPaths::default()
+        };
+        for path in [
+            &paths.metrics,
+            &paths.geometries,
+            &paths.source_data_releases,
+            &paths.data_publishers,
+            &paths.countries,
+        ] {
+            let mut df = df!(COL::COUNTRY_ID => &["bel"]).unwrap();
+            let file = std::fs::File::create(country_dir.join(path)).unwrap();
+            ParquetWriter::new(file).finish(&mut df).unwrap();
+        }
+        let config = Config::builder()
+            .base_path(tmp.path().to_string_lossy())
+            .metadata_paths("bel", paths)
+            .build()
+            .unwrap();
+
+        let metadata = CountryMetadataLoader::new("bel")
+            .load(&config)
+            .await
+            .unwrap();
+        assert_eq!(metadata.metrics.height(), 1);
+
+        // A missing overridden file is named in the error
+        std::fs::remove_file(country_dir.join("metrics_v2.parquet")).unwrap();
+        let err = CountryMetadataLoader::new("bel")
+            .load(&config)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                MetadataError::OverriddenFile { country, file, .. }
+                    if country == "bel" && file == "metrics_v2.parquet"
+            ),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn partitioned_metadata_should_be_unioned() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let dataset_dir = tmp.path().join("bel").join(PATHS::METRIC_METADATA);
+        std::fs::create_dir_all(&dataset_dir).unwrap();
+        for (part, ids) in [
+            ("part-0.parquet", vec!["a", "b"]),
+            ("part-1.parquet", vec!["c", "d", "e"]),
+        ] {
+            let mut df = df!(COL::METRIC_ID => ids).unwrap();
+            let file = std::fs::File::create(dataset_dir.join(part)).unwrap();
+            ParquetWriter::new(file).finish(&mut df).unwrap();
+        }
+        let config = Config {
+            base_path: tmp.path().to_string_lossy().to_string(),
+            ..Config::default()
+        };
+        let loader = CountryMetadataLoader::new("bel");
+
+        // A directory of parts is loaded transparently from the usual path
+        let df = loader
+            .load_metadata(PATHS::METRIC_METADATA, &config)
+            .await
+            .unwrap();
+        assert_eq!(df.height(), 5);
+
+        // As is a glob over the parts
+        let glob = format!("{}/*.parquet", PATHS::METRIC_METADATA);
+        let df = loader.load_metadata(&glob, &config).await.unwrap();
+        assert_eq!(df.height(), 5);
+    }
+
+    #[tokio::test]
+    async fn remote_glob_should_not_be_requested_with_client() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET);
+            then.status(404);
+        });
+        let config = Config {
+            base_path: server.base_url(),
+            auth: Some(AuthConfig::Bearer {
+                token: "secret-token".into(),
+            }),
+            ..Config::default()
+        };
+        let glob = format!("{}/*.parquet", PATHS::METRIC_METADATA);
+
+        let err = CountryMetadataLoader::new("bel")
+            .load_metadata(&glob, &config)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, MetadataError::RemoteGlob { .. }), "{err}");
+        mock.assert_hits(0);
+    }
+
+    #[tokio::test]
+    async fn country_names_should_be_fetched_with_bearer_token() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/countries.txt")
+                .header("authorization", "Bearer secret-token");
+            then.status(200).body("bel\nusa\n");
+        });
+        let config = Config {
+            base_path: server.base_url(),
+            auth: Some(AuthConfig::Bearer {
+                token: "secret-token".into(),
+            }),
+            ..Config::default()
+        };
+        let countries = get_country_names(&config).await.unwrap();
+        mock.assert_hits(1);
+        assert_eq!(countries, ["bel", "usa"]);
+    }
+
+    #[tokio::test]
+    async fn metadata_should_be_fetched_with_sas_token() {
+        let mut df = df!(COL::COUNTRY_ID => &["bel"]).unwrap();
+        let mut bytes = vec![];
+        ParquetWriter::new(&mut bytes).finish(&mut df).unwrap();
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path(format!("/bel/{}", PATHS::COUNTRY))
+                .query_param("sv", "2022-11-02")
+                .query_param("sig", "abc+def");
+            then.status(200).body(&bytes);
+        });
+        let config = Config {
+            base_path: server.base_url(),
+            auth: Some(AuthConfig::SasToken {
+                token: "sv=2022-11-02&sig=abc%2Bdef".into(),
+            }),
+            ..Config::default()
+        };
+        let loaded = CountryMetadataLoader::new("bel")
+            .load_metadata(PATHS::COUNTRY, &config)
+            .await
+            .unwrap();
+        mock.assert_hits(1);
+        assert_eq!(loaded, df);
+    }
+
+    #[tokio::test]
+    async fn load_countries_should_error_on_unknown_country() {
+        let (base_path, _) = flaky_country_server(0).await;
+        let config = Config {
+            base_path,
+            retry: test_retry_config(),
+            ..Config::default()
+        };
+        let result = load_countries(&config, &["bel", "gbr"]).await;
+        match result {
+            Err(MetadataError::UnknownCountry { country, .. }) => assert_eq!(country, "gbr"),
+            _ => panic!("Loading an unknown country should error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn single_country_should_have_fewer_metrics_than_all() {
+        let config = Config {
+            countries: Some(vec!["bel".into()]),
+            ..Config::default()
+        };
+        let single = load_all(&config).await.unwrap();
+        let all = load_all(&Config::default()).await.unwrap();
+        assert!(single.metrics.height() < all.metrics.height());
+    }
+
+    fn metadata_with_required_columns() -> Metadata {
+        let tables = REQUIRED_COLUMNS
+            .iter()
+            .map(|(_, columns)| {
+                DataFrame::new(
+                    columns
+                        .iter()
+                        .map(|column| Series::new(column, &["value"]))
+                        .collect(),
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        Metadata {
+            metrics: tables[0].clone(),
+            geometries: tables[1].clone(),
+            source_data_releases: tables[2].clone(),
+            data_publishers: tables[3].clone(),
+            countries: tables[4].clone(),
+        }
+    }
+
+    #[test]
+    fn validate_schema_should_name_missing_columns() {
+        let mut metadata = metadata_with_required_columns();
+        assert!(metadata.validate_schema().is_ok());
+        metadata.metrics = metadata.metrics.drop(COL::METRIC_HXL_TAG).unwrap();
+        metadata.countries = metadata.countries.drop(COL::COUNTRY_ISO3).unwrap();
+        let err = metadata.validate_schema().unwrap_err();
+        assert!(matches!(
+            &err,
+            MetadataError::InvalidSchema { missing }
+                if missing == &["metrics.metric_hxl_tag", "countries.country_iso3"]
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Metadata is missing expected columns: metrics.metric_hxl_tag, countries.country_iso3"
+        );
+    }
+
+    /// Metadata with a metric, geometry, release and publisher in each of Belgium and Northern
+    /// Ireland
+    pub(crate) fn two_country_metadata() -> Metadata {
+        Metadata {
+            metrics: df!(
+                COL::METRIC_ID => &["bel_metric", "nir_metric"],
+                COL::METRIC_SOURCE_DATA_RELEASE_ID => &["bel_release", "nir_release"],
+            )
+            .unwrap(),
+            geometries: df!(COL::GEOMETRY_ID => &["bel_geometry", "nir_geometry"]).unwrap(),
+            source_data_releases: df!(
+                COL::SOURCE_DATA_RELEASE_ID => &["bel_release", "nir_release"],
+                COL::SOURCE_DATA_RELEASE_GEOMETRY_METADATA_ID => &["bel_geometry", "nir_geometry"],
+                COL::SOURCE_DATA_RELEASE_DATA_PUBLISHER_ID => &["statbel", "nisra"],
+            )
+            .unwrap(),
+            data_publishers: df!(
+                COL::DATA_PUBLISHER_ID => &["statbel", "nisra"],
+                COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST => &[
+                    Series::new("", &["BEL"]),
+                    Series::new("", &["NIR"]),
+                ],
+            )
+            .unwrap(),
+            countries: df!(
+                COL::COUNTRY_ID => &["BEL", "NIR"],
+                COL::COUNTRY_NAME_SHORT_EN => &["Belgium", "Northern Ireland"],
+                COL::COUNTRY_NAME_OFFICIAL => &["Kingdom of Belgium", "Northern Ireland"],
+                COL::COUNTRY_ISO2 => &["BE", "GB"],
+                COL::COUNTRY_ISO3 => &["BEL", "GBR"],
+                COL::COUNTRY_ISO3166_2 => &[None::<&str>, Some("GB-NIR")],
+            )
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn exported_catalogue_should_have_all_rows() {
+        let mut metadata = two_country_metadata();
+        // List columns cannot be written to CSV as they are
+        metadata
+            .metrics
+            .with_column(Series::new(
+                COL::METRIC_POTENTIAL_DENOMINATOR_IDS,
+                &[
+                    Series::new("", &["nir_metric"]),
+                    Series::new("", &["bel_metric"]),
+                ],
+            ))
+            .unwrap();
+        let expected = metadata
+            .combined_metric_source_geometry()
+            .unwrap()
+            .as_df()
+            .collect()
+            .unwrap();
+        let tmp = tempfile::TempDir::new().unwrap();
+
+        let parquet_path = tmp.path().join("catalogue.parquet");
+        metadata
+            .export_catalogue(&parquet_path, CatalogueFormat::Parquet)
+            .unwrap();
+        let file = std::fs::File::open(&parquet_path).unwrap();
+        let exported = ParquetReader::new(file).finish().unwrap();
+        assert_eq!(exported.height(), expected.height());
+        assert_eq!(exported.get_column_names(), expected.get_column_names());
+
+        let csv_path = tmp.path().join("catalogue.csv");
+        metadata
+            .export_catalogue(&csv_path, CatalogueFormat::Csv)
+            .unwrap();
+        let exported = CsvReadOptions::default()
+            .try_into_reader_with_file_path(Some(csv_path))
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert_eq!(exported.height(), expected.height());
+        assert_eq!(exported.get_column_names(), expected.get_column_names());
+        assert_eq!(
+            exported
+                .column(COL::METRIC_POTENTIAL_DENOMINATOR_IDS)
+                .unwrap()
+                .str()
+                .unwrap()
+                .get(0),
+            Some("nir_metric")
+        );
+    }
+
+    #[test]
+    fn combined_metadata_should_have_documented_column_order() {
+        let mut metadata = two_country_metadata();
+        metadata
+            .metrics
+            .with_column(Series::new("description", &["Population", "Households"]))
+            .unwrap();
+        // Owned by the geometries, so dropped from the metrics
+        metadata
+            .metrics
+            .with_column(Series::new(COL::GEOMETRY_LEVEL, &["municipality", "ward"]))
+            .unwrap();
+        metadata
+            .geometries
+            .with_column(Series::new("description", &["Municipalities", "Wards"]))
+            .unwrap();
+        metadata
+            .geometries
+            .with_column(Series::new(COL::GEOMETRY_LEVEL, &["municipality", "ward"]))
+            .unwrap();
+        let df = metadata
+            .combined_metric_source_geometry()
+            .unwrap()
+            .as_df()
+            .collect()
+            .unwrap();
+        assert_eq!(
+            df.get_column_names(),
+            [
+                COL::METRIC_DESCRIPTION,
+                COL::METRIC_ID,
+                COL::METRIC_SOURCE_DATA_RELEASE_ID,
+                COL::SOURCE_DATA_RELEASE_DATA_PUBLISHER_ID,
+                COL::SOURCE_DATA_RELEASE_GEOMETRY_METADATA_ID,
+                COL::GEOMETRY_DESCRIPTION,
+                COL::GEOMETRY_LEVEL,
+                COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST,
+                COL::COUNTRY_ID,
+                COL::COUNTRY_ISO2,
+                COL::COUNTRY_ISO3,
+                COL::COUNTRY_ISO3166_2,
+                COL::COUNTRY_NAME_OFFICIAL,
+                COL::COUNTRY_NAME_SHORT_EN,
+            ]
+        );
+        assert_eq!(
+            df.column(COL::GEOMETRY_DESCRIPTION)
+                .unwrap()
+                .str()
+                .unwrap()
+                .get(0),
+            Some("Municipalities")
+        );
+    }
+
+    #[test]
+    fn combined_metadata_should_have_no_right_suffixed_columns() {
+        let mut metadata = two_country_metadata();
+        for (name, values) in [
+            ("description", ["Population", "Households"]),
+            ("hxl_tag", ["#population", "#household"]),
+        ] {
+            metadata
+                .metrics
+                .with_column(Series::new(name, &values))
+                .unwrap();
+        }
+        metadata
+            .geometries
+            .with_column(Series::new("description", &["Municipalities", "Wards"]))
+            .unwrap();
+        // Unprefixed columns are renamed even when only one table has them
+        metadata
+            .countries
+            .with_column(Series::new("hxl_tag", &["#country", "#country"]))
+            .unwrap();
+        let df = metadata
+            .combined_metric_source_geometry()
+            .unwrap()
+            .as_df()
+            .collect()
+            .unwrap();
+        let names = df.get_column_names();
+        assert!(
+            names.iter().all(|name| !name.ends_with("_right")),
+            "{names:?}"
+        );
+        for name in [
+            COL::METRIC_DESCRIPTION,
+            COL::METRIC_HXL_TAG,
+            COL::GEOMETRY_DESCRIPTION,
+            "country_hxl_tag",
+        ] {
+            assert!(names.contains(&name), "{name} missing from {names:?}");
+        }
+        assert!(!names.contains(&"description") && !names.contains(&"hxl_tag"));
+    }
+
+    #[test]
+    fn country_search_should_only_return_metrics_for_that_country() {
+        let expanded_metadata = two_country_metadata()
+            .combined_metric_source_geometry()
+            .unwrap();
+        for country in ["Belgium", "BEL"] {
+            let search_params = SearchParams {
+                country: Some(Country {
+                    value: country.to_string(),
+                    config: SearchConfig {
+                        match_type: MatchType::Exact,
+                        case_sensitivity: CaseSensitivity::Insensitive,
+                    },
+                }),
+                ..Default::default()
+            };
+            let results = search_params.search(&expanded_metadata);
+            assert_eq!(
+                str_values(&results.0, COL::METRIC_ID).unwrap(),
+                vec!["bel_metric"]
+            );
+            assert_eq!(
+                str_values(&results.0, COL::COUNTRY_ID).unwrap(),
+                vec!["BEL"]
+            );
+        }
+    }
+
+    #[test]
+    fn diff_should_report_added_removed_and_changed_records() {
+        let old = two_country_metadata();
+        let mut new = two_country_metadata();
+        new.metrics = df!(
+            COL::METRIC_ID => &["nir_metric", "nir_metric_2"],
+            COL::METRIC_SOURCE_DATA_RELEASE_ID => &["nir_release", "nir_release"],
+        )
+        .unwrap();
+        let diff = old.diff(&new).unwrap();
+        assert_eq!(
+            diff.metrics,
+            TableDiff {
+                added: vec!["nir_metric_2".into()],
+                removed: vec!["bel_metric".into()],
+                changed: vec![],
+            }
+        );
+        assert!(diff.geometries.is_empty() && diff.source_data_releases.is_empty());
+        assert!(old.diff(&old).unwrap().is_empty());
+
+        // Moving a metric's parquet file is a change
+        let with_path = |path: &str| {
+            let mut metadata = two_country_metadata();
+            metadata
+                .metrics
+                .with_column(Series::new(
+                    COL::METRIC_PARQUET_PATH,
+                    &["bel.parquet", path],
+                ))
+                .unwrap();
+            metadata
+        };
+        let diff = with_path("nir.parquet")
+            .diff(&with_path("nir_v2.parquet"))
+            .unwrap();
+        assert_eq!(
+            diff.metrics.changed,
+            vec![ChangedRecord {
+                id: "nir_metric".into(),
+                columns: vec![COL::METRIC_PARQUET_PATH.into()],
+            }]
+        );
+        assert!(diff
+            .to_string()
+            .contains("~ nir_metric (metric_parquet_path)"));
+    }
+
+    #[test]
+    fn merge_should_name_country_with_mismatched_schema() {
+        let bel = metadata_with_required_columns();
+        let mut usa = metadata_with_required_columns();
+        usa.metrics = usa
+            .metrics
+            .drop(COL::METRIC_DESCRIPTION)
+            .unwrap()
+            .with_column(Series::new("metric_notes", &["value"]))
+            .unwrap()
+            .clone();
+
+        let err = merge_metadata(&[("bel", &bel), ("usa", &usa)], false).unwrap_err();
+        match &err {
+            MetadataError::SchemaMismatch {
+                table,
+                country,
+                missing,
+                extra,
+                ..
+            } => {
+                assert_eq!(table, "metrics");
+                assert_eq!(country, "usa");
+                assert_eq!(missing, &[COL::METRIC_DESCRIPTION]);
+                assert_eq!(extra, &["metric_notes"]);
+            }
+            _ => panic!("Unexpected error: {err}"),
+        }
+
+        // Missing columns are filled with nulls in relaxed mode
+        let merged = merge_metadata(&[("bel", &bel), ("usa", &usa)], true).unwrap();
+        assert_eq!(merged.metrics.height(), 2);
+        assert_eq!(
+            merged
+                .metrics
+                .column(COL::METRIC_DESCRIPTION)
+                .unwrap()
+                .null_count(),
+            1
+        );
+        assert_eq!(
+            merged.metrics.column("metric_notes").unwrap().null_count(),
+            1
+        );
+        assert_eq!(merged.countries.height(), 2);
+    }
+
+    #[test]
+    fn distinct_values_should_be_sorted_and_unique() {
+        let metadata = two_country_metadata();
+        assert_eq!(
+            metadata
+                .distinct_values(COL::SOURCE_DATA_RELEASE_DATA_PUBLISHER_ID)
+                .unwrap(),
+            vec!["nisra", "statbel"]
+        );
+        // Nulls are skipped
+        assert_eq!(
+            metadata.distinct_values(COL::COUNTRY_ISO3166_2).unwrap(),
+            vec!["GB-NIR"]
+        );
+        let err = metadata.distinct_values("not_a_column").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PopgetterError>(),
+            Some(PopgetterError::UnknownFacet { available, .. })
+                if available.iter().any(|column| column == COL::COUNTRY_NAME_SHORT_EN)
+        ));
+    }
+
+    #[test]
+    fn facet_counts_should_sum_to_number_of_metrics() {
+        let mut metadata = two_country_metadata();
+        metadata.metrics = df!(
+            COL::METRIC_ID => &["bel_1", "bel_2", "nir_1"],
+            COL::METRIC_SOURCE_DATA_RELEASE_ID => &["bel_release", "bel_release", "nir_release"],
+        )
+        .unwrap();
+        let counts = metadata.facet_counts(COL::COUNTRY_NAME_SHORT_EN).unwrap();
+        assert_eq!(
+            counts,
+            vec![
+                ("Belgium".to_string(), 2),
+                ("Northern Ireland".to_string(), 1)
+            ]
+        );
+        let counts = metadata.facet_counts(COL::COUNTRY_ISO3166_2).unwrap();
+        assert_eq!(
+            counts,
+            vec![(NO_FACET_VALUE.to_string(), 2), ("GB-NIR".to_string(), 1)]
+        );
+        assert_eq!(
+            counts.iter().map(|(_, count)| count).sum::<u32>() as usize,
+            metadata.metrics.height()
+        );
+        assert!(metadata.facet_counts("not_a_column").is_err());
+    }
+
+    #[test]
+    fn source_references_should_include_archive_file_path() {
+        let mut metadata = two_country_metadata();
+        metadata.metrics = df!(
+            COL::METRIC_ID => &["archived", "direct"],
+            COL::METRIC_SOURCE_DOWNLOAD_URL => &[
+                "https://example.com/census.zip",
+                "https://example.com/population.csv",
+            ],
+            COL::METRIC_SOURCE_ARCHIVE_FILE_PATH => &[Some("tables/population.csv"), None],
+        )
+        .unwrap();
+        let archived = metadata.source_reference("archived").unwrap();
+        assert_eq!(
+            archived,
+            SourceReference {
+                download_url: "https://example.com/census.zip".into(),
+                archive_file_path: Some("tables/population.csv".into()),
+            }
+        );
+        assert_eq!(
+            archived.to_string(),
+            "https://example.com/census.zip!/tables/population.csv"
+        );
+        let direct = metadata.source_reference("direct").unwrap();
+        assert_eq!(direct.archive_file_path, None);
+        assert_eq!(direct.to_string(), "https://example.com/population.csv");
+        assert!(metadata.source_reference("missing").is_err());
+    }
+
+    #[test]
+    fn metrics_detail_should_preserve_order_and_report_missing_ids() {
+        let mut metadata = two_country_metadata();
+        metadata
+            .metrics
+            .with_column(Series::new(
+                COL::METRIC_HUMAN_READABLE_NAME,
+                &["Population", "Households"],
+            ))
+            .unwrap();
+        let detail = metadata
+            .metrics_detail(&[
+                exact_metric_id("nir_metric"),
+                exact_metric_id("missing"),
+                exact_metric_id("bel_metric"),
+            ])
+            .unwrap();
+        assert_eq!(
+            detail.found,
+            vec![
+                MetricDetail {
+                    metric_id: "nir_metric".into(),
+                    human_readable_name: Some("Households".into()),
+                    country_name_short_en: Some("Northern Ireland".into()),
+                    ..MetricDetail::default()
+                },
+                MetricDetail {
+                    metric_id: "bel_metric".into(),
+                    human_readable_name: Some("Population".into()),
+                    country_name_short_en: Some("Belgium".into()),
+                    ..MetricDetail::default()
+                },
+            ]
+        );
+        assert_eq!(detail.not_found, ["missing"]);
+        assert_eq!(
+            metadata.metrics_detail(&[]).unwrap(),
+            MetricsDetail::default()
+        );
+    }
+
+    #[test]
+    fn citations_should_be_deduplicated_by_release() {
+        let mut metadata = two_country_metadata();
+        metadata.metrics = df!(
+            COL::METRIC_ID => &["bel_1", "bel_2", "nir_1"],
+            COL::METRIC_SOURCE_DATA_RELEASE_ID => &["bel_release", "bel_release", "nir_release"],
+            COL::METRIC_SOURCE_DOCUMENTATION_URL => &[
+                Some("https://statbel.fgov.be/docs"),
+                Some("https://statbel.fgov.be/docs"),
+                None,
+            ],
+        )
+        .unwrap();
+        metadata
+            .source_data_releases
+            .with_column(Series::new(
+                COL::SOURCE_DATA_RELEASE_NAME,
+                &["Census 2021", "Census 2021"],
+            ))
+            .unwrap();
+        metadata
+            .data_publishers
+            .with_column(Series::new(COL::DATA_PUBLISHER_NAME, &["Statbel", "NISRA"]))
+            .unwrap();
+        let citations = metadata
+            .citation_for(&[exact_metric_id("bel_1"), exact_metric_id("bel_2")])
+            .unwrap();
+        assert_eq!(
+            citations,
+            vec![Citation {
+                publisher: "Statbel".into(),
+                release: "Census 2021".into(),
+                date_published: None,
+                documentation_urls: vec!["https://statbel.fgov.be/docs".into()],
+            }]
+        );
+        assert_eq!(
+            citations[0].to_string(),
+            "Statbel. Census 2021. https://statbel.fgov.be/docs"
+        );
+        let citations = metadata
+            .citation_for(&[exact_metric_id("bel_1"), exact_metric_id("nir_1")])
+            .unwrap();
+        assert_eq!(
+            citations.iter().map(|c| c.publisher.as_str()).collect_vec(),
+            vec!["NISRA", "Statbel"]
+        );
+        assert!(metadata
+            .citation_for(&[exact_metric_id("missing")])
+            .is_err());
+    }
+
+    #[test]
+    fn fuzzy_find_should_rank_misspelled_names() {
+        let metadata = Metadata {
+            metrics: df!(
+                COL::METRIC_ID => &["a", "b", "c", "d"],
+                COL::METRIC_HUMAN_READABLE_NAME => &[
+                    "Total households",
+                    "Total population",
+                    "Population aged 16 and over",
+                    "Area",
+                ],
+            )
+            .unwrap(),
+            ..metadata_with_required_columns()
+        };
+        let matches = metadata.fuzzy_find_metrics("POPULATON totl", 2).unwrap();
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].0.id, "b");
+        assert!(matches[0].1 > matches[1].1);
+    }
+
+    #[test]
+    fn denominator_should_be_one_of_the_potential_denominators() {
+        let metadata = Metadata {
+            metrics: df!(
+                COL::METRIC_ID => &["households", "population"],
+                COL::METRIC_POTENTIAL_DENOMINATOR_IDS => &[
+                    Series::new("", &["population"]),
+                    Series::new("", Vec::<&str>::new()),
+                ],
+            )
+            .unwrap(),
+            ..metadata_with_required_columns()
+        };
+        assert!(metadata
+            .validate_denominator("households", "population")
+            .is_ok());
+        let err = metadata
+            .validate_denominator("population", "households")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'households' is not a potential denominator of metric 'population', potential \
+             denominators are: []"
+        );
+        assert!(metadata
+            .validate_denominator("missing", "population")
+            .is_err());
+    }
+
+    #[test]
+    fn metric_data_type_should_be_read_from_metadata() {
+        let metadata = Metadata {
+            metrics: df!(
+                COL::METRIC_ID => &["population", "median_age", "tenure"],
+                COL::METRIC_DATA_TYPE => &[None, Some("Continuous"), Some("categorical")],
+            )
+            .unwrap(),
+            ..metadata_with_required_columns()
+        };
+        assert_eq!(
+            metadata
+                .metric_data_type(&exact_metric_id("median_age"))
+                .unwrap(),
+            MetricDataType::Continuous
+        );
+        assert_eq!(
+            metadata
+                .metric_data_type(&exact_metric_id("tenure"))
+                .unwrap(),
+            MetricDataType::Categorical
+        );
+        // Metrics without a data type default to counts
+        assert_eq!(
+            metadata
+                .metric_data_type(&exact_metric_id("population"))
+                .unwrap(),
+            MetricDataType::Count
+        );
+        assert!(metadata
+            .metric_data_type(&exact_metric_id("missing"))
+            .is_err());
+        // As do all metrics if the metadata has no data types
+        assert_eq!(
+            metadata_with_required_columns()
+                .metric_data_type(&exact_metric_id("value"))
+                .unwrap(),
+            MetricDataType::Count
+        );
+    }
+
+    #[test]
+    fn metric_unit_should_be_read_or_inferred_from_hxl() {
+        let metadata = Metadata {
+            metrics: df!(
+                COL::METRIC_ID => &["population", "households", "female_pct", "income", "cars"],
+                COL::METRIC_HXL_TAG => &[
+                    Some("#population+total"),
+                    Some("#household+total"),
+                    Some("#population+f+pct"),
+                    Some("#value+income+usd"),
+                    None,
+                ],
+                COL::METRIC_UNIT => &[None, None, None, None, Some("vehicles")],
+            )
+            .unwrap(),
+            ..metadata_with_required_columns()
+        };
+        let unit = |metric_id: &str| metadata.metric_unit(metric_id).unwrap().unwrap();
+        assert_eq!(
+            unit("population"),
+            MetricUnit {
+                unit: units::PEOPLE.to_string(),
+                inferred: true,
+            }
+        );
+        assert_eq!(unit("households").unit, units::HOUSEHOLDS);
+        assert_eq!(unit("female_pct").unit, units::PERCENT);
+        assert_eq!(unit("income").to_string(), "USD (inferred)");
+        // A unit in the metadata is used as given
+        assert_eq!(
+            unit("cars"),
+            MetricUnit {
+                unit: "vehicles".to_string(),
+                inferred: false,
+            }
+        );
+        assert_eq!(infer_unit("#indicator+num"), Some(units::COUNT.to_string()));
+        assert_eq!(infer_unit("#date"), None);
+
+        // Percentages of percentages or of other kinds of count are refused
+        assert!(metadata
+            .validate_percentage_units("female_pct", "population")
+            .is_err());
+        assert!(metadata
+            .validate_percentage_units("households", "population")
+            .is_err());
+        assert!(metadata
+            .validate_percentage_units("population", "population")
+            .is_ok());
+    }
+
+    #[test]
+    fn metric_unit_should_be_inferred_when_unit_column_is_all_null() {
+        let metadata = Metadata {
+            metrics: df!(
+                COL::METRIC_ID => &["population"],
+                COL::METRIC_HXL_TAG => &["#population+total"],
+                COL::METRIC_UNIT => &Series::full_null(COL::METRIC_UNIT, 1, &DataType::Null),
+            )
+            .unwrap(),
+            ..metadata_with_required_columns()
+        };
+        assert_eq!(
+            metadata.metric_unit("population").unwrap().unwrap().unit,
+            units::PEOPLE
+        );
+    }
+
+    #[test]
+    fn aggregate_to_level_should_sum_counts_and_flag_missing_children() {
+        let metadata = Metadata {
+            metrics: df!(
+                COL::METRIC_ID => &["population", "median_age"],
+                COL::METRIC_PARQUET_COLUMN_NAME => &["B01001_E001", "B01002_E001"],
+                COL::METRIC_DATA_TYPE => &["count", "continuous"],
+            )
+            .unwrap(),
+            ..metadata_with_required_columns()
+        };
+        // Tract T4 has no data, so county C2 is only partially covered
+        let tracts = df!(
+            COL::GEO_ID => &["T1", "T2", "T3"],
+            "B01001_E001" => &[100, 250, 400],
+            "B01002_E001" => &[30.0, 40.0, 50.0],
+        )
+        .unwrap();
+        let hierarchy = df!(
+            COL::GEO_ID => &["T1", "T2", "T3", "T4", "T1"],
+            COL::PARENT_GEO_ID => &["C1", "C1", "C2", "C2", "S1"],
+            COL::PARENT_GEOMETRY_LEVEL => &["county", "county", "county", "county", "state"],
+        )
+        .unwrap();
+
+        let counties = metadata
+            .aggregate_to_level(
+                &exact_metric_id("population"),
+                &tracts,
+                &hierarchy,
+                "county",
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            counties,
+            df!(
+                COL::GEO_ID => &["C1", "C2"],
+                "B01001_E001" => &[350, 400],
+                MISSING_CHILDREN => &[0u32, 1],
+            )
+            .unwrap()
+        );
+
+        // Continuous metrics cannot be summed without an explicit aggregation
+        assert!(metadata
+            .aggregate_to_level(
+                &exact_metric_id("median_age"),
+                &tracts,
+                &hierarchy,
+                "county",
+                None
+            )
+            .is_err());
+        let counties = metadata
+            .aggregate_to_level(
+                &exact_metric_id("median_age"),
+                &tracts,
+                &hierarchy,
+                "county",
+                Some(Aggregation::Mean),
+            )
+            .unwrap();
+        assert_eq!(
+            counties
+                .column("B01002_E001")
+                .unwrap()
+                .f64()
+                .unwrap()
+                .to_vec(),
+            [Some(35.0), Some(50.0)]
+        );
+    }
+
+    #[test]
+    fn geo_id_column_should_default_to_geo_id() {
+        let mut metadata = Metadata {
+            geometries: df!(
+                COL::GEOMETRY_LEVEL => &["tract", "lad"],
+                COL::GEOMETRY_FILEPATH_STEM => &["geometries/tract_2021", "geometries/lad_2021"],
+            )
+            .unwrap(),
+            ..metadata_with_required_columns()
+        };
+        assert_eq!(metadata.geo_id_column("lad").unwrap(), COL::GEO_ID);
+
+        metadata.geometries = metadata
+            .geometries
+            .with_column(Series::new(
+                COL::GEOMETRY_GEO_ID_COLUMN,
+                &[None, Some("lad_code")],
+            ))
+            .unwrap()
+            .clone();
+        assert_eq!(metadata.geo_id_column("tract").unwrap(), COL::GEO_ID);
+        assert_eq!(metadata.geo_id_column("lad").unwrap(), "lad_code");
+        assert!(metadata.geo_id_column("ward").is_err());
     }
 }
@@ -1,75 +1,398 @@
 use anyhow::{Context, Result};
+use futures::{StreamExt, TryStreamExt};
+use itertools::Itertools;
 use log::debug;
+use polars::io::parquet::metadata::FileMetaData;
 use polars::prelude::*;
 use std::collections::HashSet;
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
 
-use crate::COL;
+use crate::{
+    config::{redact, Cancellation, Config},
+    error::PopgetterError,
+    progress::ProgressCallback,
+    trace::{record_rows, SpanTimer},
+    COL,
+};
 
-#[derive(Debug)]
+/// Suffix given to the margin of error column of a metric in downloaded results
+pub const MARGIN_OF_ERROR_SUFFIX: &str = "_moe";
+
+#[derive(Clone, Debug)]
 pub struct MetricRequest {
     pub column: String,
     pub metric_file: String,
     pub geom_file: String,
+    /// `GEO_ID`s to restrict this metric to. An empty list requests all rows.
+    pub geoids: Vec<String>,
+    /// Where to find the margin of error of the metric, if it should be fetched too
+    pub margin_of_error: Option<MarginOfError>,
 }
 
-/// Given a `file_url` and a list of `columns`, return a `Result<DataFrame>`
-/// with the requested columns, filtered by `geo_id`s if nessesary
-fn get_metrics_from_file(
-    file_url: &String,
-    columns: &[String],
-    geo_ids: Option<&[&str]>,
-) -> Result<DataFrame> {
-    let mut cols: Vec<Expr> = columns.iter().map(|c| col(c)).collect();
+impl MetricRequest {
+    /// The columns to fetch for this request as `(file, column)` pairs, including the margin of
+    /// error if there is one
+    fn file_columns(&self) -> impl Iterator<Item = (&str, &str)> {
+        std::iter::once((self.metric_file.as_str(), self.column.as_str())).chain(
+            self.margin_of_error
+                .iter()
+                .map(|moe| (moe.file.as_str(), moe.column.as_str())),
+        )
+    }
+
+    /// The columns of the downloaded results for this request, with the margin of error renamed
+    /// to `<column>_moe`. If the request is restricted to `geoids`, the columns are null for the
+    /// other rows, which are only present because another request for the download needs them.
+    fn output_columns(&self) -> Vec<Expr> {
+        let mut columns = vec![(col(&self.column), self.column.clone())];
+        if let Some(moe) = &self.margin_of_error {
+            columns.push((
+                col(&moe.column),
+                format!("{}{MARGIN_OF_ERROR_SUFFIX}", self.column),
+            ));
+        }
+        columns
+            .into_iter()
+            .map(|(column, name)| {
+                if self.geoids.is_empty() {
+                    column.alias(&name)
+                } else {
+                    let geoids = Series::new("geoids", &self.geoids);
+                    when(col(COL::GEO_ID).is_in(lit(geoids)))
+                        .then(column)
+                        .otherwise(lit(NULL))
+                        .alias(&name)
+                }
+            })
+            .collect()
+    }
+}
+
+/// The location of the margin of error of a metric, which may be in a different file to the
+/// metric itself
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MarginOfError {
+    pub column: String,
+    pub file: String,
+}
+
+/// All the columns needed from a single metric file, merged from one or more `MetricRequest`s so
+/// that the file only needs to be fetched once
+#[derive(Debug, PartialEq, Eq)]
+pub struct FileRequest {
+    pub metric_file: String,
+    /// The requested columns without duplicates, in the order they were first requested
+    pub columns: Vec<String>,
+    /// `GEO_ID`s needed from the file, or `None` if all rows are needed. This is the union of the
+    /// `GEO_ID`s of the requests for the file, each of which is applied to its own columns after
+    /// the files are joined (see `MetricRequest::output_columns`).
+    pub geoids: Option<Vec<String>>,
+}
+
+/// Merge `metrics`, including their margins of error, into one `FileRequest` per metric file, in
+/// the order the files are first requested
+pub fn merge_metric_requests(metrics: &[MetricRequest]) -> Vec<FileRequest> {
+    let file_columns = metrics
+        .iter()
+        .flat_map(|m| {
+            m.file_columns()
+                .map(move |(file, column)| (file, column, m))
+        })
+        .collect_vec();
+    file_columns
+        .iter()
+        .map(|(file, _, _)| *file)
+        .unique()
+        .map(|metric_file| {
+            let requests = file_columns
+                .iter()
+                .filter(|(file, _, _)| *file == metric_file)
+                .collect_vec();
+            FileRequest {
+                metric_file: metric_file.to_string(),
+                columns: requests
+                    .iter()
+                    .map(|(_, column, _)| column.to_string())
+                    .unique()
+                    .collect(),
+                geoids: requested_geoids(requests.iter().map(|(_, _, m)| *m)),
+            }
+        })
+        .collect()
+}
+
+/// The `GEO_ID`s needed from a file to satisfy all of the `requests` for it, or `None` if any of
+/// them requests all rows
+fn requested_geoids<'a>(
+    requests: impl IntoIterator<Item = &'a MetricRequest>,
+) -> Option<Vec<String>> {
+    let mut geoids: Vec<String> = vec![];
+    for request in requests {
+        if request.geoids.is_empty() {
+            return None;
+        }
+        geoids.extend(request.geoids.iter().cloned());
+    }
+    Some(geoids.into_iter().unique().collect())
+}
+
+/// Build a filter on `GEO_ID` from the `geo_ids` given for the whole download and those given
+/// for the individual requests, returning `None` if all rows are needed
+fn geo_id_filter(geo_ids: Option<&[&str]>, requested: Option<&[String]>) -> Option<Expr> {
+    let geo_ids = geo_ids.map(|ids| Series::new("geo_ids", ids));
+    let requested = requested.map(|ids| Series::new("geo_ids", ids));
+    [geo_ids, requested]
+        .into_iter()
+        .flatten()
+        .map(|ids| col(COL::GEO_ID).is_in(lit(ids)))
+        .reduce(|a, b| a.and(b))
+}
+
+/// Given a `FileRequest`, return a lazy scan of the requested columns, filtered by
+/// `geo_id`s if nessesary. The filter is applied to the lazy scan so that polars can push it
+/// down into the parquet reader and skip row groups that contain none of the `geo_id`s.
+fn scan_metrics_from_file(request: &FileRequest, geo_ids: Option<&[&str]>) -> Result<LazyFrame> {
+    let mut cols: Vec<Expr> = request.columns.iter().map(|c| col(c)).collect();
     cols.push(col(COL::GEO_ID));
 
     let args = ScanArgsParquet::default();
 
-    let df = LazyFrame::scan_parquet(file_url, args)?
+    let df = LazyFrame::scan_parquet(&request.metric_file, args)?
         .with_streaming(true)
         .select(cols);
 
-    let df = if let Some(ids) = geo_ids {
-        let id_series = Series::new("geo_ids", ids);
-        df.filter(col(COL::GEO_ID).is_in(lit(id_series)))
-    } else {
-        df
-    };
+    Ok(
+        if let Some(filter) = geo_id_filter(geo_ids, request.geoids.as_deref()) {
+            df.filter(filter)
+        } else {
+            df
+        },
+    )
+}
 
-    let result = df.collect()?;
+/// Given a `FileRequest`, return a `Result<DataFrame>` with the requested
+/// columns, filtered by `geo_id`s if nessesary.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        skip_all,
+        fields(
+            file_url = %request.metric_file,
+            rows = tracing::field::Empty,
+            elapsed_ms = tracing::field::Empty,
+        ),
+    )
+)]
+fn get_metrics_from_file(request: &FileRequest, geo_ids: Option<&[&str]>) -> Result<DataFrame> {
+    let _timer = SpanTimer::start();
+    let result = scan_metrics_from_file(request, geo_ids)?.collect()?;
+    record_rows(result.height());
     Ok(result)
 }
 
 /// Given a set of metrics and optional `geo_ids`, this function will
-/// retrive all the required metrics from the cloud blob storage
+/// retrive all the required metrics from the cloud blob storage. Requests
+/// for columns in the same file are merged so that each file is scanned once.
 ///
 pub fn get_metrics(metrics: &[MetricRequest], geo_ids: Option<&[&str]>) -> Result<DataFrame> {
-    let file_list: HashSet<String> = metrics.iter().map(|m| m.metric_file.clone()).collect();
-    debug!("{:#?}", file_list);
+    get_metrics_with_progress(metrics, geo_ids, &ProgressCallback::default())
+}
+
+/// Like `get_metrics`, but reports the start and completion of each file scanned to `progress`
+pub fn get_metrics_with_progress(
+    metrics: &[MetricRequest],
+    geo_ids: Option<&[&str]>,
+    progress: &ProgressCallback,
+) -> Result<DataFrame> {
+    get_metrics_cancellable(metrics, geo_ids, progress, &Cancellation::default())
+}
+
+/// Like `get_metrics_with_progress`, but stops with `PopgetterError::Cancelled` before scanning
+/// the next file once `cancellation` is cancelled. Scans are blocking, so one already in
+/// progress runs to completion even if the caller stops waiting for it. Use
+/// `get_metrics_concurrent` for remote files whose downloads should be dropped on cancellation.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        skip_all,
+        fields(
+            metrics = metrics.len(),
+            rows = tracing::field::Empty,
+            elapsed_ms = tracing::field::Empty,
+        ),
+    )
+)]
+pub fn get_metrics_cancellable(
+    metrics: &[MetricRequest],
+    geo_ids: Option<&[&str]>,
+    progress: &ProgressCallback,
+    cancellation: &Cancellation,
+) -> Result<DataFrame> {
+    let _timer = SpanTimer::start();
+    let file_requests = merge_metric_requests(metrics);
+    debug!("{:#?}", file_requests);
     // TODO Can we do this async so we can be downloading results from each file together?
-    let dfs: Result<Vec<DataFrame>> = file_list
+    let dfs: Result<Vec<DataFrame>> = file_requests
         .iter()
-        .map(|file_url| {
-            let file_cols: Vec<String> = metrics
-                .iter()
-                .filter_map(|m| {
-                    if m.metric_file == file_url.clone() {
-                        Some(m.column.clone())
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-
-            get_metrics_from_file(file_url, &file_cols, geo_ids)
+        .map(|request| {
+            if cancellation.is_cancelled() {
+                Err(PopgetterError::Cancelled)?;
+            }
+            progress.started(&request.metric_file);
+            let result = get_metrics_from_file(request, geo_ids);
+            progress.finished(&request.metric_file, &result);
+            result
         })
         .collect();
 
+    let df = join_on_geo_id(dfs?, metrics)?;
+    record_rows(df.height());
+    Ok(df)
+}
+
+/// Given a `FileRequest` for a remote file, read the requested columns with range requests,
+/// filtered by `geo_id`s if nessesary. Only the footer and the column chunks of the requested
+/// columns and `GEO_ID` are transferred, from the row groups that may contain the `geo_id`s (see
+/// `fetch_column_chunks`).
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        skip_all,
+        fields(
+            file_url = %request.metric_file,
+            rows = tracing::field::Empty,
+            elapsed_ms = tracing::field::Empty,
+        ),
+    )
+)]
+async fn fetch_metrics_from_file(
+    config: &Config,
+    request: &FileRequest,
+    geo_ids: Option<&[&str]>,
+) -> Result<DataFrame> {
+    let _timer = SpanTimer::start();
+    let file_url = request.metric_file.as_str();
+    debug!("Fetching {:?} from {file_url}", request.columns);
+    let mut columns = request.columns.clone();
+    columns.push(COL::GEO_ID.to_string());
+    let requested = request.geoids.as_deref();
+    let chunks = fetch_column_chunks(config, file_url, &columns, geo_ids, requested).await?;
+    let filter = geo_id_filter(geo_ids, requested);
+
+    // Required because polars is blocking
+    let df = tokio::task::spawn_blocking(move || {
+        let df = chunks.read(columns)?;
+        anyhow::Ok(match filter {
+            Some(filter) => df.lazy().filter(filter).collect()?,
+            None => df,
+        })
+    })
+    .await??;
+    record_rows(df.height());
+    Ok(df)
+}
+
+/// Like `get_metrics`, but downloads the distinct metric files concurrently, with at most
+/// `Config::max_concurrent_downloads` in flight at once. All the columns requested from the same
+/// file are fetched together, and only their column chunks are downloaded (see
+/// `fetch_column_chunks`). If `Config::cancellation` is
+/// cancelled, the downloads in flight are dropped and `PopgetterError::Cancelled` is returned.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        skip_all,
+        fields(
+            metrics = metrics.len(),
+            rows = tracing::field::Empty,
+            elapsed_ms = tracing::field::Empty,
+        ),
+    )
+)]
+pub async fn get_metrics_concurrent(
+    metrics: &[MetricRequest],
+    geo_ids: Option<&[&str]>,
+    config: &Config,
+) -> Result<DataFrame> {
+    let _timer = SpanTimer::start();
+    let df = get_metrics_routed(metrics, geo_ids, config, |_| true).await?;
+    record_rows(df.height());
+    Ok(df)
+}
+
+/// Fetch `metrics` as configured by `config`. Each metric file is routed individually: remote
+/// files are downloaded as in `get_metrics_concurrent` when `Config::fetch_with_client`, so that
+/// credentials are attached, and other files are scanned by polars on a blocking thread. Progress
+/// is reported to `Config::progress` either way. Once `Config::cancellation` is cancelled no
+/// further files are fetched, downloads in flight are dropped and scans already running complete
+/// in the background.
+pub async fn get_metrics_with_config(
+    metrics: &[MetricRequest],
+    geo_ids: Option<&[&str]>,
+    config: &Config,
+) -> Result<DataFrame> {
+    get_metrics_routed(metrics, geo_ids, config, |path| {
+        config.fetch_with_client(path)
+    })
+    .await
+}
+
+/// Fetch the distinct metric files of `metrics` concurrently, with at most
+/// `Config::max_concurrent_downloads` in flight at once, downloading those for which
+/// `with_client` is true and scanning the others on a blocking thread
+async fn get_metrics_routed(
+    metrics: &[MetricRequest],
+    geo_ids: Option<&[&str]>,
+    config: &Config,
+    with_client: impl Fn(&str) -> bool,
+) -> Result<DataFrame> {
+    let file_requests = merge_metric_requests(metrics);
+    debug!("{:#?}", file_requests);
+    let owned_geo_ids = geo_ids.map(|ids| ids.iter().map(ToString::to_string).collect_vec());
+    let with_client = &with_client;
+    let owned_geo_ids = &owned_geo_ids;
+    let downloads = futures::stream::iter(file_requests)
+        .map(|request| async move {
+            let metric_file = request.metric_file.clone();
+            config.progress.started(&metric_file);
+            let result = if with_client(&metric_file) {
+                fetch_metrics_from_file(config, &request, geo_ids).await
+            } else {
+                // Required because polars is blocking
+                let geo_ids = owned_geo_ids.clone();
+                tokio::task::spawn_blocking(move || {
+                    let geo_ids = geo_ids
+                        .as_ref()
+                        .map(|ids| ids.iter().map(String::as_str).collect_vec());
+                    get_metrics_from_file(&request, geo_ids.as_deref())
+                })
+                .await
+                .with_context(|| format!("Failed to scan {metric_file}"))
+                .and_then(|result| result)
+            };
+            config.progress.finished(&metric_file, &result);
+            result
+        })
+        .buffered(config.max_concurrent_downloads.max(1))
+        .try_collect::<Vec<DataFrame>>();
+    let dfs = tokio::select! {
+        biased;
+        () = config.cancellation.cancelled() => Err(PopgetterError::Cancelled)?,
+        dfs = downloads => dfs?,
+    };
+    join_on_geo_id(dfs, metrics)
+}
+
+/// Join the dataframes fetched from each metric file on `GEO_ID`, with `GEO_ID` as the first
+/// column followed by the columns of each of the `metrics`
+fn join_on_geo_id(dfs: Vec<DataFrame>, metrics: &[MetricRequest]) -> Result<DataFrame> {
     // TODO: The following assumes that we requested metrics for the same geo_ids. This is not
     // generally true
     let mut joined_df: Option<DataFrame> = None;
 
     // Merge the dataframes from each remove file in to a single dataframe
-    for df in dfs? {
+    for df in dfs {
         if let Some(prev_dfs) = joined_df {
             joined_df = Some(prev_dfs.join(
                 &df,
@@ -85,13 +408,427 @@ pub fn get_metrics(metrics: &[MetricRequest], geo_ids: Option<&[&str]>) -> Resul
     Ok(joined_df
         .with_context(|| "Failed to combine data queries")?
         .lazy()
-        .select(&[col(COL::GEO_ID), col("*").exclude([COL::GEO_ID])])
+        .select(joined_columns(metrics))
         .collect()?)
 }
 
+/// The columns of the result of joining the files of `metrics`: `GEO_ID` followed by the
+/// columns of each of the `metrics`
+fn joined_columns(metrics: &[MetricRequest]) -> Vec<Expr> {
+    std::iter::once(col(COL::GEO_ID))
+        .chain(
+            metrics
+                .iter()
+                .unique_by(|m| &m.column)
+                .flat_map(MetricRequest::output_columns),
+        )
+        .collect_vec()
+}
+
+/// Like `get_metrics`, but writes the result to a parquet file at `output_path` instead of
+/// returning it. The scans of each file and the join between them are run with the streaming
+/// engine and sunk directly to disk, so the whole result is never held in memory at once.
+pub fn download_to_parquet(metrics: &[MetricRequest], output_path: &Path) -> Result<()> {
+    let file_requests = merge_metric_requests(metrics);
+    debug!("{:#?}", file_requests);
+    let joined = file_requests
+        .iter()
+        .map(|request| scan_metrics_from_file(request, None))
+        .reduce(|left, right| {
+            Ok(left?.join(
+                right?,
+                [col(COL::GEO_ID)],
+                [col(COL::GEO_ID)],
+                JoinArgs::new(JoinType::Inner),
+            ))
+        })
+        .with_context(|| "Failed to combine data queries")??;
+    joined
+        .select(joined_columns(metrics))
+        .with_streaming(true)
+        .sink_parquet(output_path, ParquetWriteOptions::default())
+        .with_context(|| format!("Failed to write metrics to {}", output_path.display()))?;
+    Ok(())
+}
+
+/// Suffix given to the column added by `with_percentage`
+pub const PERCENTAGE_SUFFIX: &str = "_percentage";
+
+/// Add a `<metric_column>_percentage` column giving `metric_column` as a percentage of
+/// `denominator_column`. Rows where the denominator is zero or null get a null percentage rather
+/// than an infinite or NaN value.
+pub fn with_percentage(
+    df: DataFrame,
+    metric_column: &str,
+    denominator_column: &str,
+) -> Result<DataFrame> {
+    let metric = col(metric_column).cast(DataType::Float64);
+    let denominator = col(denominator_column).cast(DataType::Float64);
+    Ok(df
+        .lazy()
+        .with_column(
+            when(denominator.clone().eq(lit(0.0)))
+                .then(lit(NULL).cast(DataType::Float64))
+                .otherwise(metric / denominator * lit(100.0))
+                .alias(&format!("{metric_column}{PERCENTAGE_SUFFIX}")),
+        )
+        .collect()?)
+}
+
+/// An estimate of the size of a download, made from the parquet file footers alone
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DownloadEstimate {
+    /// The number of metric columns that would be downloaded
+    pub metric_columns: usize,
+    /// The number of geographic units (rows) in the largest of the metric files
+    pub geographic_units: usize,
+    /// The compressed size in bytes of the requested columns, including `GEO_ID`
+    pub estimated_bytes: u64,
+}
+
+/// Sum the compressed sizes of the `columns` across all row groups of a parquet file
+fn compressed_size_of_columns(metadata: &FileMetaData, columns: &HashSet<&str>) -> u64 {
+    metadata
+        .row_groups
+        .iter()
+        .flat_map(|row_group| row_group.columns())
+        .filter(|column| {
+            column
+                .descriptor()
+                .path_in_schema
+                .first()
+                .is_some_and(|name| columns.contains(name.as_str()))
+        })
+        .map(|column| column.compressed_size().max(0) as u64)
+        .sum()
+}
+
+/// Number of bytes fetched from the end of a remote parquet file in the first request for its
+/// footer when reading columns from it, which is enough for the whole footer of most metric files
+const FOOTER_PREFETCH_BYTES: usize = 64 * 1024;
+
+/// Column chunks separated by at most this many bytes are fetched in a single range request
+const RANGE_COALESCE_GAP: usize = 64 * 1024;
+
+/// Bytes of a remote file fetched with a range request
+struct FetchedRange {
+    /// The offset of `bytes` in the file
+    start: usize,
+    bytes: Vec<u8>,
+    /// The length of the whole file
+    file_length: usize,
+}
+
+/// Fetch the bytes of `file_url` in `range`, given as in a `Range` header without the `bytes=`
+/// prefix (e.g. `0-99` or `-8`), reporting the bytes transferred to `Config::progress`. Servers
+/// that do not support range requests respond with the whole file, which is returned as is.
+async fn fetch_range(config: &Config, file_url: &str, range: &str) -> Result<FetchedRange> {
+    async {
+        let response = config
+            .get(file_url)
+            .header(reqwest::header::RANGE, format!("bytes={range}"))
+            .send()
+            .await?
+            .error_for_status()?;
+        // `Content-Range: bytes <start>-<end>/<file_length>`
+        let content_range = response
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                let (range, file_length) = value.strip_prefix("bytes ")?.split_once('/')?;
+                let (start, _) = range.split_once('-')?;
+                Some((start.parse().ok()?, file_length.parse().ok()?))
+            });
+        let partial = response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+        let bytes = config.progress.read_body(file_url, response).await?;
+        Ok(match content_range {
+            Some((start, file_length)) if partial => FetchedRange {
+                start,
+                bytes,
+                file_length,
+            },
+            _ => FetchedRange {
+                start: 0,
+                file_length: bytes.len(),
+                bytes,
+            },
+        })
+    }
+    .await
+    .map_err(redact)
+    .with_context(|| format!("Failed to download bytes {range} of {file_url}"))
+}
+
+/// A remote parquet file of which only some byte ranges have been fetched
+struct PartialFile {
+    /// The file, with the bytes that have not been fetched zeroed. The buffer is allocated zeroed
+    /// so the allocator can map it lazily, and only the fetched pages take up memory.
+    bytes: Vec<u8>,
+    /// The byte ranges `(start, end)` that have been fetched
+    fetched: Vec<(usize, usize)>,
+}
+
+impl PartialFile {
+    fn new(range: FetchedRange) -> Self {
+        let mut file = PartialFile {
+            bytes: vec![0; range.file_length],
+            fetched: vec![],
+        };
+        file.insert(range);
+        file
+    }
+
+    fn insert(&mut self, range: FetchedRange) {
+        let end = (range.start + range.bytes.len()).min(self.bytes.len());
+        self.bytes[range.start..end].copy_from_slice(&range.bytes[..end - range.start]);
+        self.fetched.push((range.start, end));
+    }
+
+    /// Whether all of the bytes from `start` to `end` have been fetched
+    fn contains(&self, start: usize, end: usize) -> bool {
+        self.fetched
+            .iter()
+            .any(|&(fetched_start, fetched_end)| fetched_start <= start && end <= fetched_end)
+    }
+
+    /// Fetch the bytes from `start` to `end`, excluding those at either end that have already
+    /// been fetched
+    async fn fetch(
+        &mut self,
+        config: &Config,
+        file_url: &str,
+        mut start: usize,
+        mut end: usize,
+    ) -> Result<()> {
+        for &(fetched_start, fetched_end) in &self.fetched {
+            if fetched_start <= start && start < fetched_end {
+                start = fetched_end;
+            }
+            if fetched_start < end && end <= fetched_end {
+                end = fetched_start;
+            }
+        }
+        if start < end {
+            let range = fetch_range(config, file_url, &format!("{start}-{}", end - 1)).await?;
+            self.insert(range);
+        }
+        Ok(())
+    }
+
+    /// The offset of the footer in the file, read from the footer length at the end of the file
+    fn footer_start(&self, file_url: &str) -> Result<usize> {
+        // A parquet file ends with the length of its footer followed by the magic bytes `PAR1`
+        let file_length = self.bytes.len();
+        let footer_start = match self.bytes.get(file_length.saturating_sub(8)..) {
+            Some([a, b, c, d, b'P', b'A', b'R', b'1']) if file_length >= 12 => {
+                (file_length - 8).checked_sub(u32::from_le_bytes([*a, *b, *c, *d]) as usize)
+            }
+            _ => None,
+        };
+        footer_start.with_context(|| format!("{file_url} is not a parquet file"))
+    }
+
+    /// Read the footer, which must already have been fetched
+    fn metadata(&self, footer_start: usize) -> Result<Arc<FileMetaData>> {
+        // Prefixing the footer with the leading magic bytes lets it be read as a parquet file
+        let mut bytes = b"PAR1".to_vec();
+        bytes.extend_from_slice(&self.bytes[footer_start..]);
+        Ok(ParquetReader::new(Cursor::new(bytes))
+            .get_metadata()?
+            .clone())
+    }
+}
+
+/// Fetch the footer of the remote parquet file at `file_url` with range requests, starting with
+/// the last `prefetch` bytes of the file. Returns the partially fetched file and its metadata.
+async fn fetch_footer(
+    config: &Config,
+    file_url: &str,
+    prefetch: usize,
+) -> Result<(PartialFile, Arc<FileMetaData>)> {
+    let tail = fetch_range(config, file_url, &format!("-{prefetch}")).await?;
+    let mut file = PartialFile::new(tail);
+    let footer_start = file.footer_start(file_url)?;
+    let file_length = file.bytes.len();
+    if !file.contains(footer_start, file_length) {
+        let footer_range = format!("-{}", file_length - footer_start);
+        file.insert(fetch_range(config, file_url, &footer_range).await?);
+    }
+    let metadata = file.metadata(footer_start)?;
+    Ok((file, metadata))
+}
+
+/// Fetch the footer of a remote parquet file with range requests carrying the configured
+/// credentials, which polars' own async reader cannot attach. Only the length of the footer is
+/// fetched first, so the rest of the file is never transferred.
+async fn fetch_file_metadata(file_url: &str, config: &Config) -> Result<Arc<FileMetaData>> {
+    Ok(fetch_footer(config, file_url, 8).await?.1)
+}
+
+/// The column chunks of some of the row groups of a remote parquet file, fetched by
+/// `fetch_column_chunks`
+struct ColumnChunks {
+    /// The file, with the bytes that were not fetched zeroed
+    file: Vec<u8>,
+    /// The rows of the row groups that were fetched, as `(offset, length)` slices
+    rows: Vec<(usize, usize)>,
+}
+
+impl ColumnChunks {
+    /// Read `columns` from the row groups that were fetched. Each slice of rows is read
+    /// separately, parallelising over row groups so that polars skips the row groups outside it
+    /// rather than decoding their zeroed chunks.
+    fn read(&self, columns: Vec<String>) -> Result<DataFrame> {
+        let read = |slice| {
+            ParquetReader::new(Cursor::new(self.file.as_slice()))
+                .with_columns(Some(columns.clone()))
+                .read_parallel(ParallelStrategy::RowGroups)
+                .with_slice(Some(slice))
+                .finish()
+        };
+        let mut dfs = self.rows.iter().map(|&slice| read(slice));
+        let mut df = dfs.next().unwrap_or_else(|| read((0, 0)))?;
+        for other in dfs {
+            df.vstack_mut(&other?)?;
+        }
+        Ok(df)
+    }
+}
+
+/// Whether a row group whose `GEO_ID`s range from `min` to `max` may contain any of the
+/// `geo_ids` and `requested` GEO_IDs, which are combined as in `geo_id_filter`. Row groups
+/// without statistics may contain any GEO_ID.
+fn may_contain_geo_ids(
+    min: Option<&[u8]>,
+    max: Option<&[u8]>,
+    geo_ids: Option<&[&str]>,
+    requested: Option<&[String]>,
+) -> bool {
+    let (Some(min), Some(max)) = (min, max) else {
+        return true;
+    };
+    let in_range = |id: &str| min <= id.as_bytes() && id.as_bytes() <= max;
+    geo_ids.is_none_or(|ids| ids.iter().any(|id| in_range(id)))
+        && requested.is_none_or(|ids| ids.iter().any(|id| in_range(id)))
+}
+
+/// Fetch the footer and the column chunks of `columns` from the remote parquet file at
+/// `file_url` with range requests, so that the other columns of wide metric files are never
+/// transferred. Row groups are skipped if the statistics of their `GEO_ID` column show they
+/// contain none of the `geo_ids` or `requested` GEO_IDs. Column chunks close together are
+/// coalesced into a single request, and the requests are made one at a time.
+async fn fetch_column_chunks(
+    config: &Config,
+    file_url: &str,
+    columns: &[String],
+    geo_ids: Option<&[&str]>,
+    requested: Option<&[String]>,
+) -> Result<ColumnChunks> {
+    let (mut file, metadata) = fetch_footer(config, file_url, FOOTER_PREFETCH_BYTES).await?;
+    let column_name = |path_in_schema: &[String]| path_in_schema.first().cloned();
+    let mut rows: Vec<(usize, usize)> = vec![];
+    let mut chunks = vec![];
+    let mut offset = 0;
+    for row_group in &metadata.row_groups {
+        let num_rows = row_group.num_rows();
+        let statistics = row_group
+            .columns()
+            .iter()
+            .find(|column| {
+                column_name(&column.descriptor().path_in_schema).as_deref() == Some(COL::GEO_ID)
+            })
+            .and_then(|column| column.metadata().statistics.as_ref());
+        let min = statistics.and_then(|stats| stats.min_value.as_deref());
+        let max = statistics.and_then(|stats| stats.max_value.as_deref());
+        if may_contain_geo_ids(min, max, geo_ids, requested) {
+            match rows.last_mut() {
+                Some((start, length)) if *start + *length == offset => *length += num_rows,
+                _ => rows.push((offset, num_rows)),
+            }
+            chunks.extend(
+                row_group
+                    .columns()
+                    .iter()
+                    .filter(|column| {
+                        column_name(&column.descriptor().path_in_schema)
+                            .is_some_and(|name| columns.contains(&name))
+                    })
+                    .map(|column| {
+                        let (start, length) = column.byte_range();
+                        (start as usize, (start + length) as usize)
+                    }),
+            );
+        }
+        offset += num_rows;
+    }
+    chunks.sort_unstable();
+    chunks.dedup();
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for (start, end) in chunks {
+        match ranges.last_mut() {
+            Some((_, last_end)) if start <= *last_end + RANGE_COALESCE_GAP => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => ranges.push((start, end)),
+        }
+    }
+    debug!("Fetching {} byte ranges of {file_url}", ranges.len());
+    for (start, end) in ranges {
+        file.fetch(config, file_url, start, end).await?;
+    }
+    // The leading magic bytes are checked when reading
+    file.fetch(config, file_url, 0, 4).await?;
+    Ok(ColumnChunks {
+        file: file.bytes,
+        rows,
+    })
+}
+
+/// Fetch the footer of a parquet file. Remote files are read with range requests so that only
+/// the metadata is transferred, and local files are read by seeking to the footer.
+async fn get_file_metadata(file_url: &str, config: &Config) -> Result<Arc<FileMetaData>> {
+    if file_url.starts_with("http://") || file_url.starts_with("https://") {
+        if config.auth.is_some() {
+            return fetch_file_metadata(file_url, config).await;
+        }
+        let mut reader = ParquetAsyncReader::from_uri(file_url, None, None).await?;
+        Ok(reader.get_metadata().await?.clone())
+    } else {
+        let file = File::open(file_url)
+            .with_context(|| format!("Failed to open parquet file {file_url}"))?;
+        Ok(ParquetReader::new(file).get_metadata()?.clone())
+    }
+}
+
+/// Estimate the size of fetching `metrics` without downloading them, using the column chunk
+/// sizes recorded in the footer of each parquet file.
+pub async fn estimate_download(
+    metrics: &[MetricRequest],
+    config: &Config,
+) -> Result<DownloadEstimate> {
+    let mut estimate = DownloadEstimate::default();
+    for request in merge_metric_requests(metrics) {
+        let file_url = request.metric_file.as_str();
+        let mut columns: HashSet<&str> = request.columns.iter().map(String::as_str).collect();
+        columns.insert(COL::GEO_ID);
+
+        estimate.metric_columns += request.columns.len();
+        let metadata = get_file_metadata(file_url, config).await?;
+        debug!("Read footer of {file_url}: {} rows", metadata.num_rows);
+        estimate.geographic_units = estimate.geographic_units.max(metadata.num_rows);
+        estimate.estimated_bytes += compressed_size_of_columns(&metadata, &columns);
+    }
+    Ok(estimate)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{config::AuthConfig, progress::ProgressEvent, test_util::file_server};
+    use httpmock::prelude::*;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
 
     #[test]
     fn test_fetching_metrics() {
@@ -100,6 +837,8 @@ mod tests {
                 metric_file: "https://popgetter.blob.core.windows.net/popgetter-cli-test/tracts_2019_fiveYear.parquet".into(),
                 column: "B17021_E006".into(),
                 geom_file: "Not needed for this test".into(),
+                geoids: vec![],
+                margin_of_error: None,
             }];
         let df = get_metrics(&metrics, None);
         assert!(df.is_ok(), "We should get back a result");
@@ -131,6 +870,8 @@ mod tests {
                 metric_file: "https://popgetter.blob.core.windows.net/popgetter-cli-test/tracts_2019_fiveYear.parquet".into(),
                 column: "B17021_E006".into(),
                 geom_file: "Not needed for this test".into(),
+                geoids: vec![],
+                margin_of_error: None,
             }];
         let df = get_metrics(
             &metrics,
@@ -150,4 +891,863 @@ mod tests {
             "The returned dataframe should have the correct number of rows"
         );
     }
+
+    #[tokio::test]
+    async fn estimate_download_should_read_sizes_from_footer() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("metrics.parquet");
+        let n_rows = 10_000;
+        let geo_ids: Vec<String> = (0..n_rows).map(|i| format!("E{i:08}")).collect();
+        let values = |scale: f64| -> Vec<f64> {
+            (0..n_rows)
+                .map(|i| ((i * 7919) % 10_007) as f64 * scale)
+                .collect()
+        };
+        let mut df = df!(
+            COL::GEO_ID => geo_ids,
+            "metric_a" => values(1.5),
+            "metric_b" => values(2.5),
+            "metric_c" => values(3.5),
+        )
+        .unwrap();
+        ParquetWriter::new(File::create(&path).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let file_size = std::fs::metadata(&path).unwrap().len();
+
+        let metric_file = path.to_string_lossy().to_string();
+        let metrics = ["metric_a", "metric_b"].map(|column| MetricRequest {
+            column: column.into(),
+            metric_file: metric_file.clone(),
+            geom_file: "Not needed for this test".into(),
+            geoids: vec![],
+            margin_of_error: None,
+        });
+        let estimate = estimate_download(&metrics, &Config::default())
+            .await
+            .unwrap();
+
+        assert_eq!(estimate.metric_columns, 2);
+        assert_eq!(estimate.geographic_units, n_rows);
+        // Two 8 byte columns plus the IDs over 10,000 rows should be in the tens of kilobytes
+        assert!(
+            (10_000..1_000_000).contains(&estimate.estimated_bytes),
+            "Unexpected estimate of {} bytes",
+            estimate.estimated_bytes
+        );
+        assert!(
+            estimate.estimated_bytes < file_size,
+            "The estimate should exclude the column that was not requested"
+        );
+    }
+
+    fn parquet_bytes(mut df: DataFrame) -> Vec<u8> {
+        let mut bytes = vec![];
+        ParquetWriter::new(&mut bytes).finish(&mut df).unwrap();
+        bytes
+    }
+
+    #[tokio::test]
+    async fn concurrent_fetch_should_download_each_file_once() {
+        let server = MockServer::start_async().await;
+        let file_a = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/a.parquet");
+                then.status(200).body(parquet_bytes(
+                    df!(
+                        COL::GEO_ID => &["E1", "E2", "E3"],
+                        "metric_1" => &[1, 2, 3],
+                        "metric_2" => &[4, 5, 6],
+                        "metric_3" => &[7, 8, 9],
+                    )
+                    .unwrap(),
+                ));
+            })
+            .await;
+        let file_b = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/b.parquet");
+                then.status(200).body(parquet_bytes(
+                    df!(
+                        COL::GEO_ID => &["E3", "E2", "E1"],
+                        "metric_4" => &[30, 20, 10],
+                    )
+                    .unwrap(),
+                ));
+            })
+            .await;
+        let metrics = [
+            ("metric_1", "a.parquet"),
+            ("metric_2", "a.parquet"),
+            ("metric_4", "b.parquet"),
+        ]
+        .map(|(column, file)| MetricRequest {
+            column: column.into(),
+            metric_file: server.url(format!("/{file}")),
+            geom_file: "Not needed for this test".into(),
+            geoids: vec![],
+            margin_of_error: None,
+        });
+        let config = Config {
+            max_concurrent_downloads: 1,
+            ..Config::default()
+        };
+
+        let df = get_metrics_concurrent(&metrics, Some(&["E1", "E3"]), &config)
+            .await
+            .unwrap()
+            .sort([COL::GEO_ID], Default::default())
+            .unwrap();
+
+        file_a.assert_hits_async(1).await;
+        file_b.assert_hits_async(1).await;
+        assert_eq!(
+            df.get_column_names(),
+            [COL::GEO_ID, "metric_1", "metric_2", "metric_4"]
+        );
+        assert_eq!(
+            df.column("metric_4").unwrap().i32().unwrap().to_vec(),
+            [Some(10), Some(30)]
+        );
+    }
+
+    #[test]
+    fn request_geoids_should_filter_rows() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("metrics.parquet");
+        let mut df = df!(
+            COL::GEO_ID => &["E1", "E2", "E3", "E4"],
+            "metric_1" => &[1, 2, 3, 4],
+            "metric_2" => &[5, 6, 7, 8],
+        )
+        .unwrap();
+        ParquetWriter::new(File::create(&path).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let request = |column: &str, geoids: &[&str]| MetricRequest {
+            column: column.into(),
+            metric_file: path.to_string_lossy().to_string(),
+            geom_file: "Not needed for this test".into(),
+            geoids: geoids.iter().map(|id| id.to_string()).collect(),
+            margin_of_error: None,
+        };
+        let geo_ids_of = |df: DataFrame| -> Vec<String> {
+            df.column(COL::GEO_ID)
+                .unwrap()
+                .str()
+                .unwrap()
+                .into_no_null_iter()
+                .map(String::from)
+                .sorted()
+                .collect()
+        };
+
+        let df = get_metrics(
+            &[
+                request("metric_1", &["E2", "E4"]),
+                request("metric_2", &["E4", "E1"]),
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(geo_ids_of(df), ["E1", "E2", "E4"]);
+
+        // An empty list of GEO_IDs requests all rows
+        let df = get_metrics(
+            &[request("metric_1", &["E2"]), request("metric_2", &[])],
+            None,
+        )
+        .unwrap();
+        assert_eq!(geo_ids_of(df), ["E1", "E2", "E3", "E4"]);
+
+        // Both the download-wide and per-request GEO_IDs are applied
+        let df = get_metrics(&[request("metric_1", &["E2", "E3"])], Some(&["E3"])).unwrap();
+        assert_eq!(geo_ids_of(df), ["E3"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn request_geoids_should_only_apply_to_their_own_columns() {
+        let bytes = parquet_bytes(
+            df!(
+                COL::GEO_ID => &["E1", "E2", "E3"],
+                "metric_1" => &[1, 2, 3],
+                "metric_2" => &[4, 5, 6],
+            )
+            .unwrap(),
+        );
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("metrics.parquet");
+        std::fs::write(&path, &bytes).unwrap();
+        let server = file_server(HashMap::from([("/metrics.parquet".to_string(), bytes)])).await;
+        let request = |file: &str, column: &str, geoids: &[&str]| MetricRequest {
+            column: column.into(),
+            metric_file: file.into(),
+            geom_file: "Not needed for this test".into(),
+            geoids: geoids.iter().map(|id| id.to_string()).collect(),
+            margin_of_error: None,
+        };
+        let requests = |file: &str| {
+            [
+                request(file, "metric_1", &["E1"]),
+                request(file, "metric_2", &["E2"]),
+            ]
+        };
+        let expected = df!(
+            COL::GEO_ID => &["E1", "E2"],
+            "metric_1" => &[Some(1), None],
+            "metric_2" => &[None, Some(5)],
+        )
+        .unwrap();
+        let config = Config {
+            auth: Some(AuthConfig::Bearer {
+                token: "secret-token".into(),
+            }),
+            ..Config::default()
+        };
+
+        let scanned = get_metrics(&requests(&path.to_string_lossy()), None).unwrap();
+        let fetched = get_metrics_concurrent(
+            &requests(&format!("{server}/metrics.parquet")),
+            None,
+            &config,
+        )
+        .await
+        .unwrap();
+
+        for df in [scanned, fetched] {
+            let df = df.sort([COL::GEO_ID], Default::default()).unwrap();
+            assert!(df.equals_missing(&expected), "{df}");
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_should_skip_row_groups_without_requested_geoids() {
+        let n_rows = 400_000;
+        let geo_ids: Vec<String> = (0..n_rows).map(|i| format!("E{i:08}")).collect();
+        let mut df = df!(
+            COL::GEO_ID => &geo_ids,
+            "metric_1" => (0..n_rows as i64).map(|i| i * 7919 % 100_003).collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let mut bytes = vec![];
+        ParquetWriter::new(&mut bytes)
+            .with_row_group_size(Some(20_000))
+            .finish(&mut df)
+            .unwrap();
+        let file_size = bytes.len() as u64;
+        let server = file_server(HashMap::from([("/metrics.parquet".to_string(), bytes)])).await;
+        let metrics = [MetricRequest {
+            column: "metric_1".into(),
+            metric_file: format!("{server}/metrics.parquet"),
+            geom_file: "Not needed for this test".into(),
+            geoids: vec!["E00000010".into(), "E00012345".into()],
+            margin_of_error: None,
+        }];
+        let transferred = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let counter = transferred.clone();
+        let config = Config {
+            auth: Some(AuthConfig::Bearer {
+                token: "secret-token".into(),
+            }),
+            progress: ProgressCallback::new(move |event| {
+                if let ProgressEvent::Transferred { bytes, .. } = event {
+                    counter.fetch_add(bytes, std::sync::atomic::Ordering::SeqCst);
+                }
+            }),
+            ..Config::default()
+        };
+
+        let fetched = get_metrics_concurrent(&metrics, Some(&["E00012345"]), &config)
+            .await
+            .unwrap();
+
+        let expected =
+            df!(COL::GEO_ID => &["E00012345"], "metric_1" => &[12345 * 7919 % 100_003i64]).unwrap();
+        assert!(fetched.equals(&expected), "{fetched}");
+        let transferred = transferred.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(
+            transferred < file_size / 4,
+            "Transferred {transferred} of {file_size} bytes"
+        );
+    }
+
+    #[test]
+    fn merge_metric_requests_should_group_columns_by_file() {
+        let request = |column: &str, file: &str, geoids: &[&str]| MetricRequest {
+            column: column.into(),
+            metric_file: file.into(),
+            geom_file: "Not needed for this test".into(),
+            geoids: geoids.iter().map(|id| id.to_string()).collect(),
+            margin_of_error: None,
+        };
+        let merged = merge_metric_requests(&[
+            request("metric_1", "b.parquet", &["E1"]),
+            request("metric_2", "a.parquet", &[]),
+            request("metric_3", "b.parquet", &["E2", "E1"]),
+            request("metric_1", "b.parquet", &["E1"]),
+        ]);
+        assert_eq!(
+            merged,
+            [
+                FileRequest {
+                    metric_file: "b.parquet".into(),
+                    columns: vec!["metric_1".into(), "metric_3".into()],
+                    geoids: Some(vec!["E1".into(), "E2".into()]),
+                },
+                FileRequest {
+                    metric_file: "a.parquet".into(),
+                    columns: vec!["metric_2".into()],
+                    geoids: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn columns_in_one_file_should_be_fetched_together() {
+        let server = MockServer::start_async().await;
+        let file = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/metrics.parquet");
+                then.status(200).body(parquet_bytes(
+                    df!(
+                        COL::GEO_ID => &["E1", "E2"],
+                        "metric_1" => &[1, 2],
+                        "metric_2" => &[3, 4],
+                        "metric_3" => &[5, 6],
+                        "metric_4" => &[7, 8],
+                    )
+                    .unwrap(),
+                ));
+            })
+            .await;
+        let metrics = ["metric_1", "metric_2", "metric_3"].map(|column| MetricRequest {
+            column: column.into(),
+            metric_file: server.url("/metrics.parquet"),
+            geom_file: "Not needed for this test".into(),
+            geoids: vec![],
+            margin_of_error: None,
+        });
+
+        let df = get_metrics_concurrent(&metrics, None, &Config::default())
+            .await
+            .unwrap();
+
+        file.assert_hits_async(1).await;
+        assert_eq!(
+            df.get_column_names(),
+            [COL::GEO_ID, "metric_1", "metric_2", "metric_3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelling_download_should_return_promptly() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/metrics.parquet");
+                then.status(200)
+                    .delay(std::time::Duration::from_secs(30))
+                    .body(parquet_bytes(
+                        df!(COL::GEO_ID => &["E1"], "metric_1" => &[1]).unwrap(),
+                    ));
+            })
+            .await;
+        let metrics = [MetricRequest {
+            column: "metric_1".into(),
+            metric_file: server.url("/metrics.parquet"),
+            geom_file: "Not needed for this test".into(),
+            geoids: vec![],
+            margin_of_error: None,
+        }];
+        let token = tokio_util::sync::CancellationToken::new();
+        let config = Config {
+            cancellation: Cancellation::new(token.clone()),
+            ..Config::default()
+        };
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            token.cancel();
+        });
+        let start = std::time::Instant::now();
+        let err = get_metrics_concurrent(&metrics, None, &config)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PopgetterError>(),
+            Some(PopgetterError::Cancelled)
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn metrics_should_be_fetched_with_bearer_token() {
+        let server = MockServer::start_async().await;
+        let file = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/metrics.parquet")
+                    .header("authorization", "Bearer secret-token");
+                then.status(200).body(parquet_bytes(
+                    df!(COL::GEO_ID => &["E1", "E2"], "metric_1" => &[1, 2]).unwrap(),
+                ));
+            })
+            .await;
+        let metrics = [MetricRequest {
+            column: "metric_1".into(),
+            metric_file: server.url("/metrics.parquet"),
+            geom_file: "Not needed for this test".into(),
+            geoids: vec![],
+            margin_of_error: None,
+        }];
+        let config = Config {
+            auth: Some(AuthConfig::Bearer {
+                token: "secret-token".into(),
+            }),
+            ..Config::default()
+        };
+
+        let df = get_metrics_concurrent(&metrics, None, &config)
+            .await
+            .unwrap();
+
+        file.assert_hits_async(1).await;
+        assert_eq!(df.shape(), (2, 2));
+
+        // Fetching as configured also attaches the credentials
+        let df = get_metrics_with_config(&metrics, Some(&["E2"]), &config)
+            .await
+            .unwrap();
+        file.assert_hits_async(2).await;
+        assert_eq!(df.shape(), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn local_metrics_should_be_scanned_with_config() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("metrics.parquet");
+        std::fs::write(
+            &path,
+            parquet_bytes(df!(COL::GEO_ID => &["E1", "E2"], "metric_1" => &[1, 2]).unwrap()),
+        )
+        .unwrap();
+        let metrics = [MetricRequest {
+            column: "metric_1".into(),
+            metric_file: path.to_string_lossy().to_string(),
+            geom_file: "Not needed for this test".into(),
+            geoids: vec![],
+            margin_of_error: None,
+        }];
+        // Local files are scanned even with credentials set
+        let config = Config {
+            auth: Some(AuthConfig::Bearer {
+                token: "secret-token".into(),
+            }),
+            ..Config::default()
+        };
+        let df = get_metrics_with_config(&metrics, Some(&["E1"]), &config)
+            .await
+            .unwrap();
+        assert_eq!(df.shape(), (1, 2));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn each_file_should_be_routed_individually_with_config() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("local.parquet");
+        std::fs::write(
+            &path,
+            parquet_bytes(df!(COL::GEO_ID => &["E1", "E2"], "metric_1" => &[1, 2]).unwrap()),
+        )
+        .unwrap();
+        let server = MockServer::start_async().await;
+        let remote = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/remote.parquet")
+                    .header("authorization", "Bearer secret-token");
+                then.status(200).body(parquet_bytes(
+                    df!(COL::GEO_ID => &["E1", "E2"], "metric_2" => &[3, 4]).unwrap(),
+                ));
+            })
+            .await;
+        let request = |column: &str, metric_file: String| MetricRequest {
+            column: column.into(),
+            metric_file,
+            geom_file: "Not needed for this test".into(),
+            geoids: vec![],
+            margin_of_error: None,
+        };
+        let metrics = [
+            request("metric_1", path.to_string_lossy().to_string()),
+            request("metric_2", server.url("/remote.parquet")),
+        ];
+        let config = Config {
+            auth: Some(AuthConfig::Bearer {
+                token: "secret-token".into(),
+            }),
+            ..Config::default()
+        };
+
+        // The local file is scanned and the remote file fetched with the credentials
+        let df = get_metrics_with_config(&metrics, None, &config)
+            .await
+            .unwrap();
+        remote.assert_hits_async(1).await;
+        assert_eq!(df.shape(), (2, 3));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn remote_files_should_be_scanned_when_only_cancellable() {
+        let bytes =
+            parquet_bytes(df!(COL::GEO_ID => &["E1", "E2"], "metric_1" => &[1, 2]).unwrap());
+        let server = file_server(HashMap::from([("/metrics.parquet".to_string(), bytes)])).await;
+        let metrics = [MetricRequest {
+            column: "metric_1".into(),
+            metric_file: format!("{server}/metrics.parquet"),
+            geom_file: "Not needed for this test".into(),
+            geoids: vec![],
+            margin_of_error: None,
+        }];
+        let events = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = events.clone();
+        let config = Config {
+            cancellation: Cancellation::new(tokio_util::sync::CancellationToken::new()),
+            progress: ProgressCallback::new(move |event| recorded.lock().unwrap().push(event)),
+            ..Config::default()
+        };
+
+        let df = get_metrics_with_config(&metrics, Some(&["E2"]), &config)
+            .await
+            .unwrap();
+
+        assert_eq!(df.shape(), (1, 2));
+        // Only downloads made with the client report the bytes transferred
+        let events = events.lock().unwrap();
+        assert!(events
+            .iter()
+            .all(|event| !matches!(event, ProgressEvent::Transferred { .. })));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, ProgressEvent::Finished { success: true, .. })));
+    }
+
+    #[tokio::test]
+    async fn progress_should_be_reported_for_each_file() {
+        let server = MockServer::start_async().await;
+        let mut total_bytes = 0;
+        for (file, column) in [("a.parquet", "metric_1"), ("b.parquet", "metric_2")] {
+            let bytes = parquet_bytes(df!(COL::GEO_ID => &["E1"], column => &[1]).unwrap());
+            total_bytes += bytes.len() as u64;
+            server
+                .mock_async(|when, then| {
+                    when.method(GET).path(format!("/{file}"));
+                    then.status(200).body(bytes);
+                })
+                .await;
+        }
+        let metrics =
+            [("metric_1", "a.parquet"), ("metric_2", "b.parquet")].map(|(column, file)| {
+                MetricRequest {
+                    column: column.into(),
+                    metric_file: server.url(format!("/{file}")),
+                    geom_file: "Not needed for this test".into(),
+                    geoids: vec![],
+                    margin_of_error: None,
+                }
+            });
+        let events = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = events.clone();
+        let config = Config {
+            progress: ProgressCallback::new(move |event| recorded.lock().unwrap().push(event)),
+            ..Config::default()
+        };
+
+        get_metrics_concurrent(&metrics, None, &config)
+            .await
+            .unwrap();
+
+        let events = events.lock().unwrap();
+        let count = |f: fn(&ProgressEvent) -> bool| events.iter().filter(|e| f(e)).count();
+        assert_eq!(count(|e| matches!(e, ProgressEvent::Started { .. })), 2);
+        assert_eq!(
+            count(|e| matches!(e, ProgressEvent::Finished { success: true, .. })),
+            2
+        );
+        let transferred: u64 = events
+            .iter()
+            .filter_map(|event| match event {
+                ProgressEvent::Transferred { bytes, .. } => Some(bytes),
+                _ => None,
+            })
+            .sum();
+        assert_eq!(transferred, total_bytes);
+    }
+
+    #[tokio::test]
+    async fn fetch_should_only_transfer_requested_columns() {
+        let n_rows = 20_000;
+        let geo_ids: Vec<String> = (0..n_rows).map(|i| format!("E{i:08}")).collect();
+        let df = DataFrame::new(
+            std::iter::once(Series::new(COL::GEO_ID, &geo_ids))
+                .chain((0..20).map(|column| {
+                    Series::new(
+                        &format!("metric_{column}"),
+                        (0..n_rows as i64)
+                            .map(|i| (i * 7919 + column) % 100_003)
+                            .collect::<Vec<_>>(),
+                    )
+                }))
+                .collect(),
+        )
+        .unwrap();
+        let bytes = parquet_bytes(df.clone());
+        let file_size = bytes.len() as u64;
+        let server = file_server(HashMap::from([("/metrics.parquet".to_string(), bytes)])).await;
+        let metrics = ["metric_3", "metric_17"].map(|column| MetricRequest {
+            column: column.into(),
+            metric_file: format!("{server}/metrics.parquet"),
+            geom_file: "Not needed for this test".into(),
+            geoids: vec![],
+            margin_of_error: None,
+        });
+        let transferred = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let counter = transferred.clone();
+        let config = Config {
+            auth: Some(AuthConfig::Bearer {
+                token: "secret-token".into(),
+            }),
+            progress: ProgressCallback::new(move |event| {
+                if let ProgressEvent::Transferred { bytes, .. } = event {
+                    counter.fetch_add(bytes, std::sync::atomic::Ordering::SeqCst);
+                }
+            }),
+            ..Config::default()
+        };
+
+        let fetched = get_metrics_concurrent(&metrics, None, &config)
+            .await
+            .unwrap();
+
+        let expected = df.select([COL::GEO_ID, "metric_3", "metric_17"]).unwrap();
+        assert!(fetched.equals(&expected));
+        let transferred = transferred.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(
+            transferred < file_size / 4,
+            "Transferred {transferred} of {file_size} bytes"
+        );
+    }
+
+    #[tokio::test]
+    async fn estimate_download_should_use_range_requests_with_sas_token() {
+        let bytes = parquet_bytes(
+            df!(
+                COL::GEO_ID => &["E1", "E2", "E3"],
+                "metric_1" => &[1, 2, 3],
+            )
+            .unwrap(),
+        );
+        let footer_length = u32::from_le_bytes(bytes[bytes.len() - 8..][..4].try_into().unwrap());
+        let suffix = |length: usize| bytes[bytes.len() - length..].to_vec();
+        let content_range = |length: usize| {
+            format!(
+                "bytes {}-{}/{}",
+                bytes.len() - length,
+                bytes.len() - 1,
+                bytes.len()
+            )
+        };
+        let server = MockServer::start_async().await;
+        let tail = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/metrics.parquet")
+                    .query_param("sig", "secret")
+                    .header("range", "bytes=-8");
+                then.status(206)
+                    .header("content-range", content_range(8))
+                    .body(suffix(8));
+            })
+            .await;
+        let footer_range = format!("bytes=-{}", footer_length + 8);
+        let footer = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/metrics.parquet")
+                    .query_param("sig", "secret")
+                    .header("range", &footer_range);
+                then.status(206)
+                    .header("content-range", content_range(footer_length as usize + 8))
+                    .body(suffix(footer_length as usize + 8));
+            })
+            .await;
+        let metrics = [MetricRequest {
+            column: "metric_1".into(),
+            metric_file: server.url("/metrics.parquet"),
+            geom_file: "Not needed for this test".into(),
+            geoids: vec![],
+            margin_of_error: None,
+        }];
+        let config = Config {
+            auth: Some(AuthConfig::SasToken {
+                token: "sig=secret".into(),
+            }),
+            ..Config::default()
+        };
+
+        let estimate = estimate_download(&metrics, &config).await.unwrap();
+
+        tail.assert_hits_async(1).await;
+        footer.assert_hits_async(1).await;
+        assert_eq!(estimate.metric_columns, 1);
+        assert_eq!(estimate.geographic_units, 3);
+    }
+
+    #[test]
+    fn download_to_parquet_should_write_joined_metrics_to_disk() {
+        let tmp = TempDir::new().unwrap();
+        let n_rows = 5_000;
+        let geo_ids: Vec<String> = (0..n_rows).map(|i| format!("E{i:08}")).collect();
+        let mut metrics = vec![];
+        for (file, columns) in [
+            ("a.parquet", ["metric_1", "metric_2"]),
+            ("b.parquet", ["metric_3", "metric_4"]),
+            ("c.parquet", ["metric_5", "metric_6"]),
+        ] {
+            let path = tmp.path().join(file);
+            // Rows are in a different order in each file
+            let mut ids = geo_ids.clone();
+            ids.rotate_left(metrics.len() * 100);
+            let mut df =
+                DataFrame::new(
+                    std::iter::once(Series::new(COL::GEO_ID, &ids))
+                        .chain(columns.map(|column| {
+                            Series::new(column, (0..n_rows as u32).collect::<Vec<_>>())
+                        }))
+                        .collect(),
+                )
+                .unwrap();
+            ParquetWriter::new(File::create(&path).unwrap())
+                .finish(&mut df)
+                .unwrap();
+            metrics.extend(columns.map(|column| MetricRequest {
+                column: column.into(),
+                metric_file: path.to_string_lossy().to_string(),
+                geom_file: "Not needed for this test".into(),
+                geoids: vec![],
+                margin_of_error: None,
+            }));
+        }
+        let output = tmp.path().join("output.parquet");
+
+        download_to_parquet(&metrics, &output).unwrap();
+
+        let df = ParquetReader::new(File::open(&output).unwrap())
+            .finish()
+            .unwrap();
+        assert_eq!(df.height(), n_rows);
+        assert_eq!(
+            df.get_column_names(),
+            [
+                COL::GEO_ID,
+                "metric_1",
+                "metric_2",
+                "metric_3",
+                "metric_4",
+                "metric_5",
+                "metric_6"
+            ]
+        );
+        // The streaming join does not guarantee the order of the rows
+        let sorted = |df: DataFrame| df.sort([COL::GEO_ID], Default::default()).unwrap();
+        assert_eq!(sorted(df), sorted(get_metrics(&metrics, None).unwrap()));
+    }
+
+    #[test]
+    fn margin_of_error_should_be_fetched_alongside_metric() {
+        let tmp = TempDir::new().unwrap();
+        let write = |name: &str, mut df: DataFrame| -> String {
+            let path = tmp.path().join(name);
+            ParquetWriter::new(File::create(&path).unwrap())
+                .finish(&mut df)
+                .unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let estimates = write(
+            "estimates.parquet",
+            df!(
+                COL::GEO_ID => &["E1", "E2"],
+                "B01001_E001" => &[100, 200],
+                "B01002_E001" => &[30.5, 41.0],
+            )
+            .unwrap(),
+        );
+        let margins = write(
+            "margins.parquet",
+            df!(
+                COL::GEO_ID => &["E2", "E1"],
+                "B01001_M001" => &[12, 8],
+            )
+            .unwrap(),
+        );
+        let metrics = [
+            MetricRequest {
+                column: "B01001_E001".into(),
+                metric_file: estimates.clone(),
+                geom_file: "Not needed for this test".into(),
+                geoids: vec![],
+                margin_of_error: Some(MarginOfError {
+                    column: "B01001_M001".into(),
+                    file: margins,
+                }),
+            },
+            // Metrics without a margin of error have no `_moe` column
+            MetricRequest {
+                column: "B01002_E001".into(),
+                metric_file: estimates,
+                geom_file: "Not needed for this test".into(),
+                geoids: vec![],
+                margin_of_error: None,
+            },
+        ];
+
+        let df = get_metrics(&metrics, None)
+            .unwrap()
+            .sort([COL::GEO_ID], Default::default())
+            .unwrap();
+
+        assert_eq!(
+            df.get_column_names(),
+            [COL::GEO_ID, "B01001_E001", "B01001_E001_moe", "B01002_E001"]
+        );
+        assert_eq!(
+            df.column("B01001_E001_moe")
+                .unwrap()
+                .i32()
+                .unwrap()
+                .to_vec(),
+            [Some(8), Some(12)]
+        );
+    }
+
+    #[test]
+    fn percentage_should_be_null_for_zero_denominator() {
+        let df = df!(
+            COL::GEO_ID => &["E1", "E2", "E3", "E4"],
+            "households" => &[Some(25), Some(3), Some(0), Some(7)],
+            "population" => &[Some(200), Some(0), Some(0), None],
+        )
+        .unwrap();
+
+        let df = with_percentage(df, "households", "population").unwrap();
+
+        assert_eq!(
+            df.column("households_percentage")
+                .unwrap()
+                .f64()
+                .unwrap()
+                .to_vec(),
+            [Some(12.5), None, None, None]
+        );
+    }
 }
@@ -0,0 +1,79 @@
+//! Progress reporting for loading metadata and downloading metrics.
+
+use std::{fmt, sync::Arc};
+
+/// An event reported while fetching a file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// Started fetching the file at `path`
+    Started { path: String },
+    /// Received a further `bytes` bytes of the file at `path`. Only reported for files fetched
+    /// directly over HTTP, not for those scanned by polars.
+    Transferred { path: String, bytes: u64 },
+    /// Finished fetching the file at `path`, whether or not it succeeded
+    Finished { path: String, success: bool },
+}
+
+type Callback = dyn Fn(ProgressEvent) + Send + Sync;
+
+/// A callback invoked with each `ProgressEvent`, e.g. to drive a progress bar. The callback may
+/// be invoked concurrently from several tasks.
+#[derive(Clone, Default)]
+pub struct ProgressCallback(Option<Arc<Callback>>);
+
+impl ProgressCallback {
+    pub fn new(callback: impl Fn(ProgressEvent) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(callback)))
+    }
+
+    pub(crate) fn report(&self, event: ProgressEvent) {
+        if let Some(callback) = self.0.as_ref() {
+            callback(event);
+        }
+    }
+
+    pub(crate) fn started(&self, path: &str) {
+        self.report(ProgressEvent::Started {
+            path: path.to_string(),
+        });
+    }
+
+    pub(crate) fn finished<T, E>(&self, path: &str, result: &Result<T, E>) {
+        self.report(ProgressEvent::Finished {
+            path: path.to_string(),
+            success: result.is_ok(),
+        });
+    }
+
+    /// Read the body of `response`, reporting the bytes of `path` received as they arrive
+    pub(crate) async fn read_body(
+        &self,
+        path: &str,
+        mut response: reqwest::Response,
+    ) -> reqwest::Result<Vec<u8>> {
+        let mut body = vec![];
+        while let Some(chunk) = response.chunk().await? {
+            self.report(ProgressEvent::Transferred {
+                path: path.to_string(),
+                bytes: chunk.len() as u64,
+            });
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ProgressCallback")
+            .field(&self.0.is_some())
+            .finish()
+    }
+}
+
+/// Callbacks cannot be compared, so they are ignored when comparing configs
+impl PartialEq for ProgressCallback {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
@@ -3,25 +3,60 @@
 use crate::{
     config::Config,
     data_request_spec::RegionSpec,
-    geo::get_geometries,
-    metadata::ExpandedMetadata,
-    parquet::{get_metrics, MetricRequest},
+    error::PopgetterError,
+    geo::{countries_in_bboxes, geo_ids_in_bbox, geometries_in_bbox, get_geometries, BBox},
+    metadata::{geo_id_columns, join_path, ExpandedMetadata, Metadata, MetricUnit},
+    parquet::{get_metrics_with_config, MarginOfError, MetricRequest, MARGIN_OF_ERROR_SUFFIX},
+    trace::{record_rows, SpanTimer},
+    transform::TransformPipeline,
     COL,
 };
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use chrono::NaiveDate;
-use log::{debug, error, warn};
+use futures::{stream, Stream, StreamExt};
+use itertools::Itertools;
+use log::{debug, error};
 use nonempty::{nonempty, NonEmpty};
-use polars::lazy::dsl::{col, lit, Expr};
-use polars::prelude::{DataFrame, DataFrameJoinOps, IntoLazy, LazyFrame};
+use polars::lazy::dsl::{col, lit, when, Expr};
+use polars::prelude::{
+    ChunkApply, CsvWriter, DataFrame, DataFrameJoinOps, GetOutput, IntoLazy, IntoSeries, LazyFrame,
+    NamedFrom, Null, ParquetCompression, ParquetReader, ParquetWriter, SerReader, SerWriter,
+    Series, SortMultipleOptions, StringChunked,
+};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, str::FromStr};
+use serde_json::{Map, Value};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
+    io::Write,
+    path::Path,
+    str::FromStr,
+};
 use tokio::try_join;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Number of metrics `SearchParams::search_stream` summarises at once
+const SEARCH_STREAM_BATCH_SIZE: usize = 256;
+
+/// Name of the column added to `SearchResults` containing the relevance score of each metric
+pub const RELEVANCE_SCORE: &str = "relevance_score";
+
+/// Name of the column in `SearchResults::group_by_concept` listing the years covered by each concept
+pub const CONCEPT_YEARS: &str = "years";
+
+/// Name of the column added by `SearchResults::with_units` flagging units that were inferred from
+/// the HXL tag rather than given in the metadata
+pub const METRIC_UNIT_INFERRED: &str = "metric_unit_inferred";
+
+/// Keys used to group metrics in `SearchResults::group_by_concept`
+const CONCEPT_NAME: &str = "concept_name";
+const CONCEPT_HXL: &str = "concept_hxl";
 
 // TODO: add trait/struct for combine_exprs
 
 /// Combine multiple queries with OR. If there are no queries in the input list, returns None.
-fn combine_exprs_with_or(exprs: Vec<Expr>) -> Option<Expr> {
+pub(crate) fn combine_exprs_with_or(exprs: Vec<Expr>) -> Option<Expr> {
     let mut query: Option<Expr> = None;
     for expr in exprs {
         query = if let Some(partial_query) = query {
@@ -68,46 +103,71 @@ fn _combine_exprs_with_and1(exprs: NonEmpty<Expr>) -> Expr {
 
 /// Search in a column case-insensitively for a string literal (i.e. not a regex!). The search
 /// parameter can appear anywhere in the column value.
-fn filter_contains(column: &str, value: &str, case_sensitivity: &CaseSensitivity) -> Expr {
+fn filter_contains(column: Expr, value: &str, case_sensitivity: &CaseSensitivity) -> Expr {
     let regex = match case_sensitivity {
         CaseSensitivity::Insensitive => format!("(?i){}", regex::escape(value)),
         CaseSensitivity::Sensitive => regex::escape(value).to_string(),
     };
-    col(column).str().contains(lit(regex), false)
+    column.str().contains(lit(regex), false)
 }
 
 /// Search in a column for a string literal (i.e. not a regex!). The search parameter must be a
 /// prefix of the column value.
-fn filter_startswith(column: &str, value: &str, case_sensitivity: &CaseSensitivity) -> Expr {
+fn filter_startswith(column: Expr, value: &str, case_sensitivity: &CaseSensitivity) -> Expr {
     let regex = match case_sensitivity {
         CaseSensitivity::Insensitive => format!("(?i)^{}", regex::escape(value)),
         CaseSensitivity::Sensitive => format!("^{}", regex::escape(value)),
     };
-    col(column).str().contains(lit(regex), false)
+    column.str().contains(lit(regex), false)
 }
 
 /// Search in a column case-insensitively for a string literal (i.e. not a regex!). The search
 /// parameter must be a prefix of the column value.
-fn filter_exact(column: &str, value: &str, case_sensitivity: &CaseSensitivity) -> Expr {
+fn filter_exact(column: Expr, value: &str, case_sensitivity: &CaseSensitivity) -> Expr {
     let regex = match case_sensitivity {
         CaseSensitivity::Insensitive => format!("(?i)^{}$", regex::escape(value)),
         CaseSensitivity::Sensitive => format!("^{}$", regex::escape(value)),
     };
-    col(column).str().contains(lit(regex), false)
+    column.str().contains(lit(regex), false)
 }
 
 /// Search in a column for a regex (case insensitively)
-fn filter_regex(column: &str, value: &str, case_sensitivity: &CaseSensitivity) -> Expr {
+fn filter_regex(column: Expr, value: &str, case_sensitivity: &CaseSensitivity) -> Expr {
     let regex = match case_sensitivity {
         CaseSensitivity::Insensitive => format!("(?i){}", value),
         CaseSensitivity::Sensitive => value.to_string(),
     };
-    col(column).str().contains(lit(regex), false)
+    column.str().contains(lit(regex), false)
+}
+
+/// Decompose `text` (Unicode NFKD) and remove combining marks, so that accented characters match
+/// their unaccented forms, e.g. "Région" becomes "Region"
+fn strip_diacritics(text: &str) -> Cow<'_, str> {
+    if text.is_ascii() {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(text.nfkd().filter(|c| !is_combining_mark(*c)).collect())
+    }
+}
+
+/// Apply `strip_diacritics` to each value of a string column
+fn strip_diacritics_expr(column: Expr) -> Expr {
+    column.map(
+        |series| {
+            Ok(Some(
+                series
+                    .str()?
+                    .apply_values(|value| strip_diacritics(value))
+                    .into_series(),
+            ))
+        },
+        GetOutput::same_type(),
+    )
 }
 
 /// Where we want to search for a text string in. Pass multiple search contexts to search in all of
 /// them.
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 pub enum SearchContext {
     Hxl,
     HumanReadableName,
@@ -120,8 +180,8 @@ impl SearchContext {
     }
 }
 
-// TODO: can  this be written with From<&MatchType> for impl Fn(&str, &str, &CaseSensitivity) -> Expr
-fn get_filter_fn(match_type: &MatchType) -> impl Fn(&str, &str, &CaseSensitivity) -> Expr {
+// TODO: can  this be written with From<&MatchType> for impl Fn(Expr, &str, &CaseSensitivity) -> Expr
+fn get_filter_fn(match_type: &MatchType) -> impl Fn(Expr, &str, &CaseSensitivity) -> Expr {
     match match_type {
         MatchType::Regex => filter_regex,
         MatchType::Exact => filter_exact,
@@ -130,33 +190,84 @@ fn get_filter_fn(match_type: &MatchType) -> impl Fn(&str, &str, &CaseSensitivity
     }
 }
 
-fn get_queries_for_search_text<F: Fn(&str, &str, &CaseSensitivity) -> Expr>(
-    filter_fn: F,
+fn get_queries_for_search_text<F: Fn(Expr, &str, &CaseSensitivity) -> Expr>(
+    default_filter_fn: F,
     val: SearchText,
 ) -> Expr {
-    let queries: NonEmpty<Expr> = val.context.map(|field| match field {
-        SearchContext::Hxl => {
-            filter_fn(COL::METRIC_HXL_TAG, &val.text, &val.config.case_sensitivity)
+    let queries: NonEmpty<Expr> = val.context.clone().map(|field| {
+        // A match type given for this context takes precedence over that of the whole search
+        let filter_fn = |column: Expr, value: &str, case_sensitivity: &CaseSensitivity| match val
+            .match_types
+            .get(&field)
+        {
+            Some(match_type) => get_filter_fn(match_type)(column, value, case_sensitivity),
+            None => default_filter_fn(column, value, case_sensitivity),
+        };
+        match field {
+            SearchContext::Hxl => filter_fn(
+                col(COL::METRIC_HXL_TAG),
+                &val.text,
+                &val.config.case_sensitivity,
+            ),
+            SearchContext::HumanReadableName => filter_fn(
+                strip_diacritics_expr(col(COL::METRIC_HUMAN_READABLE_NAME)),
+                &strip_diacritics(&val.text),
+                &val.config.case_sensitivity,
+            ),
+            SearchContext::Description => filter_fn(
+                strip_diacritics_expr(col(COL::METRIC_DESCRIPTION)),
+                &strip_diacritics(&val.text),
+                &val.config.case_sensitivity,
+            ),
         }
-        SearchContext::HumanReadableName => filter_fn(
-            COL::METRIC_HUMAN_READABLE_NAME,
-            &val.text,
-            &val.config.case_sensitivity,
-        ),
-        SearchContext::Description => filter_fn(
-            COL::METRIC_DESCRIPTION,
-            &val.text,
-            &val.config.case_sensitivity,
-        ),
     });
     combine_exprs_with_or1(queries)
 }
 
+/// Expression scoring how well each metric matches the text searches: an exact match on the human
+/// readable name scores 3, a substring match on the name scores 2 and a match on the description
+/// only scores 1. Scores are summed over all text searches.
+fn relevance_score_expr(texts: &[SearchText]) -> Expr {
+    texts
+        .iter()
+        .map(|text| {
+            let case_sensitivity = &text.config.case_sensitivity;
+            let name = strip_diacritics_expr(col(COL::METRIC_HUMAN_READABLE_NAME));
+            let description = strip_diacritics_expr(col(COL::METRIC_DESCRIPTION));
+            let text = SearchText {
+                text: strip_diacritics(&text.text).into_owned(),
+                ..text.clone()
+            };
+            let filter_contains_fn = match (text.exact, text.config.match_type) {
+                (true, MatchType::Regex) => filter_regex,
+                _ => filter_contains,
+            };
+            when(filter_exact(name.clone(), &text.text, case_sensitivity))
+                .then(lit(3u32))
+                .when(filter_contains_fn(name, &text.text, case_sensitivity))
+                .then(lit(2u32))
+                .when(filter_contains_fn(
+                    description,
+                    &text.text,
+                    case_sensitivity,
+                ))
+                .then(lit(1u32))
+                .otherwise(lit(0u32))
+        })
+        .reduce(|score, text_score| score + text_score)
+        .unwrap_or(lit(0u32))
+        .alias(RELEVANCE_SCORE)
+}
+
 /// Implementing conversion from `SearchText` to a polars expression enables a
 /// `SearchText` to be passed to polars dataframe for filtering results.
 impl From<SearchText> for Expr {
     fn from(val: SearchText) -> Self {
-        get_queries_for_search_text(get_filter_fn(&val.config.match_type), val)
+        if val.exact {
+            get_queries_for_search_text(get_filter_fn(&val.config.match_type), val)
+        } else {
+            get_queries_for_search_text(filter_contains, val)
+        }
     }
 }
 
@@ -193,7 +304,7 @@ impl From<YearRange> for Expr {
 impl From<DataPublisher> for Expr {
     fn from(value: DataPublisher) -> Self {
         get_filter_fn(&value.config.match_type)(
-            COL::DATA_PUBLISHER_NAME,
+            col(COL::DATA_PUBLISHER_NAME),
             &value.value,
             &value.config.case_sensitivity,
         )
@@ -203,7 +314,7 @@ impl From<DataPublisher> for Expr {
 impl From<SourceDownloadUrl> for Expr {
     fn from(value: SourceDownloadUrl) -> Self {
         get_filter_fn(&value.config.match_type)(
-            COL::METRIC_SOURCE_DOWNLOAD_URL,
+            col(COL::METRIC_SOURCE_DOWNLOAD_URL),
             &value.value,
             &value.config.case_sensitivity,
         )
@@ -213,7 +324,7 @@ impl From<SourceDownloadUrl> for Expr {
 impl From<SourceDataRelease> for Expr {
     fn from(value: SourceDataRelease) -> Self {
         get_filter_fn(&value.config.match_type)(
-            COL::SOURCE_DATA_RELEASE_NAME,
+            col(COL::SOURCE_DATA_RELEASE_NAME),
             &value.value,
             &value.config.case_sensitivity,
         )
@@ -223,33 +334,36 @@ impl From<SourceDataRelease> for Expr {
 impl From<GeometryLevel> for Expr {
     fn from(value: GeometryLevel) -> Self {
         get_filter_fn(&value.config.match_type)(
-            COL::GEOMETRY_LEVEL,
+            col(COL::GEOMETRY_LEVEL),
             &value.value,
             &value.config.case_sensitivity,
         )
     }
 }
 
-fn combine_country_fn<F: Fn(&str, &str, &CaseSensitivity) -> Expr>(func: F, value: &str) -> Expr {
+fn combine_country_fn<F: Fn(Expr, &str, &CaseSensitivity) -> Expr>(func: F, value: &str) -> Expr {
     // Assumes case insensitive
     combine_exprs_with_or(vec![
         func(
-            COL::COUNTRY_NAME_SHORT_EN,
+            col(COL::COUNTRY_NAME_SHORT_EN),
+            value,
+            &CaseSensitivity::Insensitive,
+        ),
+        func(
+            col(COL::COUNTRY_NAME_OFFICIAL),
             value,
             &CaseSensitivity::Insensitive,
         ),
+        func(col(COL::COUNTRY_ISO2), value, &CaseSensitivity::Insensitive),
+        func(col(COL::COUNTRY_ISO3), value, &CaseSensitivity::Insensitive),
         func(
-            COL::COUNTRY_NAME_OFFICIAL,
+            col(COL::COUNTRY_ISO3166_2),
             value,
             &CaseSensitivity::Insensitive,
         ),
-        func(COL::COUNTRY_ISO2, value, &CaseSensitivity::Insensitive),
-        func(COL::COUNTRY_ISO3, value, &CaseSensitivity::Insensitive),
-        func(COL::COUNTRY_ISO3166_2, value, &CaseSensitivity::Insensitive),
-        // TODO: add `COUNTRY_ID` for ExpandedMetadata
-        // func(COL::COUNTRY_ID, &value, &CaseSensitivity::Insensitive),
+        func(col(COL::COUNTRY_ID), value, &CaseSensitivity::Insensitive),
         func(
-            COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST,
+            col(COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST),
             value,
             &CaseSensitivity::Insensitive,
         ),
@@ -267,28 +381,47 @@ impl From<Country> for Expr {
 impl From<SourceMetricId> for Expr {
     fn from(value: SourceMetricId) -> Self {
         get_filter_fn(&value.config.match_type)(
-            COL::METRIC_SOURCE_METRIC_ID,
+            col(COL::METRIC_SOURCE_METRIC_ID),
             &value.value,
             &value.config.case_sensitivity,
         )
     }
 }
 
+impl From<HxlAttribute> for Expr {
+    fn from(value: HxlAttribute) -> Self {
+        // The attribute must be a whole `+attribute` token: followed by another attribute, space
+        // or the end of the tag, so that `+total` does not match `+totally`
+        let attribute = value.0.trim().trim_start_matches('+').trim();
+        let regex = format!(r"(?i)\+\s*{}(\s|\+|$)", regex::escape(attribute));
+        col(COL::METRIC_HXL_TAG).str().contains(lit(regex), false)
+    }
+}
+
 impl From<MetricId> for Expr {
     fn from(value: MetricId) -> Self {
         get_filter_fn(&value.config.match_type)(
-            COL::METRIC_ID,
+            col(COL::METRIC_ID),
             &value.id,
             &value.config.case_sensitivity,
         )
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Search over the text columns of the metrics. Unless `exact` is set, the text is matched as a
+/// literal substring (with any regex special characters escaped) and `config.match_type` is
+/// ignored; set `exact` to match according to `config.match_type`, e.g. exact equality or a regex.
+/// A match type given for a context in `match_types` is always used for that context, so that e.g.
+/// the HXL tag can be matched exactly while the name is matched as a substring.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct SearchText {
     pub text: String,
     pub context: NonEmpty<SearchContext>,
     pub config: SearchConfig,
+    #[serde(default)]
+    pub exact: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub match_types: BTreeMap<SearchContext, MatchType>,
 }
 
 impl Default for SearchText {
@@ -301,6 +434,93 @@ impl Default for SearchText {
                 match_type: MatchType::Exact,
                 case_sensitivity: CaseSensitivity::Insensitive,
             },
+            exact: false,
+            match_types: BTreeMap::new(),
+        }
+    }
+}
+
+/// A query built by composing the individual search filters with AND, OR and NOT, for searches
+/// that cannot be expressed with `SearchParams`. For example, metrics about population or
+/// households but not for 2011:
+///
+/// ```
+/// use popgetter::search::{SearchQuery, SearchText, YearRange};
+///
+/// let text = |text: &str| {
+///     SearchQuery::Text(SearchText {
+///         text: text.to_string(),
+///         ..SearchText::default()
+///     })
+/// };
+/// let query = text("population")
+///     .or(text("households"))
+///     .and(SearchQuery::YearRange(YearRange::Between(2011, 2011)).not());
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum SearchQuery {
+    Text(SearchText),
+    YearRange(YearRange),
+    MetricId(MetricId),
+    GeometryLevel(GeometryLevel),
+    SourceDataRelease(SourceDataRelease),
+    DataPublisher(DataPublisher),
+    SourceDownloadUrl(SourceDownloadUrl),
+    Country(Country),
+    SourceMetricId(SourceMetricId),
+    HxlAttribute(HxlAttribute),
+    /// Matches metrics matching all of the queries, or all metrics if there are none
+    And(Vec<SearchQuery>),
+    /// Matches metrics matching any of the queries, or no metrics if there are none
+    Or(Vec<SearchQuery>),
+    Not(Box<SearchQuery>),
+}
+
+impl SearchQuery {
+    pub fn and(self, other: SearchQuery) -> SearchQuery {
+        SearchQuery::And(vec![self, other])
+    }
+
+    pub fn or(self, other: SearchQuery) -> SearchQuery {
+        SearchQuery::Or(vec![self, other])
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> SearchQuery {
+        SearchQuery::Not(Box::new(self))
+    }
+
+    pub fn search(self, expanded_metadata: &ExpandedMetadata) -> anyhow::Result<SearchResults> {
+        debug!("Searching with query: {:?}", self);
+        let expr: Expr = self.into();
+        Ok(SearchResults(
+            expanded_metadata.as_df().filter(expr).collect()?,
+        ))
+    }
+}
+
+impl From<SearchQuery> for Expr {
+    fn from(value: SearchQuery) -> Self {
+        match value {
+            SearchQuery::Text(v) => v.into(),
+            SearchQuery::YearRange(v) => v.into(),
+            SearchQuery::MetricId(v) => v.into(),
+            SearchQuery::GeometryLevel(v) => v.into(),
+            SearchQuery::SourceDataRelease(v) => v.into(),
+            SearchQuery::DataPublisher(v) => v.into(),
+            SearchQuery::SourceDownloadUrl(v) => v.into(),
+            SearchQuery::Country(v) => v.into(),
+            SearchQuery::SourceMetricId(v) => v.into(),
+            SearchQuery::HxlAttribute(v) => v.into(),
+            SearchQuery::And(queries) => {
+                combine_exprs_with_and(queries.into_iter().map(Into::into).collect())
+                    .unwrap_or(lit(true))
+            }
+            SearchQuery::Or(queries) => {
+                combine_exprs_with_or(queries.into_iter().map(Into::into).collect())
+                    .unwrap_or(lit(false))
+            }
+            SearchQuery::Not(query) => Expr::from(*query).not(),
         }
     }
 }
@@ -347,13 +567,27 @@ impl FromStr for YearRange {
 }
 
 /// Search over metric IDs
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct MetricId {
     pub id: String,
     #[serde(default = "default_metric_id_search_config")]
     pub config: SearchConfig,
 }
 
+/// Search for metrics matching all of a list of text searches, e.g. both an HXL tag fragment and a
+/// human readable name. This can be used to pick out a single metric where a HXL tag is shared by
+/// metrics across years.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CompositeMetric(pub Vec<SearchText>);
+
+/// The text searches in a `CompositeMetric` are combined with AND. Returns None if there are no
+/// text searches.
+impl From<CompositeMetric> for Option<Expr> {
+    fn from(value: CompositeMetric) -> Self {
+        combine_exprs_with_and(value.0.into_iter().map(Expr::from).collect())
+    }
+}
+
 fn default_metric_id_search_config() -> SearchConfig {
     SearchConfig {
         match_type: MatchType::Startswith,
@@ -361,7 +595,7 @@ fn default_metric_id_search_config() -> SearchConfig {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub enum MatchType {
     Regex,
     #[default]
@@ -370,7 +604,7 @@ pub enum MatchType {
     Startswith,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub enum CaseSensitivity {
     #[default]
     Insensitive,
@@ -378,7 +612,7 @@ pub enum CaseSensitivity {
 }
 
 /// Configuration for searching.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SearchConfig {
     /// Whether string matching is exact or uses regex.
     pub match_type: MatchType,
@@ -387,84 +621,316 @@ pub struct SearchConfig {
 }
 
 /// Search over geometry levels
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct GeometryLevel {
     pub value: String,
     pub config: SearchConfig,
 }
 
+/// Aliases of geometry levels that are always recognised, in addition to
+/// `Config::geometry_level_aliases`
+pub const GEOMETRY_LEVEL_ALIASES: &[(&str, &[&str])] = &[
+    ("oa", &["Output Area"]),
+    (
+        "lsoa",
+        &["Lower Super Output Area", "Lower Layer Super Output Area"],
+    ),
+    (
+        "msoa",
+        &["Middle Super Output Area", "Middle Layer Super Output Area"],
+    ),
+];
+
+/// Normalize a geometry level for comparison, ignoring case and treating runs of whitespace, `_`
+/// and `-` as a single space
+fn normalize_geometry_level(level: &str) -> String {
+    level
+        .split(|c: char| c.is_whitespace() || c == '_' || c == '-')
+        .filter(|part| !part.is_empty())
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Resolve `value` to the one of `levels` that it refers to, either because they are equal once
+/// normalized or because `value` is an alias of the level in `aliases` (a map from geometry level
+/// to its aliases) or `GEOMETRY_LEVEL_ALIASES`. Returns `None` if `value` refers to none of
+/// `levels`, and an error listing the levels if it refers to more than one.
+pub fn resolve_geometry_level<S: AsRef<str>>(
+    value: &str,
+    levels: &[S],
+    aliases: &BTreeMap<String, Vec<String>>,
+) -> anyhow::Result<Option<String>> {
+    let normalized = normalize_geometry_level(value);
+    let builtin = GEOMETRY_LEVEL_ALIASES
+        .iter()
+        .flat_map(|(level, aliases)| aliases.iter().map(move |alias| (*level, *alias)));
+    let configured = aliases.iter().flat_map(|(level, aliases)| {
+        aliases
+            .iter()
+            .map(move |alias| (level.as_str(), alias.as_str()))
+    });
+    let mut targets = BTreeSet::from([normalized.clone()]);
+    targets.extend(
+        builtin
+            .chain(configured)
+            .filter(|(_, alias)| normalize_geometry_level(alias) == normalized)
+            .map(|(level, _)| normalize_geometry_level(level)),
+    );
+    let matching: Vec<&str> = levels
+        .iter()
+        .map(AsRef::as_ref)
+        .filter(|level| targets.contains(&normalize_geometry_level(level)))
+        .unique()
+        .collect();
+    match matching.as_slice() {
+        [] => Ok(None),
+        [level] => Ok(Some(level.to_string())),
+        _ => bail!(
+            "Geometry level '{value}' is ambiguous, it could refer to any of: {}",
+            matching.iter().map(|level| format!("'{level}'")).join(", ")
+        ),
+    }
+}
+
 /// Search over source data release names
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct SourceDataRelease {
     pub value: String,
     pub config: SearchConfig,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct SourceDownloadUrl {
     pub value: String,
     pub config: SearchConfig,
 }
 
 /// Search over data publisher names
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct DataPublisher {
     pub value: String,
     pub config: SearchConfig,
 }
 
 /// Search over country (short English names)
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Country {
     pub value: String,
     pub config: SearchConfig,
 }
 
 /// Search over source metric IDs in the original census table
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct SourceMetricId {
     pub value: String,
     pub config: SearchConfig,
 }
 
+/// Search for metrics whose HXL tag has the given attribute, e.g. `+total` (the leading `+` is
+/// optional). HXL tags are of the form `#hashtag+attribute1+attribute2`; only whole attributes are
+/// matched, case insensitively, so `+total` matches `#population+total+2023` but not
+/// `#population+totally`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct HxlAttribute(pub String);
+
+/// How the `text` searches of `SearchParams` are combined with each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum TextCombination {
+    /// A metric must match every text search
+    #[default]
+    All,
+    /// A metric must match at least one text search
+    Any,
+}
+
 /// This struct represents all the possible parameters one can search the metadata catalogue with.
 /// All parameters are optional in that they can either be empty vectors or None.
 ///
 /// Aside from `metric_id`, each of the fields are combined with an AND operation, so searching for
 /// both text and a year range will only return metrics that satisfy both parameters.
 ///
-/// However, if a parameter has multiple values (e.g. multiple text strings), these are combined
-/// with an OR operation. So searching for multiple text strings will return metrics that satisfy
-/// any of the text strings.
+/// However, if a parameter has multiple values (e.g. multiple year ranges), these are combined
+/// with an OR operation. So searching for multiple year ranges will return metrics that satisfy
+/// any of the year ranges. The exception is `text`, whose searches are combined as given by
+/// `text_combination`: by default a metric must satisfy all of the text searches, but with
+/// `TextCombination::Any` it need only satisfy one of them.
 ///
 /// `metric_id` is considered distinctly since the list of values uniquely identifies a set of
 /// metrics. This list of metrics is combined with the final combined expression of the other fields
 /// with an OR operation. This enables a search or recipe to contain a combination of specific
-/// `metric_id`s and other fields.
-#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+/// `metric_id`s and other fields. Each of the `composite_metric` searches is treated in the same
+/// way as a `metric_id`.
+///
+/// Finally, metrics matching any of the `exclude_*` fields are removed from the results, including
+/// those selected by `metric_id`. Empty `exclude_*` fields do not exclude anything.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
 pub struct SearchParams {
     pub text: Vec<SearchText>,
+    #[serde(default)]
+    pub text_combination: TextCombination,
     pub year_range: Option<Vec<YearRange>>,
     pub metric_id: Vec<MetricId>,
+    #[serde(default)]
+    pub composite_metric: Vec<CompositeMetric>,
     pub geometry_level: Option<GeometryLevel>,
     pub source_data_release: Option<SourceDataRelease>,
     pub data_publisher: Option<DataPublisher>,
     pub source_download_url: Option<SourceDownloadUrl>,
     pub country: Option<Country>,
     pub source_metric_id: Option<SourceMetricId>,
+    #[serde(default)]
+    pub hxl_attribute: Vec<HxlAttribute>,
+    #[serde(default)]
+    pub exclude_geometry_level: Vec<GeometryLevel>,
+    #[serde(default)]
+    pub exclude_data_publisher: Vec<DataPublisher>,
+    #[serde(default)]
+    pub exclude_country: Vec<Country>,
     pub region_spec: Vec<RegionSpec>,
 }
 
 impl SearchParams {
-    pub fn search(self, expanded_metadata: &ExpandedMetadata) -> SearchResults {
+    /// Resolve the geometry levels searched for with `MatchType::Exact` to the geometry levels of
+    /// `metadata`, so that e.g. `LSOA` and `Lower Super Output Area` both find the metrics at the
+    /// `lsoa` level (see `resolve_geometry_level`). Levels that resolve to no geometry level are
+    /// left unchanged, as are searches with other match types.
+    pub fn resolve_geometry_levels(
+        mut self,
+        metadata: &Metadata,
+        aliases: &BTreeMap<String, Vec<String>>,
+    ) -> anyhow::Result<Self> {
+        let mut to_resolve: Vec<&mut GeometryLevel> = self
+            .geometry_level
+            .iter_mut()
+            .chain(self.exclude_geometry_level.iter_mut())
+            .filter(|level| level.config.match_type == MatchType::Exact)
+            .collect();
+        if to_resolve.is_empty() {
+            return Ok(self);
+        }
+        let levels = metadata.geometry_levels()?;
+        for level in to_resolve.iter_mut() {
+            if let Some(resolved) = resolve_geometry_level(&level.value, &levels, aliases)? {
+                level.value = resolved;
+                level.config.case_sensitivity = CaseSensitivity::Sensitive;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Restrict the search to the countries of `metadata` overlapping any of the WGS84 `bboxes`,
+    /// e.g. from geocoding, replacing any country already searched for. A bounding box spanning
+    /// a border includes all the countries it overlaps, and countries without known bounds are
+    /// always included (see `geo::countries_in_bboxes`). Returns an error if the bounding boxes
+    /// overlap no country in the catalogue.
+    pub fn restrict_to_bboxes(
+        &mut self,
+        bboxes: &[BBox],
+        metadata: &Metadata,
+    ) -> anyhow::Result<()> {
+        let catalogue_countries = metadata
+            .countries
+            .column(COL::COUNTRY_ISO3)?
+            .str()?
+            .into_iter()
+            .flatten()
+            .collect_vec();
+        let countries = countries_in_bboxes(bboxes, &catalogue_countries);
+        if countries.is_empty() {
+            bail!("The bounding boxes do not overlap any country with data available");
+        }
+        self.country = Some(Country {
+            value: format!("^({})$", countries.join("|")),
+            config: SearchConfig {
+                match_type: MatchType::Regex,
+                case_sensitivity: CaseSensitivity::Insensitive,
+            },
+        });
+        Ok(())
+    }
+
+    /// Serialize the search as a JSON recipe, which can be saved and replayed with
+    /// `from_recipe_json`
+    pub fn to_recipe_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Load a search saved with `to_recipe_json`
+    pub fn from_recipe_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Like `search`, but returns the query plan for the search without collecting it, with the
+    /// relevance score of each metric
+    pub fn search_lazy(self, expanded_metadata: &ExpandedMetadata) -> LazyFrame {
         debug!("Searching with request: {:?}", self);
+        let score_expr = relevance_score_expr(&self.text);
         let expr: Option<Expr> = self.into();
         let full_results: LazyFrame = expanded_metadata.as_df();
         let result: LazyFrame = match expr {
             Some(expr) => full_results.filter(expr),
             None => full_results,
         };
-        SearchResults(result.collect().unwrap())
+        result.with_column(score_expr)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "search_results",
+            skip_all,
+            fields(rows = tracing::field::Empty, elapsed_ms = tracing::field::Empty),
+        )
+    )]
+    pub fn search(self, expanded_metadata: &ExpandedMetadata) -> SearchResults {
+        let _timer = SpanTimer::start();
+        let results = SearchResults(self.search_lazy(expanded_metadata).collect().unwrap());
+        record_rows(results.0.height());
+        results
+    }
+
+    /// Like `search`, but produces a summary of each matching metric as a stream. The search is
+    /// collected once with the polars streaming engine on a blocking thread, and the summaries are
+    /// then produced from slices of the results, so the summaries of all of the results are never
+    /// held in memory at once and each metric is produced exactly once. An error from polars is
+    /// produced as the only item of the stream. Nothing is run until the stream is first polled,
+    /// which must be within a tokio runtime.
+    pub fn search_stream(
+        self,
+        expanded_metadata: &ExpandedMetadata,
+    ) -> impl Stream<Item = anyhow::Result<MetricSummary>> {
+        self.search_stream_in_batches(expanded_metadata, SEARCH_STREAM_BATCH_SIZE)
+    }
+
+    fn search_stream_in_batches(
+        self,
+        expanded_metadata: &ExpandedMetadata,
+        batch_size: usize,
+    ) -> impl Stream<Item = anyhow::Result<MetricSummary>> {
+        let lazy = self.search_lazy(expanded_metadata);
+        stream::once(async move {
+            tokio::task::spawn_blocking(move || lazy.with_streaming(true).collect())
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| Ok(result?))
+        })
+        .map(move |result| match result {
+            Ok(df) => {
+                // Slicing the collected results is zero-copy, whereas slicing the plan would run
+                // the whole search again for each batch, possibly with the rows in another order
+                let batches = (0..df.height())
+                    .step_by(batch_size)
+                    .map(|offset| df.slice(offset as i64, batch_size))
+                    .collect_vec();
+                stream::iter(batches)
+                    .flat_map(|batch| {
+                        stream::iter(SearchResults(batch).iter().map(Ok).collect_vec())
+                    })
+                    .left_stream()
+            }
+            Err(err) => stream::iter(vec![Err(err)]).right_stream(),
+        })
+        .flatten()
     }
 }
 
@@ -473,7 +939,7 @@ fn to_queries_then_or<T: Into<Expr>>(queries: Vec<T>) -> Option<Expr> {
     combine_exprs_with_or(queries)
 }
 
-fn _to_optqueries_then_or<T: Into<Option<Expr>>>(queries: Vec<T>) -> Option<Expr> {
+fn to_optqueries_then_or<T: Into<Option<Expr>>>(queries: Vec<T>) -> Option<Expr> {
     let query_options: Vec<Option<Expr>> = queries.into_iter().map(|q| q.into()).collect();
     let queries: Vec<Expr> = query_options.into_iter().flatten().collect();
     combine_exprs_with_or(queries)
@@ -481,12 +947,16 @@ fn _to_optqueries_then_or<T: Into<Option<Expr>>>(queries: Vec<T>) -> Option<Expr
 
 impl From<SearchParams> for Option<Expr> {
     fn from(value: SearchParams) -> Self {
-        // Non-ID SearchParams handled first with AND between fields and OR within fields
-        let mut subexprs: Vec<Option<Expr>> = value
-            .text
-            .into_iter()
-            .map(|text| Some(text.into()))
-            .collect();
+        // Non-ID SearchParams handled first with AND between fields and OR within fields, except
+        // for text searches which are combined as requested
+        let mut subexprs: Vec<Option<Expr>> = match value.text_combination {
+            TextCombination::All => value
+                .text
+                .into_iter()
+                .map(|text| Some(text.into()))
+                .collect(),
+            TextCombination::Any => vec![to_queries_then_or(value.text)],
+        };
 
         if let Some(year_range) = value.year_range {
             subexprs.extend([to_queries_then_or(year_range)]);
@@ -498,6 +968,7 @@ impl From<SearchParams> for Option<Expr> {
             value.source_download_url.map(|v| v.into()),
             value.country.map(|v| v.into()),
             value.source_metric_id.map(|v| v.into()),
+            to_queries_then_or(value.hxl_attribute),
         ];
         subexprs.extend(other_subexprs);
         // Remove the Nones and unwrap the Somes
@@ -506,18 +977,48 @@ impl From<SearchParams> for Option<Expr> {
         // Combine non-IDs with AND
         let combined_non_id_expr = combine_exprs_with_and(valid_subexprs);
 
-        // Combine IDs provided in SearchParams with OR
-        let combined_id_expr = to_queries_then_or(value.metric_id);
+        // Combine IDs and composite metrics provided in SearchParams with OR
+        let combined_id_expr = combine_exprs_with_or(
+            vec![
+                to_queries_then_or(value.metric_id),
+                to_optqueries_then_or(value.composite_metric),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+        );
 
         debug!("{:#?}", combined_non_id_expr);
         debug!("{:#?}", combined_id_expr);
 
         // Combine ID and non-ID SearchParams with OR
-        combine_exprs_with_or(
+        let combined_expr = combine_exprs_with_or(
             vec![combined_non_id_expr, combined_id_expr]
                 .into_iter()
                 .flatten()
                 .collect::<Vec<_>>(),
+        );
+
+        // Exclude metrics matching any of the exclusions. A comparison with a null, e.g. a country
+        // without an ISO 3166-2 code, is null rather than false, so is filled to keep the metric
+        let exclude_expr = combine_exprs_with_or(
+            vec![
+                to_queries_then_or(value.exclude_geometry_level),
+                to_queries_then_or(value.exclude_data_publisher),
+                to_queries_then_or(value.exclude_country),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+        );
+        combine_exprs_with_and(
+            vec![
+                combined_expr,
+                exclude_expr.map(|expr| expr.fill_null(lit(false)).not()),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
         )
     }
 }
@@ -528,6 +1029,12 @@ impl From<SearchParams> for Option<Expr> {
 pub struct DownloadParams {
     pub include_geoms: bool,
     pub region_spec: Vec<RegionSpec>,
+    /// Also download the margin of error of each metric that has one, as `<column>_moe`
+    #[serde(default)]
+    pub include_moe: bool,
+    /// Transformations applied in order to the downloaded data
+    #[serde(default)]
+    pub transformations: TransformPipeline,
 }
 
 /// This struct combines `SearchParams` and `DownloadParams` into a single type to simplify
@@ -541,15 +1048,357 @@ pub struct Params {
 #[derive(Clone, Debug)]
 pub struct SearchResults(pub DataFrame);
 
+/// Summary of a single metric in `SearchResults`. Fields are `None` where the value is null or the
+/// column is not present in the results.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct MetricSummary {
+    pub metric_id: Option<String>,
+    pub human_readable_name: Option<String>,
+    pub description: Option<String>,
+    pub hxl_tag: Option<String>,
+    pub geometry_level: Option<String>,
+    pub country_name_short_en: Option<String>,
+    pub country_iso3: Option<String>,
+    pub source_download_url: Option<String>,
+    pub source_documentation_url: Option<String>,
+    pub unit: Option<MetricUnit>,
+}
+
+/// Aggregate counts over `SearchResults`, e.g. for a one line summary after the results
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SearchSummary {
+    pub metrics: usize,
+    pub geometry_levels: usize,
+    pub countries: usize,
+    pub publishers: usize,
+}
+
+impl fmt::Display for SearchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |count: usize, singular: &str, plural: &str| {
+            format!("{count} {}", if count == 1 { singular } else { plural })
+        };
+        write!(f, "{}", plural(self.metrics, "metric", "metrics"))?;
+        if self.metrics == 0 {
+            return Ok(());
+        }
+        write!(
+            f,
+            " across {}, {}, {}",
+            plural(self.geometry_levels, "geometry level", "geometry levels"),
+            plural(self.countries, "country", "countries"),
+            plural(self.publishers, "publisher", "publishers")
+        )
+    }
+}
+
 impl SearchResults {
+    /// The results as a `LazyFrame`, so that further polars operations can be chained before
+    /// collecting
+    pub fn lazy(&self) -> LazyFrame {
+        self.0.clone().lazy()
+    }
+
+    /// Collect `lazy`, e.g. results from `lazy` with further operations applied, as results
+    pub fn from_lazy(lazy: LazyFrame) -> anyhow::Result<SearchResults> {
+        Ok(SearchResults(lazy.collect()?))
+    }
+
+    /// Count the metrics in the results and the distinct geometry levels, countries and
+    /// publishers they cover. Nulls and missing columns are not counted.
+    pub fn summary(&self) -> SearchSummary {
+        let n_groups = |column: &str| {
+            self.lazy()
+                .select([col(column)])
+                .drop_nulls(None)
+                .group_by([col(column)])
+                .agg([])
+                .collect()
+                .map_or(0, |groups| groups.height())
+        };
+        SearchSummary {
+            metrics: self.0.height(),
+            geometry_levels: n_groups(COL::GEOMETRY_LEVEL),
+            countries: n_groups(COL::COUNTRY_NAME_SHORT_EN),
+            publishers: n_groups(COL::DATA_PUBLISHER_NAME),
+        }
+    }
+
+    /// Collapse metrics describing the same concept in different countries into a single row.
+    /// Metrics are grouped by their human readable name, lowercased and with whitespace
+    /// collapsed, and by the hashtag of their HXL tag (e.g. `#population` for
+    /// `#population+adm5+total+2023`). Each row has the first human readable name and HXL tag in
+    /// the group, along with the sorted distinct metric IDs, countries, geometry levels and
+    /// reference period start years of the metrics in it. Rows are sorted by human readable name.
+    pub fn group_by_concept(&self) -> anyhow::Result<DataFrame> {
+        let distinct = |expr: Expr, name: &str| {
+            expr.drop_nulls()
+                .unique()
+                .sort(Default::default())
+                .alias(name)
+        };
+        Ok(self
+            .lazy()
+            .with_columns([
+                col(COL::METRIC_HUMAN_READABLE_NAME)
+                    .str()
+                    .to_lowercase()
+                    .str()
+                    .replace_all(lit(r"\s+"), lit(" "), false)
+                    .str()
+                    .strip_chars(lit(Null {}))
+                    .alias(CONCEPT_NAME),
+                col(COL::METRIC_HXL_TAG)
+                    .str()
+                    .to_lowercase()
+                    .str()
+                    .extract(lit(r"^\s*(#[^+\s]*)"), 1)
+                    .alias(CONCEPT_HXL),
+            ])
+            .group_by_stable([col(CONCEPT_NAME), col(CONCEPT_HXL)])
+            .agg([
+                col(COL::METRIC_HUMAN_READABLE_NAME).first(),
+                col(COL::METRIC_HXL_TAG).first(),
+                distinct(col(COL::METRIC_ID), COL::METRIC_ID),
+                distinct(col(COL::COUNTRY_NAME_SHORT_EN), COL::COUNTRY_NAME_SHORT_EN),
+                distinct(col(COL::GEOMETRY_LEVEL), COL::GEOMETRY_LEVEL),
+                distinct(
+                    col(COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START)
+                        .dt()
+                        .year(),
+                    CONCEPT_YEARS,
+                ),
+            ])
+            .sort(
+                [CONCEPT_NAME, CONCEPT_HXL],
+                SortMultipleOptions::default().with_nulls_last(true),
+            )
+            .drop([CONCEPT_NAME, CONCEPT_HXL])
+            .collect()?)
+    }
+
+    /// Returns the results sorted by descending relevance score, with ties broken by metric ID so
+    /// that the ordering is stable across runs.
+    pub fn ranked(&self) -> anyhow::Result<SearchResults> {
+        Ok(SearchResults(self.0.sort(
+            [RELEVANCE_SCORE, COL::METRIC_ID],
+            SortMultipleOptions::default().with_order_descending_multi([true, false]),
+        )?))
+    }
+
+    /// Number of metrics in the results
+    pub fn len(&self) -> usize {
+        self.0.height()
+    }
+
+    /// Whether there are no metrics in the results
+    pub fn is_empty(&self) -> bool {
+        self.0.height() == 0
+    }
+
+    /// Iterates over a summary of each metric in the results
+    pub fn iter(&self) -> impl Iterator<Item = MetricSummary> + '_ {
+        let str_column = |name: &str| {
+            self.0
+                .column(name)
+                .ok()
+                .and_then(|series| series.str().ok().cloned())
+        };
+        let metric_id = str_column(COL::METRIC_ID);
+        let human_readable_name = str_column(COL::METRIC_HUMAN_READABLE_NAME);
+        let description = str_column(COL::METRIC_DESCRIPTION);
+        let hxl_tag = str_column(COL::METRIC_HXL_TAG);
+        let geometry_level = str_column(COL::GEOMETRY_LEVEL);
+        let country_name_short_en = str_column(COL::COUNTRY_NAME_SHORT_EN);
+        let country_iso3 = str_column(COL::COUNTRY_ISO3);
+        let source_download_url = str_column(COL::METRIC_SOURCE_DOWNLOAD_URL);
+        let source_documentation_url = str_column(COL::METRIC_SOURCE_DOCUMENTATION_URL);
+        let units = self.units();
+        let get = |values: &Option<StringChunked>, idx: usize| {
+            values
+                .as_ref()
+                .and_then(|values| values.get(idx))
+                .map(str::to_string)
+        };
+        (0..self.len()).map(move |idx| MetricSummary {
+            metric_id: get(&metric_id, idx),
+            human_readable_name: get(&human_readable_name, idx),
+            description: get(&description, idx),
+            hxl_tag: get(&hxl_tag, idx),
+            geometry_level: get(&geometry_level, idx),
+            country_name_short_en: get(&country_name_short_en, idx),
+            country_iso3: get(&country_iso3, idx),
+            source_download_url: get(&source_download_url, idx),
+            source_documentation_url: get(&source_documentation_url, idx),
+            unit: units[idx].clone(),
+        })
+    }
+
+    /// The unit of each metric in the results, from `metric_unit` or else inferred from the HXL
+    /// tag (see `MetricUnit::from_metadata`)
+    fn units(&self) -> Vec<Option<MetricUnit>> {
+        let str_values = |name: &str| -> Vec<Option<String>> {
+            match self.0.column(name).and_then(|series| series.str().cloned()) {
+                Ok(values) => values.into_iter().map(|v| v.map(String::from)).collect(),
+                Err(_) => vec![None; self.len()],
+            }
+        };
+        str_values(COL::METRIC_UNIT)
+            .into_iter()
+            .zip(str_values(COL::METRIC_HXL_TAG))
+            .map(|(unit, hxl_tag)| MetricUnit::from_metadata(unit.as_deref(), hxl_tag.as_deref()))
+            .collect()
+    }
+
+    /// The results with the unit of each metric in `metric_unit`, inferring it from the HXL tag
+    /// where the metadata has none, and whether it was inferred in `metric_unit_inferred`. Both
+    /// are null for metrics without a known unit.
+    pub fn with_units(&self) -> anyhow::Result<SearchResults> {
+        let units = self.units();
+        let mut df = self.0.clone();
+        df.with_column(Series::new(
+            COL::METRIC_UNIT,
+            units
+                .iter()
+                .map(|unit| unit.as_ref().map(|unit| unit.unit.clone()))
+                .collect::<Vec<_>>(),
+        ))?;
+        df.with_column(Series::new(
+            METRIC_UNIT_INFERRED,
+            units
+                .iter()
+                .map(|unit| unit.as_ref().map(|unit| unit.inferred))
+                .collect::<Vec<_>>(),
+        ))?;
+        Ok(SearchResults(df))
+    }
+
+    /// Returns at most `limit` results starting from `offset`. An `offset` past the end of the
+    /// results gives an empty page.
+    pub fn page(&self, offset: usize, limit: usize) -> SearchResults {
+        let offset = offset.min(self.len());
+        SearchResults(self.0.slice(offset as i64, limit))
+    }
+
+    /// Serializes the results as a JSON array with one object per metric. Each object has the
+    /// keys `metric_id`, `human_readable_name`, `description`, `hxl_tag`, `geometry_level`,
+    /// `source_data_release`, `data_publisher`, `country`, `country_iso3`, `source_download_url`
+    /// `source_documentation_url`, `unit` and `unit_inferred`, with missing values given as `null`.
+    /// The source URL columns are not present in all metadata releases and are also given as
+    /// `null` if missing. Units are inferred from the HXL tag where the metadata has none (see
+    /// `with_units`).
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        let fields = [
+            ("metric_id", COL::METRIC_ID),
+            ("human_readable_name", COL::METRIC_HUMAN_READABLE_NAME),
+            ("description", COL::METRIC_DESCRIPTION),
+            ("hxl_tag", COL::METRIC_HXL_TAG),
+            ("geometry_level", COL::GEOMETRY_LEVEL),
+            ("source_data_release", COL::SOURCE_DATA_RELEASE_NAME),
+            ("data_publisher", COL::DATA_PUBLISHER_NAME),
+            ("country", COL::COUNTRY_NAME_SHORT_EN),
+            ("country_iso3", COL::COUNTRY_ISO3),
+            ("source_download_url", COL::METRIC_SOURCE_DOWNLOAD_URL),
+            (
+                "source_documentation_url",
+                COL::METRIC_SOURCE_DOCUMENTATION_URL,
+            ),
+        ];
+        let optional = [
+            COL::METRIC_SOURCE_DOWNLOAD_URL,
+            COL::METRIC_SOURCE_DOCUMENTATION_URL,
+        ];
+        let columns = fields
+            .iter()
+            .map(|(_, column)| match self.0.column(column) {
+                Err(_) if optional.contains(column) => Ok(None),
+                series => series?.str().cloned().map(Some),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let units = self.units();
+        let rows = (0..self.len())
+            .map(|idx| {
+                let mut row = fields
+                    .iter()
+                    .zip(columns.iter())
+                    .map(|((key, _), values)| {
+                        let value = values
+                            .as_ref()
+                            .and_then(|values| values.get(idx))
+                            .map_or(Value::Null, |value| Value::String(value.to_string()));
+                        (key.to_string(), value)
+                    })
+                    .collect::<Map<String, Value>>();
+                let unit = units[idx].as_ref();
+                row.insert(
+                    "unit".to_string(),
+                    unit.map_or(Value::Null, |unit| Value::String(unit.unit.clone())),
+                );
+                row.insert(
+                    "unit_inferred".to_string(),
+                    unit.map_or(Value::Null, |unit| Value::Bool(unit.inferred)),
+                );
+                row
+            })
+            .map(Value::Object)
+            .collect::<Vec<_>>();
+        Ok(serde_json::to_string(&rows)?)
+    }
+
+    /// Writes the results as CSV. The metric ID, human readable name, description, HXL tag,
+    /// geometry level, source data release name and data publisher name columns come first, in
+    /// that order, followed by all other columns sorted by name, so the output does not depend on
+    /// the order in which the metadata tables were joined.
+    pub fn to_csv_writer<W: Write>(&self, w: W) -> anyhow::Result<()> {
+        let leading_columns = [
+            COL::METRIC_ID,
+            COL::METRIC_HUMAN_READABLE_NAME,
+            COL::METRIC_DESCRIPTION,
+            COL::METRIC_HXL_TAG,
+            COL::GEOMETRY_LEVEL,
+            COL::SOURCE_DATA_RELEASE_NAME,
+            COL::DATA_PUBLISHER_NAME,
+        ];
+        let mut other_columns = self
+            .0
+            .get_column_names()
+            .into_iter()
+            .filter(|name| !leading_columns.contains(name))
+            .collect::<Vec<_>>();
+        other_columns.sort();
+        let mut df = self.0.select(
+            leading_columns
+                .iter()
+                .copied()
+                .filter(|name| self.0.get_column_index(name).is_some())
+                .chain(other_columns),
+        )?;
+        CsvWriter::new(w).finish(&mut df)?;
+        Ok(())
+    }
+
+    /// Writes the results to a parquet file at `path`, e.g. to use them again offline. Parquet
+    /// stores the type of each column, so the results read back by `read_parquet` are identical.
+    pub fn write_parquet<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let file = std::fs::File::create(path)?;
+        ParquetWriter::new(file)
+            .with_compression(ParquetCompression::Zstd(None))
+            .finish(&mut self.0.clone())?;
+        Ok(())
+    }
+
+    /// Reads results written by `write_parquet` from the parquet file at `path`
+    pub fn read_parquet<P: AsRef<Path>>(path: P) -> anyhow::Result<SearchResults> {
+        let file = std::fs::File::open(path)?;
+        Ok(SearchResults(ParquetReader::new(file).finish()?))
+    }
+
     /// Convert all the metrics in the dataframe to MetricRequests
     pub fn to_metric_requests(&self, config: &Config) -> Vec<MetricRequest> {
         // Using unwrap throughout this function because if any of them fail, it means our upstream
         // data is invalid!
         // TODO: Maybe map the error type instead to provide some useful error messages
         let df = self
-            .0
-            .clone()
             .lazy()
             .select([
                 col(COL::METRIC_PARQUET_PATH),
@@ -579,12 +1428,175 @@ impl SearchResults {
             )
             .map(|((column, metric_file), geom_file)| MetricRequest {
                 column: column.to_owned(),
-                metric_file: format!("{}/{metric_file}", config.base_path),
-                geom_file: format!("{}/{geom_file}.fgb", config.base_path),
+                metric_file: join_path(&config.base_path, metric_file),
+                geom_file: join_path(&config.base_path, &format!("{geom_file}.fgb")),
+                geoids: vec![],
+                margin_of_error: None,
             })
             .collect()
     }
 
+    /// The columns that `download` will return for these results with the given
+    /// `download_params`, in order
+    pub fn download_columns(&self, download_params: &DownloadParams) -> Vec<String> {
+        let metric_requests = if download_params.include_moe {
+            self.to_metric_requests_with_moe(&Config::default())
+        } else {
+            self.to_metric_requests(&Config::default())
+        };
+        let mut columns = vec![COL::GEO_ID.to_string()];
+        if download_params.include_geoms {
+            columns.push("geometry".to_string());
+        }
+        for request in metric_requests.iter().unique_by(|request| &request.column) {
+            columns.push(request.column.clone());
+            if request.margin_of_error.is_some() {
+                columns.push(format!("{}{MARGIN_OF_ERROR_SUFFIX}", request.column));
+            }
+        }
+        columns
+    }
+
+    /// Like `to_metric_requests`, but also requests the margin of error of each metric that has
+    /// one. Metrics are left without a margin of error if the metadata has no margin of error
+    /// columns.
+    pub fn to_metric_requests_with_moe(&self, config: &Config) -> Vec<MetricRequest> {
+        let margins_of_error: Vec<Option<MarginOfError>> = match (
+            self.0.column(COL::METRIC_PARQUET_MARGIN_OF_ERROR_COLUMN),
+            self.0.column(COL::METRIC_PARQUET_MARGIN_OF_ERROR_FILE),
+        ) {
+            (Ok(columns), Ok(files)) => match (columns.str(), files.str()) {
+                (Ok(columns), Ok(files)) => columns
+                    .into_iter()
+                    .zip(files)
+                    .map(|(column, file)| match (column, file) {
+                        (Some(column), Some(file)) if !column.is_empty() && !file.is_empty() => {
+                            Some(MarginOfError {
+                                column: column.to_owned(),
+                                file: join_path(&config.base_path, file),
+                            })
+                        }
+                        _ => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            },
+            _ => vec![],
+        };
+        self.to_metric_requests(config)
+            .into_iter()
+            .zip(margins_of_error.into_iter().chain(std::iter::repeat(None)))
+            .map(|(request, margin_of_error)| MetricRequest {
+                margin_of_error,
+                ..request
+            })
+            .collect()
+    }
+
+    /// Like `to_metric_requests`, but with each request restricted to the `GEO_ID`s of the
+    /// geometries at its geometry level that intersect `bbox`, given in WGS84 longitude and
+    /// latitude. The bounding box is reprojected to the coordinate reference system of each
+    /// geometry file, and the `GEO_ID`s are pushed down into the scans of the metric files.
+    ///
+    /// Returns an error if no geometries of a geometry level intersect `bbox`, as a request with
+    /// no `GEO_ID`s would download the metrics of every geometry.
+    pub async fn to_metric_requests_in_bbox(
+        &self,
+        config: &Config,
+        bbox: &BBox,
+    ) -> anyhow::Result<Vec<MetricRequest>> {
+        let mut requests = self.to_metric_requests(config);
+        let geom_files = requests
+            .iter()
+            .map(|request| request.geom_file.clone())
+            .unique()
+            .collect_vec();
+        let mut geo_ids = HashMap::new();
+        for geom_file in geom_files {
+            let geo_id_column = self.geo_id_column(config, &geom_file)?;
+            let ids = geo_ids_in_bbox(&geom_file, bbox, &geo_id_column).await?;
+            debug!("{} geometries in {bbox:?} in {geom_file}", ids.len());
+            if ids.is_empty() {
+                bail!("No geometries in '{geom_file}' intersect the bounding box {bbox:?}");
+            }
+            geo_ids.insert(geom_file, ids);
+        }
+        for request in requests.iter_mut() {
+            request.geoids.clone_from(&geo_ids[&request.geom_file]);
+        }
+        Ok(requests)
+    }
+
+    /// The column holding the IDs of the geometries in `geom_file`, the path of a geometry file
+    /// of the results as in `MetricRequest::geom_file` (see `Metadata::geo_id_column`). Returns an
+    /// error if no geometry of the results has that file.
+    fn geo_id_column(&self, config: &Config, geom_file: &str) -> anyhow::Result<String> {
+        geo_id_columns(&self.0)?
+            .into_iter()
+            .find(|(stem, _)| join_path(&config.base_path, &format!("{stem}.fgb")) == geom_file)
+            .map(|(_, column)| column)
+            .ok_or_else(|| anyhow!("No geometry in the search results has the file '{geom_file}'"))
+    }
+
+    /// Split the results into the metrics at each of `geometry_levels`. Metrics are matched
+    /// across levels by their human readable name, and an error is returned if any metric in the
+    /// results is missing at one of the requested levels rather than leaving it out of that level.
+    pub fn by_geometry_level(
+        &self,
+        geometry_levels: &[&str],
+    ) -> anyhow::Result<BTreeMap<String, SearchResults>> {
+        let names = |df: &DataFrame| -> anyhow::Result<HashSet<String>> {
+            Ok(df
+                .column(COL::METRIC_HUMAN_READABLE_NAME)?
+                .str()?
+                .into_iter()
+                .flatten()
+                .map(str::to_string)
+                .collect())
+        };
+        let all_names = names(&self.0)?;
+        let mut results = BTreeMap::new();
+        for &geometry_level in geometry_levels {
+            let df = self
+                .0
+                .clone()
+                .lazy()
+                .filter(col(COL::GEOMETRY_LEVEL).eq(lit(geometry_level)))
+                .collect()?;
+            let available = names(&df)?;
+            let missing = all_names
+                .iter()
+                .filter(|name| !available.contains(*name))
+                .sorted()
+                .cloned()
+                .collect_vec();
+            if !missing.is_empty() {
+                Err(PopgetterError::MetricsUnavailableAtGeometry {
+                    geometry_level: geometry_level.to_string(),
+                    metrics: missing,
+                })?;
+            }
+            results.insert(geometry_level.to_string(), SearchResults(df));
+        }
+        Ok(results)
+    }
+
+    /// Download the results at each of `geometry_levels`, returning a map from geometry level to
+    /// the downloaded data. See `by_geometry_level` for how metrics are matched across levels.
+    pub async fn download_by_geometry_level(
+        self,
+        config: &Config,
+        download_params: &DownloadParams,
+        geometry_levels: &[&str],
+    ) -> anyhow::Result<BTreeMap<String, DataFrame>> {
+        let mut downloads = BTreeMap::new();
+        for (geometry_level, results) in self.by_geometry_level(geometry_levels)? {
+            let df = results.download(config, download_params).await?;
+            downloads.insert(geometry_level, df);
+        }
+        Ok(downloads)
+    }
+
     // Given a Data Request Spec
     // Return a DataFrame of the selected dataset
     pub async fn download(
@@ -592,8 +1604,11 @@ impl SearchResults {
         config: &Config,
         download_params: &DownloadParams,
     ) -> anyhow::Result<DataFrame> {
-        let metric_requests = self.to_metric_requests(config);
-        debug!("metric_requests = {:#?}", metric_requests);
+        let mut metric_requests = if download_params.include_moe {
+            self.to_metric_requests_with_moe(config)
+        } else {
+            self.to_metric_requests(config)
+        };
 
         if metric_requests.is_empty() {
             bail!(
@@ -619,42 +1634,63 @@ impl SearchResults {
                 metric_requests
             )
         }
+        let geom_file = all_geom_files.into_iter().next().unwrap();
+        let geo_id_column = &self.geo_id_column(config, &geom_file)?;
+
+        if download_params.region_spec.len() > 1 {
+            todo!(
+                "Multiple region specifications are not yet supported: {:#?}",
+                download_params.region_spec
+            );
+        }
+        let bbox = download_params
+            .region_spec
+            .first()
+            .and_then(|region_spec| region_spec.bbox());
+
+        // The geometries intersecting a bounding box, given in WGS84 longitude and latitude, are
+        // read first so that only their metrics are downloaded (see `to_metric_requests_in_bbox`)
+        let geoms_in_bbox = match bbox {
+            Some(bbox) => {
+                let geoms = geometries_in_bbox(&geom_file, &bbox, geo_id_column).await?;
+                let geo_ids = geoms
+                    .df
+                    .column(COL::GEO_ID)?
+                    .str()?
+                    .into_no_null_iter()
+                    .map(String::from)
+                    .collect_vec();
+                debug!("{} geometries in {bbox:?} in {geom_file}", geo_ids.len());
+                if geo_ids.is_empty() {
+                    bail!("No geometries in '{geom_file}' intersect the bounding box {bbox:?}");
+                }
+                for request in metric_requests.iter_mut() {
+                    request.geoids.clone_from(&geo_ids);
+                }
+                Some(geoms.df)
+            }
+            None => None,
+        };
+        debug!("metric_requests = {:#?}", metric_requests);
 
-        // Required because polars is blocking
-        let metrics = tokio::task::spawn_blocking(move || get_metrics(&metric_requests, None));
+        let metrics = get_metrics_with_config(&metric_requests, None, config);
 
         let result = if download_params.include_geoms {
-            // TODO Pass in the bbox as the second argument here
-            if download_params.region_spec.len() > 1 {
-                todo!(
-                    "Multiple region specifications are not yet supported: {:#?}",
-                    download_params.region_spec
-                );
-            }
-            let bbox = download_params
-                .region_spec
-                .first()
-                .and_then(|region_spec| region_spec.bbox().clone());
-
-            if bbox.is_some() {
-                warn!(
-                    "The bounding box should be specified in the same coordinate reference system \
-                     as the requested geometry."
-                )
-            }
-            let geoms = get_geometries(all_geom_files.iter().next().unwrap(), bbox);
+            let geoms = async {
+                match geoms_in_bbox {
+                    Some(geoms) => Ok(geoms),
+                    None => get_geometries(&geom_file, None, geo_id_column).await,
+                }
+            };
 
             // try_join requires us to have the errors from all futures be the same.
             // We use anyhow to get it back properly
-            let (metrics, geoms) = try_join!(
-                async move { metrics.await.map_err(anyhow::Error::from) },
-                geoms
-            )?;
+            let (metrics, geoms) = try_join!(metrics, geoms)?;
             debug!("geoms: {geoms:#?}");
             debug!("metrics: {metrics:#?}");
-            geoms.inner_join(&metrics?, [COL::GEO_ID], [COL::GEO_ID])?
+            geoms.inner_join(&metrics, [COL::GEO_ID], [COL::GEO_ID])?
         } else {
-            let metrics = metrics.await.map_err(anyhow::Error::from)??;
+            let metrics = metrics.await?;
             debug!("metrics: {metrics:#?}");
             metrics
         };
@@ -666,9 +1702,18 @@ impl SearchResults {
 #[cfg(test)]
 mod tests {
 
-    use polars::df;
+    use polars::{
+        df,
+        prelude::{CsvReadOptions, NamedFrom, SerReader, Series},
+    };
+    use std::io::Cursor;
 
     use super::*;
+    use crate::{
+        data_request_spec::Polygon,
+        geo::{tests::test_fgb, BBox},
+        metadata::tests::two_country_metadata,
+    };
 
     fn test_df() -> DataFrame {
         df!(
@@ -680,6 +1725,84 @@ mod tests {
         .unwrap()
     }
 
+    #[test]
+    fn geometry_level_aliases_should_resolve_to_canonical_level() -> anyhow::Result<()> {
+        let levels = ["lsoa11", "lsoa21", "msoa", "municipality"];
+        let aliases = BTreeMap::from([
+            ("lsoa21".to_string(), vec!["LSOA".to_string()]),
+            ("municipality".to_string(), vec!["Gemeente".to_string()]),
+        ]);
+        for alias in ["lsoa", "LSOA", " Lsoa21 ", "LSOA21"] {
+            assert_eq!(
+                resolve_geometry_level(alias, &levels, &aliases)?,
+                Some("lsoa21".to_string()),
+                "{alias}"
+            );
+        }
+        for alias in [
+            "msoa",
+            "Middle Super Output Area",
+            "middle-layer super output area",
+        ] {
+            assert_eq!(
+                resolve_geometry_level(alias, &levels, &aliases)?,
+                Some("msoa".to_string()),
+                "{alias}"
+            );
+        }
+        assert_eq!(
+            resolve_geometry_level("gemeente", &levels, &aliases)?,
+            Some("municipality".to_string())
+        );
+        assert_eq!(resolve_geometry_level("oa", &levels, &aliases)?, None);
+
+        // An alias of several levels is ambiguous
+        let aliases = BTreeMap::from([
+            ("lsoa11".to_string(), vec!["lsoa".to_string()]),
+            ("lsoa21".to_string(), vec!["lsoa".to_string()]),
+        ]);
+        let err = resolve_geometry_level("LSOA", &levels, &aliases)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("'lsoa11', 'lsoa21'"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn results_should_split_by_geometry_level() -> anyhow::Result<()> {
+        let results = SearchResults(df!(
+            COL::METRIC_ID => &["a_lsoa", "a_msoa", "b_lsoa", "b_msoa", "c_lsoa"],
+            COL::METRIC_HUMAN_READABLE_NAME => &["A", "A", "B", "B", "C"],
+            COL::GEOMETRY_LEVEL => &["lsoa", "msoa", "lsoa", "msoa", "lsoa"],
+        )?);
+        let metric_ids = |results: &SearchResults| {
+            results
+                .0
+                .column(COL::METRIC_ID)
+                .unwrap()
+                .str()
+                .unwrap()
+                .into_no_null_iter()
+                .map(str::to_string)
+                .collect_vec()
+        };
+
+        let both = SearchResults(results.0.slice(0, 4));
+        let by_level = both.by_geometry_level(&["lsoa", "msoa"])?;
+        assert_eq!(by_level.keys().collect_vec(), ["lsoa", "msoa"]);
+        assert_eq!(metric_ids(&by_level["lsoa"]), ["a_lsoa", "b_lsoa"]);
+        assert_eq!(metric_ids(&by_level["msoa"]), ["a_msoa", "b_msoa"]);
+
+        // "C" is only available for LSOAs
+        let err = results
+            .by_geometry_level(&["lsoa", "msoa"])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("'msoa'") && err.contains('C') && !err.contains('A'));
+        assert!(results.by_geometry_level(&["lsoa"]).is_ok());
+        Ok(())
+    }
+
     fn test_search_params(
         value: &str,
         match_type: MatchType,
@@ -693,6 +1816,8 @@ mod tests {
                     match_type,
                     case_sensitivity,
                 },
+                exact: true,
+                match_types: BTreeMap::new(),
             }],
             ..Default::default()
         }
@@ -712,6 +1837,102 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn search_stream_should_match_eager_search() -> anyhow::Result<()> {
+        use futures::TryStreamExt;
+
+        let metadata = ExpandedMetadata(test_df().lazy());
+        let search_params =
+            test_search_params("apple", MatchType::Exact, CaseSensitivity::Insensitive);
+        let streamed: Vec<MetricSummary> = search_params
+            .clone()
+            .search_stream(&metadata)
+            .try_collect()
+            .await?;
+        let eager: Vec<MetricSummary> = search_params.clone().search(&metadata).iter().collect();
+        assert_eq!(streamed.len(), 3);
+        assert_eq!(streamed, eager);
+
+        // Batches that divide the results exactly and those that do not
+        for batch_size in [1, 2] {
+            let streamed: Vec<MetricSummary> = search_params
+                .clone()
+                .search_stream_in_batches(&metadata, batch_size)
+                .try_collect()
+                .await?;
+            assert_eq!(streamed, eager, "batch size {batch_size}");
+        }
+
+        // Polars errors are produced as items of the stream, which then ends
+        let search_params = SearchParams {
+            geometry_level: Some(GeometryLevel {
+                value: "lsoa".into(),
+                config: SearchConfig {
+                    match_type: MatchType::Exact,
+                    case_sensitivity: CaseSensitivity::Insensitive,
+                },
+            }),
+            ..SearchParams::default()
+        };
+        let items: Vec<_> = search_params.search_stream(&metadata).collect().await;
+        assert_eq!(items.len(), 1);
+        assert!(items[0].is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_stream_should_produce_each_joined_metric_once() -> anyhow::Result<()> {
+        use futures::TryStreamExt;
+
+        // Enough metrics across both countries for the joins to be split across threads
+        let mut metadata = two_country_metadata();
+        let metric_ids = (0..500).map(|idx| format!("metric_{idx:03}")).collect_vec();
+        let releases = (0..500)
+            .map(|idx| ["bel_release", "nir_release"][idx % 2])
+            .collect_vec();
+        metadata.metrics = df!(
+            COL::METRIC_ID => &metric_ids,
+            COL::METRIC_SOURCE_DATA_RELEASE_ID => &releases,
+        )?;
+        let expanded_metadata = metadata.combined_metric_source_geometry()?;
+
+        for batch_size in [1, 7, SEARCH_STREAM_BATCH_SIZE] {
+            let streamed: Vec<MetricSummary> = SearchParams::default()
+                .search_stream_in_batches(&expanded_metadata, batch_size)
+                .try_collect()
+                .await?;
+            let streamed_ids = streamed
+                .into_iter()
+                .map(|summary| summary.metric_id.unwrap())
+                .collect_vec();
+            assert_eq!(
+                streamed_ids.len(),
+                metric_ids.len(),
+                "batch size {batch_size}"
+            );
+            assert_eq!(
+                streamed_ids.into_iter().sorted().collect_vec(),
+                metric_ids,
+                "batch size {batch_size}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn search_stream_should_be_created_outside_runtime() -> anyhow::Result<()> {
+        use futures::TryStreamExt;
+
+        let metadata = ExpandedMetadata(test_df().lazy());
+        let search_params =
+            test_search_params("apple", MatchType::Exact, CaseSensitivity::Insensitive);
+        let stream = search_params.search_stream(&metadata);
+        let runtime = tokio::runtime::Runtime::new()?;
+        let streamed: Vec<MetricSummary> = runtime.block_on(stream.try_collect())?;
+        assert_eq!(streamed.len(), 3);
+        Ok(())
+    }
+
     #[test]
     #[rustfmt::skip]
     fn test_search_request() -> anyhow::Result<()> {
@@ -729,4 +1950,995 @@ mod tests {
         test_from_args("Apple", MatchType::Regex, CaseSensitivity::Insensitive, &[0, 1, 3, 4])?;
         Ok(())
     }
+
+    fn filter_text(search_text: SearchText) -> anyhow::Result<Vec<u32>> {
+        let expr = Expr::from(search_text);
+        let filtered = test_df().lazy().filter(expr).collect()?;
+        Ok(filtered
+            .column("index")?
+            .u32()?
+            .into_no_null_iter()
+            .collect())
+    }
+
+    #[test]
+    fn text_searches_should_be_combined_as_requested() -> anyhow::Result<()> {
+        let text = |value: &str| SearchText {
+            text: value.to_string(),
+            context: nonempty![SearchContext::HumanReadableName],
+            ..SearchText::default()
+        };
+        let filtered_indices = |text_combination: TextCombination| -> anyhow::Result<DataFrame> {
+            let search_params = SearchParams {
+                text: vec![text("Pear"), text("lemon")],
+                text_combination,
+                ..SearchParams::default()
+            };
+            let expr = Option::<Expr>::from(search_params).unwrap();
+            Ok(test_df().lazy().filter(expr).collect()?.select(["index"])?)
+        };
+        // By default a metric must match all of the text searches
+        assert_eq!(
+            filtered_indices(TextCombination::default())?,
+            df!("index" => Vec::<u32>::new())?
+        );
+        // Otherwise the union of the matches of each text search is returned
+        assert_eq!(
+            filtered_indices(TextCombination::Any)?,
+            df!("index" => &[2u32, 5])?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn text_search_should_match_substrings_by_default() -> anyhow::Result<()> {
+        let search_text = SearchText {
+            text: "pple".to_string(),
+            context: nonempty![SearchContext::HumanReadableName],
+            ..SearchText::default()
+        };
+        assert_eq!(filter_text(search_text)?, vec![0, 1, 3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn text_search_should_match_exactly_when_requested() -> anyhow::Result<()> {
+        let search_text = SearchText {
+            text: "apple".to_string(),
+            context: nonempty![SearchContext::HumanReadableName],
+            exact: true,
+            ..SearchText::default()
+        };
+        assert_eq!(filter_text(search_text)?, vec![0, 1, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn text_search_should_escape_regex_unless_exact() -> anyhow::Result<()> {
+        let search_text = SearchText {
+            text: ".apple".to_string(),
+            context: nonempty![SearchContext::HumanReadableName],
+            config: SearchConfig {
+                match_type: MatchType::Regex,
+                case_sensitivity: CaseSensitivity::Insensitive,
+            },
+            exact: false,
+            match_types: BTreeMap::new(),
+        };
+        assert_eq!(filter_text(search_text.clone())?, vec![4]);
+        let search_text = SearchText {
+            text: "^.pple".to_string(),
+            ..search_text
+        };
+        assert_eq!(filter_text(search_text.clone())?, Vec::<u32>::new());
+        // An explicitly requested regex is passed through unescaped
+        let search_text = SearchText {
+            exact: true,
+            ..search_text
+        };
+        assert_eq!(filter_text(search_text)?, vec![0, 1, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn text_search_should_use_match_type_of_each_context() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_HUMAN_READABLE_NAME => &["Red apple", "Apple", "Redcurrant", "Pear"],
+            COL::METRIC_HXL_TAG => &["red", "red+apple", "green", "red"],
+            COL::METRIC_DESCRIPTION => &["", "", "Green fruit", "A green pear"],
+            "index" => &[0u32, 1, 2, 3]
+        )?;
+        let filter = |search_text: SearchText| -> anyhow::Result<Vec<u32>> {
+            let expr = Option::<Expr>::from(SearchParams {
+                text: vec![search_text],
+                ..SearchParams::default()
+            })
+            .unwrap();
+            Ok(df
+                .clone()
+                .lazy()
+                .filter(expr)
+                .collect()?
+                .column("index")?
+                .u32()?
+                .into_no_null_iter()
+                .collect())
+        };
+        let search_text = SearchText {
+            text: "red".to_string(),
+            context: nonempty![SearchContext::Hxl, SearchContext::HumanReadableName],
+            ..SearchText::default()
+        };
+        assert_eq!(filter(search_text.clone())?, vec![0, 1, 2, 3]);
+
+        // An exact match on the HXL tag is ORed with a substring match on the name
+        let search_text = SearchText {
+            match_types: BTreeMap::from([
+                (SearchContext::Hxl, MatchType::Exact),
+                (SearchContext::HumanReadableName, MatchType::Contains),
+            ]),
+            ..search_text
+        };
+        assert_eq!(filter(search_text.clone())?, vec![0, 2, 3]);
+
+        // Contexts without a match type of their own fall back to that of the search
+        let search_text = SearchText {
+            text: "^green".to_string(),
+            context: nonempty![SearchContext::Hxl, SearchContext::Description],
+            match_types: BTreeMap::from([(SearchContext::Description, MatchType::Regex)]),
+            ..search_text
+        };
+        assert_eq!(filter(search_text.clone())?, vec![2]);
+        let search_text = SearchText {
+            text: "green".to_string(),
+            ..search_text
+        };
+        assert_eq!(filter(search_text)?, vec![2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn text_search_should_ignore_accents_except_in_hxl_tags() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_HUMAN_READABLE_NAME => &["Population par Région", "Population", "Area"],
+            COL::METRIC_HXL_TAG => &["#population", "#population+région", "#area"],
+            COL::METRIC_DESCRIPTION => &["", "", "Superficie de la région"],
+            "index" => &[0u32, 1, 2]
+        )?;
+        let filter = |text: &str, context: NonEmpty<SearchContext>| -> anyhow::Result<Vec<u32>> {
+            let search_text = SearchText {
+                text: text.to_string(),
+                context,
+                ..SearchText::default()
+            };
+            let results = SearchParams {
+                text: vec![search_text],
+                ..SearchParams::default()
+            }
+            .search(&ExpandedMetadata(df.clone().lazy()));
+            Ok(results
+                .0
+                .column("index")?
+                .u32()?
+                .into_no_null_iter()
+                .collect())
+        };
+        assert_eq!(filter("region", SearchContext::all())?, vec![0, 2]);
+        assert_eq!(filter("RÉGION", SearchContext::all())?, vec![0, 1, 2]);
+        assert_eq!(
+            filter("region", nonempty![SearchContext::Hxl])?,
+            Vec::<u32>::new()
+        );
+        assert_eq!(filter("région", nonempty![SearchContext::Hxl])?, vec![1]);
+        Ok(())
+    }
+
+    #[test]
+    fn hxl_attribute_should_only_match_whole_attributes() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_HXL_TAG => &[
+                "#population+adm5+total+2023",
+                "#population+totally",
+                "#population+Total",
+                "#population +total +2023",
+                "#total",
+                "#population+subtotal",
+            ],
+            "index" => &[0u32, 1, 2, 3, 4, 5]
+        )?;
+        let filter = |attribute: &str| -> anyhow::Result<Vec<u32>> {
+            let expr = Expr::from(HxlAttribute(attribute.to_string()));
+            Ok(df
+                .clone()
+                .lazy()
+                .filter(expr)
+                .collect()?
+                .column("index")?
+                .u32()?
+                .into_no_null_iter()
+                .collect())
+        };
+        assert_eq!(filter("+total")?, vec![0, 2, 3]);
+        assert_eq!(filter("total")?, vec![0, 2, 3]);
+        assert_eq!(filter("+totally")?, vec![1]);
+        assert_eq!(filter("+adm5")?, vec![0]);
+        Ok(())
+    }
+
+    #[test]
+    fn ranked_results_should_be_ordered_by_relevance() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_ID => &["e", "d", "c", "b", "a"],
+            COL::METRIC_HUMAN_READABLE_NAME => &["Households", "Total population", "Population", "Total population", "Area"],
+            COL::METRIC_HXL_TAG => &["#household", "#population", "#population", "#population", "#area"],
+            COL::METRIC_DESCRIPTION => &["Households by population", "Total", "Population", "Total", "Area"],
+        )?;
+        let search_params = SearchParams {
+            text: vec![SearchText {
+                text: "population".to_string(),
+                context: nonempty![SearchContext::HumanReadableName, SearchContext::Description],
+                config: SearchConfig {
+                    match_type: MatchType::Contains,
+                    case_sensitivity: CaseSensitivity::Insensitive,
+                },
+                exact: true,
+                match_types: BTreeMap::new(),
+            }],
+            ..Default::default()
+        };
+        let results = search_params
+            .search(&ExpandedMetadata(df.lazy()))
+            .ranked()?;
+        let ids: Vec<&str> = results
+            .0
+            .column(COL::METRIC_ID)?
+            .str()?
+            .into_no_null_iter()
+            .collect();
+        // Exact name match, then substring name matches tied by metric ID, then description only
+        assert_eq!(ids, vec!["c", "b", "d", "e"]);
+        Ok(())
+    }
+
+    #[test]
+    fn page_should_slice_results() -> anyhow::Result<()> {
+        let results = SearchResults(test_df());
+        assert_eq!(results.len(), 6);
+        let page = results.page(2, 3);
+        assert_eq!(page.0.select(["index"])?, df!("index" => &[2u32, 3, 4])?);
+        // Partial last page
+        let page = results.page(4, 3);
+        assert_eq!(page.0.select(["index"])?, df!("index" => &[4u32, 5])?);
+        // Offset past the end
+        let page = results.page(10, 3);
+        assert!(page.is_empty());
+        assert_eq!(page.0.schema(), results.0.schema());
+        Ok(())
+    }
+
+    #[test]
+    fn lazy_results_should_chain_further_operations() -> anyhow::Result<()> {
+        let results = SearchResults(test_df());
+        let filtered = SearchResults::from_lazy(
+            results
+                .lazy()
+                .filter(col(COL::METRIC_HXL_TAG).eq(lit("Green")))
+                .select([col("index")]),
+        )?;
+        assert_eq!(filtered.0, df!("index" => &[2u32, 4])?);
+        // The original results are unchanged
+        assert_eq!(results.len(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn to_json_should_serialize_each_result() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_ID => &["a", "b"],
+            COL::METRIC_HUMAN_READABLE_NAME => &["Population", "Households"],
+            COL::METRIC_DESCRIPTION => &[Some("Total population"), None],
+            COL::METRIC_HXL_TAG => &["#population", "#household"],
+            COL::GEOMETRY_LEVEL => &["oa", "oa"],
+            COL::SOURCE_DATA_RELEASE_NAME => &["Census 2021", "Census 2021"],
+            COL::DATA_PUBLISHER_NAME => &["ONS", "ONS"],
+            COL::COUNTRY_NAME_SHORT_EN => &["Northern Ireland", "Northern Ireland"],
+            COL::COUNTRY_ISO3 => &["GBR", "GBR"],
+        )?;
+        let json = SearchResults(df.clone()).to_json()?;
+        let values: Vec<Value> = serde_json::from_str(&json)?;
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0]["metric_id"], "a");
+        assert_eq!(values[0]["description"], "Total population");
+        assert_eq!(values[1]["description"], Value::Null);
+        assert_eq!(values[0]["source_download_url"], Value::Null);
+        assert_eq!(values[0]["unit"], "people");
+        assert_eq!(values[0]["unit_inferred"], true);
+
+        let mut df = df;
+        df.with_column(Series::new(
+            COL::METRIC_SOURCE_DOWNLOAD_URL,
+            &[
+                "https://example.com/census.zip",
+                "https://example.com/census.zip",
+            ],
+        ))?;
+        df.with_column(Series::new(
+            COL::METRIC_SOURCE_DOCUMENTATION_URL,
+            &[Some("https://example.com/docs"), None],
+        ))?;
+        let json = SearchResults(df).to_json()?;
+        let values: Vec<Value> = serde_json::from_str(&json)?;
+        assert_eq!(
+            values[0]["source_download_url"],
+            "https://example.com/census.zip"
+        );
+        assert_eq!(
+            values[0]["source_documentation_url"],
+            "https://example.com/docs"
+        );
+        assert_eq!(values[1]["source_documentation_url"], Value::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn to_csv_writer_should_round_trip() -> anyhow::Result<()> {
+        let df = df!(
+            "z_extra" => &[1u32, 2],
+            COL::METRIC_DESCRIPTION => &["Total, all \"usual\" residents", "Households"],
+            COL::METRIC_ID => &["a", "b"],
+            "a_extra" => &["x", "y"],
+        )?;
+        let mut buf = Vec::new();
+        SearchResults(df).to_csv_writer(&mut buf)?;
+        let read = CsvReadOptions::default()
+            .with_has_header(true)
+            .into_reader_with_file_handle(Cursor::new(buf))
+            .finish()?;
+        assert_eq!(
+            read.get_column_names(),
+            vec![
+                COL::METRIC_ID,
+                COL::METRIC_DESCRIPTION,
+                "a_extra",
+                "z_extra"
+            ]
+        );
+        assert_eq!(
+            read.column(COL::METRIC_DESCRIPTION)?.str()?.get(0),
+            Some("Total, all \"usual\" residents")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parquet_should_round_trip_column_types() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_ID => &["a", "b"],
+            COL::METRIC_DESCRIPTION => &[Some("Total population"), None],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START => &[
+                NaiveDate::from_ymd_opt(2021, 3, 21).unwrap(),
+                NaiveDate::from_ymd_opt(2011, 3, 27).unwrap(),
+            ],
+            COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST => &[
+                Series::new("", &["GBR"]),
+                Series::new("", &["GBR", "IRL"]),
+            ],
+            RELEVANCE_SCORE => &[1.5f64, 0.25],
+            "count" => &[1u32, 2],
+        )?;
+        let tmp = tempfile::TempDir::new()?;
+        let path = tmp.path().join("results.parquet");
+        SearchResults(df.clone()).write_parquet(&path)?;
+        let read = SearchResults::read_parquet(&path)?;
+        assert_eq!(read.0.schema(), df.schema());
+        assert!(read.0.equals_missing(&df));
+        Ok(())
+    }
+
+    #[test]
+    fn iter_should_summarise_each_metric() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_ID => &["a", "b"],
+            COL::METRIC_HUMAN_READABLE_NAME => &["Population", "Households"],
+            COL::METRIC_DESCRIPTION => &[Some("Total population"), None],
+            COL::METRIC_HXL_TAG => &["#population", "#household"],
+        )?;
+        let summaries = SearchResults(df).iter().collect::<Vec<_>>();
+        assert_eq!(
+            summaries,
+            vec![
+                MetricSummary {
+                    metric_id: Some("a".to_string()),
+                    human_readable_name: Some("Population".to_string()),
+                    description: Some("Total population".to_string()),
+                    hxl_tag: Some("#population".to_string()),
+                    geometry_level: None,
+                    country_name_short_en: None,
+                    country_iso3: None,
+                    source_download_url: None,
+                    source_documentation_url: None,
+                    unit: Some(MetricUnit {
+                        unit: "people".to_string(),
+                        inferred: true,
+                    }),
+                },
+                MetricSummary {
+                    metric_id: Some("b".to_string()),
+                    human_readable_name: Some("Households".to_string()),
+                    description: None,
+                    hxl_tag: Some("#household".to_string()),
+                    geometry_level: None,
+                    country_name_short_en: None,
+                    country_iso3: None,
+                    source_download_url: None,
+                    source_documentation_url: None,
+                    unit: Some(MetricUnit {
+                        unit: "households".to_string(),
+                        inferred: true,
+                    }),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn composite_metric_should_narrow_to_single_metric() -> anyhow::Result<()> {
+        let text = |text: &str, context: SearchContext| SearchText {
+            text: text.to_string(),
+            context: nonempty![context],
+            ..SearchText::default()
+        };
+        let search_params = SearchParams {
+            composite_metric: vec![CompositeMetric(vec![
+                text("green", SearchContext::Hxl),
+                text("pear", SearchContext::HumanReadableName),
+            ])],
+            ..Default::default()
+        };
+        let expr = Option::<Expr>::from(search_params).unwrap();
+        let filtered = test_df().lazy().filter(expr).collect()?;
+        assert_eq!(filtered.select(["index"])?, df!("index" => &[2u32])?);
+        Ok(())
+    }
+
+    #[test]
+    fn nested_query_should_combine_filters() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_HUMAN_READABLE_NAME => &["Population", "Households", "Population", "Area"],
+            COL::METRIC_HXL_TAG => &["#population", "#household", "#population", "#area"],
+            COL::METRIC_DESCRIPTION => &["", "", "", ""],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START => &[
+                NaiveDate::from_ymd_opt(2021, 3, 21).unwrap(),
+                NaiveDate::from_ymd_opt(2021, 3, 21).unwrap(),
+                NaiveDate::from_ymd_opt(2011, 3, 27).unwrap(),
+                NaiveDate::from_ymd_opt(2021, 3, 21).unwrap(),
+            ],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END => &[
+                NaiveDate::from_ymd_opt(2021, 3, 21).unwrap(),
+                NaiveDate::from_ymd_opt(2021, 3, 21).unwrap(),
+                NaiveDate::from_ymd_opt(2011, 3, 27).unwrap(),
+                NaiveDate::from_ymd_opt(2021, 3, 21).unwrap(),
+            ],
+            "index" => &[0u32, 1, 2, 3],
+        )?;
+        let text = |text: &str| {
+            SearchQuery::Text(SearchText {
+                text: text.to_string(),
+                ..SearchText::default()
+            })
+        };
+        let query = text("population")
+            .or(text("households"))
+            .and(SearchQuery::YearRange(YearRange::Between(2011, 2011)).not());
+        let results = query.search(&ExpandedMetadata(df.lazy()))?;
+        assert_eq!(results.0.select(["index"])?, df!("index" => &[0u32, 1])?);
+        Ok(())
+    }
+
+    #[test]
+    fn exclusions_should_remove_matching_metrics() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_ID => &["a", "b", "c"],
+            COL::GEOMETRY_LEVEL => &["oa", "lsoa", "msoa"],
+            "index" => &[0u32, 1, 2],
+        )?;
+        let geometry_level = |value: &str| GeometryLevel {
+            value: value.to_string(),
+            config: SearchConfig {
+                match_type: MatchType::Exact,
+                case_sensitivity: CaseSensitivity::Insensitive,
+            },
+        };
+        let search = |search_params: SearchParams| -> anyhow::Result<DataFrame> {
+            let filtered = match Option::<Expr>::from(search_params) {
+                Some(expr) => df.clone().lazy().filter(expr).collect()?,
+                None => df.clone(),
+            };
+            Ok(filtered.select(["index"])?)
+        };
+
+        // Excluding one of several geometry levels
+        let search_params = SearchParams {
+            exclude_geometry_level: vec![geometry_level("lsoa")],
+            ..Default::default()
+        };
+        assert_eq!(search(search_params)?, df!("index" => &[0u32, 2])?);
+
+        // Exclusions also apply to explicit metric IDs
+        let search_params = SearchParams {
+            metric_id: vec![MetricId {
+                id: "b".to_string(),
+                config: default_metric_id_search_config(),
+            }],
+            exclude_geometry_level: vec![geometry_level("lsoa")],
+            ..Default::default()
+        };
+        assert_eq!(search(search_params)?, df!("index" => Vec::<u32>::new())?);
+
+        // An empty exclusion is a no-op
+        let search_params = SearchParams {
+            exclude_geometry_level: vec![],
+            ..Default::default()
+        };
+        assert_eq!(search(search_params)?, df!("index" => &[0u32, 1, 2])?);
+        Ok(())
+    }
+
+    #[test]
+    fn exclusions_should_keep_metrics_with_null_country_columns() -> anyhow::Result<()> {
+        // Belgium has no ISO 3166-2 code
+        let expanded_metadata = two_country_metadata().combined_metric_source_geometry()?;
+        let search_params = SearchParams {
+            exclude_country: vec![Country {
+                value: "NIR".into(),
+                config: SearchConfig {
+                    match_type: MatchType::Exact,
+                    case_sensitivity: CaseSensitivity::Insensitive,
+                },
+            }],
+            ..Default::default()
+        };
+        let results = search_params.search(&expanded_metadata);
+        assert_eq!(
+            results
+                .0
+                .column(COL::METRIC_ID)?
+                .str()?
+                .into_no_null_iter()
+                .collect_vec(),
+            ["bel_metric"]
+        );
+        Ok(())
+    }
+
+    fn filter_year_range(year_range: YearRange) -> anyhow::Result<Vec<u32>> {
+        let date = |year| NaiveDate::from_ymd_opt(year, 6, 1).unwrap();
+        // Reference periods: 2001, 2009-2012, 2011, 2019-2022, 2021
+        let df = df!(
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START => &[date(2001), date(2009), date(2011), date(2019), date(2021)],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_END => &[date(2001), date(2012), date(2011), date(2022), date(2021)],
+            "index" => &[0u32, 1, 2, 3, 4],
+        )?;
+        let filtered = df.lazy().filter(year_range.into()).collect()?;
+        Ok(filtered
+            .column("index")?
+            .u32()?
+            .into_no_null_iter()
+            .collect())
+    }
+
+    #[test]
+    fn year_range_should_include_partially_overlapping_periods() -> anyhow::Result<()> {
+        // Closed
+        assert_eq!(
+            filter_year_range(YearRange::Between(2011, 2020))?,
+            vec![1, 2, 3]
+        );
+        assert_eq!(filter_year_range(YearRange::Between(2010, 2010))?, vec![1]);
+        // Open start
+        assert_eq!(filter_year_range(YearRange::Before(2010))?, vec![0, 1]);
+        // Open end
+        assert_eq!(filter_year_range(YearRange::After(2012))?, vec![1, 3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn summary_should_count_distinct_values() -> anyhow::Result<()> {
+        let results = SearchResults(df!(
+            COL::METRIC_ID => &["a", "b", "c", "d", "e"],
+            COL::GEOMETRY_LEVEL => &["oa", "ltla", "oa", "tract", "tract"],
+            COL::COUNTRY_NAME_SHORT_EN => &["UK", "UK", "UK", "US", "US"],
+            COL::DATA_PUBLISHER_NAME => &[Some("ONS"), Some("NRS"), None, Some("Census"), Some("Census")],
+        )?);
+        let summary = results.summary();
+        assert_eq!(
+            summary,
+            SearchSummary {
+                metrics: 5,
+                geometry_levels: 3,
+                countries: 2,
+                publishers: 3,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "5 metrics across 3 geometry levels, 2 countries, 3 publishers"
+        );
+
+        let empty = SearchResults(results.0.head(Some(0)));
+        assert_eq!(empty.summary(), SearchSummary::default());
+        assert_eq!(empty.summary().to_string(), "0 metrics");
+        let single = SearchResults(results.0.head(Some(1)));
+        assert_eq!(
+            single.summary().to_string(),
+            "1 metric across 1 geometry level, 1 country, 1 publisher"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn group_by_concept_should_collapse_metrics_across_countries() -> anyhow::Result<()> {
+        let date = |year| NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+        let results = SearchResults(df!(
+            COL::METRIC_ID => &["bel", "nir", "area"],
+            COL::METRIC_HUMAN_READABLE_NAME => &["Total population", "total  Population", "Area"],
+            COL::METRIC_HXL_TAG => &["#population+total", "#Population+total+2021", "#area"],
+            COL::COUNTRY_NAME_SHORT_EN => &["Belgium", "Northern Ireland", "Belgium"],
+            COL::GEOMETRY_LEVEL => &["municipality", "sdz21", "municipality"],
+            COL::SOURCE_DATA_RELEASE_REFERENCE_PERIOD_START => &[date(2022), date(2021), date(2022)],
+        )?);
+        let grouped = results.group_by_concept()?;
+        assert_eq!(grouped.height(), 2);
+        assert_eq!(
+            grouped.column(COL::METRIC_HUMAN_READABLE_NAME)?,
+            &Series::new(
+                COL::METRIC_HUMAN_READABLE_NAME,
+                &["Area", "Total population"]
+            )
+        );
+        let row = |column: &str| -> anyhow::Result<Series> {
+            Ok(grouped.column(column)?.list()?.get_as_series(1).unwrap())
+        };
+        assert_eq!(
+            row(COL::METRIC_ID)?
+                .str()?
+                .into_no_null_iter()
+                .collect_vec(),
+            vec!["bel", "nir"]
+        );
+        assert_eq!(
+            row(COL::COUNTRY_NAME_SHORT_EN)?
+                .str()?
+                .into_no_null_iter()
+                .collect_vec(),
+            vec!["Belgium", "Northern Ireland"]
+        );
+        assert_eq!(
+            row(COL::GEOMETRY_LEVEL)?
+                .str()?
+                .into_no_null_iter()
+                .collect_vec(),
+            vec!["municipality", "sdz21"]
+        );
+        assert_eq!(
+            row(CONCEPT_YEARS)?.i32()?.into_no_null_iter().collect_vec(),
+            vec![2021, 2022]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn metric_requests_with_moe_should_only_include_available_margins() -> anyhow::Result<()> {
+        let results = SearchResults(df!(
+            COL::METRIC_PARQUET_PATH => &["estimates.parquet", "estimates.parquet"],
+            COL::METRIC_PARQUET_COLUMN_NAME => &["B01001_E001", "B01002_E001"],
+            COL::GEOMETRY_FILEPATH_STEM => &["tract", "tract"],
+            COL::METRIC_PARQUET_MARGIN_OF_ERROR_COLUMN => &[Some("B01001_M001"), None],
+            COL::METRIC_PARQUET_MARGIN_OF_ERROR_FILE => &[Some("margins.parquet"), None],
+        )?);
+        let config = Config {
+            base_path: "base".into(),
+            ..Config::default()
+        };
+        let margins = results
+            .to_metric_requests_with_moe(&config)
+            .into_iter()
+            .map(|request| request.margin_of_error)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            margins,
+            vec![
+                Some(MarginOfError {
+                    column: "B01001_M001".into(),
+                    file: "base/margins.parquet".into(),
+                }),
+                None
+            ]
+        );
+        // Without the margin of error columns no margins are requested
+        let results = SearchResults(results.0.drop(COL::METRIC_PARQUET_MARGIN_OF_ERROR_FILE)?);
+        assert!(results
+            .to_metric_requests_with_moe(&config)
+            .iter()
+            .all(|request| request.margin_of_error.is_none()));
+        Ok(())
+    }
+
+    fn recipe_search_params(seed: u32) -> SearchParams {
+        let bit = |n: u32| seed & (1 << n) != 0;
+        let config = SearchConfig {
+            match_type: if bit(0) {
+                MatchType::Regex
+            } else {
+                MatchType::Startswith
+            },
+            case_sensitivity: if bit(1) {
+                CaseSensitivity::Sensitive
+            } else {
+                CaseSensitivity::Insensitive
+            },
+        };
+        let text = SearchText {
+            text: format!("population \\d+ \"{seed}\""),
+            context: nonempty![SearchContext::Hxl, SearchContext::Description],
+            config: config.clone(),
+            exact: bit(2),
+            match_types: BTreeMap::new(),
+        };
+        SearchParams {
+            text: if bit(3) { vec![text.clone()] } else { vec![] },
+            text_combination: if bit(4) {
+                TextCombination::Any
+            } else {
+                TextCombination::All
+            },
+            year_range: match seed % 3 {
+                0 => None,
+                1 => Some(vec![]),
+                _ => Some(vec![
+                    YearRange::Before(2011),
+                    YearRange::Between(2015, 2020),
+                ]),
+            },
+            metric_id: if bit(4) {
+                vec![MetricId {
+                    id: "f29c1976".into(),
+                    config: config.clone(),
+                }]
+            } else {
+                vec![]
+            },
+            composite_metric: if bit(2) {
+                vec![CompositeMetric(vec![]), CompositeMetric(vec![text])]
+            } else {
+                vec![]
+            },
+            geometry_level: bit(0).then(|| GeometryLevel {
+                value: "oa".into(),
+                config: config.clone(),
+            }),
+            source_data_release: bit(1).then(|| SourceDataRelease {
+                value: "census 2021".into(),
+                config: config.clone(),
+            }),
+            data_publisher: bit(3).then(|| DataPublisher {
+                value: "ONS".into(),
+                config: config.clone(),
+            }),
+            source_download_url: bit(4).then(|| SourceDownloadUrl {
+                value: "https://example.com/".into(),
+                config: config.clone(),
+            }),
+            country: bit(2).then(|| Country {
+                value: "Scotland".into(),
+                config: config.clone(),
+            }),
+            source_metric_id: bit(0).then(|| SourceMetricId {
+                value: "TS009".into(),
+                config: config.clone(),
+            }),
+            hxl_attribute: if bit(3) {
+                vec![HxlAttribute("+total".into())]
+            } else {
+                vec![]
+            },
+            exclude_geometry_level: if bit(1) {
+                vec![GeometryLevel {
+                    value: "ltla".into(),
+                    config: config.clone(),
+                }]
+            } else {
+                vec![]
+            },
+            exclude_data_publisher: vec![],
+            exclude_country: if bit(4) {
+                vec![Country {
+                    value: "Wales".into(),
+                    config,
+                }]
+            } else {
+                vec![]
+            },
+            region_spec: match seed % 4 {
+                0 => vec![],
+                1 => vec![RegionSpec::BoundingBox(BBox([
+                    -0.1276,
+                    51.5072,
+                    0.1 + 0.2,
+                    1.0 / 3.0,
+                ]))],
+                2 => vec![RegionSpec::NamedArea("Bristol".into())],
+                _ => vec![RegionSpec::Polygon(Polygon)],
+            },
+        }
+    }
+
+    #[test]
+    fn recipe_json_should_round_trip_search_params() -> anyhow::Result<()> {
+        for seed in 0..32 {
+            let params = recipe_search_params(seed);
+            let json = params.to_recipe_json()?;
+            assert_eq!(SearchParams::from_recipe_json(&json)?, params, "{json}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn recipe_json_should_keep_empty_year_ranges_distinct_from_none() -> anyhow::Result<()> {
+        let none = recipe_search_params(0);
+        let empty = recipe_search_params(1);
+        assert_eq!(none.year_range, None);
+        assert_eq!(empty.year_range, Some(vec![]));
+        assert_eq!(
+            SearchParams::from_recipe_json(&empty.to_recipe_json()?)?.year_range,
+            Some(vec![])
+        );
+        Ok(())
+    }
+
+    /// Search results for a `population` metric of the geometries of `geo::tests::test_fgb`,
+    /// written with the geometries to a temporary base path
+    fn local_population_results() -> (tempfile::TempDir, Config, SearchResults) {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let file = std::fs::File::create(tmp.path().join("geometries.fgb")).unwrap();
+        test_fgb().write(&mut &file).unwrap();
+        let mut metrics = df!(COL::GEO_ID => &["one", "two"], "population" => &[10, 20]).unwrap();
+        let file = std::fs::File::create(tmp.path().join("metrics.parquet")).unwrap();
+        ParquetWriter::new(file).finish(&mut metrics).unwrap();
+        let config = Config {
+            base_path: tmp.path().to_string_lossy().to_string(),
+            ..Config::default()
+        };
+        let results = SearchResults(
+            df!(
+                COL::METRIC_PARQUET_PATH => &["metrics.parquet"],
+                COL::METRIC_PARQUET_COLUMN_NAME => &["population"],
+                COL::GEOMETRY_FILEPATH_STEM => &["geometries"],
+            )
+            .unwrap(),
+        );
+        (tmp, config, results)
+    }
+
+    #[tokio::test]
+    async fn download_should_only_include_geometries_in_bbox() {
+        let (_tmp, config, results) = local_population_results();
+        for include_geoms in [false, true] {
+            let download_params = DownloadParams {
+                include_geoms,
+                // Covers the first polygon only, given in WGS84
+                region_spec: vec![RegionSpec::BoundingBox(BBox([-3.0, 51.8, -1.4, 53.0]))],
+                include_moe: false,
+                transformations: TransformPipeline::default(),
+            };
+            let df = results
+                .clone()
+                .download(&config, &download_params)
+                .await
+                .unwrap();
+            assert_eq!(
+                df.column(COL::GEO_ID)
+                    .unwrap()
+                    .str()
+                    .unwrap()
+                    .into_no_null_iter()
+                    .collect_vec(),
+                ["one"]
+            );
+            assert_eq!(df.get_column_names().contains(&"geometry"), include_geoms);
+        }
+    }
+
+    #[tokio::test]
+    async fn download_should_fail_if_no_geometries_are_in_bbox() {
+        let (_tmp, config, results) = local_population_results();
+        let download_params = DownloadParams {
+            include_geoms: false,
+            // Inside the bounding box of the first polygon but outside the polygon itself
+            region_spec: vec![RegionSpec::BoundingBox(BBox([-2.5, 52.7, -2.4, 52.8]))],
+            include_moe: false,
+            transformations: TransformPipeline::default(),
+        };
+        let err = results
+            .clone()
+            .download(&config, &download_params)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("intersect the bounding box"),
+            "{err}"
+        );
+        let err = results
+            .to_metric_requests_in_bbox(&config, &BBox([-2.5, 52.7, -2.4, 52.8]))
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("intersect the bounding box"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn geo_id_column_should_error_for_unknown_geometry_file() {
+        let (_tmp, config, results) = local_population_results();
+        let geom_file = join_path(&config.base_path, "geometries.fgb");
+        assert_eq!(
+            results.geo_id_column(&config, &geom_file).unwrap(),
+            COL::GEO_ID
+        );
+        let err = results
+            .geo_id_column(&config, "other.fgb")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("other.fgb"), "{err}");
+    }
+
+    #[test]
+    fn bboxes_should_restrict_search_to_overlapping_countries() {
+        let search = |metadata: &Metadata, bboxes: &[BBox]| -> anyhow::Result<Vec<String>> {
+            let mut search_params = SearchParams::default();
+            search_params.restrict_to_bboxes(bboxes, metadata)?;
+            let results = search_params.search(&metadata.combined_metric_source_geometry()?);
+            Ok(results
+                .0
+                .column(COL::METRIC_ID)?
+                .str()?
+                .into_no_null_iter()
+                .map(String::from)
+                .collect())
+        };
+        let metadata = two_country_metadata();
+        // Belfast
+        let belfast = BBox::new(-6.05, 54.55, -5.8, 54.65).unwrap();
+        assert_eq!(
+            search(&metadata, std::slice::from_ref(&belfast)).unwrap(),
+            ["nir_metric"]
+        );
+        let mut search_params = SearchParams::default();
+        search_params
+            .restrict_to_bboxes(&[belfast], &metadata)
+            .unwrap();
+        assert_eq!(search_params.country.unwrap().value, "^(GBR)$");
+        // Spanning the Channel from Kent to Flanders
+        let channel = BBox::new(1.0, 50.9, 3.0, 51.3).unwrap();
+        assert_eq!(
+            search(&metadata, &[channel]).unwrap(),
+            ["bel_metric", "nir_metric"]
+        );
+        // Mid-Atlantic
+        let atlantic = BBox::new(-40.0, 40.0, -39.0, 41.0).unwrap();
+        assert!(search(&metadata, std::slice::from_ref(&atlantic)).is_err());
+
+        // A country without known bounds cannot be ruled out
+        let mut metadata = two_country_metadata();
+        metadata
+            .countries
+            .replace(
+                COL::COUNTRY_ISO3,
+                Series::new(COL::COUNTRY_ISO3, &["FRA", "GBR"]),
+            )
+            .unwrap();
+        assert_eq!(search(&metadata, &[atlantic]).unwrap(), ["bel_metric"]);
+    }
 }
@@ -0,0 +1,126 @@
+//! Raw TCP servers shared by the tests, for the behaviour that httpmock cannot mock: the `HEAD`
+//! and ranged `GET` requests made by polars' HTTP scans, servers that never respond and servers
+//! that fail a given number of times.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// Serves `files`, keyed by their path from the root of the server (e.g. `/bel/metrics.parquet`),
+/// answering `HEAD` requests and ranged `GET` requests with `206 Partial Content`. Unknown paths
+/// are `404 Not Found`. Returns the base URL.
+pub(crate) async fn file_server(files: HashMap<String, Vec<u8>>) -> String {
+    let files = Arc::new(files);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let files = files.clone();
+            tokio::spawn(async move {
+                let mut buffer = [0; 4096];
+                let read = stream.read(&mut buffer).await.unwrap();
+                let request = String::from_utf8_lossy(&buffer[..read]);
+                let mut lines = request.lines();
+                let mut request_line = lines.next().unwrap_or_default().split(' ');
+                let method = request_line.next().unwrap_or_default();
+                let path = request_line.next().unwrap_or_default();
+                let range = lines.find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("range")
+                        .then(|| value.trim().trim_start_matches("bytes=").to_string())
+                });
+                let Some(bytes) = files.get(path) else {
+                    let response =
+                        "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                    return;
+                };
+                let len = bytes.len();
+                // Ranges are `start-end` (inclusive), `start-` or `-suffix_length`
+                let range = range.and_then(|range| {
+                    let (start, end) = range.split_once('-')?;
+                    Some(match (start.parse::<usize>(), end.parse::<usize>()) {
+                        (Ok(start), Ok(end)) => (start, (end + 1).min(len)),
+                        (Ok(start), Err(_)) => (start, len),
+                        (Err(_), Ok(suffix)) => (len.saturating_sub(suffix), len),
+                        _ => return None,
+                    })
+                });
+                let (status, body) = match range {
+                    Some((start, end)) => (
+                        format!(
+                            "206 Partial Content\r\ncontent-range: bytes {start}-{}/{len}",
+                            end - 1
+                        ),
+                        &bytes[start..end],
+                    ),
+                    None => ("200 OK".to_string(), &bytes[..]),
+                };
+                let mut response = format!(
+                    "HTTP/1.1 {status}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                    body.len()
+                )
+                .into_bytes();
+                if method != "HEAD" {
+                    response.extend_from_slice(body);
+                }
+                stream.write_all(&response).await.unwrap();
+            });
+        }
+    });
+    base_url
+}
+
+/// Accepts connections but never responds. Returns the base URL.
+pub(crate) async fn silent_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut connections = vec![];
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            connections.push(stream);
+        }
+    });
+    base_url
+}
+
+/// Responds to every request with `body`, except for the first `failures` requests which get
+/// `503 Service Unavailable`. Returns the base URL and a counter of the requests received.
+pub(crate) async fn flaky_server(
+    failures: usize,
+    body: &'static str,
+) -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 1024];
+            let _ = stream.read(&mut buffer).await.unwrap();
+            let response = if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                    .to_string()
+            } else {
+                format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+            };
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    (base_url, requests)
+}
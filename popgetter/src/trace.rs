@@ -0,0 +1,196 @@
+//! Fields of the `tracing` spans around loads, searches and metric fetches, which are only
+//! emitted with the `tracing` feature. Without the feature these helpers do nothing, so they can
+//! be called unconditionally.
+
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+/// Record `rows` as the `rows` field of the current span
+pub(crate) fn record_rows(rows: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("rows", rows);
+    #[cfg(not(feature = "tracing"))]
+    let _ = rows;
+}
+
+/// Records the time in milliseconds between being started and dropped as the `elapsed_ms` field
+/// of the span that was current when it was started
+pub(crate) struct SpanTimer {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "tracing")]
+    start: Instant,
+}
+
+impl SpanTimer {
+    pub(crate) fn start() -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::current(),
+            #[cfg(feature = "tracing")]
+            start: Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for SpanTimer {
+    fn drop(&mut self) {
+        let elapsed_ms = u64::try_from(self.start.elapsed().as_millis()).unwrap_or(u64::MAX);
+        self.span.record("elapsed_ms", elapsed_ms);
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        fmt,
+        sync::{Arc, Mutex},
+    };
+
+    use polars::{df, prelude::ParquetWriter};
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Subscriber,
+    };
+    use tracing_subscriber::{
+        layer::{Context, SubscriberExt},
+        registry::LookupSpan,
+        Layer, Registry,
+    };
+
+    use crate::{
+        config::Config,
+        metadata::{paths as PATHS, CountryMetadataLoader},
+        parquet::{get_metrics, MetricRequest},
+        COL,
+    };
+
+    /// The name and fields of a span
+    type RecordedSpan = (&'static str, BTreeMap<&'static str, String>);
+
+    /// A layer recording the name and fields of each span, in the order they were created
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<Mutex<Vec<RecordedSpan>>>);
+
+    /// The index in the `SpanRecorder` of a span, stored in the span's extensions
+    #[derive(Clone, Copy)]
+    struct RecordedIndex(usize);
+
+    struct FieldVisitor<'a>(&'a mut BTreeMap<&'static str, String>);
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name(), format!("{value:?}"));
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SpanRecorder {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let mut fields = BTreeMap::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            let mut spans = self.0.lock().unwrap();
+            spans.push((attrs.metadata().name(), fields));
+            if let Some(span) = ctx.span(id) {
+                span.extensions_mut().insert(RecordedIndex(spans.len() - 1));
+            }
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+            let Some(span) = ctx.span(id) else {
+                return;
+            };
+            let Some(&RecordedIndex(index)) = span.extensions().get::<RecordedIndex>() else {
+                return;
+            };
+            let (_, fields) = &mut self.0.lock().unwrap()[index];
+            values.record(&mut FieldVisitor(fields));
+        }
+    }
+
+    impl SpanRecorder {
+        /// The fields of each recorded span named `name`
+        fn spans(&self, name: &str) -> Vec<BTreeMap<&'static str, String>> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(span_name, _)| *span_name == name)
+                .map(|(_, fields)| fields.clone())
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn country_load_should_emit_spans() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let country_dir = tmp.path().join("bel");
+        std::fs::create_dir(&country_dir).unwrap();
+        for path in [
+            PATHS::METRIC_METADATA,
+            PATHS::GEOMETRY_METADATA,
+            PATHS::SOURCE,
+            PATHS::PUBLISHER,
+            PATHS::COUNTRY,
+        ] {
+            let mut df = df!(COL::COUNTRY_ID => &["bel", "bel"]).unwrap();
+            let file = std::fs::File::create(country_dir.join(path)).unwrap();
+            ParquetWriter::new(file).finish(&mut df).unwrap();
+        }
+        let config = Config {
+            base_path: tmp.path().to_string_lossy().to_string(),
+            ..Config::default()
+        };
+        let recorder = SpanRecorder::default();
+        let _guard = tracing::subscriber::set_default(Registry::default().with(recorder.clone()));
+
+        CountryMetadataLoader::new("bel")
+            .load(&config)
+            .await
+            .unwrap();
+
+        let loads = recorder.spans("load");
+        assert_eq!(loads.len(), 1);
+        assert_eq!(loads[0]["country"], "bel");
+        assert_eq!(loads[0]["rows"], "2");
+        assert!(loads[0].contains_key("elapsed_ms"));
+        let metadata_loads = recorder.spans("load_metadata");
+        assert_eq!(metadata_loads.len(), 5);
+        for fields in metadata_loads {
+            assert_eq!(fields["rows"], "2");
+        }
+    }
+
+    #[test]
+    fn scanned_metric_fetch_should_emit_spans() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("metrics.parquet");
+        let mut df = df!(COL::GEO_ID => &["E1", "E2", "E3"], "metric_1" => &[1, 2, 3]).unwrap();
+        ParquetWriter::new(std::fs::File::create(&path).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let metrics = [MetricRequest {
+            column: "metric_1".into(),
+            metric_file: path.to_string_lossy().to_string(),
+            geom_file: "Not needed for this test".into(),
+            geoids: vec![],
+            margin_of_error: None,
+        }];
+        let recorder = SpanRecorder::default();
+        let _guard = tracing::subscriber::set_default(Registry::default().with(recorder.clone()));
+
+        get_metrics(&metrics, Some(&["E1", "E3"])).unwrap();
+
+        let fetches = recorder.spans("get_metrics_cancellable");
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0]["metrics"], "1");
+        assert_eq!(fetches[0]["rows"], "2");
+        assert!(fetches[0].contains_key("elapsed_ms"));
+        let file_fetches = recorder.spans("get_metrics_from_file");
+        assert_eq!(file_fetches.len(), 1);
+        assert_eq!(file_fetches[0]["file_url"], metrics[0].metric_file);
+        assert_eq!(file_fetches[0]["rows"], "2");
+    }
+}
@@ -0,0 +1,842 @@
+//! Transformations applied to downloaded metrics, e.g. renaming columns or deriving new columns
+//! from existing ones. Transforms are applied in order by a `TransformPipeline`, which can be
+//! validated against the metadata before any data is downloaded.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, bail, Result};
+use enum_dispatch::enum_dispatch;
+use geo::{Area, BooleanOps, Geometry, LineString, MultiPolygon, Polygon};
+use h3o::{
+    geom::{ContainmentMode, PolyfillConfig, ToCells},
+    CellIndex, Resolution,
+};
+use itertools::Itertools;
+use polars::{lazy::frame::pivot::pivot_stable, prelude::*};
+use serde::{Deserialize, Serialize};
+use wkt::{ToWkt, TryFromWkt};
+
+use crate::{
+    metadata::{Metadata, MetricDataType, MetricUnit},
+    COL,
+};
+
+/// Trait implemented by each transformation of the downloaded metrics
+#[enum_dispatch]
+pub trait Transform {
+    /// Check that the transform can be applied to a `DataFrame` with the given `columns`,
+    /// returning the columns it would output. This only uses the metadata so can be run before
+    /// any data is downloaded.
+    fn validate(&self, columns: Vec<String>, metadata: &Metadata) -> Result<Vec<String>>;
+    /// Apply the transform to the downloaded metrics
+    fn apply(&self, df: DataFrame, metadata: &Metadata) -> Result<DataFrame>;
+}
+
+/// Enum of the available transforms, one for each implementation of `Transform`
+#[enum_dispatch(Transform)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PopgetterTransform {
+    Rename(RenameColumn),
+    RenameColumns(RenameColumns),
+    Formula(FormulaColumn),
+    InterpolateToH3(InterpolateToH3),
+    PivotToLong(PivotToLong),
+    PivotToWide(PivotToWide),
+}
+
+/// An ordered list of transforms, each applied to the output of the previous one
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransformPipeline(pub Vec<PopgetterTransform>);
+
+impl TransformPipeline {
+    /// Validate each transform in turn against the columns output by the previous one, starting
+    /// from the `columns` that will be downloaded
+    pub fn validate(&self, columns: Vec<String>, metadata: &Metadata) -> Result<Vec<String>> {
+        self.0.iter().try_fold(columns, |columns, transform| {
+            transform.validate(columns, metadata)
+        })
+    }
+
+    /// Apply each transform in turn
+    pub fn apply(&self, df: DataFrame, metadata: &Metadata) -> Result<DataFrame> {
+        self.0
+            .iter()
+            .try_fold(df, |df, transform| transform.apply(df, metadata))
+    }
+}
+
+/// Check that a transform's new column `name` does not already exist and add it to `columns`
+fn add_column(mut columns: Vec<String>, name: &str) -> Result<Vec<String>> {
+    if columns.iter().any(|column| column == name) {
+        bail!("Column '{name}' already exists");
+    }
+    columns.push(name.to_string());
+    Ok(columns)
+}
+
+/// Rename the column `from` to `to`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenameColumn {
+    pub from: String,
+    pub to: String,
+}
+
+impl Transform for RenameColumn {
+    fn validate(&self, columns: Vec<String>, _metadata: &Metadata) -> Result<Vec<String>> {
+        if !columns.contains(&self.from) {
+            bail!("Cannot rename missing column '{}'", self.from);
+        }
+        let columns = columns
+            .into_iter()
+            .filter(|column| column != &self.from)
+            .collect();
+        add_column(columns, &self.to)
+    }
+
+    fn apply(&self, mut df: DataFrame, _metadata: &Metadata) -> Result<DataFrame> {
+        df.rename(&self.from, &self.to)?;
+        Ok(df)
+    }
+}
+
+/// Rename several columns at once, given a mapping from the current column names to the new
+/// ones. Columns may be swapped, but two columns may not be given the same name.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenameColumns(pub HashMap<String, String>);
+
+impl RenameColumns {
+    /// Rename the columns of the given metrics to their human readable names
+    pub fn to_human_readable_names(metadata: &Metadata, metric_ids: &[&str]) -> Result<Self> {
+        let names = metadata
+            .metrics
+            .clone()
+            .lazy()
+            .filter(col(COL::METRIC_ID).is_in(lit(Series::new("metric_ids", metric_ids))))
+            .select([
+                col(COL::METRIC_PARQUET_COLUMN_NAME),
+                col(COL::METRIC_HUMAN_READABLE_NAME),
+            ])
+            .collect()?;
+        let columns = names.column(COL::METRIC_PARQUET_COLUMN_NAME)?.str()?;
+        let human_readable_names = names.column(COL::METRIC_HUMAN_READABLE_NAME)?.str()?;
+        Ok(RenameColumns(
+            columns
+                .into_iter()
+                .zip(human_readable_names)
+                .filter_map(|(column, name)| Some((column?.to_string(), name?.to_string())))
+                .collect(),
+        ))
+    }
+
+    /// The columns after renaming, erroring if a column to rename is missing or if two columns
+    /// would end up with the same name
+    fn renamed(&self, columns: &[String]) -> Result<Vec<String>> {
+        if let Some(missing) = self.0.keys().sorted().find(|from| !columns.contains(from)) {
+            bail!("Cannot rename missing column '{missing}'");
+        }
+        let renamed = columns
+            .iter()
+            .map(|column| self.0.get(column).unwrap_or(column).clone())
+            .collect_vec();
+        if let Some(duplicate) = renamed.iter().duplicates().sorted().next() {
+            bail!("Renaming would give more than one column the name '{duplicate}'");
+        }
+        Ok(renamed)
+    }
+}
+
+impl Transform for RenameColumns {
+    fn validate(&self, columns: Vec<String>, _metadata: &Metadata) -> Result<Vec<String>> {
+        self.renamed(&columns)
+    }
+
+    fn apply(&self, df: DataFrame, _metadata: &Metadata) -> Result<DataFrame> {
+        let columns = df
+            .get_column_names()
+            .into_iter()
+            .map(String::from)
+            .collect_vec();
+        let renamed = self.renamed(&columns)?;
+        Ok(df
+            .lazy()
+            .select(
+                columns
+                    .iter()
+                    .zip(renamed)
+                    .map(|(from, to)| col(from).alias(&to))
+                    .collect_vec(),
+            )
+            .collect()?)
+    }
+}
+
+/// An arithmetic formula over the columns of the downloaded metrics
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Formula {
+    /// A column of the `DataFrame` by name
+    Column(String),
+    /// A metric by its metric ID, which is looked up in the metadata to find its column
+    Metric(String),
+    Literal(f64),
+    Add(Box<Formula>, Box<Formula>),
+    Sub(Box<Formula>, Box<Formula>),
+    Mul(Box<Formula>, Box<Formula>),
+    /// Division, which gives null rather than an infinite or NaN value where the divisor is zero
+    Div(Box<Formula>, Box<Formula>),
+}
+
+impl Formula {
+    /// The names of the columns used by the formula
+    fn columns(&self, metadata: &Metadata) -> Result<Vec<String>> {
+        Ok(match self {
+            Formula::Column(name) => vec![name.clone()],
+            Formula::Metric(metric_id) => vec![metadata.parquet_column_name(metric_id)?],
+            Formula::Literal(_) => vec![],
+            Formula::Add(a, b) | Formula::Sub(a, b) | Formula::Mul(a, b) | Formula::Div(a, b) => {
+                let mut columns = a.columns(metadata)?;
+                columns.extend(b.columns(metadata)?);
+                columns
+            }
+        })
+    }
+
+    /// The unit of the values of the formula, where it is known. Adding or subtracting metrics in
+    /// different units, e.g. people and households, is an error. Products and quotients have no
+    /// known unit.
+    fn unit(&self, metadata: &Metadata) -> Result<Option<MetricUnit>> {
+        Ok(match self {
+            Formula::Metric(metric_id) => metadata.metric_unit(metric_id)?,
+            Formula::Column(_) | Formula::Literal(_) => None,
+            Formula::Add(a, b) | Formula::Sub(a, b) => {
+                match (a.unit(metadata)?, b.unit(metadata)?) {
+                    (Some(a_unit), Some(b_unit)) if a_unit.unit != b_unit.unit => {
+                        bail!("Cannot add or subtract metrics in {a_unit} and {b_unit}")
+                    }
+                    (a_unit, b_unit) => a_unit.or(b_unit),
+                }
+            }
+            Formula::Mul(..) | Formula::Div(..) => None,
+        })
+    }
+
+    /// Convert the formula to a polars expression
+    fn to_expr(&self, metadata: &Metadata) -> Result<Expr> {
+        let binary = |a: &Formula, b: &Formula| -> Result<(Expr, Expr)> {
+            Ok((a.to_expr(metadata)?, b.to_expr(metadata)?))
+        };
+        Ok(match self {
+            Formula::Column(name) => col(name).cast(DataType::Float64),
+            Formula::Metric(metric_id) => {
+                col(&metadata.parquet_column_name(metric_id)?).cast(DataType::Float64)
+            }
+            Formula::Literal(value) => lit(*value),
+            Formula::Add(a, b) => {
+                let (a, b) = binary(a, b)?;
+                a + b
+            }
+            Formula::Sub(a, b) => {
+                let (a, b) = binary(a, b)?;
+                a - b
+            }
+            Formula::Mul(a, b) => {
+                let (a, b) = binary(a, b)?;
+                a * b
+            }
+            Formula::Div(a, b) => {
+                let (a, b) = binary(a, b)?;
+                when(b.clone().eq(lit(0.0)))
+                    .then(lit(NULL).cast(DataType::Float64))
+                    .otherwise(a / b)
+            }
+        })
+    }
+}
+
+/// Add a column `name` computed from `formula`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FormulaColumn {
+    pub name: String,
+    pub formula: Formula,
+}
+
+impl Transform for FormulaColumn {
+    fn validate(&self, columns: Vec<String>, metadata: &Metadata) -> Result<Vec<String>> {
+        if let Some(missing) = self
+            .formula
+            .columns(metadata)?
+            .into_iter()
+            .find(|column| !columns.contains(column))
+        {
+            bail!(
+                "Formula for '{}' uses missing column '{missing}'",
+                self.name
+            );
+        }
+        self.formula.unit(metadata)?;
+        add_column(columns, &self.name)
+    }
+
+    fn apply(&self, df: DataFrame, metadata: &Metadata) -> Result<DataFrame> {
+        Ok(df
+            .lazy()
+            .with_column(self.formula.to_expr(metadata)?.alias(&self.name))
+            .collect()?)
+    }
+}
+
+/// Name of the column holding the H3 index of each cell output by `InterpolateToH3`
+pub const H3_INDEX: &str = "h3_index";
+
+/// Areal interpolation of metrics onto the cells of an H3 grid at `resolution`. The metrics must
+/// have been downloaded with their geometries, in longitude and latitude. Counts are split
+/// between the cells overlapping each geometry in proportion to the area of overlap, while
+/// continuous metrics are averaged over the geometries overlapping each cell, weighted by area.
+/// The output has one row per cell, with its index in `h3_index` and its boundary in `geometry`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterpolateToH3 {
+    pub resolution: u8,
+}
+
+impl InterpolateToH3 {
+    fn resolution(&self) -> Result<Resolution> {
+        Resolution::try_from(self.resolution)
+            .map_err(|_| anyhow!("Invalid H3 resolution {}", self.resolution))
+    }
+
+    /// The data type of the metric in `column`, which must be one that can be interpolated
+    fn data_type(metadata: &Metadata, column: &str) -> Result<MetricDataType> {
+        match metadata.metric_data_type_of_column(column)? {
+            MetricDataType::Categorical => {
+                bail!("Categorical metric column '{column}' cannot be interpolated to H3")
+            }
+            data_type => Ok(data_type),
+        }
+    }
+}
+
+/// The polygon covered by an H3 cell
+fn cell_polygon(cell: CellIndex) -> Polygon {
+    let boundary = cell.boundary();
+    Polygon::new(
+        LineString::from(
+            boundary
+                .iter()
+                .map(|vertex| (vertex.lng(), vertex.lat()))
+                .collect_vec(),
+        ),
+        vec![],
+    )
+}
+
+impl Transform for InterpolateToH3 {
+    fn validate(&self, columns: Vec<String>, metadata: &Metadata) -> Result<Vec<String>> {
+        self.resolution()?;
+        if !columns.iter().any(|column| column == "geometry") {
+            bail!("Interpolating to H3 requires the metrics to be downloaded with geometries");
+        }
+        let metrics = columns
+            .into_iter()
+            .filter(|column| column != "geometry" && column != COL::GEO_ID)
+            .collect_vec();
+        for column in &metrics {
+            Self::data_type(metadata, column)?;
+        }
+        Ok([H3_INDEX.to_string(), "geometry".to_string()]
+            .into_iter()
+            .chain(metrics)
+            .collect())
+    }
+
+    fn apply(&self, df: DataFrame, metadata: &Metadata) -> Result<DataFrame> {
+        let config =
+            PolyfillConfig::new(self.resolution()?).containment_mode(ContainmentMode::Covers);
+        let metric_columns = df
+            .get_column_names()
+            .into_iter()
+            .filter(|&column| column != "geometry" && column != COL::GEO_ID)
+            .map(String::from)
+            .collect_vec();
+        let data_types = metric_columns
+            .iter()
+            .map(|column| Self::data_type(metadata, column))
+            .collect::<Result<Vec<_>>>()?;
+        let values = metric_columns
+            .iter()
+            .map(|column| Ok(df.column(column)?.cast(&DataType::Float64)?.f64()?.to_vec()))
+            .collect::<Result<Vec<_>>>()?;
+
+        // For each cell, the rows overlapping it as `(row, overlap, area of the row's geometry)`
+        let mut cells: BTreeMap<CellIndex, Vec<(usize, f64, f64)>> = BTreeMap::new();
+        for (row, wkt) in df.column("geometry")?.str()?.into_iter().enumerate() {
+            let Some(wkt) = wkt else {
+                continue;
+            };
+            let polygons = match Geometry::<f64>::try_from_wkt_str(wkt)
+                .map_err(|err| anyhow!("Failed to parse geometry: {err}"))?
+            {
+                Geometry::Polygon(polygon) => MultiPolygon::new(vec![polygon]),
+                Geometry::MultiPolygon(polygons) => polygons,
+                _ => bail!("Only polygon geometries can be interpolated to H3"),
+            };
+            let total_area = polygons.unsigned_area();
+            if total_area == 0.0 {
+                continue;
+            }
+            for polygon in &polygons {
+                for cell in h3o::geom::Polygon::from_degrees(polygon.clone())?.to_cells(config) {
+                    let overlap = cell_polygon(cell).intersection(polygon).unsigned_area();
+                    if overlap == 0.0 {
+                        continue;
+                    }
+                    cells
+                        .entry(cell)
+                        .or_default()
+                        .push((row, overlap, total_area));
+                }
+            }
+        }
+
+        let mut output = vec![
+            Series::new(
+                H3_INDEX,
+                cells.keys().map(ToString::to_string).collect_vec(),
+            ),
+            Series::new(
+                "geometry",
+                cells
+                    .keys()
+                    .map(|cell| cell_polygon(*cell).wkt_string())
+                    .collect_vec(),
+            ),
+        ];
+        for (idx, (column, data_type)) in metric_columns.iter().zip(&data_types).enumerate() {
+            let interpolated = cells
+                .values()
+                .map(|overlaps| -> Option<f64> {
+                    let overlaps = overlaps
+                        .iter()
+                        .filter_map(|&(row, overlap, total_area)| {
+                            Some((values[idx][row]?, overlap, total_area))
+                        })
+                        .collect_vec();
+                    if overlaps.is_empty() {
+                        return None;
+                    }
+                    match data_type {
+                        // Counts are split between cells in proportion to the area of the
+                        // geometry in each cell
+                        MetricDataType::Count => Some(
+                            overlaps
+                                .iter()
+                                .map(|(value, overlap, total_area)| value * (overlap / total_area))
+                                .sum(),
+                        ),
+                        // Other metrics are averaged over the geometries in each cell, weighted
+                        // by their overlap. The weights are normalised before summing so that a
+                        // cell within a single geometry is given exactly its value.
+                        _ => {
+                            let area: f64 = overlaps.iter().map(|(_, overlap, _)| overlap).sum();
+                            Some(
+                                overlaps
+                                    .iter()
+                                    .map(|(value, overlap, _)| value * (overlap / area))
+                                    .sum(),
+                            )
+                        }
+                    }
+                })
+                .collect_vec();
+            output.push(Series::new(column, interpolated));
+        }
+        Ok(DataFrame::new(output)?)
+    }
+}
+
+/// Name of the column holding the name of the metric column each row came from in the long
+/// format output by `pivot_to_long`
+pub const METRIC_NAME: &str = "metric";
+/// Name of the column holding the value of the metric in the long format output by
+/// `pivot_to_long`
+pub const METRIC_VALUE: &str = "value";
+
+/// Split `columns` into those identifying each geometry, which are kept as they are when pivoting
+/// to long format (`GEO_ID`, and `geometry` if the metrics were downloaded with geometries), and
+/// the metric columns
+fn split_pivot_columns<'a>(
+    columns: impl IntoIterator<Item = &'a str>,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let (index, metrics): (Vec<_>, Vec<_>) = columns
+        .into_iter()
+        .map(String::from)
+        .partition(|column| column == COL::GEO_ID || column == "geometry");
+    if !index.iter().any(|column| column == COL::GEO_ID) {
+        bail!(
+            "Pivoting to long format requires a '{}' column",
+            COL::GEO_ID
+        );
+    }
+    if metrics.is_empty() {
+        bail!("There are no metric columns to pivot to long format");
+    }
+    Ok((index, metrics))
+}
+
+/// Reshape downloaded metrics from wide format, with a column per metric, to long format, with a
+/// row per geometry and metric. The name of the metric's column is in `metric` and its value in
+/// `value`. Metrics of different data types share the `value` column, so are cast to a common
+/// type, e.g. integer counts alongside ratios become floats.
+pub fn pivot_to_long(df: &DataFrame) -> Result<DataFrame> {
+    let (index, metrics) = split_pivot_columns(df.get_column_names())?;
+    Ok(df.unpivot2(UnpivotArgsIR {
+        on: metrics.into_iter().map(Into::into).collect(),
+        index: index.into_iter().map(Into::into).collect(),
+        variable_name: Some(METRIC_NAME.into()),
+        value_name: Some(METRIC_VALUE.into()),
+    })?)
+}
+
+/// Reshape metrics in the long format output by `pivot_to_long` back to wide format, with a
+/// column per metric in the order the metrics first appear. Geometries keep the order they first
+/// appear in, and metrics without a value for a geometry are null. The metric columns have the
+/// type of the `value` column. Returns an error if a metric has more than one value for the same
+/// geometry.
+pub fn pivot_to_wide(df: &DataFrame) -> Result<DataFrame> {
+    let index = wide_index_columns(df.get_column_names())?;
+    Ok(pivot_stable(
+        df,
+        [METRIC_NAME],
+        Some(index),
+        Some([METRIC_VALUE]),
+        false,
+        None,
+        None,
+    )?)
+}
+
+/// The columns of a long format `DataFrame` that identify each row of the wide format, i.e. all
+/// but the metric name and value columns. Returns an error if any of the columns needed to pivot
+/// are missing.
+fn wide_index_columns<'a>(columns: impl IntoIterator<Item = &'a str>) -> Result<Vec<&'a str>> {
+    let columns = columns.into_iter().collect_vec();
+    for column in [COL::GEO_ID, METRIC_NAME, METRIC_VALUE] {
+        if !columns.contains(&column) {
+            bail!("Pivoting to wide format requires a '{column}' column");
+        }
+    }
+    Ok(columns
+        .into_iter()
+        .filter(|&column| column != METRIC_NAME && column != METRIC_VALUE)
+        .collect())
+}
+
+/// Reshape the downloaded metrics to long format with `pivot_to_long`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PivotToLong;
+
+impl Transform for PivotToLong {
+    fn validate(&self, columns: Vec<String>, _metadata: &Metadata) -> Result<Vec<String>> {
+        let (index, _) = split_pivot_columns(columns.iter().map(String::as_str))?;
+        Ok(index
+            .into_iter()
+            .chain([METRIC_NAME.to_string(), METRIC_VALUE.to_string()])
+            .collect())
+    }
+
+    fn apply(&self, df: DataFrame, _metadata: &Metadata) -> Result<DataFrame> {
+        pivot_to_long(&df)
+    }
+}
+
+/// Reshape metrics in long format back to wide format with `pivot_to_wide`. The metric columns
+/// are only known once the data is downloaded, so validation only returns the index columns and
+/// later transforms cannot refer to the metrics.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PivotToWide;
+
+impl Transform for PivotToWide {
+    fn validate(&self, columns: Vec<String>, _metadata: &Metadata) -> Result<Vec<String>> {
+        Ok(wide_index_columns(columns.iter().map(String::as_str))?
+            .into_iter()
+            .map(String::from)
+            .collect())
+    }
+
+    fn apply(&self, df: DataFrame, _metadata: &Metadata) -> Result<DataFrame> {
+        pivot_to_wide(&df)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> Metadata {
+        let empty = DataFrame::empty();
+        Metadata {
+            metrics: df!(
+                COL::METRIC_ID => &["population_id", "households_id", "dwellings_id"],
+                COL::METRIC_PARQUET_COLUMN_NAME => &["B01001_E001", "B11001_E001", "B25001_E001"],
+                COL::METRIC_HUMAN_READABLE_NAME => &["Total population", "Total households", "Total households"],
+            )
+            .unwrap(),
+            geometries: empty.clone(),
+            source_data_releases: empty.clone(),
+            data_publishers: empty.clone(),
+            countries: empty,
+        }
+    }
+
+    fn metrics() -> DataFrame {
+        df!(
+            COL::GEO_ID => &["E1", "E2"],
+            "B01001_E001" => &[200, 0],
+            "B11001_E001" => &[50, 10],
+        )
+        .unwrap()
+    }
+
+    fn columns(df: &DataFrame) -> Vec<String> {
+        df.get_column_names()
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn rename() -> PopgetterTransform {
+        PopgetterTransform::Rename(RenameColumn {
+            from: "B11001_E001".into(),
+            to: "households".into(),
+        })
+    }
+
+    fn people_per_household() -> PopgetterTransform {
+        PopgetterTransform::Formula(FormulaColumn {
+            name: "people_per_household".into(),
+            formula: Formula::Div(
+                Box::new(Formula::Metric("population_id".into())),
+                Box::new(Formula::Column("households".into())),
+            ),
+        })
+    }
+
+    #[test]
+    fn pipeline_should_apply_transforms_in_order() {
+        let metadata = metadata();
+        let pipeline = TransformPipeline(vec![rename(), people_per_household()]);
+        let df = metrics();
+
+        assert_eq!(
+            pipeline.validate(columns(&df), &metadata).unwrap(),
+            [
+                COL::GEO_ID,
+                "B01001_E001",
+                "households",
+                "people_per_household"
+            ]
+        );
+        let df = pipeline.apply(df, &metadata).unwrap();
+        assert_eq!(
+            df.column("people_per_household")
+                .unwrap()
+                .f64()
+                .unwrap()
+                .to_vec(),
+            [Some(4.0), Some(0.0)]
+        );
+    }
+
+    #[test]
+    fn pipeline_validation_should_depend_on_order() {
+        let metadata = metadata();
+        // The formula uses the renamed column so cannot come before the rename
+        let pipeline = TransformPipeline(vec![people_per_household(), rename()]);
+        let err = pipeline
+            .validate(columns(&metrics()), &metadata)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Formula for 'people_per_household' uses missing column 'households'"
+        );
+    }
+
+    #[test]
+    fn division_by_zero_should_be_null() {
+        let transform = FormulaColumn {
+            name: "ratio".into(),
+            formula: Formula::Div(
+                Box::new(Formula::Column("B11001_E001".into())),
+                Box::new(Formula::Column("B01001_E001".into())),
+            ),
+        };
+        let df = transform.apply(metrics(), &metadata()).unwrap();
+        assert_eq!(
+            df.column("ratio").unwrap().f64().unwrap().to_vec(),
+            [Some(0.25), None]
+        );
+    }
+
+    #[test]
+    fn rename_columns_should_use_human_readable_names() {
+        let metadata = metadata();
+        let rename =
+            RenameColumns::to_human_readable_names(&metadata, &["population_id", "households_id"])
+                .unwrap();
+        let df = rename.apply(metrics(), &metadata).unwrap();
+        assert_eq!(
+            columns(&df),
+            [COL::GEO_ID, "Total population", "Total households"]
+        );
+
+        // Columns can be swapped
+        let swap = RenameColumns(HashMap::from([
+            ("B01001_E001".to_string(), "B11001_E001".to_string()),
+            ("B11001_E001".to_string(), "B01001_E001".to_string()),
+        ]));
+        let df = swap.apply(metrics(), &metadata).unwrap();
+        assert_eq!(
+            df.column("B11001_E001").unwrap().i32().unwrap().to_vec(),
+            [Some(200), Some(0)]
+        );
+    }
+
+    #[test]
+    fn rename_columns_should_reject_missing_and_colliding_columns() {
+        let metadata = metadata();
+        let missing = RenameColumns(HashMap::from([(
+            "B17021_E006".to_string(),
+            "Poverty".to_string(),
+        )]));
+        assert_eq!(
+            missing.apply(metrics(), &metadata).unwrap_err().to_string(),
+            "Cannot rename missing column 'B17021_E006'"
+        );
+
+        // Both metrics are named "Total households"
+        let mut df = metrics();
+        df.rename("B01001_E001", "B25001_E001").unwrap();
+        let colliding =
+            RenameColumns::to_human_readable_names(&metadata, &["households_id", "dwellings_id"])
+                .unwrap();
+        assert_eq!(
+            colliding
+                .validate(columns(&df), &metadata)
+                .unwrap_err()
+                .to_string(),
+            "Renaming would give more than one column the name 'Total households'"
+        );
+        assert!(colliding.apply(df, &metadata).is_err());
+    }
+
+    #[test]
+    fn interpolation_to_h3_should_conserve_counts() {
+        let metadata = Metadata {
+            metrics: df!(
+                COL::METRIC_ID => &["population_id", "income_id"],
+                COL::METRIC_PARQUET_COLUMN_NAME => &["population", "median_income"],
+                COL::METRIC_DATA_TYPE => &["count", "continuous"],
+            )
+            .unwrap(),
+            ..metadata()
+        };
+        // Two adjacent squares of roughly 11km by 11km
+        let df = df!(
+            COL::GEO_ID => &["W", "E"],
+            "geometry" => &[
+                "POLYGON((0 0,0.1 0,0.1 0.1,0 0.1,0 0))",
+                "POLYGON((0.1 0,0.2 0,0.2 0.1,0.1 0.1,0.1 0))",
+            ],
+            "population" => &[1000, 3000],
+            "median_income" => &[20_000.0, 30_000.0],
+        )
+        .unwrap();
+        let transform = InterpolateToH3 { resolution: 7 };
+
+        assert_eq!(
+            transform.validate(columns(&df), &metadata).unwrap(),
+            [H3_INDEX, "geometry", "population", "median_income"]
+        );
+        let interpolated = transform.apply(df, &metadata).unwrap();
+
+        assert!(interpolated.height() > 2);
+        // Allow for floating point rounding relative to the size of the values
+        let tolerance = |expected: f64| expected.abs() * 1e-9;
+        let population = interpolated.column("population").unwrap().f64().unwrap();
+        let total = population.sum().unwrap();
+        assert!(
+            (total - 4000.0).abs() <= tolerance(4000.0),
+            "Population should be conserved but totals {total}"
+        );
+        let income = interpolated.column("median_income").unwrap().f64().unwrap();
+        for income in income.into_no_null_iter() {
+            assert!(
+                income >= 20_000.0 - tolerance(20_000.0)
+                    && income <= 30_000.0 + tolerance(30_000.0),
+                "Median income {income} should be between those of the geometries"
+            );
+        }
+    }
+
+    #[test]
+    fn pivoting_to_long_and_back_should_give_original_metrics() {
+        let df = df!(
+            COL::GEO_ID => &["E1", "E2", "E3"],
+            "B01001_E001" => &[Some(200), None, Some(30)],
+            "B11001_E001" => &[Some(50), Some(10), None],
+        )
+        .unwrap();
+        assert_eq!(
+            PivotToLong.validate(columns(&df), &metadata()).unwrap(),
+            [COL::GEO_ID, METRIC_NAME, METRIC_VALUE]
+        );
+
+        let long = PivotToLong.apply(df.clone(), &metadata()).unwrap();
+        assert_eq!(long.height(), 6);
+        assert_eq!(
+            long.column(METRIC_NAME)
+                .unwrap()
+                .str()
+                .unwrap()
+                .into_no_null_iter()
+                .collect_vec(),
+            [
+                "B01001_E001",
+                "B01001_E001",
+                "B01001_E001",
+                "B11001_E001",
+                "B11001_E001",
+                "B11001_E001"
+            ]
+        );
+        assert_eq!(long.column(METRIC_VALUE).unwrap().dtype(), &DataType::Int32);
+
+        let wide = pivot_to_wide(&long).unwrap();
+        assert_eq!(wide, df);
+    }
+
+    #[test]
+    fn pipeline_should_pivot_to_long_and_back() {
+        let df = metrics();
+        let pipeline = TransformPipeline(vec![
+            PopgetterTransform::PivotToLong(PivotToLong),
+            PopgetterTransform::PivotToWide(PivotToWide),
+        ]);
+
+        assert_eq!(
+            pipeline.validate(columns(&df), &metadata()).unwrap(),
+            [COL::GEO_ID]
+        );
+        assert_eq!(pipeline.apply(df.clone(), &metadata()).unwrap(), df);
+        assert!(PivotToWide.validate(columns(&df), &metadata()).is_err());
+    }
+
+    #[test]
+    fn pivoting_to_wide_should_reject_duplicate_values() {
+        let long = df!(
+            COL::GEO_ID => &["E1", "E1"],
+            METRIC_NAME => &["B01001_E001", "B01001_E001"],
+            METRIC_VALUE => &[200, 201],
+        )
+        .unwrap();
+        assert!(pivot_to_wide(&long).is_err());
+    }
+}
@@ -14,9 +14,9 @@ use popgetter::{
     },
     geo::BBox,
     search::{
-        CaseSensitivity, Country, DataPublisher, DownloadParams, GeometryLevel, MatchType,
-        MetricId, Params, SearchConfig, SearchContext, SearchParams, SearchText, SourceDataRelease,
-        SourceDownloadUrl, SourceMetricId, YearRange,
+        CaseSensitivity, Country, DataPublisher, DownloadParams, GeometryLevel, HxlAttribute,
+        MatchType, MetricId, Params, SearchConfig, SearchContext, SearchParams, SearchText,
+        SourceDataRelease, SourceDownloadUrl, SourceMetricId, TextCombination, YearRange,
     },
     Popgetter,
 };
@@ -28,7 +28,7 @@ use strum_macros::EnumString;
 
 use crate::display::{
     display_column, display_column_unique, display_countries, display_metdata_columns,
-    display_search_results, display_summary,
+    display_search_results, display_summary, DisplayStyle,
 };
 
 const DEFAULT_PROGRESS_SPINNER: Spinners = Spinners::Dots;
@@ -107,6 +107,11 @@ struct DownloadParamsArgs {
         help = "When set, no geometry data is included in the results"
     )]
     no_geometry: bool,
+    #[arg(
+        long = "include-moe",
+        help = "When set, the margin of error of each metric that has one is included as <metric>_moe"
+    )]
+    include_moe: bool,
 }
 
 /// A type combining both the `SearchParamsArgs` and `DownloadParamsArgs` to enable `DownloadParams`
@@ -126,6 +131,8 @@ impl From<CombinedParamsArgs> for DownloadParams {
                 .map(|bbox| vec![RegionSpec::BoundingBox(bbox)])
                 .unwrap_or_default(),
             include_geoms: !combined_params_args.download_params_args.no_geometry,
+            include_moe: combined_params_args.download_params_args.include_moe,
+            transformations: Default::default(),
         }
     }
 }
@@ -159,7 +166,7 @@ impl RunCommand for DataCommand {
         });
         let popgetter = Popgetter::new_with_config_and_cache(config).await?;
         let search_params: SearchParams = self.search_params_args.clone().into();
-        let search_results = popgetter.search(&search_params);
+        let search_results = popgetter.search(&search_params)?;
 
         // sp.stop_and_persist is potentially a better method, but not obvious how to
         // store the timing. Leaving below until that option is ruled out.
@@ -220,6 +227,12 @@ pub struct MetricsCommand {
     summary_options: SummaryOptions,
     #[clap(flatten)]
     metrics_results_options: MetricsResultsOptions,
+    #[cfg(feature = "interactive")]
+    #[arg(
+        long,
+        help = "Refine the search interactively. Ignored if stdin or stdout is not a terminal."
+    )]
+    interactive: bool,
     #[arg(from_global)]
     quiet: bool,
 }
@@ -248,6 +261,37 @@ pub struct MetricsResultsOptions {
     full: bool,
     #[arg(long, help = "Exclude description from search results")]
     exclude_description: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = MetricsFormatArgs::Table,
+        help = "Format to display search results in"
+    )]
+    format: MetricsFormatArgs,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DisplayStyle::Detailed,
+        help = "Style of table to display search results in"
+    )]
+    style: DisplayStyle,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "COLUMN NAME,...",
+        help = "\
+            Comma-separated metadata columns to display for each metric. Defaults to a\n\
+            selection depending on the display style. Use --display-metadata-columns to\n\
+            list the available columns."
+    )]
+    columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, clap::ValueEnum, Copy)]
+enum MetricsFormatArgs {
+    Table,
+    Json,
+    Csv,
 }
 
 #[derive(Debug, Clone, clap::ValueEnum, Copy)]
@@ -324,6 +368,14 @@ struct SearchParamsArgs {
     // Filters for text
     #[arg(long, help="Filter by HXL tag", num_args=0..)]
     hxl: Vec<String>,
+    #[arg(
+        long,
+        help = "\
+            Filter by HXL tag attribute, e.g. '+total'. Only whole attributes are matched,\n\
+            case insensitively.",
+        num_args=0..
+    )]
+    hxl_attribute: Vec<String>,
     #[arg(long, help="Filter by metric name", num_args=0..)]
     name: Vec<String>,
     #[arg(long, help="Filter by metric description", num_args=0..)]
@@ -333,16 +385,16 @@ struct SearchParamsArgs {
     #[arg(
         short,
         long,
-        value_name = "LEFT,BOTTOM,RIGHT,TOP",
+        value_name = "MIN_LON,MIN_LAT,MAX_LON,MAX_LAT",
         allow_hyphen_values = true,
+        value_parser = parse_bbox,
         help = "\
-            Bounding box in which to get the results. The bounding box provided must be in\n\
-            the same coordinate system as used in the requested geometry file. For\n\
-            example, United States has geometries with latitude and longitude (EPSG:4326),\n\
-            Great Britain has geometries with the British National Grid (EPSG:27700),\n\
-            Northern Ireland has geometries with the Irish Grid (EPSG:29902), and\n\
-            Beligum has geometries with the Belgian Lambert 2008 reference system\n\
-            (EPSG:3812)."
+            Bounding box in which to get the results, in WGS84 longitude and latitude\n\
+            (EPSG:4326) whatever the coordinate system of the requested geometry file. It is\n\
+            reprojected to the coordinate system of the geometries, which can be latitude and\n\
+            longitude (EPSG:4326), Web Mercator (EPSG:3857), the British National Grid\n\
+            (EPSG:27700), the Irish Grid (EPSG:29902) or the Belgian Lambert 2008 reference\n\
+            system (EPSG:3812)."
     )]
     bbox: Option<BBox>,
     #[arg(
@@ -380,6 +432,13 @@ fn parse_year_range(value: &str) -> Result<Vec<YearRange>, anyhow::Error> {
         .collect::<Result<Vec<YearRange>, anyhow::Error>>()
 }
 
+/// Parse a WGS84 bounding box given as `MIN_LON,MIN_LAT,MAX_LON,MAX_LAT`, checking that it is
+/// valid (see `BBox::new`)
+fn parse_bbox(value: &str) -> Result<BBox, anyhow::Error> {
+    let bbox: BBox = value.parse().map_err(anyhow::Error::msg)?;
+    BBox::new(bbox[0], bbox[1], bbox[2], bbox[3])
+}
+
 // A simple function to manage similaries across multiple cases.
 // May ultimately be generalised to a function to manage all progress UX
 // that can be switched on and off.
@@ -403,6 +462,8 @@ fn text_searches_from_args(
             match_type,
             case_sensitivity,
         },
+        exact: true,
+        match_types: Default::default(),
     }));
     all_text_searches.extend(name.iter().map(|t| SearchText {
         text: t.clone(),
@@ -411,6 +472,8 @@ fn text_searches_from_args(
             match_type,
             case_sensitivity,
         },
+        exact: true,
+        match_types: Default::default(),
     }));
     all_text_searches.extend(description.iter().map(|t| SearchText {
         text: t.clone(),
@@ -419,6 +482,8 @@ fn text_searches_from_args(
             match_type,
             case_sensitivity,
         },
+        exact: true,
+        match_types: Default::default(),
     }));
     all_text_searches.extend(text.iter().map(|t| SearchText {
         text: t.clone(),
@@ -428,6 +493,8 @@ fn text_searches_from_args(
             match_type: MatchType::Regex,
             case_sensitivity,
         },
+        exact: true,
+        match_types: Default::default(),
     }));
     all_text_searches
 }
@@ -443,6 +510,7 @@ impl From<SearchParamsArgs> for SearchParams {
                 args.match_type.into(),
                 args.case_sensitivity.into(),
             ),
+            text_combination: TextCombination::All,
             year_range: args.year_range.clone(),
             geometry_level: args.geometry_level.clone().map(|value| GeometryLevel {
                 value,
@@ -504,6 +572,11 @@ impl From<SearchParamsArgs> for SearchParams {
                     },
                 })
                 .collect(),
+            composite_metric: vec![],
+            hxl_attribute: args.hxl_attribute.into_iter().map(HxlAttribute).collect(),
+            exclude_geometry_level: vec![],
+            exclude_data_publisher: vec![],
+            exclude_country: vec![],
             region_spec: args
                 .bbox
                 .map(|bbox| vec![RegionSpec::BoundingBox(bbox)])
@@ -525,7 +598,25 @@ impl RunCommand for MetricsCommand {
         });
         let popgetter = Popgetter::new_with_config_and_cache(config).await?;
 
-        let search_results = popgetter.search(&self.search_params_args.to_owned().into());
+        #[cfg(feature = "interactive")]
+        if self.interactive {
+            use std::io::IsTerminal;
+            if io::stdin().is_terminal() && io::stdout().is_terminal() {
+                if let Some(mut s) = sp {
+                    s.stop_with_symbol(COMPLETE_PROGRESS_STRING);
+                }
+                return crate::interactive::run(
+                    &popgetter,
+                    self.search_params_args.to_owned().into(),
+                    self.metrics_results_options.exclude_description,
+                    self.metrics_results_options.style,
+                    &self.metrics_results_options.columns,
+                );
+            }
+            log::warn!("Not running interactively as stdin or stdout is not a terminal");
+        }
+
+        let search_results = popgetter.search(&self.search_params_args.to_owned().into())?;
         if let Some(mut s) = sp {
             s.stop_with_symbol(COMPLETE_PROGRESS_STRING);
         }
@@ -534,7 +625,7 @@ impl RunCommand for MetricsCommand {
         // Output options:
         // Display: metadata columns
         if self.summary_options.display_metadata_columns {
-            display_metdata_columns(&popgetter.metadata.combined_metric_source_geometry())?;
+            display_metdata_columns(&popgetter.metadata.combined_metric_source_geometry()?)?;
         // Display: summary
         } else if self.summary_options.summary {
             display_summary(search_results)?;
@@ -544,6 +635,12 @@ impl RunCommand for MetricsCommand {
         // Display: column
         } else if let Some(column) = self.summary_options.column.as_ref() {
             display_column(search_results, column)?;
+        // Display: metrics results as JSON
+        } else if self.metrics_results_options.format == MetricsFormatArgs::Json {
+            println!("{}", search_results.to_json()?);
+        // Display: metrics results as CSV
+        } else if self.metrics_results_options.format == MetricsFormatArgs::Csv {
+            search_results.to_csv_writer(std::io::stdout().lock())?;
         // Display: metrics results
         } else {
             // MetricsResultsOptions: exclude description
@@ -560,6 +657,8 @@ impl RunCommand for MetricsCommand {
                     search_results,
                     Some(50),
                     self.metrics_results_options.exclude_description,
+                    self.metrics_results_options.style,
+                    &self.metrics_results_options.columns,
                 )?;
                 println!(
                     "{} more results not shown. Use --full to show all results.",
@@ -570,6 +669,8 @@ impl RunCommand for MetricsCommand {
                     search_results,
                     None,
                     self.metrics_results_options.exclude_description,
+                    self.metrics_results_options.style,
+                    &self.metrics_results_options.columns,
                 )?;
             }
         }
@@ -634,10 +735,10 @@ pub struct RecipeCommand {
 impl RunCommand for RecipeCommand {
     async fn run(&self, config: Config) -> Result<()> {
         let popgetter = Popgetter::new_with_config(config).await?;
-        let recipe = std::fs::read_to_string(&self.recipe_file)?;
-        let data_request: DataRequestSpec = serde_json::from_str(&recipe)?;
+        let data_request = DataRequestSpec::from_recipe_file(&self.recipe_file)?;
+        data_request.validate(&popgetter.metadata)?;
         let params: Params = data_request.try_into()?;
-        let search_results = popgetter.search(&params.search);
+        let search_results = popgetter.search(&params.search)?;
         let data = search_results
             .download(&popgetter.config, &params.download)
             .await?;
@@ -740,6 +841,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn bbox_should_be_parsed_as_wgs84() {
+        assert_eq!(
+            parse_bbox("-3.0,51.8,-1.4,53.0").unwrap(),
+            BBox([-3.0, 51.8, -1.4, 53.0])
+        );
+        // British National Grid coordinates are out of range
+        assert!(parse_bbox("530000,180000,531000,181000").is_err());
+        // Inverted
+        assert!(parse_bbox("-1.4,51.8,-3.0,53.0").is_err());
+        assert!(parse_bbox("-3.0,51.8,-1.4").is_err());
+    }
+
     #[test]
     fn output_type_should_deserialize_properly() {
         let output_format = OutputFormat::from_str("GeoJSON");
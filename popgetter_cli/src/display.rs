@@ -2,10 +2,18 @@ use std::collections::HashMap;
 use std::io::Write;
 use std::sync::OnceLock;
 
+use anyhow::bail;
 use comfy_table::{presets::NOTHING, *};
 use itertools::izip;
-use polars::{frame::DataFrame, prelude::SortMultipleOptions};
-use popgetter::{metadata::ExpandedMetadata, search::SearchResults, COL};
+use polars::{
+    frame::DataFrame,
+    prelude::{AnyValue, SortMultipleOptions},
+};
+use popgetter::{
+    metadata::ExpandedMetadata,
+    search::{SearchResults, METRIC_UNIT_INFERRED},
+    COL,
+};
 
 static LOOKUP: OnceLock<HashMap<&'static str, &'static str>> = OnceLock::new();
 
@@ -29,6 +37,13 @@ fn lookup() -> &'static HashMap<&'static str, &'static str> {
         hm.insert(COL::COUNTRY_NAME_SHORT_EN, "Country");
         hm.insert(COL::GEOMETRY_LEVEL, "Geometry level");
         hm.insert(COL::METRIC_SOURCE_DOWNLOAD_URL, "Source download URL");
+        hm.insert(
+            COL::METRIC_SOURCE_DOCUMENTATION_URL,
+            "Source documentation URL",
+        );
+        hm.insert(COL::DATA_PUBLISHER_NAME, "Publisher");
+        hm.insert(COL::METRIC_UNIT, "Unit");
+        hm.insert(METRIC_UNIT_INFERRED, "Unit inferred");
         hm
     })
 }
@@ -110,89 +125,195 @@ pub fn display_countries(countries: DataFrame, max_results: Option<usize>) -> an
     Ok(writeln!(&mut std::io::stdout(), "\n{}", table)?)
 }
 
+/// Format a cell for display. Nulls, which are common in fields such as the HXL tag or
+/// description, are shown as an empty string.
+fn format_value(value: AnyValue) -> String {
+    match value {
+        AnyValue::Null => String::new(),
+        AnyValue::String(value) => value.to_string(),
+        value => format!("{value}"),
+    }
+}
+
+/// Render each metric in `df` as its own table, with one row for each `(column, label)` pair in
+/// `columns`. Columns missing from `df` are an error so that schema changes are caught rather than
+/// silently skipped.
+fn render_search_results(df: &DataFrame, columns: &[(&str, &str)]) -> anyhow::Result<Vec<Table>> {
+    let mut df = df.clone();
+    df.as_single_chunk_par();
+    let names = columns.iter().map(|(col, _)| *col).collect::<Vec<_>>();
+    // See example for iteration over SeriesIter: https://stackoverflow.com/a/72443329
+    let series = df.columns(&names)?;
+    let mut iters = series.iter().map(|s| s.iter()).collect::<Vec<_>>();
+
+    let mut tables = vec![];
+    for _ in 0..df.height() {
+        let mut table = create_table(Some(100), None);
+        for (iter, (col, label)) in iters.iter_mut().zip(columns) {
+            let value = format_value(iter.next().unwrap());
+            table.add_row(vec![
+                Cell::new(label).add_attribute(Attribute::Bold),
+                value.as_str().into(),
+            ]);
+            // Also show the short form of metric IDs that can be used in searches
+            if *col == COL::METRIC_ID {
+                table.add_row(vec![
+                    Cell::new("Metric ID (short)").add_attribute(Attribute::Bold),
+                    value.chars().take(8).collect::<String>().into(),
+                ]);
+            }
+        }
+        tables.push(table);
+    }
+    Ok(tables)
+}
+
+/// How search results are displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum DisplayStyle {
+    /// A table for each metric showing all of its fields
+    #[default]
+    Detailed,
+    /// A single table with a row for each metric
+    Compact,
+}
+
+/// Maximum number of characters shown in a cell of the compact table
+const COMPACT_MAX_CHARS: usize = 48;
+
+/// Truncate `value` to at most `max_chars` characters, ending with an ellipsis if shortened
+fn truncate(value: &str, max_chars: usize) -> String {
+    if value.chars().count() <= max_chars {
+        value.to_string()
+    } else {
+        let mut truncated = value
+            .chars()
+            .take(max_chars.saturating_sub(1))
+            .collect::<String>();
+        truncated.push('…');
+        truncated
+    }
+}
+
+/// Columns shown in the detailed display if none are requested
+const DEFAULT_DETAILED_COLUMNS: [&str; 9] = [
+    COL::METRIC_ID,
+    COL::METRIC_HUMAN_READABLE_NAME,
+    COL::METRIC_DESCRIPTION,
+    COL::METRIC_HXL_TAG,
+    COL::METRIC_UNIT,
+    COL::SOURCE_DATA_RELEASE_COLLECTION_PERIOD_START,
+    COL::COUNTRY_NAME_SHORT_EN,
+    COL::GEOMETRY_LEVEL,
+    COL::METRIC_SOURCE_DOWNLOAD_URL,
+];
+
+/// Columns shown in the compact display if none are requested
+const DEFAULT_COMPACT_COLUMNS: [&str; 4] = [
+    COL::METRIC_ID,
+    COL::METRIC_HUMAN_READABLE_NAME,
+    COL::GEOMETRY_LEVEL,
+    COL::DATA_PUBLISHER_NAME,
+];
+
+/// The columns to display paired with their labels: the `requested` columns, or `defaults` if
+/// none are requested. Requested columns missing from `df` are an error listing the available
+/// columns.
+fn display_columns<'a>(
+    df: &DataFrame,
+    requested: &'a [String],
+    defaults: &[&'a str],
+) -> anyhow::Result<Vec<(&'a str, &'a str)>> {
+    let columns = if requested.is_empty() {
+        defaults.to_vec()
+    } else {
+        requested.iter().map(String::as_str).collect()
+    };
+    let unknown = requested
+        .iter()
+        .filter(|col| df.get_column_index(col).is_none())
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        bail!(
+            "Unknown column(s) {unknown:?}, available columns are: {}",
+            df.get_column_names().join(", ")
+        );
+    }
+    Ok(columns
+        .into_iter()
+        .map(|col| (col, lookup().get(col).copied().unwrap_or(col)))
+        .collect())
+}
+
+/// Render all metrics in `df` as a single table with a row for each metric and alternate rows
+/// shaded. Long values are truncated so that the columns stay aligned.
+fn render_compact_search_results(
+    df: &DataFrame,
+    columns: &[(&str, &str)],
+) -> anyhow::Result<Table> {
+    let header = columns.iter().map(|(_, label)| *label).collect::<Vec<_>>();
+    let mut table = create_table(None, Some(&header));
+    let mut df = df.clone();
+    df.as_single_chunk_par();
+    let names = columns.iter().map(|(col, _)| *col).collect::<Vec<_>>();
+    let series = df.columns(&names)?;
+    let mut iters = series.iter().map(|s| s.iter()).collect::<Vec<_>>();
+    for row in 0..df.height() {
+        let cells = iters.iter_mut().zip(&names).map(|(iter, col)| {
+            let value = format_value(iter.next().unwrap());
+            let value = if *col == COL::METRIC_ID {
+                // Show the short form of metric IDs that can be used in searches
+                value.chars().take(8).collect()
+            } else {
+                truncate(&value, COMPACT_MAX_CHARS)
+            };
+            let cell = Cell::new(value);
+            if row % 2 == 1 {
+                cell.bg(Color::DarkGrey)
+            } else {
+                cell
+            }
+        });
+        table.add_row(cells.collect::<Vec<_>>());
+    }
+    Ok(table)
+}
+
+/// Display the search results in the given `style`, showing the given `columns` of each metric or
+/// a default set if empty. The unit of each metric is available to display, inferred from its HXL
+/// tag if the metadata has none (see `SearchResults::with_units`).
 pub fn display_search_results(
     results: SearchResults,
     max_results: Option<usize>,
     exclude_description: bool,
+    style: DisplayStyle,
+    columns: &[String],
 ) -> anyhow::Result<()> {
-    let mut df_to_show = match max_results {
+    let results = results.with_units()?;
+    let summary = results.summary();
+    let df_to_show = match max_results {
         Some(max) => results.0.head(Some(max)),
         None => results.0,
     };
-    df_to_show.as_single_chunk_par();
-
+    let defaults = match style {
+        DisplayStyle::Detailed => DEFAULT_DETAILED_COLUMNS.as_slice(),
+        DisplayStyle::Compact => DEFAULT_COMPACT_COLUMNS.as_slice(),
+    };
+    let mut columns = display_columns(&df_to_show, columns, defaults)?;
     // Set columns conditional on exclude_description arg
-    let mut cols = vec![
-        COL::METRIC_ID,
-        COL::METRIC_HUMAN_READABLE_NAME,
-        COL::METRIC_DESCRIPTION,
-        COL::METRIC_HXL_TAG,
-        COL::SOURCE_DATA_RELEASE_COLLECTION_PERIOD_START,
-        COL::COUNTRY_NAME_SHORT_EN,
-        COL::GEOMETRY_LEVEL,
-        COL::METRIC_SOURCE_DOWNLOAD_URL,
-    ];
     if exclude_description {
-        cols.retain(|&col| col.ne(COL::METRIC_DESCRIPTION));
+        columns.retain(|&(col, _)| col.ne(COL::METRIC_DESCRIPTION));
+    }
+    if style == DisplayStyle::Compact {
+        let table = render_compact_search_results(&df_to_show, &columns)?;
+        writeln!(&mut std::io::stdout(), "{}", table)?;
+        return Ok(writeln!(&mut std::io::stdout(), "{summary}")?);
     }
-    // See example for iteration over SeriesIter: https://stackoverflow.com/a/72443329
-    let mut iters = df_to_show
-        .columns(&cols)?
-        .iter()
-        .map(|s| s.iter())
-        .collect::<Vec<_>>();
 
-    for _ in 0..df_to_show.height() {
-        let mut table = create_table(Some(100), None);
-        for (iter, col) in iters.iter_mut().zip(cols.to_vec()) {
-            let value = iter.next().unwrap();
-            match col {
-                // Format: metric ID
-                COL::METRIC_ID => {
-                    table
-                        .add_row(vec![
-                            Cell::new(lookup().get(col).unwrap()).add_attribute(Attribute::Bold),
-                            value.clone().get_str().unwrap().into(),
-                        ])
-                        .add_row(vec![
-                            Cell::new("Metric ID (short)").add_attribute(Attribute::Bold),
-                            value
-                                .get_str()
-                                .unwrap()
-                                .chars()
-                                .take(8)
-                                .collect::<String>()
-                                .into(),
-                        ]);
-                }
-                // Format: str
-                COL::COUNTRY_NAME_SHORT_EN
-                | COL::METRIC_HUMAN_READABLE_NAME
-                | COL::METRIC_DESCRIPTION
-                | COL::METRIC_HXL_TAG
-                | COL::GEOMETRY_LEVEL
-                | COL::METRIC_SOURCE_DOWNLOAD_URL => {
-                    table.add_row(vec![
-                        Cell::new(lookup().get(col).unwrap()).add_attribute(Attribute::Bold),
-                        value.get_str().unwrap().into(),
-                    ]);
-                }
-                // Format: dates
-                COL::SOURCE_DATA_RELEASE_COLLECTION_PERIOD_START => {
-                    table.add_row(vec![
-                        Cell::new(lookup().get(col).unwrap()).add_attribute(Attribute::Bold),
-                        format!("{value}").into(),
-                    ]);
-                }
-                // No missing columns are possible since all matching should be include in columns
-                _ => {
-                    unreachable!()
-                }
-            }
-        }
+    for table in render_search_results(&df_to_show, &columns)? {
         writeln!(&mut std::io::stdout(), "{}", table)?;
     }
-    Ok(())
+    Ok(writeln!(&mut std::io::stdout(), "{summary}")?)
 }
 
 pub fn display_summary(results: SearchResults) -> anyhow::Result<()> {
@@ -228,25 +349,29 @@ pub fn display_summary(results: SearchResults) -> anyhow::Result<()> {
     Ok(writeln!(&mut std::io::stdout(), "\n{}", table)?)
 }
 
+/// The values of `column` formatted for display, with nulls shown as empty strings and
+/// non-string values (e.g. dates) formatted rather than rejected
+fn column_values(df: &DataFrame, column: &str, unique: bool) -> anyhow::Result<Vec<String>> {
+    let series = df.column(column)?;
+    let series = if unique {
+        series.unique()?
+    } else {
+        series.rechunk()
+    };
+    Ok(series.iter().map(format_value).collect())
+}
+
 /// Display a given column from the search results
 pub fn display_column(search_results: SearchResults, column: &str) -> anyhow::Result<()> {
-    Ok(search_results
-        .0
-        .column(column)?
-        .rechunk()
+    Ok(column_values(&search_results.0, column, false)?
         .iter()
-        .map(|el| el.get_str().map(|s| s.to_string()).unwrap())
         .try_for_each(|el| writeln!(&mut std::io::stdout(), "{el}"))?)
 }
 
 /// Display the unique values of a given column from the search results
 pub fn display_column_unique(search_results: SearchResults, column: &str) -> anyhow::Result<()> {
-    Ok(search_results
-        .0
-        .column(column)?
-        .unique()?
+    Ok(column_values(&search_results.0, column, true)?
         .iter()
-        .map(|el| el.get_str().map(|s| s.to_string()).unwrap())
         .try_for_each(|el| writeln!(&mut std::io::stdout(), "{el}"))?)
 }
 
@@ -260,3 +385,199 @@ pub fn display_metdata_columns(expanded_metadata: &ExpandedMetadata) -> anyhow::
         .into_iter()
         .try_for_each(|el| writeln!(&mut std::io::stdout(), "{el}"))?)
 }
+
+#[cfg(test)]
+mod tests {
+    use polars::{
+        df,
+        prelude::{NamedFrom, Series},
+    };
+    use popgetter::metadata::Metadata;
+
+    use super::*;
+
+    #[test]
+    fn render_search_results_should_show_a_table_per_metric() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_ID => &["abcdef0123456789", "9876543210fedcba"],
+            COL::METRIC_HUMAN_READABLE_NAME => &[Some("Population"), None],
+            "unused" => &["x", "y"],
+        )?;
+        let columns = [
+            (COL::METRIC_ID, "Metric ID"),
+            (COL::METRIC_HUMAN_READABLE_NAME, "Human readable name"),
+        ];
+        let tables = render_search_results(&df, &columns)?;
+        assert_eq!(tables.len(), 2);
+        let rows = tables
+            .iter()
+            .map(|table| {
+                table
+                    .row_iter()
+                    .map(|row| {
+                        row.cell_iter()
+                            .map(|cell| cell.content())
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows[0],
+            vec![
+                vec!["Metric ID", "abcdef0123456789"],
+                vec!["Metric ID (short)", "abcdef01"],
+                vec!["Human readable name", "Population"],
+            ]
+        );
+        assert_eq!(rows[1][2], vec!["Human readable name", ""]);
+        Ok(())
+    }
+
+    #[test]
+    fn compact_style_should_show_one_table_for_all_metrics() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_ID => &["abcdef0123456789", "9876543210fedcba", "0011223344556677"],
+            COL::METRIC_HUMAN_READABLE_NAME => &[
+                Some("Population"),
+                None,
+                Some("Number of people aged 16 and over in employment in the week before the census"),
+            ],
+            COL::GEOMETRY_LEVEL => &["oa", "ltla", "oa"],
+            COL::DATA_PUBLISHER_NAME => &["ONS", "ONS", "NRS"],
+        )?;
+        let columns = display_columns(&df, &[], &DEFAULT_COMPACT_COLUMNS)?;
+        let table = render_compact_search_results(&df, &columns)?;
+        let rows = table
+            .row_iter()
+            .map(|row| {
+                row.cell_iter()
+                    .map(|cell| cell.content())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], vec!["abcdef01", "Population", "oa", "ONS"]);
+        assert_eq!(rows[1][1], "");
+        assert_eq!(rows[2][1].chars().count(), COMPACT_MAX_CHARS);
+        assert!(rows[2][1].ends_with('…'));
+
+        let columns = [(COL::METRIC_ID, "Metric ID")];
+        assert_eq!(render_search_results(&df, &columns)?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn null_cells_should_be_displayed_as_empty() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_ID => &["abcdef0123456789", "9876543210fedcba"],
+            COL::METRIC_HXL_TAG => &[None, Some("#population")],
+            COL::METRIC_DESCRIPTION => &[None::<&str>, None],
+            "year" => &[Some(2021), None],
+        )?;
+        assert_eq!(
+            column_values(&df, COL::METRIC_HXL_TAG, false)?,
+            ["", "#population"]
+        );
+        assert_eq!(column_values(&df, "year", false)?, ["2021", ""]);
+        assert_eq!(column_values(&df, COL::METRIC_DESCRIPTION, true)?, [""]);
+        let columns = [
+            (COL::METRIC_HXL_TAG, "HXL tag"),
+            (COL::METRIC_DESCRIPTION, "Description"),
+        ];
+        assert_eq!(render_search_results(&df, &columns)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn display_columns_should_use_requested_columns() -> anyhow::Result<()> {
+        let df = df!(
+            COL::METRIC_ID => &["abcdef0123456789"],
+            COL::METRIC_SOURCE_DOWNLOAD_URL => &["https://example.com"],
+            "metric_unit" => &["people"],
+        )?;
+        let requested = [
+            COL::METRIC_SOURCE_DOWNLOAD_URL.to_string(),
+            "metric_unit".to_string(),
+        ];
+        let columns = display_columns(&df, &requested, &DEFAULT_DETAILED_COLUMNS)?;
+        assert_eq!(
+            columns,
+            [
+                (COL::METRIC_SOURCE_DOWNLOAD_URL, "Source download URL"),
+                ("metric_unit", "Unit")
+            ]
+        );
+        let tables = render_search_results(&df, &columns)?;
+        assert_eq!(tables[0].row_iter().count(), 2);
+
+        let err = display_columns(&df, &["typo".to_string()], &DEFAULT_DETAILED_COLUMNS)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("typo"), "{err}");
+        assert!(err.contains("metric_unit"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn both_renderers_should_find_columns_of_legacy_metadata() -> anyhow::Result<()> {
+        // Older metadata releases have unprefixed `description` and `hxl_tag` columns
+        let metadata = Metadata {
+            metrics: df!(
+                COL::METRIC_ID => &["abcdef0123456789"],
+                COL::METRIC_HUMAN_READABLE_NAME => &["Population"],
+                "description" => &["Total population"],
+                "hxl_tag" => &["#population+total"],
+                COL::METRIC_SOURCE_DOWNLOAD_URL => &["https://example.com"],
+                COL::METRIC_SOURCE_DATA_RELEASE_ID => &["census"],
+            )?,
+            source_data_releases: df!(
+                COL::SOURCE_DATA_RELEASE_ID => &["census"],
+                COL::SOURCE_DATA_RELEASE_COLLECTION_PERIOD_START => &["2021-03-21"],
+                COL::SOURCE_DATA_RELEASE_GEOMETRY_METADATA_ID => &["wards"],
+                COL::SOURCE_DATA_RELEASE_DATA_PUBLISHER_ID => &["nisra"],
+            )?,
+            geometries: df!(
+                COL::GEOMETRY_ID => &["wards"],
+                COL::GEOMETRY_LEVEL => &["ward"],
+                "description" => &["Electoral wards"],
+            )?,
+            data_publishers: df!(
+                COL::DATA_PUBLISHER_ID => &["nisra"],
+                COL::DATA_PUBLISHER_NAME => &["NISRA"],
+                COL::DATA_PUBLISHER_COUNTRIES_OF_INTEREST => &[Series::new("", &["NIR"])],
+            )?,
+            countries: df!(
+                COL::COUNTRY_ID => &["NIR"],
+                COL::COUNTRY_NAME_SHORT_EN => &["Northern Ireland"],
+            )?,
+        };
+        let results =
+            SearchResults::from_lazy(metadata.combined_metric_source_geometry()?.as_df())?
+                .with_units()?;
+        let df = &results.0;
+
+        let columns = display_columns(df, &[], &DEFAULT_DETAILED_COLUMNS)?;
+        let tables = render_search_results(df, &columns)?;
+        // Each column has a row, along with the short metric ID
+        assert_eq!(
+            tables[0].row_iter().count(),
+            DEFAULT_DETAILED_COLUMNS.len() + 1
+        );
+        let table = tables[0].to_string();
+        assert!(table.contains("Total population"), "{table}");
+        assert!(table.contains("#population+total"), "{table}");
+
+        let columns = display_columns(df, &[], &DEFAULT_COMPACT_COLUMNS)?;
+        let table = render_compact_search_results(df, &columns)?;
+        assert_eq!(table.row_iter().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn render_search_results_should_fail_on_missing_columns() -> anyhow::Result<()> {
+        let df = df!(COL::METRIC_ID => &["abcdef0123456789"])?;
+        assert!(render_search_results(&df, &[(COL::METRIC_HXL_TAG, "HXL tag")]).is_err());
+        Ok(())
+    }
+}
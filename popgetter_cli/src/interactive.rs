@@ -0,0 +1,306 @@
+//! An interactive loop for refining a metrics search, enabled with the `interactive` feature.
+//! After each search the results are shown along with the geometry levels, publishers and
+//! countries they cover, and filters on these can be toggled without retyping the whole command.
+
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use itertools::Itertools;
+use popgetter::{
+    search::{
+        CaseSensitivity, Country, DataPublisher, GeometryLevel, MatchType, SearchConfig,
+        SearchParams, SearchResults,
+    },
+    Popgetter, COL,
+};
+
+use crate::display::{display_search_results, DisplayStyle};
+
+/// Maximum number of results shown after each refinement
+const MAX_RESULTS: usize = 20;
+
+const HELP: &str = "\
+Commands:
+  g, geometry <LEVEL>     Toggle a filter on geometry level
+  p, publisher <NAME>     Toggle a filter on data publisher
+  c, country <NAME>       Toggle a filter on country
+  r, reset                Remove all filters added in this session
+  h, help                 Show this help
+  q, quit                 Exit";
+
+/// A filter that can be toggled from the prompt
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Refinement {
+    GeometryLevel(String),
+    Publisher(String),
+    Country(String),
+}
+
+/// A command entered at the prompt
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Toggle(Refinement),
+    Reset,
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (command, value) = match s.trim().split_once(char::is_whitespace) {
+            Some((command, value)) => (command, value.trim()),
+            None => (s.trim(), ""),
+        };
+        let refinement = match (command.to_lowercase().as_str(), value) {
+            ("r" | "reset", "") => return Ok(Command::Reset),
+            ("h" | "help" | "?", "") => return Ok(Command::Help),
+            ("q" | "quit" | "exit", "") => return Ok(Command::Quit),
+            (_, "") => bail!("Unknown command '{}'", s.trim()),
+            ("g" | "geometry", value) => Refinement::GeometryLevel(value.to_string()),
+            ("p" | "publisher", value) => Refinement::Publisher(value.to_string()),
+            ("c" | "country", value) => Refinement::Country(value.to_string()),
+            _ => bail!("Unknown command '{}'", s.trim()),
+        };
+        Ok(Command::Toggle(refinement))
+    }
+}
+
+/// The search being refined. Toggling a filter that is already set restores the value given on
+/// the command line, if any.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RefinementState {
+    initial: SearchParams,
+    current: SearchParams,
+}
+
+fn exact_match() -> SearchConfig {
+    SearchConfig {
+        match_type: MatchType::Exact,
+        case_sensitivity: CaseSensitivity::Insensitive,
+    }
+}
+
+impl RefinementState {
+    pub fn new(params: SearchParams) -> Self {
+        Self {
+            initial: params.clone(),
+            current: params,
+        }
+    }
+
+    pub fn params(&self) -> &SearchParams {
+        &self.current
+    }
+
+    pub fn apply(&mut self, command: &Command) {
+        let (current, initial) = (&mut self.current, &self.initial);
+        let is_set = |value: Option<&String>, new: &str| {
+            value.is_some_and(|value| value.eq_ignore_ascii_case(new))
+        };
+        match command {
+            Command::Toggle(Refinement::GeometryLevel(value)) => {
+                current.geometry_level = if is_set(
+                    current.geometry_level.as_ref().map(|level| &level.value),
+                    value,
+                ) {
+                    initial.geometry_level.clone()
+                } else {
+                    Some(GeometryLevel {
+                        value: value.clone(),
+                        config: exact_match(),
+                    })
+                }
+            }
+            Command::Toggle(Refinement::Publisher(value)) => {
+                current.data_publisher = if is_set(
+                    current
+                        .data_publisher
+                        .as_ref()
+                        .map(|publisher| &publisher.value),
+                    value,
+                ) {
+                    initial.data_publisher.clone()
+                } else {
+                    Some(DataPublisher {
+                        value: value.clone(),
+                        config: exact_match(),
+                    })
+                }
+            }
+            Command::Toggle(Refinement::Country(value)) => {
+                current.country = if is_set(
+                    current.country.as_ref().map(|country| &country.value),
+                    value,
+                ) {
+                    initial.country.clone()
+                } else {
+                    Some(Country {
+                        value: value.clone(),
+                        config: exact_match(),
+                    })
+                }
+            }
+            Command::Reset => *current = initial.clone(),
+            Command::Help | Command::Quit => {}
+        }
+    }
+}
+
+/// The distinct values of `column` in the results, as a comma separated list
+fn facet(results: &SearchResults, column: &str) -> Result<String> {
+    Ok(results
+        .0
+        .column(column)?
+        .unique_stable()?
+        .str()?
+        .into_iter()
+        .flatten()
+        .join(", "))
+}
+
+fn show_results(
+    results: SearchResults,
+    exclude_description: bool,
+    style: DisplayStyle,
+    columns: &[String],
+) -> Result<()> {
+    let mut stdout = io::stdout();
+    writeln!(stdout, "Found {} metric(s).", results.0.height())?;
+    writeln!(
+        stdout,
+        "Geometry levels: {}",
+        facet(&results, COL::GEOMETRY_LEVEL)?
+    )?;
+    writeln!(
+        stdout,
+        "Publishers: {}",
+        facet(&results, COL::DATA_PUBLISHER_NAME)?
+    )?;
+    writeln!(
+        stdout,
+        "Countries: {}",
+        facet(&results, COL::COUNTRY_NAME_SHORT_EN)?
+    )?;
+    display_search_results(
+        results,
+        Some(MAX_RESULTS),
+        exclude_description,
+        style,
+        columns,
+    )
+}
+
+/// Run the search and refine it with commands read from stdin until the user quits
+pub fn run(
+    popgetter: &Popgetter,
+    params: SearchParams,
+    exclude_description: bool,
+    style: DisplayStyle,
+    columns: &[String],
+) -> Result<()> {
+    let mut state = RefinementState::new(params);
+    let mut refresh = true;
+    let mut line = String::new();
+    loop {
+        if refresh {
+            show_results(
+                popgetter.search(state.params())?,
+                exclude_description,
+                style,
+                columns,
+            )?;
+        }
+        print!("> ");
+        io::stdout().flush()?;
+        line.clear();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        refresh = match line.parse::<Command>() {
+            Ok(Command::Quit) => return Ok(()),
+            Ok(Command::Help) => {
+                println!("{HELP}");
+                false
+            }
+            Ok(command) => {
+                state.apply(&command);
+                true
+            }
+            Err(err) => {
+                println!("{err}\n{HELP}");
+                false
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geometry_level(params: &SearchParams) -> Option<&str> {
+        params
+            .geometry_level
+            .as_ref()
+            .map(|level| level.value.as_str())
+    }
+
+    #[test]
+    fn commands_should_parse() {
+        assert_eq!(
+            "g oa".parse::<Command>().unwrap(),
+            Command::Toggle(Refinement::GeometryLevel("oa".into()))
+        );
+        assert_eq!(
+            "publisher  Office for National Statistics "
+                .parse::<Command>()
+                .unwrap(),
+            Command::Toggle(Refinement::Publisher(
+                "Office for National Statistics".into()
+            ))
+        );
+        assert_eq!("q".parse::<Command>().unwrap(), Command::Quit);
+        assert_eq!("reset".parse::<Command>().unwrap(), Command::Reset);
+        assert!("g".parse::<Command>().is_err());
+        assert!("x oa".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn toggling_filters_should_update_search_params() {
+        let initial = SearchParams {
+            geometry_level: Some(GeometryLevel {
+                value: "ltla".into(),
+                config: exact_match(),
+            }),
+            ..SearchParams::default()
+        };
+        let mut state = RefinementState::new(initial.clone());
+
+        state.apply(&"g oa".parse().unwrap());
+        assert_eq!(geometry_level(state.params()), Some("oa"));
+
+        state.apply(&"p ONS".parse().unwrap());
+        assert_eq!(
+            state.params().data_publisher,
+            Some(DataPublisher {
+                value: "ONS".into(),
+                config: exact_match(),
+            })
+        );
+
+        // Toggling an active filter off restores the value from the command line
+        state.apply(&"g OA".parse().unwrap());
+        assert_eq!(geometry_level(state.params()), Some("ltla"));
+        state.apply(&"p ons".parse().unwrap());
+        assert_eq!(state.params().data_publisher, None);
+
+        state.apply(&"c Scotland".parse().unwrap());
+        state.apply(&"g oa".parse().unwrap());
+        assert_ne!(state.params(), &initial);
+        state.apply(&Command::Reset);
+        assert_eq!(state.params(), &initial);
+    }
+}
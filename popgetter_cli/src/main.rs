@@ -1,11 +1,13 @@
 mod cli;
 mod display;
+#[cfg(feature = "interactive")]
+mod interactive;
 
 use anyhow::Result;
 use clap::Parser;
 use cli::{Cli, RunCommand};
 use log::debug;
-use popgetter::config::Config;
+use popgetter::config::{Config, ConfigBuilder};
 
 const DEFAULT_LOGGING_LEVEL: &str = "warn";
 
@@ -42,7 +44,12 @@ fn read_config_from_toml() -> Config {
         .join("popgetter")
         .join("config.toml");
     match std::fs::read_to_string(file_path) {
-        Ok(contents) => toml::from_str(&contents).expect("Invalid TOML in config file"),
+        Ok(contents) => {
+            let config: Config = toml::from_str(&contents).expect("Invalid TOML in config file");
+            ConfigBuilder::from(config)
+                .build()
+                .expect("Invalid config file")
+        }
         Err(e) => {
             if e.kind() == std::io::ErrorKind::NotFound {
                 Config::default()
@@ -38,7 +38,7 @@ fn convert_py_dict<T: DeserializeOwned>(obj: &Bound<'_, PyAny>) -> PyResult<T> {
 async fn _search(search_params: SearchParams) -> anyhow::Result<DataFrame> {
     let search_results = Popgetter::new_with_config_and_cache(Config::default())
         .await?
-        .search(&search_params);
+        .search(&search_params)?;
     Ok(search_results.0.select([
         COL::METRIC_ID,
         COL::METRIC_HUMAN_READABLE_NAME,
@@ -60,6 +60,8 @@ async fn _search_and_download(search_params: SearchParams) -> anyhow::Result<Dat
             download: DownloadParams {
                 include_geoms: true,
                 region_spec: search_params.region_spec,
+                include_moe: false,
+                transformations: Default::default(),
             },
         })
         .await